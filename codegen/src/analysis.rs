@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    ast::{Segment, Span, Token, TokenExt},
+    eval::TAPE_SIZE,
+};
+
+/// How many steps are evaluated exactly by default
+pub const DEFAULT_BUDGET: u64 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What [`analyze`] found out about a program without knowing its input
+pub struct Analysis {
+    /// Loops whose cell is always 0 when they are reached, so their body never runs
+    pub dead_loops: Vec<Span>,
+    /// Loops that every execution reaching them enters, because their cell is
+    /// known to be nonzero there
+    pub entered_loops: Vec<Span>,
+    /// The number of steps every execution starts with, before anything depends on the input
+    pub concrete_steps: u64,
+    /// The loop or `.` where the execution first depends on the input (or the
+    /// step budget ran out), or `None` if the program could be evaluated entirely
+    pub concrete_until: Option<Span>,
+    /// Whether the concrete prefix ended because the step budget ran out
+    pub budget_exhausted: bool,
+    /// The output every execution starts with
+    pub known_output: Vec<u8>,
+    /// Whether `known_output` is all the program ever prints
+    pub output_complete: bool,
+    /// The lowest and highest cells the pointer ever reaches, if they could be proven
+    pub pointer_bounds: Option<(usize, usize)>,
+    /// The loop that moves the pointer by an unknown amount, if the bounds could not be proven
+    pub unbounded_loop: Option<Span>,
+}
+
+/// Runs a program on an abstract tape whose cells are exact values until they
+/// depend on the input. A loop whose cell is known is run exactly (or skipped),
+/// others are assumed to run any number of times, which forgets every cell
+/// their body changes. The analysis gives up on exact values after `budget` steps.
+pub fn analyze<T: TokenExt>(segments: &[Segment<T>], budget: u64) -> Analysis {
+    let mut analyzer = Analyzer {
+        budget,
+        steps: 0,
+        concrete_steps: 0,
+        concrete_until: None,
+        budget_exhausted: false,
+        exact: true,
+        known_output: Vec::new(),
+        output_exact: true,
+        speculative: 0,
+        lowest: 0,
+        highest: 0,
+        visits: BTreeMap::new(),
+    };
+
+    let mut state = State {
+        tape: vec![Some(0); TAPE_SIZE],
+        pointer: 0,
+    };
+
+    let unbounded_loop = analyzer.segments(segments, &mut state).err();
+
+    let dead_loops = analyzer
+        .visits
+        .values()
+        .filter(|visit| !visit.entered)
+        .map(|visit| visit.span)
+        .collect();
+
+    let entered_loops = analyzer
+        .visits
+        .values()
+        .filter(|visit| visit.entered_exactly)
+        .map(|visit| visit.span)
+        .collect();
+
+    Analysis {
+        dead_loops,
+        entered_loops,
+        concrete_steps: analyzer.concrete_steps,
+        concrete_until: analyzer.concrete_until,
+        budget_exhausted: analyzer.budget_exhausted,
+        known_output: analyzer.known_output,
+        output_complete: analyzer.output_exact && unbounded_loop.is_none(),
+        pointer_bounds: unbounded_loop
+            .is_none()
+            .then_some((analyzer.lowest, analyzer.highest)),
+        unbounded_loop,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    /// `None` for cells whose value depends on the input
+    tape: Vec<Option<u8>>,
+    pointer: usize,
+}
+
+#[derive(Debug)]
+struct Visit {
+    span: Span,
+    /// Whether the loop's cell was (or may have been) nonzero when it was reached
+    entered: bool,
+    /// Whether the loop's cell was known to be nonzero when it was reached
+    /// outside of a loop with an unknown cell
+    entered_exactly: bool,
+}
+
+struct Analyzer {
+    budget: u64,
+    steps: u64,
+    concrete_steps: u64,
+    concrete_until: Option<Span>,
+    budget_exhausted: bool,
+    /// Whether every decision so far was made on exact values
+    exact: bool,
+    known_output: Vec<u8>,
+    output_exact: bool,
+    /// How many bodies of loops with unknown cells are being analyzed, which
+    /// stand for any number of iterations, so their output and steps do not count
+    speculative: usize,
+    lowest: usize,
+    highest: usize,
+    /// The loops reached so far, by the offset of their `[`
+    visits: BTreeMap<usize, Visit>,
+}
+
+impl Analyzer {
+    /// Analyzes segments, or returns the loop where the pointer was lost.
+    fn segments<T: TokenExt>(
+        &mut self,
+        segments: &[Segment<T>],
+        state: &mut State,
+    ) -> Result<(), Span> {
+        for segment in segments {
+            match segment {
+                Segment::Executable { code, .. } => {
+                    for (token, span) in code.tokens.iter().zip(&code.spans) {
+                        self.token(token.token(), token.count(), *span, state);
+                    }
+                }
+                Segment::Loop { body, span } => {
+                    if let Err(lost) = self.run_loop(body, *span, state) {
+                        // the loop can run again with values that were not analyzed
+                        self.forget_loops(body);
+                        return Err(lost);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count_steps(&mut self, steps: usize) {
+        self.steps += steps as u64;
+        if self.exact && self.speculative == 0 {
+            self.concrete_steps += steps as u64;
+        }
+    }
+
+    /// Stops counting the concrete prefix at `span`.
+    fn inexact(&mut self, span: Span) {
+        if self.exact && self.speculative == 0 {
+            self.exact = false;
+            self.concrete_until = Some(span);
+        }
+    }
+
+    fn token(&mut self, token: Token, count: usize, span: Span, state: &mut State) {
+        self.count_steps(count);
+
+        let cell = &mut state.tape[state.pointer];
+
+        match token {
+            Token::PointerAdd => {
+                state.pointer = (state.pointer + count).min(TAPE_SIZE - 1);
+                self.highest = self.highest.max(state.pointer);
+            }
+            Token::PointerSub => {
+                state.pointer = state.pointer.saturating_sub(count);
+                self.lowest = self.lowest.min(state.pointer);
+            }
+            Token::ValueAdd => *cell = cell.map(|value| value.wrapping_add(count as u8)),
+            Token::ValueSub => *cell = cell.map(|value| value.wrapping_sub(count as u8)),
+            Token::Read => *cell = None,
+            Token::Write => {
+                if self.speculative > 0 || !self.output_exact {
+                    return;
+                }
+
+                match *cell {
+                    Some(value) => self.known_output.extend((0..count).map(|_| value)),
+                    None => {
+                        self.output_exact = false;
+                        self.inexact(span);
+                    }
+                }
+            }
+            Token::Multiply | Token::Divide => {
+                *cell = None;
+                self.highest = self.highest.max((state.pointer + 1).min(TAPE_SIZE - 1));
+            }
+            Token::LoopStart | Token::LoopEnd => {}
+        }
+    }
+
+    fn run_loop<T: TokenExt>(
+        &mut self,
+        body: &[Segment<T>],
+        span: Span,
+        state: &mut State,
+    ) -> Result<(), Span> {
+        let cell = state.tape[state.pointer];
+
+        let visit = self.visits.entry(span.start).or_insert(Visit {
+            span,
+            entered: false,
+            entered_exactly: false,
+        });
+        visit.entered |= cell != Some(0);
+        visit.entered_exactly |= self.speculative == 0 && cell.is_some_and(|value| value != 0);
+
+        loop {
+            self.count_steps(1);
+
+            match state.tape[state.pointer] {
+                Some(0) => return Ok(()),
+                Some(_) if self.steps < self.budget => self.segments(body, state)?,
+                Some(_) => {
+                    if self.exact && self.speculative == 0 {
+                        self.budget_exhausted = true;
+                    }
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        // the cell is unknown (or treated as such), so the body runs an unknown
+        // number of times
+        self.inexact(span);
+
+        let mut effects = Effects::default();
+        let mut offset = 0;
+        if !effects.collect(body, &mut offset) || offset != 0 {
+            return Err(span);
+        }
+
+        let lowest = state.pointer as isize + effects.lowest;
+        let highest = state.pointer as isize + effects.highest;
+        if lowest < 0 || highest >= TAPE_SIZE as isize {
+            // the pointer would be clamped, so the body is no longer balanced
+            return Err(span);
+        }
+
+        for offset in &effects.touched {
+            state.tape[(state.pointer as isize + offset) as usize] = None;
+        }
+
+        if effects.writes {
+            self.output_exact = false;
+        }
+
+        // every iteration starts from a state like this one, so analyzing the
+        // body once finds every cell the pointer reaches and visits inner loops
+        self.speculative += 1;
+        let result = self.segments(body, &mut state.clone());
+        self.speculative -= 1;
+        result?;
+
+        state.tape[state.pointer] = Some(0);
+
+        Ok(())
+    }
+
+    /// Marks every loop in these segments as possibly entered.
+    fn forget_loops<T: TokenExt>(&mut self, segments: &[Segment<T>]) {
+        for segment in segments {
+            if let Segment::Loop { body, span } = segment {
+                self.visits
+                    .entry(span.start)
+                    .or_insert(Visit {
+                        span: *span,
+                        entered: false,
+                        entered_exactly: false,
+                    })
+                    .entered = true;
+                self.forget_loops(body);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// The cells a loop body can change, relative to where it starts
+pub(crate) struct Effects {
+    pub touched: BTreeSet<isize>,
+    pub lowest: isize,
+    pub highest: isize,
+    /// Whether the body contains `.`
+    pub writes: bool,
+}
+
+impl Effects {
+    /// Collects the effects of segments starting at `offset`, returning `false`
+    /// if a loop among them moves the pointer by a runtime-dependent amount.
+    pub fn collect<T: TokenExt>(&mut self, segments: &[Segment<T>], offset: &mut isize) -> bool {
+        for segment in segments {
+            match segment {
+                Segment::Executable { code, .. } => {
+                    for token in &code.tokens {
+                        match token.token() {
+                            Token::PointerAdd => *offset += token.count() as isize,
+                            Token::PointerSub => *offset -= token.count() as isize,
+                            Token::ValueAdd | Token::ValueSub | Token::Read => {
+                                self.touched.insert(*offset);
+                            }
+                            Token::Write => self.writes = true,
+                            // they also read the next cell
+                            Token::Multiply | Token::Divide => {
+                                self.touched.insert(*offset);
+                                self.highest = self.highest.max(*offset + 1);
+                            }
+                            Token::LoopStart | Token::LoopEnd => {}
+                        }
+
+                        self.lowest = self.lowest.min(*offset);
+                        self.highest = self.highest.max(*offset);
+                    }
+                }
+                Segment::Loop { body, .. } => {
+                    let start = *offset;
+                    if !self.collect(body, offset) || *offset != start {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
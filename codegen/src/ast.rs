@@ -1,13 +1,26 @@
 use std::str::FromStr;
 
+use clap::ValueEnum;
 use thiserror::Error;
 
+use crate::pragma::{self, Pragmas};
+
 pub trait TokenExt {
     fn token(&self) -> Token;
 
     fn count(&self) -> usize;
 
+    /// Tokenizes plain Brainfuck code.
     fn tokenize(code: &str) -> Tokens<Self>
+    where
+        Self: Sized,
+    {
+        Self::tokenize_in(code, Dialect::Standard)
+    }
+
+    /// Tokenizes code in `dialect`, where its extra instructions are not
+    /// comments.
+    fn tokenize_in(code: &str, dialect: Dialect) -> Tokens<Self>
     where
         Self: Sized;
 }
@@ -21,18 +34,37 @@ impl TokenExt for Token {
         1
     }
 
-    fn tokenize(code: &str) -> Tokens<Self> {
+    fn tokenize_in(code: &str, dialect: Dialect) -> Tokens<Self> {
         let mut tokens = Vec::new();
+        let mut spans = Vec::new();
 
-        for c in code.chars() {
-            if let Some(token) = Token::from_char(c) {
+        let mut line = 1;
+        let mut column = 1;
+        let skipped = pragma::skipped(code);
+
+        for (start, c) in code.char_indices() {
+            let in_pragma = skipped.iter().any(|range| range.contains(&start));
+            if let Some(token) = Token::from_char_in(c, dialect).filter(|_| !in_pragma) {
                 tokens.push(token);
+                spans.push(Span {
+                    start,
+                    end: start + c.len_utf8(),
+                    line,
+                    column,
+                });
+            }
+
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
             }
         }
 
         trace!("tokenizer found {} tokens", tokens.len());
 
-        Tokens { tokens }
+        Tokens { tokens, spans }
     }
 }
 
@@ -45,21 +77,33 @@ impl TokenExt for Repeated {
         self.count
     }
 
-    fn tokenize(code: &str) -> Tokens<Self> {
-        let unoptimized = Token::tokenize(code);
+    fn tokenize_in(code: &str, dialect: Dialect) -> Tokens<Self> {
+        let unoptimized = Token::tokenize_in(code, dialect);
 
         let mut tokens = Vec::new();
+        let mut spans = Vec::new();
 
-        let mut iter = unoptimized.tokens.into_iter().peekable();
+        let mut iter = unoptimized
+            .tokens
+            .into_iter()
+            .zip(unoptimized.spans)
+            .peekable();
 
-        while let Some(token) = iter.next() {
+        while let Some((token, mut span)) = iter.next() {
             let mut count = 1;
 
-            while let Some(next) = iter.peek() {
-                if !matches!(token, Token::LoopStart | Token::LoopEnd | Token::Read)
-                    && next == &token
+            while let Some((next, next_span)) = iter.peek() {
+                if !matches!(
+                    token,
+                    Token::LoopStart
+                        | Token::LoopEnd
+                        | Token::Read
+                        | Token::Multiply
+                        | Token::Divide
+                ) && next == &token
                 {
                     count += 1;
+                    span = span.to(*next_span);
                     iter.next();
                 } else {
                     break;
@@ -67,11 +111,12 @@ impl TokenExt for Repeated {
             }
 
             tokens.push(Repeated { token, count });
+            spans.push(span);
         }
 
         trace!("tokenizer optimized to {} tokens", tokens.len());
 
-        Tokens { tokens }
+        Tokens { tokens, spans }
     }
 }
 
@@ -95,11 +140,18 @@ macro_rules! tokens {
                 }
             }
 
+            /// The plain Brainfuck instruction `c` is, if any.
             pub fn from_char(c: char) -> Option<Self> {
+                Self::from_char_in(c, Dialect::Standard)
+            }
+
+            /// The instruction `c` is in `dialect`, if any.
+            pub fn from_char_in(c: char, dialect: Dialect) -> Option<Self> {
                 match c {
                     $($c => Some(Self::$token)),*,
                     _ => None
                 }
+                .filter(|token| token.in_dialect(dialect))
             }
         }
     };
@@ -122,51 +174,113 @@ tokens! {
     LoopStart = '[',
     /// Skip if the cell under the pointer is 0, otherwise jump back to the matching `[`.
     LoopEnd = ']',
+    /// Multiply the cell under the pointer by the next one (`float-ext` only).
+    Multiply = '*',
+    /// Divide the cell under the pointer by the next one (`float-ext` only).
+    Divide = '/',
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+/// Which instructions a program is made of
+pub enum Dialect {
+    #[default]
+    /// Plain Brainfuck, where anything else is a comment
+    Standard,
+    /// An experiment in extended semantics: `*` and `/` multiply and divide
+    /// the current cell by the next one, and the code generator gives the
+    /// program floating-point cells
+    FloatExt,
+}
+
+impl Token {
+    /// Whether the instruction exists in `dialect`, rather than being a
+    /// comment there
+    pub fn in_dialect(&self, dialect: Dialect) -> bool {
+        match self {
+            Self::Multiply | Self::Divide => dialect == Dialect::FloatExt,
+            _ => true,
+        }
+    }
+}
+
+/// Where something is in the source code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    /// The byte offset of the first character
+    pub start: usize,
+    /// The byte offset just past the last character
+    pub end: usize,
+    /// The line of the first character, starting at 1
+    pub line: usize,
+    /// The column of the first character, in characters, starting at 1
+    pub column: usize,
+}
+
+impl Span {
+    /// The span from the start of this one to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tokens<T> {
     pub tokens: Vec<T>,
+    /// The span of each token, in the same order
+    pub spans: Vec<Span>,
 }
 
 impl<T: TokenExt> Tokens<T> {
-    pub fn new(tokens: Vec<T>) -> Self {
-        Self { tokens }
+    pub fn new(tokens: Vec<T>, spans: Vec<Span>) -> Self {
+        Self { tokens, spans }
     }
 }
 
 impl<T: TokenExt + Clone> Tokens<T> {
     pub fn segment(self) -> Vec<Segment<T>> {
-        let (segments, _) = Self::segment_inner(&self.tokens[..]);
+        let (segments, _) = Self::segment_inner(&self.tokens[..], &self.spans[..]);
         segments
     }
 
-    /// Takes a slice of repeated tokens and outputs the contained segments,
-    /// as well as the number of tokens that were consumed.
-    fn segment_inner(slice: &[T]) -> (Vec<Segment<T>>, usize) {
+    /// Takes a slice of repeated tokens (and their spans) and outputs the
+    /// contained segments, as well as the number of tokens that were consumed.
+    fn segment_inner(slice: &[T], spans: &[Span]) -> (Vec<Segment<T>>, usize) {
         let mut segments = Vec::new();
 
         let mut iter = slice.iter().peekable().enumerate();
 
         let mut consumed = 0usize;
         let mut code = Vec::new();
+        let mut code_spans = Vec::new();
 
         while let Some((idx, token)) = iter.next() {
             match token.token() {
                 Token::LoopStart => {
                     if !code.is_empty() {
-                        segments.push(Segment::Executable(Tokens::new(code)));
+                        segments.push(Segment::executable(code, code_spans));
                         code = Vec::new();
+                        code_spans = Vec::new();
                     }
 
-                    let (inner, count) = Self::segment_inner(&slice[idx + 1..]);
-                    segments.push(Segment::Loop(inner));
+                    let (inner, count) = Self::segment_inner(&slice[idx + 1..], &spans[idx + 1..]);
+                    // an unmatched `[` runs to the end of the file
+                    let end = spans
+                        .get(idx + count + 1)
+                        .unwrap_or(&spans[spans.len() - 1]);
+                    segments.push(Segment::Loop {
+                        body: inner,
+                        span: spans[idx].to(*end),
+                    });
                     iter.nth(count);
                     consumed += count + 2;
                 }
                 Token::LoopEnd => {
                     if !code.is_empty() {
-                        segments.push(Segment::Executable(Tokens::new(code)));
+                        segments.push(Segment::executable(code, code_spans));
                     }
 
                     return (segments, consumed);
@@ -174,10 +288,15 @@ impl<T: TokenExt + Clone> Tokens<T> {
                 _ => {
                     consumed += 1;
                     code.push(token.clone());
+                    code_spans.push(spans[idx]);
                 }
             }
         }
 
+        if !code.is_empty() {
+            segments.push(Segment::executable(code, code_spans));
+        }
+
         (segments, consumed)
     }
 }
@@ -190,14 +309,111 @@ pub struct Repeated {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Segment<T> {
-    Executable(Tokens<T>),
-    Loop(Vec<Segment<T>>),
+    Executable {
+        code: Tokens<T>,
+        span: Span,
+    },
+    Loop {
+        body: Vec<Segment<T>>,
+        /// From the `[` to the matching `]`
+        span: Span,
+    },
+}
+
+impl<T: TokenExt> Segment<T> {
+    /// A segment of code without loops, which must not be empty
+    fn executable(tokens: Vec<T>, spans: Vec<Span>) -> Self {
+        let span = spans[0].to(spans[spans.len() - 1]);
+        Segment::Executable {
+            code: Tokens::new(tokens, spans),
+            span,
+        }
+    }
+}
+
+/// The number of tokens in some segments, including loops and their brackets
+pub fn count_tokens<T>(segments: &[Segment<T>]) -> usize {
+    segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Executable { code, .. } => code.tokens.len(),
+            Segment::Loop { body, .. } => count_tokens(body) + 2,
+        })
+        .sum()
+}
+
+/// How much input a program can read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputUsage {
+    /// The program never reads input
+    None,
+    /// Every `,` is outside of any loop, so the program reads at most this many values
+    AtMost(usize),
+    /// Some `,` is inside a loop, so the program can read any amount of input
+    Streaming,
+}
+
+impl InputUsage {
+    /// Finds how much input some segments can read
+    pub fn of<T: TokenExt>(segments: &[Segment<T>]) -> Self {
+        let mut reads = 0;
+
+        for segment in segments {
+            match segment {
+                Segment::Executable { code, .. } => {
+                    reads += code
+                        .tokens
+                        .iter()
+                        .filter(|t| t.token() == Token::Read)
+                        .map(|t| t.count())
+                        .sum::<usize>();
+                }
+                Segment::Loop { body, .. } => {
+                    if Self::of(body) != Self::None {
+                        return Self::Streaming;
+                    }
+                }
+            }
+        }
+
+        if reads == 0 {
+            Self::None
+        } else {
+            Self::AtMost(reads)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct File<T> {
     pub segments: Vec<Segment<T>>,
     pub needs_input: bool,
+    pub input_usage: InputUsage,
+    /// The semantics asked for by the `@pragma` directives that could be read
+    /// (see [`crate::pragma`])
+    #[serde(default)]
+    pub pragmas: Pragmas,
+    #[serde(default)]
+    pub dialect: Dialect,
+}
+
+impl<T: TokenExt + Clone> File<T> {
+    /// Parses code in `dialect`. Parsing with [`str::parse`] assumes plain
+    /// Brainfuck.
+    pub fn parse_in(s: &str, dialect: Dialect) -> Self {
+        let segments = T::tokenize_in(s, dialect).segment();
+
+        let input_usage = InputUsage::of(&segments);
+        trace!("input usage: {:?}", input_usage);
+
+        Self {
+            segments,
+            needs_input: input_usage != InputUsage::None,
+            input_usage,
+            pragmas: pragma::parse_pragmas(s).0,
+            dialect,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -207,14 +423,30 @@ impl<T: TokenExt + Clone> FromStr for File<T> {
     type Err = ParseFileError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let tokens = T::tokenize(s);
+        Ok(Self::parse_in(s, Dialect::Standard))
+    }
+}
 
-        let needs_input = tokens.tokens.iter().any(|t| t.token() == Token::Read);
+/// Writes segments back out as Brainfuck code, without any comments
+pub fn to_code<T: TokenExt>(segments: &[Segment<T>]) -> String {
+    let mut code = String::new();
 
-        let segments = tokens.segment();
-        Ok(Self {
-            segments,
-            needs_input,
-        })
+    for segment in segments {
+        match segment {
+            Segment::Executable { code: tokens, .. } => {
+                for token in &tokens.tokens {
+                    for _ in 0..token.count() {
+                        code.push(token.token().as_char());
+                    }
+                }
+            }
+            Segment::Loop { body, .. } => {
+                code.push('[');
+                code.push_str(&to_code(body));
+                code.push(']');
+            }
+        }
     }
+
+    code
 }
@@ -0,0 +1,151 @@
+use crate::{
+    ast::{File, InputUsage, TokenExt},
+    eval::{Evaluator, Outcome},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What a program did with one input
+pub struct Run {
+    pub output: Vec<u8>,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The result of comparing two programs
+pub enum Verdict {
+    /// Both programs printed the same output for every input. If neither
+    /// program reads input, the single run covers every input and `proven` is set.
+    Same { inputs: usize, proven: bool },
+    /// The programs printed different output for this input
+    Different { input: Vec<u8>, a: Run, b: Run },
+    /// No difference was found, but on this input at least one program ran out
+    /// of steps before the outputs could be compared completely
+    Inconclusive { input: Vec<u8>, a: Run, b: Run },
+}
+
+fn run<T: TokenExt>(file: &File<T>, input: &[u8], max_steps: u64) -> Run {
+    let mut evaluator = Evaluator::new(input.to_vec(), max_steps);
+    let outcome = evaluator.run(&file.segments);
+
+    Run {
+        output: evaluator.output,
+        outcome,
+    }
+}
+
+/// Compares what two programs print for each input, running each for at most
+/// `max_steps` steps, and stops at the first input that tells them apart.
+pub fn check<T: TokenExt>(a: &File<T>, b: &File<T>, inputs: &[Vec<u8>], max_steps: u64) -> Verdict {
+    let input_free = a.input_usage == InputUsage::None && b.input_usage == InputUsage::None;
+    let empty = [Vec::new()];
+    let inputs = if input_free { &empty[..] } else { inputs };
+
+    let mut inconclusive = None;
+
+    for input in inputs {
+        let run_a = run(a, input, max_steps);
+        let run_b = run(b, input, max_steps);
+
+        let common = run_a.output.len().min(run_b.output.len());
+        let halted = run_a.outcome == Outcome::Halted && run_b.outcome == Outcome::Halted;
+
+        // a run that ran out of steps could still print more, but not change what it printed
+        if run_a.output[..common] != run_b.output[..common]
+            || (run_a.outcome == Outcome::Halted && run_a.output.len() < run_b.output.len())
+            || (run_b.outcome == Outcome::Halted && run_b.output.len() < run_a.output.len())
+        {
+            return Verdict::Different {
+                input: input.clone(),
+                a: run_a,
+                b: run_b,
+            };
+        }
+
+        if !halted && inconclusive.is_none() {
+            inconclusive = Some(Verdict::Inconclusive {
+                input: input.clone(),
+                a: run_a,
+                b: run_b,
+            });
+        }
+    }
+
+    inconclusive.unwrap_or(Verdict::Same {
+        inputs: inputs.len(),
+        proven: input_free,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Repeated;
+
+    fn verdict(a: &str, b: &str, inputs: &[&[u8]]) -> Verdict {
+        let parse = |code: &str| -> File<Repeated> {
+            match code.parse() {
+                Ok(file) => file,
+                Err(e) => match e {},
+            }
+        };
+        let inputs: Vec<Vec<u8>> = inputs.iter().map(|input| input.to_vec()).collect();
+        check(&parse(a), &parse(b), &inputs, 1000)
+    }
+
+    #[test]
+    fn programs_without_input_are_proven_by_one_run() {
+        assert_eq!(
+            verdict("++.", "+++-.", &[b"a", b"b"]),
+            Verdict::Same {
+                inputs: 1,
+                proven: true
+            }
+        );
+    }
+
+    #[test]
+    fn programs_reading_input_are_only_compared_on_the_inputs() {
+        assert_eq!(
+            verdict(",+.", ",++-.", &[b"", b"a", b"b"]),
+            Verdict::Same {
+                inputs: 3,
+                proven: false
+            }
+        );
+    }
+
+    #[test]
+    fn the_first_input_telling_them_apart_is_reported() {
+        let Verdict::Different { input, a, b } = verdict(",.", ",[-].", &[&[0], b"x", b"y"]) else {
+            panic!("no difference found");
+        };
+        assert_eq!(input, b"x");
+        assert_eq!((a.output, b.output), (b"x".to_vec(), vec![0]));
+    }
+
+    #[test]
+    fn a_halted_program_printing_less_is_different() {
+        assert!(matches!(
+            verdict("+.", "+.+.+[]", &[]),
+            Verdict::Different { .. }
+        ));
+    }
+
+    #[test]
+    fn running_out_of_steps_is_inconclusive_unless_another_input_differs() {
+        let Verdict::Inconclusive { input, a, b } = verdict(",.", ",.+[]", &[b"a", b"b"]) else {
+            panic!("not inconclusive");
+        };
+        assert_eq!(input, b"a");
+        assert_eq!(
+            (a.outcome, b.outcome),
+            (Outcome::Halted, Outcome::OutOfSteps)
+        );
+
+        // `b` never halts on "b", but prints something else for 1 anyway
+        assert!(matches!(
+            verdict(",.", ",-[+.[]]++.", &[b"b", b"\x01"]),
+            Verdict::Different { input, .. } if input == b"\x01"
+        ));
+    }
+}
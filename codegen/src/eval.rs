@@ -0,0 +1,128 @@
+use crate::ast::{Segment, Token, TokenExt};
+
+/// The number of cells on the evaluator's tape, the same as the interpreter's
+pub const TAPE_SIZE: usize = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why the evaluator stopped
+pub enum Outcome {
+    /// The end of the program was reached
+    Halted,
+    /// The step limit was reached first
+    OutOfSteps,
+    /// A `/` of the `float-ext` dialect divided by a cell holding 0
+    DivisionByZero,
+}
+
+/// Runs a parsed program directly, with the same semantics as the interpreter:
+/// cells wrap, the pointer is clamped to the tape and `,` leaves the cell
+/// unchanged at the end of the input.
+#[derive(Debug, Clone)]
+pub struct Evaluator {
+    pub tape: Vec<u8>,
+    pub pointer: usize,
+    pub input: Vec<u8>,
+    pub input_pos: usize,
+    pub output: Vec<u8>,
+    /// The number of instructions executed so far, where a repeated token
+    /// counts once per repetition and every loop condition check counts once
+    pub steps: u64,
+    /// Stop after this many steps
+    pub max_steps: u64,
+}
+
+impl Evaluator {
+    pub fn new(input: Vec<u8>, max_steps: u64) -> Self {
+        Self {
+            tape: vec![0; TAPE_SIZE],
+            pointer: 0,
+            input,
+            input_pos: 0,
+            output: Vec::new(),
+            steps: 0,
+            max_steps,
+        }
+    }
+
+    /// Runs segments until they finish or the step limit is reached.
+    pub fn run<T: TokenExt>(&mut self, segments: &[Segment<T>]) -> Outcome {
+        match self.run_segments(segments) {
+            Ok(()) => Outcome::Halted,
+            Err(outcome) => outcome,
+        }
+    }
+
+    fn run_segments<T: TokenExt>(&mut self, segments: &[Segment<T>]) -> Result<(), Outcome> {
+        for segment in segments {
+            match segment {
+                Segment::Executable { code, .. } => {
+                    for token in &code.tokens {
+                        self.execute(token.token(), token.count())?;
+                    }
+                }
+                Segment::Loop { body, .. } => loop {
+                    self.count_steps(1)?;
+
+                    if self.tape[self.pointer] == 0 {
+                        break;
+                    }
+
+                    self.run_segments(body)?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count_steps(&mut self, steps: usize) -> Result<(), Outcome> {
+        self.steps += steps as u64;
+
+        if self.steps > self.max_steps {
+            Err(Outcome::OutOfSteps)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn execute(&mut self, token: Token, count: usize) -> Result<(), Outcome> {
+        self.count_steps(count)?;
+
+        let cell = &mut self.tape[self.pointer];
+
+        match token {
+            Token::PointerAdd => self.pointer = (self.pointer + count).min(TAPE_SIZE - 1),
+            Token::PointerSub => self.pointer = self.pointer.saturating_sub(count),
+            Token::ValueAdd => *cell = cell.wrapping_add(count as u8),
+            Token::ValueSub => *cell = cell.wrapping_sub(count as u8),
+            Token::Read => {
+                for _ in 0..count {
+                    if let Some(byte) = self.input.get(self.input_pos) {
+                        *cell = *byte;
+                        self.input_pos += 1;
+                    }
+                }
+            }
+            Token::Write => {
+                for _ in 0..count {
+                    self.output.push(*cell);
+                }
+            }
+            // the tokenizer never merges these, and the cells stay bytes
+            Token::Multiply => {
+                let next = self.tape[(self.pointer + 1).min(TAPE_SIZE - 1)];
+                self.tape[self.pointer] = self.tape[self.pointer].wrapping_mul(next);
+            }
+            Token::Divide => {
+                let next = self.tape[(self.pointer + 1).min(TAPE_SIZE - 1)];
+                self.tape[self.pointer] = self.tape[self.pointer]
+                    .checked_div(next)
+                    .ok_or(Outcome::DivisionByZero)?;
+            }
+            // loops are segments, not tokens
+            Token::LoopStart | Token::LoopEnd => {}
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! The template for the experimental `float-ext` dialect, whose cells are
+//! `f64`s. `+` and `-` add and subtract 1, `*` and `/` multiply and divide the
+//! current cell by the next one, `.` writes the cell as a number followed by a
+//! space and `,` parses the next whitespace-delimited number, whatever `--io`
+//! says. Dividing by 0 gives an infinity or NaN like any float division, and
+//! a loop only ends once its cell is exactly 0.
+//!
+//! Of the generator's options, only the memory size, pointer safety, fixed
+//! input and EOF behavior apply to it.
+
+use color_eyre::eyre::{eyre, Result};
+use proc_macro2::{Literal, TokenStream};
+use quote::quote;
+
+use codegen::ast::{File, Segment, Token, TokenExt, Tokens};
+
+use crate::generator::{
+    BrainfuckToRust, CellSize, CodegenStyle, EofBehavior, FlushPolicy, LoopExtraction,
+    PointerSafety,
+};
+
+impl BrainfuckToRust {
+    /// Generates a program in the `float-ext` dialect (see [`crate::float_ext`]).
+    pub(crate) fn float_ext<T: TokenExt>(&self, file: &File<T>) -> Result<TokenStream> {
+        let unsupported = [
+            (!matches!(self.cell_size, CellSize::U8), "other cell sizes"),
+            (
+                !matches!(self.loop_extraction, LoopExtraction::None),
+                "loop extraction",
+            ),
+            (self.runtime_config, "runtime configuration"),
+            (self.input_args, "input arguments"),
+            (
+                self.input_env.is_some(),
+                "input from an environment variable",
+            ),
+            (self.io_shim, "the output shim of the min-size profile"),
+            (self.panic_free, "panic-free programs"),
+            (
+                self.flush != FlushPolicy::PerLine,
+                "flush policies other than per-line",
+            ),
+            (self.resumable_input, "resumable input"),
+            (
+                self.style == CodegenStyle::StateMachine,
+                "the state-machine codegen style",
+            ),
+            (self.debug_server, "the debug server"),
+            (self.lib, "library crates"),
+            (self.exit_cell.is_some(), "exiting with the value of a cell"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(used, _)| *used) {
+            return Err(eyre!("the float-ext dialect does not support {}", option));
+        }
+
+        let mem_size = self.memory_size;
+        let body = self.float_body(&file.segments);
+
+        let input_def = match &self.fixed_input {
+            _ if !file.needs_input => quote! {},
+            Some(fixed) => {
+                let text = String::from_utf8_lossy(fixed);
+                quote! {
+                    let mut input = #text.split_whitespace();
+                }
+            }
+            None => quote! {
+                let mut text = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)
+                    .expect("failed to read stdin");
+                let mut input = text.split_whitespace();
+            },
+        };
+
+        Ok(quote! {
+            fn main() {
+                let mut pointer = 0usize;
+                let mut tape = vec![0f64; #mem_size];
+
+                #input_def
+
+                #body
+            }
+        })
+    }
+
+    fn float_body<T: TokenExt>(&self, segments: &[Segment<T>]) -> TokenStream {
+        let mut blocks = Vec::new();
+
+        for segment in segments {
+            match segment {
+                Segment::Executable { code, .. } => blocks.push(self.float_statements(code)),
+                Segment::Loop { body, .. } => {
+                    let body = self.float_body(body);
+                    blocks.push(quote! {
+                        while tape[pointer] != 0.0 {
+                            #body
+                        }
+                    });
+                }
+            }
+        }
+
+        quote! {
+            #(#blocks)*
+        }
+    }
+
+    fn float_statements<T: TokenExt>(&self, tokens: &Tokens<T>) -> TokenStream {
+        let mem_size = self.memory_size;
+        // the cell `>` would move to
+        let next = match self.pointer_safety {
+            PointerSafety::Wrap => quote! { (pointer + 1) % #mem_size },
+            PointerSafety::Clamp => quote! { (pointer + 1).min(#mem_size - 1) },
+            PointerSafety::None => quote! { pointer + 1 },
+        };
+
+        let mut statements = Vec::new();
+
+        for token in &tokens.tokens {
+            let count = token.count();
+            let amount = Literal::f64_suffixed(count as f64);
+
+            statements.push(match token.token() {
+                Token::PointerAdd => match self.pointer_safety {
+                    PointerSafety::Wrap => quote! { pointer = (pointer + #count) % #mem_size; },
+                    PointerSafety::Clamp => {
+                        quote! { pointer = (pointer + #count).min(#mem_size - 1); }
+                    }
+                    PointerSafety::None => quote! { pointer += #count; },
+                },
+                Token::PointerSub => match self.pointer_safety {
+                    PointerSafety::Wrap => quote! {
+                        pointer = if pointer < #count {
+                            #mem_size - (#count - pointer)
+                        } else {
+                            pointer - #count
+                        };
+                    },
+                    PointerSafety::Clamp => quote! { pointer = pointer.max(#count) - #count; },
+                    PointerSafety::None => quote! { pointer -= #count; },
+                },
+                Token::ValueAdd => quote! { tape[pointer] += #amount; },
+                Token::ValueSub => quote! { tape[pointer] -= #amount; },
+                // the tokenizer never merges these
+                Token::Multiply => quote! { tape[pointer] *= tape[#next]; },
+                Token::Divide => quote! { tape[pointer] /= tape[#next]; },
+                Token::Write => quote! {
+                    for _ in 0..#count {
+                        print!("{} ", tape[pointer]);
+                    }
+                },
+                Token::Read => {
+                    let eof = match self.eof_behavior {
+                        EofBehavior::NoChange => quote! {},
+                        EofBehavior::Fixed(value) => {
+                            let value = Literal::f64_suffixed(value as f64);
+                            quote! {
+                                else {
+                                    tape[pointer] = #value;
+                                }
+                            }
+                        }
+                    };
+                    quote! {
+                        if let Some(_number) = input.next() {
+                            tape[pointer] = _number.parse().expect("invalid numeric input");
+                        } #eof
+                    }
+                }
+                Token::LoopStart | Token::LoopEnd => {
+                    unreachable!("loop characters are not included in the tokenized code")
+                }
+            });
+        }
+
+        quote! {
+            #(#statements)*
+        }
+    }
+}
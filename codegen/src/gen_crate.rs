@@ -1,59 +1,611 @@
 use std::{
     fs,
-    io::{Read, Write},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    thread,
 };
 
 use chrono::Utc;
+use clap::ValueEnum;
+use codegen::{
+    ast::Dialect,
+    pragma::{CellWidth, Effective},
+    source_map::{Placer, SourceMap},
+    verbosity,
+};
 use color_eyre::eyre::{eyre, Result};
-use proc_macro2::TokenStream;
 
-use crate::Cli;
+use crate::{
+    generator::{BrainfuckToRust, Profile},
+    lock::{self, Lock},
+    target, Cli, File,
+};
 
 const MANIFEST_TEMPLATE: &str = include_str!("./Cargo.toml.TEMPLATE");
 const README_TEMPLATE: &str = include_str!("./README.md.TEMPLATE");
 
+/// How big a module of a split program gets, in bytes
+const PART_SIZE: usize = 512 << 10;
+
+/// Roughly how much CPU time a release build takes per MiB of generated code,
+/// once it is split into modules
+const SPLIT_SECONDS_PER_MIB: f64 = 1000.0;
+
+/// The release profile settings needed for `--static`
+const STATIC_PROFILE: &[(&str, &str)] = &[
+    ("panic", r#""abort""#),
+    ("lto", "true"),
+    ("codegen-units", "1"),
+    ("strip", "true"),
+];
+
+/// The release profile settings for `--profile min-size`
+const MIN_SIZE_PROFILE: &[(&str, &str)] = &[
+    ("opt-level", r#""z""#),
+    ("lto", "true"),
+    ("codegen-units", "1"),
+    ("panic", r#""abort""#),
+    ("strip", "true"),
+];
+
+/// The code of the generated `main.rs`, or `lib.rs` with `--lib`
+pub enum Code<'a> {
+    /// All of it at once
+    Text(String),
+    /// Generated a piece at a time on `jobs` threads (see
+    /// [`BrainfuckToRust::stream_body`])
+    Streamed {
+        generator: &'a BrainfuckToRust,
+        file: &'a File,
+        jobs: usize,
+    },
+}
+
+/// Counts the lines written through it the way [`str::lines`] does.
+struct LineCounter<W> {
+    inner: W,
+    newlines: usize,
+    /// Whether the last byte written was not a newline
+    unterminated: bool,
+}
+
+impl<W: Write> Write for LineCounter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let buf = &buf[..written];
+        self.newlines += buf.iter().filter(|&&byte| byte == b'\n').count();
+        if let Some(&last) = buf.last() {
+            self.unterminated = last != b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> LineCounter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            newlines: 0,
+            unterminated: false,
+        }
+    }
+
+    fn lines(&self) -> usize {
+        self.newlines + self.unterminated as usize
+    }
+}
+
+/// The modules a program too big for one file is split into, which are
+/// written one at a time
+struct Parts {
+    src: PathBuf,
+    /// The code of a module around its piece of the program
+    template: (String, String),
+    current: Option<LineCounter<BufWriter<fs::File>>>,
+    placer: Placer,
+    /// The size of the current module so far
+    size: usize,
+    count: usize,
+    lines: usize,
+}
+
+impl Parts {
+    fn new(src: &Path, template: (String, String)) -> Self {
+        Self {
+            src: src.to_path_buf(),
+            template,
+            current: None,
+            placer: Placer::new(""),
+            size: 0,
+            count: 0,
+            lines: 0,
+        }
+    }
+
+    /// Adds a piece of the program, in a new module if the current one is full.
+    fn push(&mut self, code: &str, map: &mut Option<SourceMap>) -> Result<()> {
+        if self.current.is_none() || self.size >= PART_SIZE {
+            self.close()?;
+            self.count += 1;
+
+            let name = format!("part_{:03}.rs", self.count);
+            let mut part =
+                LineCounter::new(BufWriter::new(fs::File::create(self.src.join(&name))?));
+            self.placer = Placer::new(&format!("src/{}", name));
+            write_piece(&mut part, &self.template.0, &mut self.placer, map)?;
+            self.current = Some(part);
+            self.size = 0;
+        }
+
+        let part = self.current.as_mut().unwrap();
+        write_piece(part, code, &mut self.placer, map)?;
+        self.size += code.len();
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(mut part) = self.current.take() {
+            part.write_all(self.template.1.as_bytes())?;
+            part.flush()?;
+            self.lines += part.lines();
+        }
+        Ok(())
+    }
+
+    /// Closes the last module, returning how many there are and their lines.
+    fn finish(mut self) -> Result<(usize, usize)> {
+        self.close()?;
+        Ok((self.count, self.lines))
+    }
+}
+
 struct Replacements<'a> {
     package_name: &'a str,
+    version: &'a str,
+    metadata: &'a str,
     source_filename: &'a str,
     source_code: &'a str,
     timestamp: &'a str,
+    semantics: &'a str,
 }
 
 impl<'a> Replacements<'a> {
     pub fn run(&self, orig: &str) -> String {
-        orig.replace("%%PACKAGE_NAME%%", &self.package_name)
-            .replace("%%SOURCE_FILENAME%%", &self.source_filename)
-            .replace("%%SOURCE_CODE%%", &self.source_code)
-            .replace("%%TIMESTAMP%%", &self.timestamp)
+        orig.replace("%%PACKAGE_NAME%%", self.package_name)
+            .replace("%%VERSION%%", self.version)
+            .replace("%%METADATA%%", self.metadata)
+            .replace("%%SOURCE_FILENAME%%", self.source_filename)
+            .replace("%%SOURCE_CODE%%", self.source_code)
+            .replace("%%TIMESTAMP%%", self.timestamp)
+            .replace("%%SEMANTICS%%", self.semantics)
     }
 }
 
-pub fn generate_crate_for_code(cli: &Cli, in_code: &str, out_code: TokenStream) -> Result<()> {
-    fs::create_dir_all(&cli.output)?;
+/// The `[profile.release]` section of the manifest, if the options need one.
+fn release_profile(cli: &Cli) -> String {
+    let mut settings: Vec<(&str, &str)> = Vec::new();
+
+    if cli.profile == Profile::MinSize {
+        settings.extend(MIN_SIZE_PROFILE);
+    }
+
+    if cli.static_binary {
+        for (key, value) in STATIC_PROFILE {
+            if !settings.iter().any(|(existing, _)| existing == key) {
+                settings.push((key, value));
+            }
+        }
+    }
+
+    if settings.is_empty() {
+        return String::new();
+    }
+
+    let mut profile = "\n[profile.release]\n".to_string();
+    for (key, value) in settings {
+        profile.push_str(&format!("{} = {}\n", key, value));
+    }
+
+    profile
+}
+
+/// `value` as a TOML string. JSON escapes strings the way TOML does.
+fn toml_string(value: &str) -> String {
+    serde_json::to_string(value).expect("strings always serialize")
+}
+
+/// Whether `version` is a semantic version, like `1.2.3` or `0.1.0-beta.1`.
+fn is_semver(version: &str) -> bool {
+    let version = version
+        .split_once('+')
+        .map_or(version, |(version, _)| version);
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+
+    let numbers: Vec<&str> = core.split('.').collect();
+    numbers.len() == 3
+        && numbers
+            .iter()
+            .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        && pre.is_none_or(|pre| {
+            pre.split('.').all(|part| {
+                !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+        })
+}
+
+/// The comment on the first line of a program, if it has one. A line is taken
+/// for a comment if it has no instructions, apart from a full stop at its end.
+fn leading_comment(code: &str) -> Option<&str> {
+    let line = code.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let text = line.trim_end_matches('.');
+    (!text.contains(|c| "+-<>[],.".contains(c)) && text.chars().any(char::is_alphanumeric))
+        .then_some(text)
+}
+
+/// The license in an `SPDX-License-Identifier: <license>` line of a program.
+fn spdx_license(code: &str) -> Option<&str> {
+    code.lines().find_map(|line| {
+        let (_, license) = line.split_once("SPDX-License-Identifier:")?;
+        Some(license.trim()).filter(|license| !license.is_empty())
+    })
+}
+
+/// The lines of the `[package]` section after the name, version and edition,
+/// from the options with the source as the fallback.
+fn package_metadata(cli: &Cli, in_code: &str, source_filename: &str) -> String {
+    let description = match &cli.description {
+        Some(description) => description.clone(),
+        None => match leading_comment(in_code) {
+            Some(comment) => comment.to_string(),
+            None => format!("{}, compiled from Brainfuck to Rust", source_filename),
+        },
+    };
+
+    let mut metadata = String::new();
+    if !cli.authors.is_empty() {
+        let authors: Vec<String> = cli
+            .authors
+            .iter()
+            .map(|author| toml_string(author.trim()))
+            .collect();
+        metadata.push_str(&format!("authors = [{}]\n", authors.join(", ")));
+    }
+    metadata.push_str(&format!("description = {}\n", toml_string(&description)));
+    if let Some(license) = cli.license.as_deref().or_else(|| spdx_license(in_code)) {
+        metadata.push_str(&format!("license = {}\n", toml_string(license)));
+    }
+
+    metadata
+}
+
+/// The semantics the program was generated with, as a list for the README.
+fn semantics(effective: &Effective, dialect: Dialect) -> String {
+    let pointer = if effective.wrap_pointer {
+        "wraps around at either end of the tape"
+    } else {
+        "does not wrap around"
+    };
+    let eof = match effective.eof {
+        Some(value) => format!("`,` stores {}", value),
+        None => "`,` leaves the cell unchanged".to_string(),
+    };
+    let policy = effective
+        .policy
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+
+    let cells = match effective.cell_size {
+        _ if dialect == Dialect::FloatExt => {
+            "64-bit floating-point numbers (the `float-ext` dialect)".to_string()
+        }
+        CellWidth::Bits(bits) => format!("{} bits", bits),
+        CellWidth::BigInt => "arbitrarily big integers, which never overflow".to_string(),
+    };
+
+    format!(
+        "- Cells: {}\n- Pointer: {}\n- EOF: {}\n- Pragma policy: `{}`",
+        cells, pointer, eof, policy
+    )
+}
+
+/// Writes the generated crate, returning the number of lines of its code.
+pub fn generate_crate_for_code(
+    cli: &Cli,
+    generator: &BrainfuckToRust,
+    in_code: &str,
+    out_code: Code,
+    effective: &Effective,
+) -> Result<usize> {
+    if !is_semver(&cli.crate_version) {
+        return Err(eyre!(
+            "`{}` is not a version like `1.2.3`, which cargo needs",
+            cli.crate_version
+        ));
+    }
 
     let package_name = cli.output.file_stem().unwrap().to_str().unwrap();
-    let source_filename = cli.input.file_name().unwrap().to_str().unwrap();
+    let source_filename = cli.source_filename();
+
+    // the lock of the crate being regenerated, for the files it keeps
+    let previous = if cli.force {
+        lock::read(&cli.output).ok().flatten()
+    } else {
+        lock::check(&cli.output, source_filename, &cli.keep)?
+    };
+    fs::create_dir_all(&cli.output)?;
+
+    // every file written, for the lock
+    let mut files = Vec::new();
     let timestamp = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
     let replacements = Replacements {
         package_name,
+        version: &toml_string(&cli.crate_version),
+        metadata: &package_metadata(cli, in_code, source_filename),
         source_filename,
         source_code: in_code,
         timestamp: &timestamp,
+        semantics: &semantics(effective, cli.dialect),
     };
 
-    let manifest = replacements.run(MANIFEST_TEMPLATE);
-    fs::write(cli.output.join("Cargo.toml"), manifest)?;
+    let mut manifest = replacements.run(MANIFEST_TEMPLATE);
+    if effective.cell_size == CellWidth::BigInt {
+        manifest.push_str("num-bigint = \"0.4.4\"\n");
+    }
+    if cli.lib {
+        manifest.push_str(&format!(
+            "\n[lib]\npath = \"src/lib.rs\"\n\n[[bin]]\nname = \"{}\"\npath = \"src/main.rs\"\n",
+            package_name
+        ));
+    }
+    manifest.push_str(&release_profile(cli));
+    write_unless_kept(cli, &mut files, "Cargo.toml".into(), manifest)?;
 
     let readme = replacements.run(README_TEMPLATE);
-    fs::write(cli.output.join("README.md"), readme)?;
+    write_unless_kept(cli, &mut files, "README.md".into(), readme)?;
+
+    write_unless_kept(cli, &mut files, source_filename.into(), in_code)?;
 
-    fs::copy(&cli.input, cli.output.join(source_filename))?;
+    if let Some(triple) = cli.target_triple() {
+        write_unless_kept(
+            cli,
+            &mut files,
+            Path::new(".cargo").join("config.toml"),
+            target::cargo_config(&triple, cli.static_binary)?,
+        )?;
+    }
 
     fs::create_dir_all(cli.output.join("src"))?;
 
-    if !cli.format {
-        fs::write(cli.output.join("src").join("main.rs"), out_code.to_string())?;
+    let src = cli.output.join("src");
+    remove_stale(&src, cli.lib, &cli.keep)?;
+
+    // with `--lib`, the program goes in `lib.rs` and `main.rs` only runs it
+    let root_name = if cli.lib { "lib.rs" } else { "main.rs" };
+    let root = src.join(root_name);
+
+    let mut map = cli.source_map.then(|| SourceMap::new(source_filename));
+    let mut placer = Placer::new(&format!("src/{}", root_name));
+
+    let lines = match out_code {
+        Code::Text(code) => {
+            let format = if code.len() > cli.split_above {
+                verbosity::warn(size_warning(code.len(), None));
+                false
+            } else {
+                cli.format
+            };
+            write_file(&root, format, |out| {
+                write_piece(out, &code, &mut placer, &mut map)
+            })?
+        }
+        Code::Streamed {
+            generator,
+            file,
+            jobs,
+        } => {
+            // the pieces are held back until they are too big for one file
+            let mut pending = Vec::new();
+            let mut size = 0;
+            let mut parts: Option<Parts> = None;
+
+            generator.stream_body(file, jobs, &mut |code| {
+                size += code.len();
+                match &mut parts {
+                    Some(parts) => parts.push(&code, &mut map)?,
+                    None => {
+                        pending.push(code);
+                        if size > cli.split_above {
+                            let mut split = Parts::new(&src, generator.part_template(file));
+                            for code in pending.drain(..) {
+                                split.push(&code, &mut map)?;
+                            }
+                            parts = Some(split);
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+
+            match parts {
+                Some(parts) => {
+                    let (count, lines) = parts.finish()?;
+                    verbosity::warn(size_warning(size, Some(count)));
+                    files.extend(
+                        (1..=count)
+                            .map(|part| Path::new("src").join(format!("part_{:03}.rs", part))),
+                    );
+
+                    let main = generator.split_main(file, count).to_string();
+                    lines + write_file(&root, false, |out| Ok(out.write_all(main.as_bytes())?))?
+                }
+                None => {
+                    let (before, after) = generator.streamed_template(file);
+                    write_file(&root, cli.format, |out| {
+                        out.write_all(before.as_bytes())?;
+                        for code in &pending {
+                            write_piece(out, code, &mut placer, &mut map)?;
+                        }
+                        out.write_all(after.as_bytes())?;
+                        Ok(())
+                    })?
+                }
+            }
+        }
+    };
+
+    let lines = if cli.lib {
+        let main = generator
+            .lib_main(&package_name.replace('-', "_"))
+            .to_string();
+        lines
+            + write_file(&src.join("main.rs"), cli.format, |out| {
+                Ok(out.write_all(main.as_bytes())?)
+            })?
+    } else {
+        lines
+    };
+
+    if let Some(map) = map {
+        write_unless_kept(
+            cli,
+            &mut files,
+            "source-map.json".into(),
+            serde_json::to_string(&map)?,
+        )?;
+    }
+
+    files.push(Path::new("src").join(root_name));
+    if cli.lib {
+        files.push(Path::new("src").join("main.rs"));
+    }
+    let mut lock = Lock::new(
+        &cli.output,
+        source_filename,
+        in_code,
+        std::env::args().skip(1).collect(),
+        &files,
+    )?;
+    if let Some(previous) = previous {
+        lock.keep(&previous, &cli.keep);
+    }
+    lock.write(&cli.output)?;
+
+    Ok(lines)
+}
+
+/// Writes a piece of a generated file, placing the markers of the source map
+/// in it if there is one.
+fn write_piece(
+    out: &mut dyn Write,
+    code: &str,
+    placer: &mut Placer,
+    map: &mut Option<SourceMap>,
+) -> Result<()> {
+    match map {
+        Some(map) => out.write_all(placer.place(code, map).as_bytes())?,
+        None => out.write_all(code.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `file` in the crate and adds it to `files`, unless it
+/// matches `--keep`.
+fn write_unless_kept(
+    cli: &Cli,
+    files: &mut Vec<PathBuf>,
+    file: PathBuf,
+    contents: impl AsRef<[u8]>,
+) -> Result<()> {
+    if lock::is_kept(&cli.keep, &file.to_string_lossy()) {
+        verbosity::info(format!("keeping {}", file.display()));
+        return Ok(());
+    }
+
+    let path = cli.output.join(&file);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)?;
+    files.push(file);
+    Ok(())
+}
+
+/// Removes the modules of an earlier split from `src`, and the `lib.rs` of an
+/// earlier `--lib` unless it is one again, which cargo would build otherwise.
+/// Files matching `keep` stay.
+fn remove_stale(src: &Path, lib: bool, keep: &[String]) -> Result<()> {
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let stale =
+            (name.starts_with("part_") && name.ends_with(".rs")) || (name == "lib.rs" && !lib);
+        if stale && !lock::is_kept(keep, &format!("src/{}", name)) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The warning for generated code of `size` bytes, split into `parts` modules
+/// if it could be, with a rough idea of how long a release build takes.
+fn size_warning(size: usize, parts: Option<usize>) -> String {
+    let mib = size as f64 / (1 << 20) as f64;
+
+    match parts {
+        Some(parts) => format!(
+            "the generated code is {:.1} MiB, so it is split into {} module{} and not formatted; \
+             expect a release build to take about {} of CPU time or more",
+            mib,
+            parts,
+            if parts == 1 { "" } else { "s" },
+            estimate(mib * SPLIT_SECONDS_PER_MIB)
+        ),
+        None => format!(
+            "the generated code is {:.1} MiB, so it is not formatted; only programs in the \
+             straight codegen style without loop extraction can be split into modules, so expect a \
+             release build to take well over {} of CPU time",
+            mib,
+            estimate(mib * SPLIT_SECONDS_PER_MIB)
+        ),
+    }
+}
+
+/// A number of seconds, roughly.
+fn estimate(seconds: f64) -> String {
+    if seconds < 90.0 {
+        format!("{:.0} seconds", seconds.max(1.0))
+    } else if seconds < 90.0 * 60.0 {
+        format!("{:.0} minutes", seconds / 60.0)
+    } else {
+        format!("{:.1} hours", seconds / 3600.0)
+    }
+}
+
+/// Writes the source file at `path`, formatting it with rustfmt if `format`,
+/// and returns its number of lines.
+fn write_file(
+    path: &Path,
+    format: bool,
+    write_code: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<usize> {
+    if !format {
+        let mut out = LineCounter::new(BufWriter::new(fs::File::create(path)?));
+        write_code(&mut out)?;
+        out.flush()?;
+
+        Ok(out.lines())
     } else {
         let mut cmd = Command::new("rustfmt");
         cmd.arg("--emit=stdout");
@@ -65,25 +617,49 @@ pub fn generate_crate_for_code(cli: &Cli, in_code: &str, out_code: TokenStream)
 
         let mut child = cmd.spawn()?;
 
-        let mut stdin = child.stdin.take().unwrap();
+        let stdin = child.stdin.take().unwrap();
         let mut stdout = child.stdout.take().unwrap();
 
-        stdin.write_all(out_code.to_string().as_bytes())?;
+        // read while writing, since rustfmt may fill the pipe before it has
+        // all of the input
+        let reader = thread::spawn(move || -> io::Result<String> {
+            let mut out = String::new();
+            stdout.read_to_string(&mut out)?;
+            Ok(out)
+        });
 
-        drop(stdin);
-
-        let mut out = String::new();
+        let mut stdin = BufWriter::new(stdin);
+        write_code(&mut stdin)?;
+        stdin.flush()?;
 
-        stdout.read_to_string(&mut out)?;
+        drop(stdin);
 
-        drop(stdout);
+        let out = reader
+            .join()
+            .map_err(|_| eyre!("reading the output of rustfmt panicked"))??;
 
-        fs::write(cli.output.join("src").join("main.rs"), out)?;
+        fs::write(path, &out)?;
 
         let status = child.wait()?;
         if !status.success() {
             return Err(eyre!("rustfmt failed"));
         }
+
+        Ok(out.lines().count())
+    }
+}
+
+/// Builds the generated crate in release mode.
+pub fn build_crate(output: &Path) -> Result<()> {
+    // cargo only picks up the crate's `.cargo/config.toml` when run from inside it
+    let status = Command::new("cargo")
+        .arg("build")
+        .arg("--release")
+        .current_dir(output)
+        .status()?;
+
+    if !status.success() {
+        return Err(eyre!("building the generated crate failed"));
     }
 
     Ok(())
@@ -1,11 +1,16 @@
-use ascii::AsciiString;
-use color_eyre::eyre::Result;
-use proc_macro2::TokenStream;
-use quote::quote;
-use syn::LitByte;
+use std::{str::FromStr, thread};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result};
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::{format_ident, quote};
+use syn::LitByteStr;
 use typed_builder::TypedBuilder;
 
-use crate::ast::{File, Segment, Token, TokenExt, Tokens};
+use codegen::{
+    ast::{count_tokens, Dialect, File, InputUsage, Segment, Span, Token, TokenExt, Tokens},
+    source_map,
+};
 
 #[derive(Default, Debug, Clone, Copy)]
 /// The size of a cell on the tape
@@ -17,9 +22,12 @@ pub enum CellSize {
     U16,
     /// `u32`
     U32,
+    /// `num_bigint::BigInt`, which never overflows, so the overflow behavior
+    /// does not apply
+    BigInt,
 }
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 /// How to handle pointer overflow/underflow
 pub enum PointerSafety {
     /// Wrap around to the maximum pointer size or zero
@@ -53,6 +61,120 @@ pub enum EofBehavior {
     Fixed(u8),
 }
 
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// How `.` and `,` interpret cell values
+pub enum IoMode {
+    #[default]
+    /// `.` writes the cell as a character and `,` reads a single character
+    Text,
+    /// `.` writes the cell as a decimal number followed by a space and
+    /// `,` parses the next whitespace-delimited integer
+    Numeric,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// When the generated program flushes what `.` writes to stdout
+pub enum FlushPolicy {
+    /// After every `.`, so interactive programs show each character as soon
+    /// as it is written
+    PerWrite,
+    #[default]
+    /// At the end of every line and before `,` waits for input, like any Rust
+    /// program writing to stdout
+    PerLine,
+    /// Only before `,` waits for input and when the program ends, which is
+    /// fastest for programs that write a lot. What is still buffered when the
+    /// program panics is lost
+    OnExit,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// What the generated crate is optimized for
+pub enum Profile {
+    #[default]
+    /// Cargo's usual release profile
+    Default,
+    /// `opt-level = "z"`, LTO, one codegen unit, `panic = "abort"`, stripped
+    /// symbols and the `write` shim instead of `print!`, for the smallest binary
+    MinSize,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// The shape of the generated program
+pub enum CodegenStyle {
+    #[default]
+    /// Straight-line code in `main`, with loops as `while` loops
+    Straight,
+    /// A `Program` struct whose `step` runs one op at a time, so the program
+    /// can be paused, resumed and inspected
+    StateMachine,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+/// Which loops are moved out of `main` into their own functions. Giant
+/// functions are slow to compile, so this can cut compile times dramatically.
+pub enum LoopExtraction {
+    #[default]
+    /// Generate everything inside `main`
+    None,
+    /// Extract every loop that is not inside another loop
+    TopLevel,
+    /// Extract every loop, at any depth, made of at least this many tokens
+    MinTokens(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The cell whose value the program exits with once it ends
+pub enum ExitCell {
+    /// Whichever cell the pointer ends up on
+    Current,
+    Index(usize),
+}
+
+impl FromStr for ExitCell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "current" => Ok(Self::Current),
+            _ => s
+                .parse()
+                .map(Self::Index)
+                .map_err(|_| format!("`{}` is not `current` or the index of a cell", s)),
+        }
+    }
+}
+
+/// How many top-level segments a thread of [`BrainfuckToRust::stream_body`]
+/// generates at a time
+const STREAM_CHUNK: usize = 1024;
+
+/// Stands in for the body of a streamed program in its template, which is
+/// split around it
+const STREAM_BODY: &str = "__bf_streamed_body__";
+
+/// Splits code around the [`STREAM_BODY`] in it.
+fn split_template(template: TokenStream) -> (String, String) {
+    let template = template.to_string();
+    let (before, after) = template
+        .split_once(STREAM_BODY)
+        .expect("the template contains the body");
+    (before.to_string(), after.to_string())
+}
+
+/// The functions generated for extracted loops so far
+struct Extracted {
+    functions: Vec<TokenStream>,
+    /// Whether the program defines `input` and `input_pos`, which then have to
+    /// be passed to every function
+    has_input: bool,
+    /// Whether `input` is a [`BrainfuckToRust::lazy_input_def`] reader rather
+    /// than a slice
+    lazy_input: bool,
+    /// Whether `.` writes to an `output` writer rather than stdout
+    output_sink: bool,
+}
+
 #[derive(Debug, TypedBuilder)]
 pub struct BrainfuckToRust {
     /// The size of the memory array ("tape")
@@ -63,40 +185,315 @@ pub struct BrainfuckToRust {
     pub overflow_behavior: OverflowBehavior,
     #[builder(default)]
     pub cell_size: CellSize,
+    /// Embed these bytes as the input, rather than reading stdin
     #[builder(default)]
-    pub fixed_input: Option<AsciiString>,
+    pub fixed_input: Option<Vec<u8>>,
     #[builder(default)]
     pub eof_behavior: EofBehavior,
+    #[builder(default)]
+    pub io_mode: IoMode,
+    #[builder(default)]
+    pub loop_extraction: LoopExtraction,
+    /// Read the memory size, EOF behavior and input source from the generated
+    /// program's arguments and environment, using the options above as defaults
+    #[builder(default)]
+    pub runtime_config: bool,
+    /// Read the input from the file or the `--input-str` text given as the
+    /// generated program's argument, like the interpreter does, and only read
+    /// stdin without one
+    #[builder(default)]
+    pub input_args: bool,
+    /// Read the input from this environment variable of the generated program
+    /// instead of stdin, failing if it is not set
+    #[builder(default)]
+    pub input_env: Option<String>,
+    /// Write output with direct `write` calls instead of `print!`, which keeps
+    /// the formatting machinery out of the binary (Unix only)
+    #[builder(default)]
+    pub io_shim: bool,
+    /// Put the program in a `run()` returning `Result<(), BfRuntimeError>` instead
+    /// of panicking, and let `main` turn the error into an exit code
+    #[builder(default)]
+    pub panic_free: bool,
+    /// When to flush the output. The output shim of the min-size profile
+    /// writes every byte right away, so it cannot buffer it until the end
+    #[builder(default)]
+    pub flush: FlushPolicy,
+    /// Run the program on its own thread behind a `run()` that returns
+    /// `Paused::NeedsInput` with a continuation whenever `,` runs out of input,
+    /// so embedders can supply input the same way as with the interpreter
+    #[builder(default)]
+    pub resumable_input: bool,
+    #[builder(default)]
+    pub style: CodegenStyle,
+    /// Generate a library whose public `run()` runs the program, leaving
+    /// `main` to [`BrainfuckToRust::lib_main`]
+    #[builder(default)]
+    pub lib: bool,
+    /// With the state-machine style, let `bf attach` drive the program over TCP
+    /// when `BF_DEBUG_ADDR` is set
+    #[builder(default)]
+    pub debug_server: bool,
+    /// Put a [`source_map::marker`] in front of the code of every instruction
+    /// and loop, for a source map
+    #[builder(default)]
+    pub source_map: bool,
+    /// Exit with the value this cell has once the program ends, so it can tell
+    /// a shell whether it succeeded
+    #[builder(default)]
+    pub exit_cell: Option<ExitCell>,
 }
 
 impl BrainfuckToRust {
-    pub fn generate<T: TokenExt>(&self, file: File<T>) -> Result<TokenStream> {
-        let body = self.generate_body(&file.segments);
+    fn extracted<T>(&self, file: &File<T>) -> Extracted {
+        Extracted {
+            functions: Vec::new(),
+            has_input: self.fixed_input.is_some() || file.needs_input,
+            lazy_input: self.lazy_input(file.input_usage),
+            output_sink: self.style == CodegenStyle::StateMachine,
+        }
+    }
 
-        let full = self.template(body, file.needs_input);
+    pub fn generate<T: TokenExt>(&self, file: &File<T>) -> Result<TokenStream> {
+        let mut extracted = self.extracted(file);
+
+        if file.dialect == Dialect::FloatExt {
+            return self.float_ext(file);
+        }
+        if self.big_cells() && self.io_shim {
+            return Err(eyre!(
+                "the output shim of the min-size profile cannot write big-integer cells"
+            ));
+        }
+        if self.big_cells() && self.debug_server {
+            return Err(eyre!("the debug server cannot send big-integer cells"));
+        }
+        if self.buffered() && self.io_shim {
+            return Err(eyre!(
+                "the output shim of the min-size profile cannot buffer the output until exit"
+            ));
+        }
+        if self.buffered() && self.style == CodegenStyle::StateMachine {
+            return Err(eyre!(
+                "the state-machine codegen style writes to the `Program::output` it is given, so \
+                 buffering it is up to the caller"
+            ));
+        }
+
+        if let Some(reason) = self.exit_cell_unsupported() {
+            return Err(eyre!("{}", reason));
+        }
+
+        if self.style == CodegenStyle::StateMachine {
+            return Ok(self.state_machine(file, &extracted));
+        }
+        if self.debug_server {
+            return Err(eyre!(
+                "the debug server needs the state-machine codegen style"
+            ));
+        }
+
+        let body = self.generate_body(&file.segments, &mut extracted, 0, false);
+
+        let full = self.template(body, extracted.functions, file.input_usage);
 
         Ok(full)
     }
 
-    fn generate_body<T: TokenExt>(&self, segments: &Vec<Segment<T>>) -> TokenStream {
+    /// Whether [`BrainfuckToRust::stream_body`] can generate `file`: in the
+    /// straight style, without extracted loops, whose functions are numbered
+    /// across the whole program, and in the standard dialect.
+    pub fn can_stream<T>(&self, file: &File<T>) -> bool {
+        file.dialect == Dialect::Standard
+            && self.style == CodegenStyle::Straight
+            && self.loop_extraction == LoopExtraction::None
+            && !self.debug_server
+            && !(self.big_cells() && self.io_shim)
+            && !(self.buffered() && self.io_shim)
+            && self.exit_cell_unsupported().is_none()
+    }
+
+    /// Why the program cannot exit with the value of [`BrainfuckToRust::exit_cell`],
+    /// if it cannot.
+    fn exit_cell_unsupported(&self) -> Option<String> {
+        let exit_cell = self.exit_cell?;
+
+        if let ExitCell::Index(index) = exit_cell {
+            if index >= self.memory_size {
+                return Some(format!(
+                    "cannot exit with the value of cell {}, the tape only has {} cells",
+                    index, self.memory_size
+                ));
+            }
+        }
+
+        let unsupported = [
+            (self.big_cells(), "big-integer cells"),
+            (self.lib, "library crates"),
+            (self.resumable_input, "resumable input"),
+            (
+                self.style == CodegenStyle::StateMachine,
+                "the state-machine codegen style",
+            ),
+        ];
+        unsupported
+            .iter()
+            .find(|(used, _)| *used)
+            .map(|(_, option)| format!("{} cannot exit with the value of a cell", option))
+    }
+
+    /// Exits with the value of [`BrainfuckToRust::exit_cell`], at the end of
+    /// the program.
+    fn exit_with_cell(&self) -> TokenStream {
+        match self.exit_cell {
+            None => quote! {},
+            Some(ExitCell::Current) => quote! { std::process::exit(tape[pointer] as i32); },
+            Some(ExitCell::Index(index)) => quote! { std::process::exit(tape[#index] as i32); },
+        }
+    }
+
+    /// The code of [`BrainfuckToRust::generate`] before and after the body,
+    /// for the body of [`BrainfuckToRust::stream_body`].
+    pub fn streamed_template<T>(&self, file: &File<T>) -> (String, String) {
+        let placeholder = format_ident!("{}", STREAM_BODY);
+        split_template(self.template(quote! { #placeholder }, Vec::new(), file.input_usage))
+    }
+
+    /// Generates the body of the program a piece at a time, handing each piece
+    /// to `emit` in order instead of holding all of it at once. The top-level
+    /// segments are split into chunks that `jobs` threads generate side by
+    /// side, and each round of chunks is emitted before the next one starts,
+    /// so a huge program never takes more memory than a round's worth of
+    /// code. A program that is one big loop is still a single chunk.
+    ///
+    /// Only for programs that [`BrainfuckToRust::can_stream`].
+    pub fn stream_body<T: TokenExt + Sync>(
+        &self,
+        file: &File<T>,
+        jobs: usize,
+        emit: &mut dyn FnMut(String) -> Result<()>,
+    ) -> Result<()> {
+        for round in file.segments.chunks(STREAM_CHUNK * jobs.max(1)) {
+            let chunks = thread::scope(|scope| {
+                let threads: Vec<_> = round
+                    .chunks(STREAM_CHUNK)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            // nothing is extracted, so each chunk can have its own
+                            let mut extracted = self.extracted(file);
+                            let mut code = self
+                                .generate_body(chunk, &mut extracted, 0, false)
+                                .to_string();
+                            code.push(' ');
+                            code
+                        })
+                    })
+                    .collect();
+
+                threads
+                    .into_iter()
+                    .map(|thread| {
+                        thread
+                            .join()
+                            .map_err(|_| eyre!("a code generation thread panicked"))
+                    })
+                    .collect::<Result<Vec<String>>>()
+            })?;
+
+            for chunk in chunks {
+                emit(chunk)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The code of a module before and after a piece of the body of
+    /// [`BrainfuckToRust::stream_body`], for splitting a program too big to
+    /// compile well into modules. The module's `run` function runs its piece
+    /// on the state of the program, as the function of an extracted loop does.
+    pub fn part_template<T>(&self, file: &File<T>) -> (String, String) {
+        let placeholder = format_ident!("{}", STREAM_BODY);
+        let function = self.function(
+            quote! { pub fn run },
+            quote! { #placeholder },
+            &self.extracted(file),
+        );
+
+        split_template(quote! {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #function
+        })
+    }
+
+    /// The `main.rs` (or `lib.rs`) of a program split into `parts` modules
+    /// named `part_001` and so on (see [`BrainfuckToRust::part_template`]), which runs them
+    /// one after the other.
+    pub fn split_main<T>(&self, file: &File<T>, parts: usize) -> TokenStream {
+        let extracted = self.extracted(file);
+        let names: Vec<Ident> = (1..=parts)
+            .map(|part| format_ident!("part_{:03}", part))
+            .collect();
+
+        let modules = names.iter().map(|name| quote! { mod #name; }).collect();
+        let calls = names
+            .iter()
+            .map(|name| self.call(quote! { #name::run }, &extracted, false));
+
+        self.template(quote! { #(#calls)* }, modules, file.input_usage)
+    }
+
+    /// `depth` is the number of loops the segments are nested in, and
+    /// `in_function` whether they end up in an extracted loop's function.
+    fn generate_body<T: TokenExt>(
+        &self,
+        segments: &[Segment<T>],
+        extracted: &mut Extracted,
+        depth: usize,
+        in_function: bool,
+    ) -> TokenStream {
         let mut blocks = Vec::new();
 
         for segment in segments {
             match segment {
-                Segment::Executable(code) => {
-                    let segments = self.generate_statements(code);
+                Segment::Executable { code, .. } => {
+                    let segments = self.generate_statements(code, extracted);
                     blocks.push(quote! {
                         #(#segments)*
                     });
                 }
-                Segment::Loop(segments) => {
-                    let body = self.generate_body(segments);
+                Segment::Loop {
+                    body: segments,
+                    span,
+                } => {
+                    blocks.push(self.marker(*span));
 
-                    blocks.push(quote! {
-                        while tape[pointer] != 0 {
-                            #body
-                        }
-                    });
+                    let extract = match self.loop_extraction {
+                        LoopExtraction::None => false,
+                        LoopExtraction::TopLevel => depth == 0,
+                        LoopExtraction::MinTokens(min) => count_tokens(segments) + 2 >= min,
+                    };
+
+                    if extract {
+                        let body = self.generate_body(segments, extracted, depth + 1, true);
+                        let name = format_ident!("loop_{}", extracted.functions.len());
+
+                        let function = self.loop_function(&name, body, extracted);
+                        extracted.functions.push(function);
+
+                        blocks.push(self.call(quote! { #name }, extracted, in_function));
+                    } else {
+                        let body = self.generate_body(segments, extracted, depth + 1, in_function);
+                        let nonzero = self.cell_nonzero();
+
+                        blocks.push(quote! {
+                            while #nonzero {
+                                #body
+                            }
+                        });
+                    }
                 }
             }
         }
@@ -106,23 +503,38 @@ impl BrainfuckToRust {
         }
     }
 
-    fn generate_statements<T: TokenExt>(&self, tokens: &Tokens<T>) -> Vec<TokenStream> {
+    fn generate_statements<T: TokenExt>(
+        &self,
+        tokens: &Tokens<T>,
+        extracted: &Extracted,
+    ) -> Vec<TokenStream> {
         let mut statements = Vec::new();
 
-        for token in &tokens.tokens {
-            let count_u8 = token.count() as u8;
+        let mem_size = self.mem_size();
+        let cell_code = self.cell_code();
+
+        for (token, span) in tokens.tokens.iter().zip(&tokens.spans) {
+            let count_u8 = self.cell_literal(token.count());
             let count_usize = token.count();
 
             let stmt = match token.token() {
                 Token::PointerAdd => match self.pointer_safety {
                     PointerSafety::Wrap => {
                         quote! {
-                            pointer = (pointer + #count_usize) % MEM_SIZE;
+                            pointer = (pointer + #count_usize) % #mem_size;
                         }
                     }
                     PointerSafety::Clamp => {
                         quote! {
-                            pointer = (pointer + #count_usize).min(MEM_SIZE - 1);
+                            pointer = (pointer + #count_usize).min(#mem_size - 1);
+                        }
+                    }
+                    PointerSafety::None if self.panic_free => {
+                        quote! {
+                            pointer += #count_usize;
+                            if pointer >= #mem_size {
+                                return Err(BfRuntimeError::PointerOutOfBounds);
+                            }
                         }
                     }
                     PointerSafety::None => {
@@ -135,7 +547,7 @@ impl BrainfuckToRust {
                     PointerSafety::Wrap => {
                         quote! {
                             pointer = if pointer < #count_usize {
-                                MEM_SIZE - (#count_usize - pointer)
+                                #mem_size - (#count_usize - pointer)
                             } else {
                                 pointer - #count_usize
                             };
@@ -146,13 +558,36 @@ impl BrainfuckToRust {
                             pointer = pointer.max(#count_usize) - #count_usize;
                         }
                     }
+                    PointerSafety::None if self.panic_free => {
+                        quote! {
+                            pointer = pointer
+                                .checked_sub(#count_usize)
+                                .ok_or(BfRuntimeError::PointerOutOfBounds)?;
+                        }
+                    }
                     PointerSafety::None => {
                         quote! {
                             pointer -= #count_usize;
                         }
                     }
                 },
+                Token::ValueAdd if self.big_cells() => {
+                    quote! {
+                        tape[pointer] += #count_usize;
+                    }
+                }
+                Token::ValueSub if self.big_cells() => {
+                    quote! {
+                        tape[pointer] -= #count_usize;
+                    }
+                }
                 Token::ValueAdd => match self.overflow_behavior {
+                    // overflowing would panic in debug builds
+                    OverflowBehavior::None if self.panic_free => {
+                        quote! {
+                            tape[pointer] = tape[pointer].wrapping_add(#count_u8);
+                        }
+                    }
                     OverflowBehavior::None => {
                         quote! {
                             tape[pointer] += #count_u8;
@@ -163,6 +598,13 @@ impl BrainfuckToRust {
                             tape[pointer] = tape[pointer].wrapping_add(#count_u8);
                         }
                     }
+                    OverflowBehavior::Abort if self.panic_free => {
+                        quote! {
+                            tape[pointer] = tape[pointer]
+                                .checked_add(#count_u8)
+                                .ok_or(BfRuntimeError::CellOverflow { cell: pointer })?;
+                        }
+                    }
                     OverflowBehavior::Abort => {
                         quote! {
                             tape[pointer] = tape[pointer].checked_add(#count_u8).unwrap();
@@ -170,6 +612,12 @@ impl BrainfuckToRust {
                     }
                 },
                 Token::ValueSub => match self.overflow_behavior {
+                    // overflowing would panic in debug builds
+                    OverflowBehavior::None if self.panic_free => {
+                        quote! {
+                            tape[pointer] = tape[pointer].wrapping_sub(#count_u8);
+                        }
+                    }
                     OverflowBehavior::None => {
                         quote! {
                             tape[pointer] -= #count_u8;
@@ -180,6 +628,13 @@ impl BrainfuckToRust {
                             tape[pointer] = tape[pointer].wrapping_sub(#count_u8);
                         }
                     }
+                    OverflowBehavior::Abort if self.panic_free => {
+                        quote! {
+                            tape[pointer] = tape[pointer]
+                                .checked_sub(#count_u8)
+                                .ok_or(BfRuntimeError::CellOverflow { cell: pointer })?;
+                        }
+                    }
                     OverflowBehavior::Abort => {
                         quote! {
                             tape[pointer] = tape[pointer].checked_sub(#count_u8).unwrap();
@@ -190,101 +645,1745 @@ impl BrainfuckToRust {
                     if count_usize > 1 {
                         unimplemented!("sequential reads not implemented due to lack of utility")
                     }
-                    match self.eof_behavior {
-                        EofBehavior::NoChange => {
-                            quote! {
-                                if let Some(_c) = input.get(input_pos) {
-                                    tape[pointer] = _c.as_byte();
-                                    input_pos += #count_usize;
-                                }
+                    let read = self.read_expr(extracted);
+                    if self.runtime_config && self.big_cells() {
+                        quote! {
+                            if let Some(_c) = #read {
+                                tape[pointer] = _c;
+                            } else if let Some(_eof) = &eof {
+                                tape[pointer] = _eof.clone();
                             }
                         }
-                        EofBehavior::Fixed(ch) => {
-                            let lit = LitByte::new(ch, proc_macro2::Span::call_site());
-                            quote! {
-                                if let Some(_c) = input.get(input_pos) {
-                                    tape[pointer] = _c.as_byte();
-                                    input_pos += #count_usize;
-                                } else {
-                                    tape[pointer] = #lit;
+                    } else if self.runtime_config {
+                        quote! {
+                            if let Some(_c) = #read {
+                                tape[pointer] = _c;
+                            } else if let Some(_eof) = eof {
+                                tape[pointer] = _eof;
+                            }
+                        }
+                    } else {
+                        match self.eof_behavior {
+                            EofBehavior::NoChange => {
+                                quote! {
+                                    if let Some(_c) = #read {
+                                        tape[pointer] = _c;
+                                    }
+                                }
+                            }
+                            EofBehavior::Fixed(ch) => {
+                                let value = self.cell_value(ch);
+                                quote! {
+                                    if let Some(_c) = #read {
+                                        tape[pointer] = _c;
+                                    } else {
+                                        tape[pointer] = #value;
+                                    }
                                 }
                             }
                         }
                     }
                 }
+                Token::Write if self.io_shim && !extracted.output_sink => match self.io_mode {
+                    IoMode::Text => {
+                        quote! {
+                            for _ in 0..#count_usize {
+                                shim::put(tape[pointer] as u8);
+                            }
+                        }
+                    }
+                    IoMode::Numeric => {
+                        quote! {
+                            for _ in 0..#count_usize {
+                                shim::put_number(tape[pointer] as u64);
+                                shim::put(b' ');
+                            }
+                        }
+                    }
+                },
                 Token::Write => {
-                    quote! {
-                        let __c = tape[pointer].to_ascii_char().unwrap().as_char();
-                        for _ in 0..#count_usize {
-                            print!("{}", __c);
+                    let output = if extracted.output_sink {
+                        quote! { output }
+                    } else {
+                        self.stdout()
+                    };
+                    let write_failed = self.output_failed();
+                    let write = match self.io_mode {
+                        IoMode::Text
+                            if extracted.output_sink || self.panic_free || self.buffered() =>
+                        {
+                            let ascii_failed = if self.panic_free {
+                                quote! { map_err(|_| BfRuntimeError::NonAsciiOutput(#cell_code as u32))? }
+                            } else {
+                                quote! { unwrap() }
+                            };
+                            quote! {
+                                let __c = #cell_code.to_ascii_char().#ascii_failed.as_byte();
+                                for _ in 0..#count_usize {
+                                    std::io::Write::write_all(#output, &[__c]).#write_failed;
+                                }
+                            }
+                        }
+                        IoMode::Numeric
+                            if extracted.output_sink || self.panic_free || self.buffered() =>
+                        {
+                            quote! {
+                                for _ in 0..#count_usize {
+                                    std::io::Write::write_fmt(#output, format_args!("{} ", tape[pointer]))
+                                        .#write_failed;
+                                }
+                            }
+                        }
+                        IoMode::Text => {
+                            quote! {
+                                let __c = #cell_code.to_ascii_char().unwrap().as_char();
+                                for _ in 0..#count_usize {
+                                    print!("{}", __c);
+                                }
+                            }
                         }
+                        IoMode::Numeric => {
+                            quote! {
+                                for _ in 0..#count_usize {
+                                    print!("{} ", tape[pointer]);
+                                }
+                            }
+                        }
+                    };
+
+                    if self.flush == FlushPolicy::PerWrite {
+                        quote! {
+                            #write
+                            std::io::Write::flush(#output).#write_failed;
+                        }
+                    } else {
+                        write
                     }
                 }
                 _ => unreachable!("loop characters are not included in the tokenized code"),
             };
 
-            statements.push(stmt);
+            let marker = self.marker(*span);
+            statements.push(quote! { #marker #stmt });
         }
 
         statements
     }
 
-    fn template(&self, body: TokenStream, needs_input: bool) -> TokenStream {
-        let mem_size = self.memory_size;
-        let cell_type = match self.cell_size {
-            CellSize::U8 => quote! { u8 },
-            CellSize::U16 => quote! { u16 },
-            CellSize::U32 => quote! { u32 },
+    /// The [`source_map::marker`] for the code of the instructions at `span`,
+    /// if there is to be a source map.
+    fn marker(&self, span: Span) -> TokenStream {
+        if !self.source_map {
+            return quote! {};
+        }
+
+        let marker = format_ident!("{}", source_map::marker(span));
+        quote! { #marker }
+    }
+
+    /// An expression evaluating to `Some(value)` with the next input value
+    /// (advancing `input_pos`), or `None` at EOF.
+    fn read_expr(&self, extracted: &Extracted) -> TokenStream {
+        let get = if extracted.lazy_input && self.panic_free {
+            quote! { input.get(input_pos)? }
+        } else {
+            quote! { input.get(input_pos) }
         };
 
-        let input_def = if let Some(fixed_input) = &self.fixed_input {
-            let fixed = fixed_input.as_str();
+        match self.io_mode {
+            IoMode::Text => {
+                let value = match self.cell_size {
+                    CellSize::U8 => quote! { _c },
+                    CellSize::BigInt => quote! { num_bigint::BigInt::from(_c) },
+                    _ => {
+                        let cell_type = self.cell_type();
+                        quote! { _c as #cell_type }
+                    }
+                };
+                quote! {
+                    #get.map(|&_c| {
+                        input_pos += 1;
+                        #value
+                    })
+                }
+            }
+            IoMode::Numeric => {
+                let (number_type, cast) = if self.big_cells() {
+                    (quote! { num_bigint::BigInt }, quote! {})
+                } else {
+                    let cell_type = self.cell_type();
+                    (quote! { i64 }, quote! { as #cell_type })
+                };
+                let parsed = if self.panic_free {
+                    quote! { _number.ok_or(BfRuntimeError::InvalidNumber)? }
+                } else {
+                    quote! { _number.expect("input is not a number") }
+                };
+                quote! {
+                    {
+                        while #get.is_some_and(|_c| _c.is_ascii_whitespace()) {
+                            input_pos += 1;
+                        }
+                        let mut _number = Vec::new();
+                        while let Some(&_c) = #get.filter(|_c| !_c.is_ascii_whitespace()) {
+                            _number.push(_c);
+                            input_pos += 1;
+                        }
+                        if _number.is_empty() {
+                            None
+                        } else {
+                            let _number = std::str::from_utf8(&_number)
+                                .ok()
+                                .and_then(|_number| _number.parse::<#number_type>().ok());
+                            Some(#parsed #cast)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// A function running a single extracted loop. The state is copied into
+    /// locals of the same names as in `main`, so the body is generated the same way.
+    fn loop_function(&self, name: &Ident, body: TokenStream, extracted: &Extracted) -> TokenStream {
+        let nonzero = self.cell_nonzero();
+
+        self.function(
             quote! {
-                let input = {
-                    use ascii::AsAsciiStr;
+                #[inline]
+                fn #name
+            },
+            quote! {
+                while #nonzero {
+                    #body
+                }
+            },
+            extracted,
+        )
+    }
 
-                    let input = #fixed;
-                    let input_ascii = input.as_ascii_str().expect("input is not ASCII");
-                    input_ascii.chars().collect::<Vec<_>>()
-                };
+    /// A function named by `signature` that runs `body` on the tape, pointer
+    /// and input passed in by [`BrainfuckToRust::call`].
+    fn function(
+        &self,
+        signature: TokenStream,
+        body: TokenStream,
+        extracted: &Extracted,
+    ) -> TokenStream {
+        let cell_type = self.cell_type();
+        let has_input = extracted.has_input;
 
-                let mut input_pos = 0usize;
+        let input_type = if extracted.lazy_input {
+            quote! { &mut LazyInput }
+        } else {
+            quote! { &[u8] }
+        };
+
+        let (mut input_params, input_load, input_store) = if has_input {
+            (
+                quote! { , input: #input_type, _input_pos: &mut usize },
+                quote! { let mut input_pos = *_input_pos; },
+                quote! { *_input_pos = input_pos; },
+            )
+        } else {
+            (quote! {}, quote! {}, quote! {})
+        };
+
+        let (tape_type, mem_size_load) = if self.runtime_config {
+            if has_input {
+                input_params.extend(quote! { , eof: Option<#cell_type> });
             }
-        } else if needs_input {
-            quote! {
-                use ascii::AsciiChar;
+            (
+                quote! { [#cell_type] },
+                quote! { let mem_size = tape.len(); },
+            )
+        } else {
+            (quote! { [#cell_type; MEM_SIZE] }, quote! {})
+        };
 
-                let input = {
-                    use std::io::Read;
-                    use ascii::AsAsciiStr;
+        let (return_type, ok) = if self.panic_free {
+            (quote! { -> Result<(), BfRuntimeError> }, quote! { Ok(()) })
+        } else {
+            (quote! {}, quote! {})
+        };
 
-                    let mut stdin = std::io::stdin();
-                    let mut input = String::new();
+        quote! {
+            #[allow(unused_mut, unused_variables)]
+            #signature(tape: &mut #tape_type, _pointer: &mut usize #input_params) #return_type {
+                let mut pointer = *_pointer;
+                #mem_size_load
+                #input_load
 
-                    stdin.read_to_string(&mut input).expect("failed to read stdin");
-                    let input_ascii = input.as_ascii_str().expect("input is not ASCII");
-                    input_ascii.chars().collect::<Vec<_>>()
-                };
+                #body
 
-                let mut input_pos = 0usize;
+                *_pointer = pointer;
+                #input_store
+                #ok
             }
+        }
+    }
+
+    /// A call of a [`BrainfuckToRust::function`], from another one if
+    /// `in_function`.
+    fn call(&self, function: TokenStream, extracted: &Extracted, in_function: bool) -> TokenStream {
+        let tape = if in_function {
+            quote! { tape }
         } else {
-            quote! {}
+            quote! { &mut tape }
+        };
+        let mut input = match (extracted.has_input, in_function) {
+            (false, _) => quote! {},
+            (true, false) if extracted.lazy_input => {
+                quote! { , &mut input, &mut input_pos }
+            }
+            (true, false) => quote! { , &input, &mut input_pos },
+            (true, true) => quote! { , input, &mut input_pos },
+        };
+        if extracted.has_input && self.runtime_config {
+            input.extend(if self.big_cells() {
+                quote! { , eof.clone() }
+            } else {
+                quote! { , eof }
+            });
+        }
+
+        let call = quote! { #function(#tape, &mut pointer #input) };
+        if self.panic_free {
+            quote! { #call?; }
+        } else {
+            quote! { #call; }
+        }
+    }
+
+    /// Whether a program reading this much input needs [`BrainfuckToRust::lazy_input_def`].
+    /// Reading all of stdin up front only works if the program never waits for
+    /// a reply to its own output, so that is only done when the number of bytes
+    /// it reads is known.
+    fn lazy_input(&self, usage: InputUsage) -> bool {
+        if self.fixed_input.is_some() {
+            return false;
+        }
+
+        match usage {
+            InputUsage::None => false,
+            // numbers can be any number of bytes long, and resumable input is
+            // only asked for when it is needed
+            InputUsage::AtMost(_) => {
+                self.io_mode == IoMode::Numeric
+                    || self.resumable_input
+                    || self.style == CodegenStyle::StateMachine
+            }
+            InputUsage::Streaming => true,
+        }
+    }
+
+    /// A reader that pulls input one byte at a time, when `,` first needs it,
+    /// so compiled programs can be used interactively
+    fn lazy_input_def(&self) -> TokenStream {
+        let stdout = if self.buffered() {
+            quote! { output() }
+        } else {
+            quote! { std::io::stdout() }
+        };
+        let (return_type, read_error, ok) = if self.panic_free {
+            (
+                quote! { Result<Option<&u8>, BfRuntimeError> },
+                quote! { return Err(BfRuntimeError::InputReadFailed(e)) },
+                quote! { Ok(self.buffer.get(pos)) },
+            )
+        } else {
+            (
+                quote! { Option<&u8> },
+                quote! { panic!("failed to read input: {}", e) },
+                quote! { self.buffer.get(pos) },
+            )
         };
 
         quote! {
-            use ascii::ToAsciiChar;
+            struct LazyInput {
+                source: Box<dyn std::io::Read>,
+                buffer: Vec<u8>,
+                eof: bool,
+            }
 
-            fn main() {
-                const MEM_SIZE: usize = #mem_size;
+            impl LazyInput {
+                fn new(source: Box<dyn std::io::Read>) -> Self {
+                    Self {
+                        source,
+                        buffer: Vec::new(),
+                        eof: false,
+                    }
+                }
 
-                let mut pointer = 0usize;
-                let mut tape: [#cell_type; MEM_SIZE] = [0; MEM_SIZE];
+                /// The byte at `pos`, reading up to it if it has not been read yet.
+                fn get(&mut self, pos: usize) -> #return_type {
+                    use std::io::Write;
 
-                #input_def
+                    if pos >= self.buffer.len() && !self.eof {
+                        // make sure any prompt is visible before waiting for a reply
+                        #stdout.flush().ok();
+                    }
 
-                #body
+                    while pos >= self.buffer.len() && !self.eof {
+                        let mut byte = [0u8];
+                        match self.source.read(&mut byte) {
+                            Ok(0) => self.eof = true,
+                            Ok(_) => self.buffer.push(byte[0]),
+                            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                            Err(e) => #read_error,
+                        }
+                    }
+
+                    #ok
+                }
             }
         }
     }
+
+    /// `input_source()`, which reads the arguments of the program for
+    /// `input_args`: an input file or `--input-str <text>`, like the
+    /// interpreter takes, or nothing for stdin
+    fn input_source_def(&self) -> TokenStream {
+        let stdin = self.stdin();
+        quote! {
+            const USAGE: &str = "\
+Usage: [input-file] [--input-str <text>]
+  <input-file>          Read the input from this file instead of stdin
+  --input-str <text>    Use this text as the input";
+
+            /// Where `,` reads from, as the arguments say
+            fn input_source() -> std::io::Result<Box<dyn std::io::Read>> {
+                let args: Vec<String> = std::env::args().skip(1).collect();
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+                match args[..] {
+                    [] => Ok(Box::new(#stdin)),
+                    ["-h" | "--help"] => {
+                        println!("{}", USAGE);
+                        std::process::exit(0);
+                    }
+                    ["--input-str", text] => {
+                        Ok(Box::new(std::io::Cursor::new(text.as_bytes().to_vec())))
+                    }
+                    [path] if !path.starts_with('-') => {
+                        let file = std::fs::File::open(path)?;
+                        Ok(Box::new(std::io::BufReader::new(file)))
+                    }
+                    _ => {
+                        eprintln!("error: unexpected arguments `{}`\n\n{}", args.join(" "), USAGE);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A minimal replacement for `print!`, used with `io_shim`
+    fn io_shim_def(&self) -> TokenStream {
+        if !self.io_shim {
+            return quote! {};
+        }
+
+        quote! {
+            #[allow(dead_code)]
+            mod shim {
+                extern "C" {
+                    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+                }
+
+                /// Writes a single byte to stdout.
+                pub fn put(byte: u8) {
+                    unsafe {
+                        write(1, &byte, 1);
+                    }
+                }
+
+                /// Writes a number in decimal to stdout.
+                pub fn put_number(mut n: u64) {
+                    let mut digits = [0u8; 20];
+                    let mut len = 0;
+                    loop {
+                        digits[19 - len] = b'0' + (n % 10) as u8;
+                        len += 1;
+                        n /= 10;
+                        if n == 0 {
+                            break;
+                        }
+                    }
+                    unsafe {
+                        write(1, digits[20 - len..].as_ptr(), len);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The expression for the number of cells on the tape
+    fn mem_size(&self) -> TokenStream {
+        if self.runtime_config {
+            quote! { mem_size }
+        } else {
+            quote! { MEM_SIZE }
+        }
+    }
+
+    fn cell_type(&self) -> TokenStream {
+        match self.cell_size {
+            CellSize::U8 => quote! { u8 },
+            CellSize::U16 => quote! { u16 },
+            CellSize::U32 => quote! { u32 },
+            CellSize::BigInt => quote! { num_bigint::BigInt },
+        }
+    }
+
+    /// Whether cells are big integers, which are not `Copy` and have their
+    /// own methods
+    fn big_cells(&self) -> bool {
+        matches!(self.cell_size, CellSize::BigInt)
+    }
+
+    /// A literal of the cell type, wrapping around like adding one at a time
+    /// would. Big integers take a `usize`.
+    fn cell_literal(&self, value: usize) -> Literal {
+        match self.cell_size {
+            CellSize::U8 => Literal::u8_suffixed(value as u8),
+            CellSize::U16 => Literal::u16_suffixed(value as u16),
+            CellSize::U32 => Literal::u32_suffixed(value as u32),
+            CellSize::BigInt => Literal::usize_suffixed(value),
+        }
+    }
+
+    /// A cell holding `value`
+    fn cell_value(&self, value: u8) -> TokenStream {
+        if self.big_cells() {
+            quote! { num_bigint::BigInt::from(#value) }
+        } else {
+            let literal = self.cell_literal(value as usize);
+            quote! { #literal }
+        }
+    }
+
+    /// A cell holding 0, which can fill an array even for big integers
+    fn cell_zero(&self) -> TokenStream {
+        if self.big_cells() {
+            quote! { num_bigint::BigInt::ZERO }
+        } else {
+            quote! { 0 }
+        }
+    }
+
+    /// Whether the current cell is not 0
+    fn cell_nonzero(&self) -> TokenStream {
+        if self.big_cells() {
+            quote! { tape[pointer].sign() != num_bigint::Sign::NoSign }
+        } else {
+            quote! { tape[pointer] != 0 }
+        }
+    }
+
+    /// The current cell as a character code. A big integer that does not fit
+    /// in a `u32` is `u32::MAX`, which is not a character either.
+    fn cell_code(&self) -> TokenStream {
+        if self.big_cells() {
+            quote! { u32::try_from(&tape[pointer]).unwrap_or(u32::MAX) }
+        } else {
+            quote! { tape[pointer] }
+        }
+    }
+
+    /// Handles the `io::Result` of reading the input, by panicking with this
+    /// message or (with `panic_free`) returning `BfRuntimeError::InputReadFailed`
+    fn input_failed(&self, message: &str) -> TokenStream {
+        if self.panic_free {
+            quote! { map_err(BfRuntimeError::InputReadFailed)? }
+        } else {
+            quote! { expect(#message) }
+        }
+    }
+
+    /// What `,` reads from when nothing else was given: stdin, or the contents
+    /// of the `input_env` variable
+    fn stdin(&self) -> TokenStream {
+        match &self.input_env {
+            Some(var) => quote! {
+                std::io::Cursor::new(
+                    std::env::var_os(#var)
+                        .unwrap_or_else(|| {
+                            eprintln!("error: the environment variable `{}` is not set", #var);
+                            std::process::exit(2);
+                        })
+                        .into_encoded_bytes(),
+                )
+            },
+            None => quote! { std::io::stdin() },
+        }
+    }
+
+    /// Handles the `io::Result` of writing the output, like [`BrainfuckToRust::input_failed`]
+    fn output_failed(&self) -> TokenStream {
+        if self.panic_free {
+            quote! { map_err(BfRuntimeError::OutputWriteFailed)? }
+        } else {
+            quote! { expect("failed to write output") }
+        }
+    }
+
+    /// Whether `.` writes to the [`BrainfuckToRust::output_def`] buffer rather
+    /// than to stdout, for [`FlushPolicy::OnExit`]
+    fn buffered(&self) -> bool {
+        self.flush == FlushPolicy::OnExit
+    }
+
+    /// Where `.` writes to when it writes to stdout
+    fn stdout(&self) -> TokenStream {
+        if self.buffered() {
+            quote! { &mut *output() }
+        } else {
+            quote! { &mut std::io::stdout() }
+        }
+    }
+
+    /// Flushes the [`BrainfuckToRust::output_def`] buffer with `failed` handling
+    /// the error, if there is one.
+    fn flush_buffer(&self, failed: TokenStream) -> TokenStream {
+        if self.buffered() {
+            quote! { std::io::Write::flush(&mut *output()).#failed; }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// The buffer everything `.` writes stays in until it is flushed, for
+    /// [`FlushPolicy::OnExit`]. It is shared, so that `,` can flush it from
+    /// anywhere before waiting for input.
+    fn output_def(&self) -> TokenStream {
+        if !self.buffered() {
+            return quote! {};
+        }
+
+        quote! {
+            /// The output, buffered until `,` waits for input or the program ends
+            fn output() -> std::sync::MutexGuard<'static, std::io::BufWriter<std::io::Stdout>> {
+                static OUTPUT: std::sync::OnceLock<
+                    std::sync::Mutex<std::io::BufWriter<std::io::Stdout>>,
+                > = std::sync::OnceLock::new();
+
+                OUTPUT
+                    .get_or_init(|| {
+                        std::sync::Mutex::new(std::io::BufWriter::with_capacity(
+                            1 << 16,
+                            std::io::stdout(),
+                        ))
+                    })
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+            }
+        }
+    }
+
+    /// `main`, running the program. With `panic_free`, the program runs in
+    /// `run()` instead, and `main` reports its error and exits with a code
+    /// for the kind of error. With `lib`, the program always runs in a public
+    /// `run()`, and `main` is left to [`BrainfuckToRust::lib_main`].
+    fn entry_point(&self, program: TokenStream) -> TokenStream {
+        if self.resumable_input {
+            return self.resumable_entry_point(program);
+        }
+
+        let flush = self.flush_buffer(self.output_failed());
+        // what was written before an error still goes out
+        let flush_on_error = self.flush_buffer(quote! { ok() });
+
+        if !self.panic_free && self.lib {
+            return quote! {
+                /// Runs the program on stdin and stdout.
+                pub fn run() {
+                    #program
+
+                    #flush
+                }
+            };
+        }
+        let exit = self.exit_with_cell();
+        if !self.panic_free {
+            return quote! {
+                fn main() {
+                    #program
+
+                    #flush
+
+                    #exit
+                }
+            };
+        }
+
+        let error_def = self.runtime_error_def();
+
+        if self.lib {
+            // `main` cannot see the buffer, so it is flushed here on errors too
+            let run = if self.buffered() {
+                quote! {
+                    /// Runs the program on stdin and stdout, stopping at the first error.
+                    pub fn run() -> Result<(), BfRuntimeError> {
+                        let result = execute();
+                        #flush_on_error
+                        result
+                    }
+
+                    fn execute() -> Result<(), BfRuntimeError> {
+                        #program
+
+                        #flush
+
+                        Ok(())
+                    }
+                }
+            } else {
+                quote! {
+                    /// Runs the program on stdin and stdout, stopping at the first error.
+                    pub fn run() -> Result<(), BfRuntimeError> {
+                        #program
+
+                        Ok(())
+                    }
+                }
+            };
+
+            return quote! {
+                #error_def
+
+                #run
+            };
+        }
+
+        // the exit leaves `Ok(())` unreachable
+        let allow = if self.exit_cell.is_some() {
+            quote! { #[allow(unreachable_code)] }
+        } else {
+            quote! {}
+        };
+
+        quote! {
+            #error_def
+
+            #allow
+            fn run() -> Result<(), BfRuntimeError> {
+                #program
+
+                #flush
+
+                #exit
+
+                Ok(())
+            }
+
+            fn main() {
+                if let Err(e) = run() {
+                    #flush_on_error
+                    eprintln!("error: {}", e);
+                    std::process::exit(e.exit_code());
+                }
+            }
+        }
+    }
+
+    /// The `main.rs` of a crate generated with `lib`, which runs the `run()`
+    /// of the library as [`BrainfuckToRust::entry_point`] would have.
+    /// `krate` is the name the library is used by.
+    pub fn lib_main(&self, krate: &str) -> TokenStream {
+        let krate = format_ident!("{}", krate);
+
+        let main = if self.resumable_input {
+            self.resumable_main()
+        } else if self.panic_free {
+            quote! {
+                fn main() {
+                    if let Err(e) = run() {
+                        eprintln!("error: {}", e);
+                        std::process::exit(e.exit_code());
+                    }
+                }
+            }
+        } else {
+            quote! {
+                fn main() {
+                    run();
+                }
+            }
+        };
+
+        quote! {
+            use #krate::*;
+
+            #main
+        }
+    }
+
+    /// The entry point for `resumable_input`: the program runs in `execute` on
+    /// its own thread, reading from a source that asks for input over a channel,
+    /// and `run()` hands back a `Continuation` whenever it waits. `main`
+    /// drives it with stdin.
+    fn resumable_entry_point(&self, program: TokenStream) -> TokenStream {
+        let (error_def, outcome, ok) = if self.panic_free {
+            (
+                self.runtime_error_def(),
+                quote! { Result<(), BfRuntimeError> },
+                quote! { Ok(()) },
+            )
+        } else {
+            (quote! {}, quote! { () }, quote! {})
+        };
+        let flush = self.flush_buffer(self.output_failed());
+        // for what was written before an error
+        let flush_on_finish = self.flush_buffer(quote! { ok() });
+        let main = if self.lib {
+            quote! {}
+        } else {
+            self.resumable_main()
+        };
+
+        quote! {
+            #error_def
+
+            /// How the program finished
+            pub type Outcome = #outcome;
+
+            /// Why [`run`] or [`Continuation::resume`] returned
+            pub enum Paused {
+                /// A `,` is waiting for input. Pass some to [`Continuation::resume`],
+                /// or an empty slice for EOF
+                NeedsInput(Continuation),
+                Finished(Outcome),
+            }
+
+            /// Sent from the program's thread
+            enum Event {
+                NeedsInput,
+                Finished(Outcome),
+            }
+
+            /// The rest of a run that is waiting for input
+            pub struct Continuation {
+                input: std::sync::mpsc::Sender<Vec<u8>>,
+                events: std::sync::mpsc::Receiver<Event>,
+                thread: std::thread::JoinHandle<()>,
+            }
+
+            impl Continuation {
+                /// Continues the program with more input, until it needs more or finishes.
+                pub fn resume(self, bytes: &[u8]) -> Paused {
+                    // the program is waiting for exactly this
+                    self.input.send(bytes.to_vec()).ok();
+                    self.wait()
+                }
+
+                fn wait(self) -> Paused {
+                    match self.events.recv() {
+                        Ok(Event::NeedsInput) => Paused::NeedsInput(self),
+                        Ok(Event::Finished(outcome)) => {
+                            self.thread.join().ok();
+                            Paused::Finished(outcome)
+                        }
+                        // the program panicked before it could say how it finished
+                        Err(_) => match self.thread.join() {
+                            Err(panic) => std::panic::resume_unwind(panic),
+                            Ok(()) => unreachable!("the program always reports how it finished"),
+                        },
+                    }
+                }
+            }
+
+            /// Input that is asked for over a channel whenever it runs out
+            struct ChannelInput {
+                events: std::sync::mpsc::Sender<Event>,
+                input: std::sync::mpsc::Receiver<Vec<u8>>,
+                buffer: Vec<u8>,
+                pos: usize,
+                eof: bool,
+            }
+
+            impl std::io::Read for ChannelInput {
+                fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                    while self.pos >= self.buffer.len() && !self.eof {
+                        self.events.send(Event::NeedsInput).ok();
+                        match self.input.recv() {
+                            Ok(bytes) if !bytes.is_empty() => {
+                                self.buffer = bytes;
+                                self.pos = 0;
+                            }
+                            _ => self.eof = true,
+                        }
+                    }
+
+                    let len = (self.buffer.len() - self.pos).min(buf.len());
+                    buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+                    self.pos += len;
+                    Ok(len)
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn execute(source: Box<dyn std::io::Read>) -> Outcome {
+                #program
+
+                #flush
+
+                #ok
+            }
+
+            /// Starts the program and runs it until it needs input or finishes.
+            pub fn run() -> Paused {
+                let (events_tx, events) = std::sync::mpsc::channel();
+                let (input, input_rx) = std::sync::mpsc::channel();
+
+                let thread = std::thread::spawn(move || {
+                    let source = ChannelInput {
+                        events: events_tx.clone(),
+                        input: input_rx,
+                        buffer: Vec::new(),
+                        pos: 0,
+                        eof: false,
+                    };
+                    let outcome = execute(Box::new(source));
+                    #flush_on_finish
+                    events_tx.send(Event::Finished(outcome)).ok();
+                });
+
+                Continuation {
+                    input,
+                    events,
+                    thread,
+                }
+                .wait()
+            }
+
+            #main
+        }
+    }
+
+    /// `main` for `resumable_input`, which resumes the program with stdin
+    /// until it finishes.
+    fn resumable_main(&self) -> TokenStream {
+        let (finish, read_error) = if self.panic_free {
+            (
+                quote! {
+                    if let Err(e) = outcome {
+                        eprintln!("error: {}", e);
+                        std::process::exit(e.exit_code());
+                    }
+                },
+                quote! {
+                    {
+                        let e = BfRuntimeError::InputReadFailed(e);
+                        eprintln!("error: {}", e);
+                        std::process::exit(e.exit_code());
+                    }
+                },
+            )
+        } else {
+            (
+                quote! { let () = outcome; },
+                quote! { panic!("failed to read input: {}", e) },
+            )
+        };
+
+        quote! {
+            fn main() {
+                use std::io::Read;
+
+                let mut paused = run();
+                loop {
+                    match paused {
+                        Paused::NeedsInput(continuation) => {
+                            let mut buffer = [0u8; 4096];
+                            let len = loop {
+                                match std::io::stdin().read(&mut buffer) {
+                                    Ok(len) => break len,
+                                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                                    Err(e) => #read_error,
+                                }
+                            };
+                            paused = continuation.resume(&buffer[..len]);
+                        }
+                        Paused::Finished(outcome) => {
+                            #finish
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// `BfRuntimeError`, for `panic_free`
+    fn runtime_error_def(&self) -> TokenStream {
+        quote! {
+            /// Everything that can stop the program early
+            #[derive(Debug)]
+            #[allow(dead_code)]
+            pub enum BfRuntimeError {
+                /// `.` wrote a value that is not an ASCII character
+                NonAsciiOutput(u32),
+                /// Reading the input failed
+                InputReadFailed(std::io::Error),
+                /// Writing the output failed
+                OutputWriteFailed(std::io::Error),
+                /// A cell went past its minimum or maximum value
+                CellOverflow { cell: usize },
+                /// The pointer moved off either end of the tape
+                PointerOutOfBounds,
+                /// `,` read something that is not a number
+                InvalidNumber,
+            }
+
+            impl BfRuntimeError {
+                /// The exit code `main` exits with for the error
+                pub fn exit_code(&self) -> i32 {
+                    match self {
+                        Self::NonAsciiOutput(_) => 3,
+                        Self::InputReadFailed(_) => 4,
+                        Self::OutputWriteFailed(_) => 5,
+                        Self::CellOverflow { .. } => 6,
+                        Self::PointerOutOfBounds => 7,
+                        Self::InvalidNumber => 8,
+                    }
+                }
+            }
+
+            impl std::fmt::Display for BfRuntimeError {
+                fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    match self {
+                        Self::NonAsciiOutput(value) => {
+                            write!(f, "cannot output {}, which is not an ASCII character", value)
+                        }
+                        Self::InputReadFailed(e) => write!(f, "failed to read input: {}", e),
+                        Self::OutputWriteFailed(e) => write!(f, "failed to write output: {}", e),
+                        Self::CellOverflow { cell } => write!(f, "cell {} overflowed", cell),
+                        Self::PointerOutOfBounds => write!(f, "the pointer moved off the tape"),
+                        Self::InvalidNumber => write!(f, "the input is not a number"),
+                    }
+                }
+            }
+
+            impl std::error::Error for BfRuntimeError {}
+        }
+    }
+
+    /// Flattens the segments into the arms of `Program::step`, one per op,
+    /// along with the byte offset each op starts at in the source. Each arm may
+    /// change `next`, the op that runs after it.
+    fn states<T: TokenExt>(
+        &self,
+        segments: &Vec<Segment<T>>,
+        extracted: &Extracted,
+        states: &mut Vec<(usize, TokenStream)>,
+    ) {
+        for segment in segments {
+            match segment {
+                Segment::Executable { code, .. } => {
+                    let statements = self.generate_statements(code, extracted);
+                    let starts = code.spans.iter().map(|span| span.start);
+                    states.extend(starts.zip(statements));
+                }
+                Segment::Loop { body, span } => {
+                    let start = states.len();
+                    // filled in once it is known where the loop ends
+                    states.push((span.start, quote! {}));
+                    self.states(body, extracted, states);
+
+                    let after = states.len() + 1;
+                    let first = start + 1;
+                    let nonzero = self.cell_nonzero();
+                    let marker = self.marker(*span);
+                    states[start].1 = quote! {
+                        #marker
+                        if !(#nonzero) {
+                            next = #after;
+                        }
+                    };
+                    states.push((
+                        span.end - 1,
+                        quote! {
+                            #marker
+                            if #nonzero {
+                                next = #first;
+                            }
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The program as a `Program` struct with a `step` method running one op
+    /// (a run of repeated instructions, or a bracket) at a time, and a `run`
+    /// method running many.
+    fn state_machine<T: TokenExt>(&self, file: &File<T>, extracted: &Extracted) -> TokenStream {
+        let mut states = Vec::new();
+        self.states(&file.segments, extracted, &mut states);
+        let (positions, states): (Vec<usize>, Vec<TokenStream>) = states.into_iter().unzip();
+
+        let mem_size = self.memory_size;
+        let cell_type = self.cell_type();
+        let zero = self.cell_zero();
+        let numbers = 0..states.len();
+        let halt = states.len();
+
+        let (input_field, input_init, input_load, input_store) = if !extracted.has_input {
+            (quote! {}, quote! {}, quote! {}, quote! {})
+        } else if let Some(fixed) = self.fixed_input_literal() {
+            (
+                quote! {
+                    input: &'static [u8],
+                    input_pos: usize,
+                },
+                quote! {
+                    input: #fixed,
+                    input_pos: 0,
+                },
+                quote! {
+                    let input = self.input;
+                    let mut input_pos = self.input_pos;
+                },
+                quote! { self.input_pos = input_pos; },
+            )
+        } else {
+            (
+                quote! {
+                    input: LazyInput,
+                    input_pos: usize,
+                },
+                quote! {
+                    input: LazyInput::new(source),
+                    input_pos: 0,
+                },
+                quote! {
+                    let input = &mut self.input;
+                    let mut input_pos = self.input_pos;
+                },
+                quote! { self.input_pos = input_pos; },
+            )
+        };
+
+        let (step_result, continue_result, halted_result, try_step) = if self.panic_free {
+            (
+                quote! { Result<StepResult, BfRuntimeError> },
+                quote! { Ok(StepResult::Continue) },
+                quote! { Ok(StepResult::Halted) },
+                quote! { ? },
+            )
+        } else {
+            (
+                quote! { StepResult },
+                quote! { StepResult::Continue },
+                quote! { StepResult::Halted },
+                quote! {},
+            )
+        };
+
+        let io_shim = self.io_shim_def();
+        let lazy_input = if extracted.lazy_input {
+            self.lazy_input_def()
+        } else {
+            quote! {}
+        };
+        let (input_source, program) = if self.input_args && extracted.lazy_input {
+            let open_failed = self.input_failed("failed to open the input file");
+            (
+                self.input_source_def(),
+                quote! {
+                    Program::with_io(input_source().#open_failed, Box::new(std::io::stdout()))
+                },
+            )
+        } else {
+            (quote! {}, quote! { Program::new() })
+        };
+        let (debug_server, run) = if self.debug_server {
+            (
+                self.debug_server_def(),
+                quote! {
+                    if let Ok(addr) = std::env::var("BF_DEBUG_ADDR") {
+                        serve(&addr) #try_step;
+                    } else {
+                        let mut program = #program;
+                        while program.run(u64::MAX) #try_step == StepResult::Continue {}
+                    }
+                },
+            )
+        } else {
+            (
+                quote! {},
+                quote! {
+                    let mut program = #program;
+                    while program.run(u64::MAX) #try_step == StepResult::Continue {}
+                },
+            )
+        };
+        let entry_point = self.entry_point(run);
+        let stdin = self.stdin();
+
+        quote! {
+            #[allow(unused_imports)]
+            use ascii::ToAsciiChar;
+
+            #io_shim
+
+            #lazy_input
+
+            #input_source
+
+            const MEM_SIZE: usize = #mem_size;
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            /// The outcome of [`Program::step`]
+            pub enum StepResult {
+                /// An op was executed and the program can continue
+                Continue,
+                /// The end of the program was reached
+                Halted,
+            }
+
+            /// The byte offset in the source at which each op starts
+            pub const POSITIONS: [usize; #halt] = [#(#positions),*];
+
+            /// The program, paused between two ops
+            pub struct Program {
+                pub tape: Box<[#cell_type; MEM_SIZE]>,
+                pub pointer: usize,
+                /// The op that runs next
+                pub state: usize,
+                /// How many ops have run
+                pub ops: u64,
+                /// Where `.` writes to
+                pub output: Box<dyn std::io::Write>,
+                #input_field
+            }
+
+            #[allow(dead_code)]
+            impl Program {
+                /// The program at its start, reading input from stdin when it
+                /// needs it and writing to stdout.
+                pub fn new() -> Self {
+                    Self::with_io(Box::new(#stdin), Box::new(std::io::stdout()))
+                }
+
+                /// The program at its start, reading input from `source` when it
+                /// needs it and writing to `output`.
+                #[allow(unused_variables)]
+                pub fn with_io(
+                    source: Box<dyn std::io::Read>,
+                    output: Box<dyn std::io::Write>,
+                ) -> Self {
+                    Self {
+                        tape: Box::new([#zero; MEM_SIZE]),
+                        pointer: 0,
+                        state: 0,
+                        ops: 0,
+                        output,
+                        #input_init
+                    }
+                }
+
+                /// Whether every op has run
+                pub fn halted(&self) -> bool {
+                    self.state >= #halt
+                }
+
+                /// Runs the next op.
+                pub fn step(&mut self) -> #step_result {
+                    self.run(1)
+                }
+
+                /// Runs at most `ops` ops, stopping early if the program halts.
+                /// The state stays in locals in between, so this is much faster
+                /// than as many calls to [`Program::step`].
+                #[allow(unused_mut, unused_variables, unused_assignments)]
+                pub fn run(&mut self, ops: u64) -> #step_result {
+                    let mut state = self.state;
+                    let mut pointer = self.pointer;
+                    let mut ran = 0;
+                    let tape = &mut *self.tape;
+                    let output = &mut self.output;
+                    #input_load
+
+                    while ran < ops {
+                        let mut next = state + 1;
+                        match state {
+                            #(#numbers => { #states })*
+                            _ => break,
+                        }
+                        state = next;
+                        ran += 1;
+                    }
+
+                    self.state = state;
+                    self.pointer = pointer;
+                    self.ops += ran;
+                    #input_store
+
+                    if self.halted() {
+                        #halted_result
+                    } else {
+                        #continue_result
+                    }
+                }
+            }
+
+            #debug_server
+
+            #entry_point
+        }
+    }
+
+    /// `serve`, which lets `bf attach` drive the state machine over TCP. The
+    /// program greets the debugger with `bf-debug 1 <positions>`, then answers
+    /// every `run <ops>` line (0 only reports the state) with
+    /// `<ops> <state> <pointer> <halted> <output> <tape>`, the last two in hex
+    /// (`-` for no output). What the program writes is still printed, too.
+    fn debug_server_def(&self) -> TokenStream {
+        let (return_type, run_failed, ok) = if self.panic_free {
+            (
+                quote! { -> Result<(), BfRuntimeError> },
+                quote! {
+                    if let Err(e) = result {
+                        writeln!(writer, "error {}", e).ok();
+                        return Err(e);
+                    }
+                },
+                quote! { Ok(()) },
+            )
+        } else {
+            (quote! {}, quote! { let _ = result; }, quote! {})
+        };
+        let stdin = self.stdin();
+
+        quote! {
+            /// Keeps what the program writes for the debugger, and prints it
+            struct DebugOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+            impl std::io::Write for DebugOutput {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    self.0.borrow_mut().extend_from_slice(buf);
+                    std::io::stdout().write_all(buf)?;
+                    Ok(buf.len())
+                }
+
+                fn flush(&mut self) -> std::io::Result<()> {
+                    std::io::stdout().flush()
+                }
+            }
+
+            fn hex(bytes: impl Iterator<Item = u8>) -> String {
+                const DIGITS: &[u8; 16] = b"0123456789abcdef";
+                let mut hex = String::new();
+                for byte in bytes {
+                    hex.push(DIGITS[(byte >> 4) as usize] as char);
+                    hex.push(DIGITS[(byte & 0xf) as usize] as char);
+                }
+                if hex.is_empty() {
+                    hex.push('-');
+                }
+                hex
+            }
+
+            /// Waits for `bf attach` on `addr` and runs the program as it asks.
+            fn serve(addr: &str) #return_type {
+                use std::io::{BufRead, Write};
+
+                let listener =
+                    std::net::TcpListener::bind(addr).expect("failed to listen for the debugger");
+                eprintln!("waiting for `bf attach {}`", addr);
+                let (stream, _) = listener.accept().expect("failed to accept the debugger");
+                // every reply is a single write, which should not wait for more
+                stream.set_nodelay(true).ok();
+                let mut reader =
+                    std::io::BufReader::new(stream.try_clone().expect("failed to read the connection"));
+                let mut writer = stream;
+
+                let output = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+                let mut program = Program::with_io(
+                    Box::new(#stdin),
+                    Box::new(DebugOutput(output.clone())),
+                );
+
+                let positions: Vec<String> = POSITIONS.iter().map(|p| p.to_string()).collect();
+                writeln!(writer, "bf-debug 1 {}", positions.join(",")).ok();
+
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let ops = line
+                        .trim()
+                        .strip_prefix("run ")
+                        .and_then(|ops| ops.parse().ok())
+                        .unwrap_or(0);
+                    line.clear();
+
+                    if ops > 0 {
+                        let result = program.run(ops);
+                        #run_failed
+                    }
+
+                    let written = hex(output.borrow_mut().drain(..));
+                    let tape = hex(program.tape.iter().map(|cell| *cell as u8));
+                    let reply = format!(
+                        "{} {} {} {} {} {}\n",
+                        program.ops,
+                        program.state,
+                        program.pointer,
+                        program.halted() as u8,
+                        written,
+                        tape
+                    );
+                    if writer.write_all(reply.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+
+                #ok
+            }
+        }
+    }
+
+    /// The fixed input as a byte string literal, if there is one
+    fn fixed_input_literal(&self) -> Option<LitByteStr> {
+        self.fixed_input
+            .as_ref()
+            .map(|bytes| LitByteStr::new(bytes, proc_macro2::Span::call_site()))
+    }
+
+    fn template(
+        &self,
+        body: TokenStream,
+        functions: Vec<TokenStream>,
+        input_usage: InputUsage,
+    ) -> TokenStream {
+        if self.runtime_config {
+            return self.runtime_template(body, functions, input_usage);
+        }
+
+        let mem_size = self.memory_size;
+        let cell_type = self.cell_type();
+        let read_failed = self.input_failed("failed to read stdin");
+
+        let input_def = if let Some(fixed) = self.fixed_input_literal() {
+            quote! {
+                let input: &[u8] = #fixed;
+
+                let mut input_pos = 0usize;
+            }
+        } else if self.lazy_input(input_usage) {
+            let source = if self.resumable_input {
+                quote! { source }
+            } else if self.input_args {
+                let open_failed = self.input_failed("failed to open the input file");
+                quote! { input_source().#open_failed }
+            } else {
+                let stdin = self.stdin();
+                quote! { Box::new(#stdin) }
+            };
+            quote! {
+                let mut input = LazyInput::new(#source);
+
+                let mut input_pos = 0usize;
+            }
+        } else if let InputUsage::AtMost(reads) = input_usage {
+            let reads = reads as u64;
+            let source = if self.input_args {
+                let open_failed = self.input_failed("failed to open the input file");
+                quote! { input_source().#open_failed }
+            } else {
+                self.stdin()
+            };
+            quote! {
+                let input = {
+                    use std::io::Read;
+
+                    let mut input = Vec::new();
+
+                    #source
+                        .take(#reads)
+                        .read_to_end(&mut input)
+                        .#read_failed;
+                    input
+                };
+
+                let mut input_pos = 0usize;
+            }
+        } else {
+            quote! {}
+        };
+
+        let io_shim = self.io_shim_def();
+        let output = self.output_def();
+        let lazy_input = if self.lazy_input(input_usage) {
+            self.lazy_input_def()
+        } else {
+            quote! {}
+        };
+        let input_source = if self.input_args
+            && !self.resumable_input
+            && self.fixed_input.is_none()
+            && input_usage != InputUsage::None
+        {
+            self.input_source_def()
+        } else {
+            quote! {}
+        };
+        let zero = self.cell_zero();
+        let entry_point = self.entry_point(quote! {
+            let mut pointer = 0usize;
+            let mut tape: [#cell_type; MEM_SIZE] = [#zero; MEM_SIZE];
+
+            #input_def
+
+            #body
+        });
+
+        quote! {
+            #[allow(unused_imports)]
+            use ascii::ToAsciiChar;
+
+            #io_shim
+
+            #output
+
+            #lazy_input
+
+            #input_source
+
+            const MEM_SIZE: usize = #mem_size;
+
+            #(#functions)*
+
+            #entry_point
+        }
+    }
+
+    /// The same program as [`BrainfuckToRust::template`], but the memory
+    /// size, EOF behavior and input source can be changed when it is run.
+    fn runtime_template(
+        &self,
+        body: TokenStream,
+        functions: Vec<TokenStream>,
+        input_usage: InputUsage,
+    ) -> TokenStream {
+        let mem_size = self.memory_size;
+        let cell_type = self.cell_type();
+
+        let eof = match self.eof_behavior {
+            EofBehavior::NoChange => quote! { None },
+            EofBehavior::Fixed(ch) if self.big_cells() => {
+                let value = self.cell_value(ch);
+                quote! { Some(#value) }
+            }
+            EofBehavior::Fixed(ch) => quote! { Some(#ch as #cell_type) },
+        };
+
+        let read_failed = self.input_failed("failed to read stdin");
+        let open_failed = self.input_failed("failed to open the input file");
+        let file_read_failed = self.input_failed("failed to read the input file");
+
+        // without `--input`, read the fixed input if there is one, or stdin
+        let default_input = if let Some(fixed) = self.fixed_input_literal() {
+            quote! { #fixed.to_vec() }
+        } else {
+            let stdin = self.stdin();
+            let stdin = match input_usage {
+                InputUsage::AtMost(reads) => {
+                    let reads = reads as u64;
+                    quote! { #stdin.take(#reads) }
+                }
+                _ => stdin,
+            };
+            quote! {
+                {
+                    use std::io::Read;
+
+                    let mut input = Vec::new();
+                    #stdin
+                        .read_to_end(&mut input)
+                        .#read_failed;
+                    input
+                }
+            }
+        };
+
+        let stdin = self.stdin();
+        let input_def = if self.lazy_input(input_usage) {
+            quote! {
+                let eof = config.eof;
+
+                let mut input = match &config.input {
+                    Some(Input::File(path)) => {
+                        let file = std::fs::File::open(path).#open_failed;
+                        LazyInput::new(Box::new(std::io::BufReader::new(file)))
+                    }
+                    Some(Input::Text(text)) => {
+                        LazyInput::new(Box::new(std::io::Cursor::new(text.clone().into_bytes())))
+                    }
+                    None => LazyInput::new(Box::new(#stdin)),
+                };
+
+                let mut input_pos = 0usize;
+            }
+        } else if self.fixed_input.is_some() || input_usage != InputUsage::None {
+            quote! {
+                let eof = config.eof;
+
+                let input = match &config.input {
+                    Some(Input::File(path)) => std::fs::read(path).#file_read_failed,
+                    Some(Input::Text(text)) => text.clone().into_bytes(),
+                    None => #default_input,
+                };
+
+                let mut input_pos = 0usize;
+            }
+        } else {
+            quote! {}
+        };
+
+        let io_shim = self.io_shim_def();
+        let output = self.output_def();
+        let lazy_input = if self.lazy_input(input_usage) {
+            self.lazy_input_def()
+        } else {
+            quote! {}
+        };
+
+        let mut usage = "\
+Options (each can also be set with the environment variable in brackets):
+  --memory-size <cells>      The number of cells on the tape [BF_MEMORY_SIZE]
+  --eof unchanged|<value>    What `,` stores at the end of the input [BF_EOF]
+  --input <file>             Read the input from this file instead of the default [BF_INPUT]"
+            .to_string();
+        let (input_file_arg, input_str_arm) = if self.input_args {
+            usage.push_str(
+                "
+  <input-file>               The same as `--input <input-file>`
+  --input-str <text>         Use this text as the input",
+            );
+            (
+                quote! {
+                    if !arg.starts_with('-') {
+                        settings.push(("--input".to_string(), arg));
+                        continue;
+                    }
+                },
+                quote! {
+                    "--input-str" => config.input = Some(Input::Text(value)),
+                },
+            )
+        } else {
+            (quote! {}, quote! {})
+        };
+
+        let zero = self.cell_zero();
+        let entry_point = self.entry_point(quote! {
+            let config = Config::load();
+            let mem_size = config.memory_size;
+
+            let mut pointer = 0usize;
+            let mut tape: Vec<#cell_type> = vec![#zero; mem_size];
+
+            #input_def
+
+            #body
+        });
+
+        quote! {
+            #[allow(unused_imports)]
+            use ascii::ToAsciiChar;
+
+            #io_shim
+
+            #output
+
+            #lazy_input
+
+            const USAGE: &str = #usage;
+
+            /// The settings that can be changed without regenerating the program
+            struct Config {
+                memory_size: usize,
+                eof: Option<#cell_type>,
+                input: Option<Input>,
+            }
+
+            /// Where the input comes from, instead of the default
+            #[allow(dead_code)]
+            enum Input {
+                File(String),
+                /// From `--input-str`, with `input_args`
+                Text(String),
+            }
+
+            impl Config {
+                /// Reads the environment, then lets the arguments override it.
+                fn load() -> Self {
+                    let mut config = Config {
+                        memory_size: #mem_size,
+                        eof: #eof,
+                        input: None,
+                    };
+
+                    let mut settings = Vec::new();
+                    for (name, var) in [
+                        ("--memory-size", "BF_MEMORY_SIZE"),
+                        ("--eof", "BF_EOF"),
+                        ("--input", "BF_INPUT"),
+                    ] {
+                        if let Ok(value) = std::env::var(var) {
+                            settings.push((name.to_string(), value));
+                        }
+                    }
+
+                    let mut args = std::env::args().skip(1);
+                    while let Some(arg) = args.next() {
+                        if arg == "-h" || arg == "--help" {
+                            println!("{}", USAGE);
+                            std::process::exit(0);
+                        }
+                        #input_file_arg
+                        match args.next() {
+                            Some(value) => settings.push((arg, value)),
+                            None => Self::fail(&format!("missing a value for `{}`", arg)),
+                        }
+                    }
+
+                    for (name, value) in settings {
+                        match name.as_str() {
+                            "--memory-size" => {
+                                config.memory_size = value
+                                    .parse()
+                                    .unwrap_or_else(|_| Self::fail("the memory size must be a number"));
+                            }
+                            "--eof" => {
+                                config.eof = if value == "unchanged" {
+                                    None
+                                } else {
+                                    Some(value.parse().unwrap_or_else(|_| {
+                                        Self::fail("the EOF value must be `unchanged` or a number")
+                                    }))
+                                };
+                            }
+                            "--input" => config.input = Some(Input::File(value)),
+                            #input_str_arm
+                            _ => Self::fail(&format!("unknown option `{}`", name)),
+                        }
+                    }
+
+                    config
+                }
+
+                fn fail(message: &str) -> ! {
+                    eprintln!("error: {}\n\n{}", message, USAGE);
+                    std::process::exit(2);
+                }
+            }
+
+            #(#functions)*
+
+            #entry_point
+        }
+    }
 }
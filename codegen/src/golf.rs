@@ -0,0 +1,295 @@
+use std::fmt;
+
+use crate::{
+    analysis::{analyze, Effects, DEFAULT_BUDGET},
+    ast::{to_code, File, Repeated, Segment, Token, TokenExt},
+    eval::TAPE_SIZE,
+    lint::{self, LintConfig, Rule, Severity},
+    synth::{synthesize_print_bytes, Strategy},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A way of making a program shorter without changing what it prints
+pub enum Transform {
+    /// Remove everything that is not an instruction
+    StripComments,
+    /// Remove loops that are never entered, cancelling `+-` pairs and
+    /// instructions at the end that change nothing, like `bf lint --fix`
+    RemoveDeadCode,
+    /// Replace each run of `+` and `-` (or `<` and `>`) by its net effect, going
+    /// the shorter way around for cell values
+    MergeRuns,
+    /// Replace long runs of `+` or `-` by a multiplication loop like
+    /// `>++++++[<+++++++>-]<`, where the cell next to them is known to be 0
+    MultiplyConstants,
+}
+
+impl Transform {
+    pub const ALL: [Transform; 4] = [
+        Transform::StripComments,
+        Transform::RemoveDeadCode,
+        Transform::MergeRuns,
+        Transform::MultiplyConstants,
+    ];
+
+    pub fn apply(&self, code: &str) -> String {
+        match self {
+            Transform::StripComments => code
+                .chars()
+                .filter(|c| Token::from_char(*c).is_some())
+                .collect(),
+            Transform::RemoveDeadCode => {
+                let config = LintConfig {
+                    overrides: Rule::ALL
+                        .iter()
+                        .map(|rule| (*rule, Severity::Note))
+                        .collect(),
+                };
+                lint::fix(code, &config)
+            }
+            Transform::MergeRuns => merge_runs(code),
+            Transform::MultiplyConstants => multiply_constants(code),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Transform::StripComments => "strip-comments",
+            Transform::RemoveDeadCode => "remove-dead-code",
+            Transform::MergeRuns => "merge-runs",
+            Transform::MultiplyConstants => "multiply-constants",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A program after one more transform was applied
+pub struct Step {
+    pub transform: Transform,
+    pub code: String,
+}
+
+/// Applies every transform in turn, each to the result of the one before.
+///
+/// Merging pointer runs assumes the program never moves left of the first
+/// cell or right of the last, where the pointer is clamped and `<>` is not a
+/// no-op.
+pub fn golf(code: &str) -> Vec<Step> {
+    let mut code = code.to_string();
+
+    Transform::ALL
+        .iter()
+        .map(|transform| {
+            code = transform.apply(&code);
+            Step {
+                transform: *transform,
+                code: code.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Finds a program that prints the same output as `code` with
+/// [`synthesize_print_bytes`], if the whole program can be evaluated without
+/// input (so it always prints the same thing and halts).
+pub fn search_print(code: &str) -> Option<String> {
+    let file: File<Repeated> = code.parse().ok()?;
+    let analysis = analyze(&file.segments, DEFAULT_BUDGET);
+    if analysis.concrete_until.is_some() {
+        return None;
+    }
+
+    Some(synthesize_print_bytes(
+        &analysis.known_output,
+        Strategy::Best,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where the temporary cell of a multiplication loop is
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// The shortest code that adds `delta` to the current cell, either as a plain
+/// run of `+` or `-` or as a multiplication loop using the cell on `side`,
+/// which must be 0 and is 0 again afterwards.
+pub fn add_constant(delta: u8, side: Side) -> String {
+    let (there, back) = match side {
+        Side::Left => ('<', '>'),
+        Side::Right => ('>', '<'),
+    };
+
+    let mut best = run(delta);
+
+    for times in 2..=32u8 {
+        for step in 2..=32u8 {
+            for op in ['+', '-'] {
+                let product = times.wrapping_mul(step);
+                let added = if op == '+' {
+                    product
+                } else {
+                    product.wrapping_neg()
+                };
+                let rest = run(delta.wrapping_sub(added));
+
+                // `>` times `+` `[<` step `op` `>-]<` and the rest
+                let length = 6 + times as usize + step as usize + rest.len();
+                if length < best.len() {
+                    best = format!(
+                        "{there}{}[{back}{}{there}-]{back}{rest}",
+                        "+".repeat(times as usize),
+                        op.to_string().repeat(step as usize),
+                    );
+                }
+            }
+        }
+    }
+
+    best
+}
+
+/// The shortest run of `+` or `-` that changes a cell by `delta`
+pub(crate) fn run(delta: u8) -> String {
+    if delta <= 128 {
+        "+".repeat(delta as usize)
+    } else {
+        "-".repeat(256 - delta as usize)
+    }
+}
+
+fn merge_runs(code: &str) -> String {
+    let mut merged = String::new();
+    let mut chars = code.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let (up, down) = match c {
+            '+' | '-' => ('+', '-'),
+            '<' | '>' => ('>', '<'),
+            _ => {
+                merged.push(c);
+                continue;
+            }
+        };
+
+        let mut net = if c == up { 1i64 } else { -1 };
+        while let Some(&next) = chars.peek() {
+            if next == up {
+                net += 1;
+            } else if next == down {
+                net -= 1;
+            } else {
+                break;
+            }
+            chars.next();
+        }
+
+        if up == '+' {
+            net = net.rem_euclid(256);
+            if net > 128 {
+                net -= 256;
+            }
+        }
+
+        let op = if net > 0 { up } else { down };
+        merged.extend(std::iter::repeat_n(op, net.unsigned_abs() as usize));
+    }
+
+    merged
+}
+
+/// Rewrites runs of `+` and `-` at the top level of the program, where it is
+/// simple to track which cells are known to be 0.
+fn multiply_constants(code: &str) -> String {
+    let mut depth = 0i64;
+    for c in code.chars() {
+        depth += match c {
+            '[' => 1,
+            ']' => -1,
+            _ => 0,
+        };
+        if depth < 0 {
+            // everything after an unmatched `]` would be lost when parsing
+            return code.to_string();
+        }
+    }
+
+    let file: File<Repeated> = match code.parse() {
+        Ok(file) => file,
+        Err(e) => match e {},
+    };
+
+    let mut tape: Vec<Option<u8>> = vec![Some(0); TAPE_SIZE];
+    let mut pointer = 0usize;
+    let mut out = String::new();
+
+    for (i, segment) in file.segments.iter().enumerate() {
+        match segment {
+            Segment::Executable { code, .. } => {
+                for token in &code.tokens {
+                    let count = token.count();
+                    match token.token() {
+                        Token::ValueAdd | Token::ValueSub => {
+                            let delta = if token.token() == Token::ValueAdd {
+                                count as u8
+                            } else {
+                                (count as u8).wrapping_neg()
+                            };
+
+                            let side = if pointer + 1 < TAPE_SIZE && tape[pointer + 1] == Some(0) {
+                                Some(Side::Right)
+                            } else if pointer > 0 && tape[pointer - 1] == Some(0) {
+                                Some(Side::Left)
+                            } else {
+                                None
+                            };
+
+                            match side {
+                                Some(side) => out.push_str(&add_constant(delta, side)),
+                                None => {
+                                    out.extend(std::iter::repeat_n(token.token().as_char(), count))
+                                }
+                            }
+
+                            tape[pointer] = tape[pointer].map(|value| value.wrapping_add(delta));
+                            continue;
+                        }
+                        Token::PointerAdd => pointer = (pointer + count).min(TAPE_SIZE - 1),
+                        Token::PointerSub => pointer = pointer.saturating_sub(count),
+                        Token::Read | Token::Multiply | Token::Divide => tape[pointer] = None,
+                        Token::Write | Token::LoopStart | Token::LoopEnd => {}
+                    }
+
+                    out.extend(std::iter::repeat_n(token.token().as_char(), count));
+                }
+            }
+            Segment::Loop { body, .. } => {
+                out.push_str(&to_code(std::slice::from_ref(segment)));
+
+                let mut effects = Effects::default();
+                let mut offset = 0;
+                let balanced = effects.collect(body, &mut offset) && offset == 0;
+                let lowest = pointer as isize + effects.lowest;
+                let highest = pointer as isize + effects.highest;
+
+                if !balanced || lowest < 0 || highest >= TAPE_SIZE as isize {
+                    // the pointer is lost, so nothing more is known about the tape
+                    out.push_str(&to_code(&file.segments[i + 1..]));
+                    break;
+                }
+
+                for offset in &effects.touched {
+                    tape[(pointer as isize + offset) as usize] = None;
+                }
+                tape[pointer] = Some(0);
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+use crate::{
+    analysis::{analyze, Effects},
+    ast::{Segment, Span, TokenExt},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a loop looks like it never terminates
+pub enum NonTermination {
+    /// The loop's cell is known to be nonzero when it is reached, and the body
+    /// never changes it
+    Certain,
+    /// The body never changes the loop's cell and returns the pointer to it,
+    /// so the loop never ends once it is entered
+    IfEntered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A loop that probably runs forever
+pub struct HaltingWarning {
+    pub span: Span,
+    pub kind: NonTermination,
+}
+
+impl NonTermination {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            NonTermination::Certain => {
+                "infinite loop: its cell is nonzero when it is reached and the body never changes it"
+            }
+            NonTermination::IfEntered => {
+                "loop never ends once entered: the body always returns to its cell without changing it"
+            }
+        }
+    }
+}
+
+impl fmt::Display for HaltingWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {} column {}: {}",
+            self.span.line,
+            self.span.column,
+            self.kind.reason()
+        )
+    }
+}
+
+/// Finds loops that obviously never terminate, like `+[]` or `+[>+<]`, using
+/// [`analyze`] (with this step budget) to tell which of them are certainly
+/// entered and to leave out the ones that never are.
+pub fn check<T: TokenExt>(segments: &[Segment<T>], budget: u64) -> Vec<HaltingWarning> {
+    let analysis = analyze(segments, budget);
+
+    let mut warnings = Vec::new();
+    find_stuck_loops(segments, &mut |span| {
+        if analysis.dead_loops.contains(&span) {
+            return;
+        }
+
+        let kind = if analysis.entered_loops.contains(&span) {
+            NonTermination::Certain
+        } else {
+            NonTermination::IfEntered
+        };
+
+        warnings.push(HaltingWarning { span, kind });
+    });
+
+    warnings
+}
+
+/// Calls `found` with every loop whose body leaves its cell and the pointer unchanged.
+fn find_stuck_loops<T: TokenExt>(segments: &[Segment<T>], found: &mut impl FnMut(Span)) {
+    for segment in segments {
+        if let Segment::Loop { body, span } = segment {
+            let mut effects = Effects::default();
+            let mut offset = 0;
+
+            if effects.collect(body, &mut offset) && offset == 0 && !effects.touched.contains(&0) {
+                found(*span);
+            }
+
+            find_stuck_loops(body, found);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! A stable JSON form of the optimized program, for backends outside of this
+//! crate. A program looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "needs_input": false,
+//!   "ops": [
+//!     { "op": "add", "amount": 8, "line": 1, "column": 1 },
+//!     { "op": "loop", "line": 1, "column": 9, "body": [
+//!       { "op": "move", "offset": 1, "line": 1, "column": 10 },
+//!       { "op": "add", "amount": 9, "line": 1, "column": 11 },
+//!       { "op": "move", "offset": -1, "line": 1, "column": 20 },
+//!       { "op": "add", "amount": -1, "line": 1, "column": 21 }
+//!     ] },
+//!     { "op": "move", "offset": 1, "line": 1, "column": 23 },
+//!     { "op": "write", "count": 1, "line": 1, "column": 24 }
+//!   ]
+//! }
+//! ```
+//!
+//! Every op has the `line` and `column` of its first instruction in the
+//! source, and one of these kinds:
+//!
+//! - `add`: Add `amount` (never 0) to the current cell, wrapping around. A
+//!   run of `+` is positive, a run of `-` negative
+//! - `move`: Move the pointer by `offset` (never 0) cells, positive to the right
+//! - `write`: Write the current cell `count` times
+//! - `read`: Read one value into the current cell
+//! - `loop`: Run `body` while the current cell is not 0
+//! - `multiply` / `divide`: Multiply or divide the current cell by the next
+//!   one, only in the experimental `float-ext` dialect
+//!
+//! `dialect` is `"standard"` (plain Brainfuck) or `"float-ext"`, whose cells
+//! are floating-point numbers for backends that can do that.
+//!
+//! `pragmas` holds the semantics the program asks for with `@pragma`
+//! directives (see [`crate::pragma`]), like
+//! `{ "wrap_pointer": true, "cell_size": 16, "eof": 0 }`, where `false` and
+//! `null` leave it to the backend. `cell_size` is `"bigint"` for cells that
+//! never overflow.
+//!
+//! `version` only changes when existing programs would be read differently,
+//! so new fields can be added without changing it.
+
+use crate::{
+    ast::{Dialect, File, Segment, Token, TokenExt},
+    pragma::Pragmas,
+};
+
+/// The version of the format written by [`Program::from_file`]
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Program {
+    pub version: u32,
+    /// Whether the program contains a `read` op
+    pub needs_input: bool,
+    pub ops: Vec<Node>,
+    #[serde(default)]
+    pub pragmas: Pragmas,
+    #[serde(default)]
+    pub dialect: Dialect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// An op and where it came from in the source
+pub struct Node {
+    #[serde(flatten)]
+    pub op: Op,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    Add { amount: i64 },
+    Move { offset: i64 },
+    Write { count: usize },
+    Read,
+    Loop { body: Vec<Node> },
+    Multiply,
+    Divide,
+}
+
+impl Program {
+    pub fn from_file<T: TokenExt>(file: &File<T>) -> Self {
+        Self {
+            version: VERSION,
+            needs_input: file.needs_input,
+            ops: to_nodes(&file.segments),
+            pragmas: file.pragmas,
+            dialect: file.dialect,
+        }
+    }
+}
+
+fn to_nodes<T: TokenExt>(segments: &[Segment<T>]) -> Vec<Node> {
+    let mut nodes = Vec::new();
+
+    for segment in segments {
+        match segment {
+            Segment::Executable { code, .. } => {
+                for (token, span) in code.tokens.iter().zip(&code.spans) {
+                    let count = token.count();
+                    let op = match token.token() {
+                        Token::ValueAdd => Op::Add {
+                            amount: count as i64,
+                        },
+                        Token::ValueSub => Op::Add {
+                            amount: -(count as i64),
+                        },
+                        Token::PointerAdd => Op::Move {
+                            offset: count as i64,
+                        },
+                        Token::PointerSub => Op::Move {
+                            offset: -(count as i64),
+                        },
+                        Token::Write => Op::Write { count },
+                        // the tokenizer never merges reads
+                        Token::Read => Op::Read,
+                        Token::Multiply => Op::Multiply,
+                        Token::Divide => Op::Divide,
+                        Token::LoopStart | Token::LoopEnd => continue,
+                    };
+
+                    nodes.push(Node {
+                        op,
+                        line: span.line,
+                        column: span.column,
+                    });
+                }
+            }
+            Segment::Loop { body, span } => nodes.push(Node {
+                op: Op::Loop {
+                    body: to_nodes(body),
+                },
+                line: span.line,
+                column: span.column,
+            }),
+        }
+    }
+
+    nodes
+}
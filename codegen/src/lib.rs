@@ -0,0 +1,25 @@
+//! The parts of the code generator that other tools can build on: the AST, an
+//! evaluator for it and tools built on top of those.
+
+#[macro_use]
+extern crate tracing;
+#[macro_use]
+extern crate serde;
+
+pub mod analysis;
+pub mod ast;
+pub mod equiv;
+pub mod eval;
+pub mod golf;
+pub mod halting;
+pub mod ir;
+pub mod link;
+pub mod lint;
+pub mod mutate;
+pub mod pragma;
+pub mod random;
+pub mod regions;
+pub mod rng;
+pub mod source_map;
+pub mod synth;
+pub mod verbosity;
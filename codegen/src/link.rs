@@ -0,0 +1,202 @@
+//! Links Brainfuck programs into one, so code can be reused between programs
+//! like a library. Each module runs on its own region of the tape: it is
+//! written as if it started on cell 0, and the linker moves the pointer to the
+//! cell its region starts at before it. Each module starts with an `@region`
+//! comment (see [`crate::regions`]), so overlapping modules can be found.
+
+use thiserror::Error;
+
+use crate::{
+    ast::{File, Repeated, Segment, Span, Token, TokenExt},
+    regions::footprint,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A program to link, and where its region of the tape starts
+pub struct Module {
+    /// What the module is called in the linked program's comments and in errors
+    pub name: String,
+    pub code: String,
+    pub offset: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error(
+        "{module}: the loop on line {} moves the pointer by an amount only known at runtime, so it cannot be moved back to cell 0",
+        .span.line
+    )]
+    UnknownEnd { module: String, span: Span },
+}
+
+/// How far the pointer ends up from where it started, or the first loop that
+/// moves it by a runtime-dependent amount.
+pub fn net_movement<T: TokenExt>(segments: &[Segment<T>]) -> Result<isize, Span> {
+    let mut offset = 0;
+
+    for segment in segments {
+        match segment {
+            Segment::Executable { code, .. } => {
+                for token in &code.tokens {
+                    match token.token() {
+                        Token::PointerAdd => offset += token.count() as isize,
+                        Token::PointerSub => offset -= token.count() as isize,
+                        _ => {}
+                    }
+                }
+            }
+            Segment::Loop { body, span } => {
+                if net_movement(body)? != 0 {
+                    return Err(*span);
+                }
+            }
+        }
+    }
+
+    Ok(offset)
+}
+
+/// The instructions that move the pointer by `offset`.
+fn moves(offset: isize) -> String {
+    let c = if offset < 0 { '<' } else { '>' };
+    std::iter::repeat_n(c, offset.unsigned_abs()).collect()
+}
+
+/// Makes text safe to put in a comment, by replacing its instructions.
+fn comment(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if Token::from_char(c).is_some() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Concatenates the modules, moving the pointer to the start of each one's
+/// region before it. Without `glue`, a module's region starts `offset` cells
+/// to the right of wherever the one before it left the pointer. With it, the
+/// pointer is moved back to cell 0 after each module, so the offsets are
+/// cells, which only works for modules that end on a known cell.
+pub fn link(modules: &[Module], glue: bool) -> Result<String, LinkError> {
+    let mut linked = String::new();
+
+    for (i, module) in modules.iter().enumerate() {
+        let file: File<Repeated> = match module.code.parse() {
+            Ok(file) => file,
+            Err(e) => match e {},
+        };
+
+        // name the module's code, and with absolute offsets the cells it touches
+        let name = comment(&module.name).replace(char::is_whitespace, "_");
+        let cells = footprint(&file.segments)
+            .filter(|_| glue)
+            .map(|(lowest, highest)| {
+                let offset = module.offset as isize;
+                format!(" {}:{}", offset + lowest, offset + highest + 1)
+            })
+            .unwrap_or_default();
+        let placed = if glue {
+            format!("module at cell {}", module.offset)
+        } else {
+            format!(
+                "module starting {} cells right of where the previous one ends",
+                module.offset
+            )
+        };
+        linked.push_str(&format!("@region {}{} ({})\n", name, cells, placed));
+        if module.offset > 0 {
+            linked.push_str(&moves(module.offset as isize));
+            linked.push('\n');
+        }
+
+        linked.push_str(module.code.trim_end());
+        linked.push('\n');
+
+        if glue && i + 1 < modules.len() {
+            let net = net_movement(&file.segments).map_err(|span| LinkError::UnknownEnd {
+                module: module.name.clone(),
+                span,
+            })?;
+
+            let back = moves(-(module.offset as isize + net));
+            if !back.is_empty() {
+                linked.push_str(&format!("back to cell 0\n{}\n", back));
+            }
+        }
+    }
+
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regions::check;
+
+    fn module(name: &str, code: &str, offset: usize) -> Module {
+        Module {
+            name: name.to_string(),
+            code: code.to_string(),
+            offset,
+        }
+    }
+
+    #[test]
+    fn glued_modules_start_on_their_cell_and_go_back_to_cell_0() {
+        let modules = [module("input buffer", ",>,", 2), module("print", ".", 0)];
+        assert_eq!(
+            link(&modules, true).unwrap(),
+            "@region input_buffer 2:4 (module at cell 2)\n>>\n,>,\n\
+             back to cell 0\n<<<\n\
+             @region print 0:1 (module at cell 0)\n.\n"
+        );
+    }
+
+    #[test]
+    fn without_glue_a_module_starts_right_of_the_one_before() {
+        let modules = [module("a", "+>", 0), module("b", "-", 3)];
+        assert_eq!(
+            link(&modules, false).unwrap(),
+            "@region a (module starting 0 cells right of where the previous one ends)\n+>\n\
+             @region b (module starting 3 cells right of where the previous one ends)\n>>>\n-\n"
+        );
+    }
+
+    #[test]
+    fn the_regions_of_glued_modules_hold_the_cells_they_touch() {
+        let modules = [
+            module("a", "+[->+<]", 0),
+            module("b", "++>++<", 2),
+            module("c", ">>-", 4),
+        ];
+        let linked = link(&modules, true).unwrap();
+        let file: File<Repeated> = match linked.parse() {
+            Ok(file) => file,
+            Err(e) => match e {},
+        };
+
+        let report = check(&linked, &file.segments);
+        assert_eq!(report.warnings, []);
+        let touched: Vec<Vec<isize>> = report
+            .regions
+            .iter()
+            .map(|usage| usage.touched.keys().copied().collect())
+            .collect();
+        assert_eq!(touched, [vec![0, 1], vec![2, 3], vec![6]]);
+    }
+
+    #[test]
+    fn a_module_ending_on_an_unknown_cell_can_only_be_glued_last() {
+        let modules = [module("scan", "+[>]", 0), module("after", "+", 5)];
+        assert!(matches!(
+            link(&modules, true),
+            Err(LinkError::UnknownEnd { module, span }) if module == "scan" && span.line == 1
+        ));
+
+        assert!(link(&modules[..1], true).is_ok());
+        assert!(link(&modules, false).is_ok());
+    }
+}
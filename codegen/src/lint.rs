@@ -0,0 +1,457 @@
+use std::{collections::BTreeSet, fmt};
+
+use clap::ValueEnum;
+
+use crate::{
+    analysis::{analyze, DEFAULT_BUDGET},
+    ast::{File, Repeated, Segment, Span, Token, TokenExt},
+    halting,
+    regions::{self, RegionProblem},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+/// Something the linter checks for
+pub enum Rule {
+    /// Neighboring instructions that cancel out, like `+-` or `<>`
+    RedundantOpPairs,
+    /// A loop right after another loop, which is never entered because the
+    /// cell is 0 once the first loop (usually a clear loop like `[-]`) ends
+    UnreachableAfterClearLoop,
+    /// A loop whose cell is always 0 when it is reached
+    LoopNeverEntered,
+    /// A bracket in the middle of what looks like comment text, e.g. `see [1]`,
+    /// which is really a loop
+    SuspiciousUnmatchedLookingCommentBrackets,
+    /// A `[` without a `]` or the other way around
+    UnmatchedBracket,
+    /// A loop that obviously never terminates, see [`halting::check`]
+    NonTerminatingLoop,
+    /// Instructions at the end of the program that are not followed by any
+    /// output or input, so they change nothing
+    TrailingDeadCode,
+    /// Code touching a cell outside the cells its `@region` declared, see
+    /// [`regions::check`]
+    OutOfRegion,
+    /// Two `@region`s declaring or touching the same cells
+    RegionOverlap,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 9] = [
+        Rule::RedundantOpPairs,
+        Rule::UnreachableAfterClearLoop,
+        Rule::LoopNeverEntered,
+        Rule::SuspiciousUnmatchedLookingCommentBrackets,
+        Rule::UnmatchedBracket,
+        Rule::NonTerminatingLoop,
+        Rule::TrailingDeadCode,
+        Rule::OutOfRegion,
+        Rule::RegionOverlap,
+    ];
+
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Rule::RedundantOpPairs => Severity::Note,
+            Rule::UnreachableAfterClearLoop | Rule::LoopNeverEntered => Severity::Warning,
+            Rule::SuspiciousUnmatchedLookingCommentBrackets => Severity::Warning,
+            Rule::UnmatchedBracket => Severity::Error,
+            Rule::NonTerminatingLoop => Severity::Warning,
+            Rule::TrailingDeadCode => Severity::Note,
+            Rule::OutOfRegion | Rule::RegionOverlap => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+/// How serious a diagnostic is
+pub enum Severity {
+    /// Do not report the rule at all
+    Off,
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Off => "off",
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// The severity of each rule, where rules without an override use their default
+pub struct LintConfig {
+    pub overrides: Vec<(Rule, Severity)>,
+}
+
+impl LintConfig {
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|(overridden, _)| *overridden == rule)
+            .map(|(_, severity)| *severity)
+            .unwrap_or_else(|| rule.default_severity())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A problem found by the linter
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+    /// The instructions to delete to fix the problem, or empty if there is no
+    /// safe fix. Only instructions are deleted, so comments stay intact
+    pub fix: Vec<Span>,
+}
+
+/// Checks a program's source code against every rule that is not turned off.
+pub fn lint(code: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let tokens = Token::tokenize(code);
+    let file: File<Repeated> = match code.parse() {
+        Ok(file) => file,
+        Err(e) => match e {},
+    };
+
+    let mut found = Vec::new();
+
+    redundant_op_pairs(&tokens.tokens, &tokens.spans, &mut found);
+    let after_loops = loops_after_loops(&file.segments, &mut found);
+    comment_brackets(code, &tokens.tokens, &tokens.spans, &mut found);
+    let balanced = unmatched_brackets(&tokens.tokens, &tokens.spans, &mut found);
+    if balanced {
+        trailing_dead_code(&file.segments, &mut found);
+    }
+
+    // a loop at the start that holds text is the conventional comment loop
+    let leading_comment = match file.segments.first() {
+        Some(Segment::Loop { span, .. }) => code[span.start..span.end]
+            .chars()
+            .any(|c| !c.is_whitespace() && Token::from_char(c).is_none())
+            .then_some(*span),
+        _ => None,
+    };
+    let dead_loops = analyze(&file.segments, DEFAULT_BUDGET).dead_loops;
+    for span in dead_loops.iter().copied() {
+        // covered by the rule above
+        if Some(span) == leading_comment
+            || (after_loops.contains(&span)
+                && config.severity(Rule::UnreachableAfterClearLoop) != Severity::Off)
+        {
+            continue;
+        }
+
+        found.push((
+            Rule::LoopNeverEntered,
+            span,
+            "this loop is never entered, its cell is always 0 when it is reached".to_string(),
+        ));
+    }
+
+    for warning in halting::check(&file.segments, DEFAULT_BUDGET) {
+        found.push((
+            Rule::NonTerminatingLoop,
+            warning.span,
+            warning.kind.reason().to_string(),
+        ));
+    }
+
+    for warning in regions::check(code, &file.segments).warnings {
+        let rule = match warning.problem {
+            RegionProblem::OutOfRange { .. } => Rule::OutOfRegion,
+            RegionProblem::DeclaredOverlap { .. } | RegionProblem::SharedCell { .. } => {
+                Rule::RegionOverlap
+            }
+        };
+        found.push((rule, warning.span, warning.problem.to_string()));
+    }
+
+    // code in a loop that never runs is a comment, so only its brackets matter
+    let commented_out = |span: &Span| {
+        dead_loops
+            .iter()
+            .chain(&after_loops)
+            .any(|dead| dead.start <= span.start && span.end <= dead.end)
+    };
+    found.retain(|(rule, span, _)| {
+        matches!(
+            rule,
+            Rule::UnmatchedBracket | Rule::LoopNeverEntered | Rule::UnreachableAfterClearLoop
+        ) || !commented_out(span)
+    });
+
+    let mut diagnostics: Vec<_> = found
+        .into_iter()
+        .map(|(rule, span, message)| {
+            let fixable = match rule {
+                // `<` on the first cell does nothing, so `<>` there moves the pointer
+                Rule::RedundantOpPairs => matches!(&code[span.start..span.end][..1], "+" | "-"),
+                Rule::UnreachableAfterClearLoop | Rule::LoopNeverEntered => balanced,
+                Rule::TrailingDeadCode => true,
+                _ => false,
+            };
+
+            let fix = if fixable {
+                tokens
+                    .spans
+                    .iter()
+                    .filter(|token| span.start <= token.start && token.end <= span.end)
+                    .copied()
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            Diagnostic {
+                rule,
+                severity: config.severity(rule),
+                span,
+                message,
+                fix,
+            }
+        })
+        .filter(|diagnostic| diagnostic.severity != Severity::Off)
+        .collect();
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.start, diagnostic.rule));
+
+    diagnostics
+}
+
+/// Applies the fixes of every diagnostic that is not turned off, repeating
+/// until none are left since a fix can make another one possible (removing
+/// the dead loop in `+[-][]-` leaves `+-`). Fixes only delete instructions and
+/// never newlines, so every line of the result matches a line of `code`.
+pub fn fix(code: &str, config: &LintConfig) -> String {
+    let mut code = code.to_string();
+
+    loop {
+        let deleted: BTreeSet<usize> = lint(&code, config)
+            .into_iter()
+            .flat_map(|diagnostic| diagnostic.fix)
+            .map(|span| span.start)
+            .collect();
+
+        if deleted.is_empty() {
+            return code;
+        }
+
+        code = code
+            .char_indices()
+            .filter(|(offset, _)| !deleted.contains(offset))
+            .map(|(_, c)| c)
+            .collect();
+    }
+}
+
+/// Formats the changes [`fix`] made as a unified diff with three lines of context.
+pub fn diff(path: &str, before: &str, after: &str) -> String {
+    const CONTEXT: usize = 3;
+
+    let old: Vec<_> = before.split('\n').collect();
+    let new: Vec<_> = after.split('\n').collect();
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+
+    let mut out = String::new();
+    if changed.is_empty() {
+        return out;
+    }
+
+    out.push_str(&format!("--- {}\n+++ {}\n", path, path));
+
+    let mut i = 0;
+    while i < changed.len() {
+        // extend the hunk while the next change is close enough to share context
+        let mut j = i;
+        while j + 1 < changed.len() && changed[j + 1] - changed[j] <= 2 * CONTEXT {
+            j += 1;
+        }
+
+        let start = changed[i].saturating_sub(CONTEXT);
+        let end = (changed[j] + CONTEXT + 1).min(old.len());
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start + 1,
+            end - start,
+            start + 1,
+            end - start
+        ));
+
+        for line in start..end {
+            if old[line] == new[line] {
+                out.push_str(&format!(" {}\n", old[line]));
+            } else {
+                out.push_str(&format!("-{}\n", old[line]));
+                out.push_str(&format!("+{}\n", new[line]));
+            }
+        }
+
+        i = j + 1;
+    }
+
+    out
+}
+
+type Found = Vec<(Rule, Span, String)>;
+
+fn redundant_op_pairs(tokens: &[Token], spans: &[Span], found: &mut Found) {
+    let mut i = 0;
+
+    while i + 1 < tokens.len() {
+        let cancels = matches!(
+            (tokens[i], tokens[i + 1]),
+            (Token::ValueAdd, Token::ValueSub)
+                | (Token::ValueSub, Token::ValueAdd)
+                | (Token::PointerAdd, Token::PointerSub)
+                | (Token::PointerSub, Token::PointerAdd)
+        );
+
+        if cancels {
+            found.push((
+                Rule::RedundantOpPairs,
+                spans[i].to(spans[i + 1]),
+                format!(
+                    "`{}{}` cancels out",
+                    tokens[i].as_char(),
+                    tokens[i + 1].as_char()
+                ),
+            ));
+            // `+-+` is one redundant pair, not two
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Reports loops that directly follow another loop, and returns their spans.
+fn loops_after_loops<T: TokenExt>(segments: &[Segment<T>], found: &mut Found) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut previous_loop = false;
+
+    for segment in segments {
+        match segment {
+            Segment::Executable { .. } => previous_loop = false,
+            Segment::Loop { body, span } => {
+                if previous_loop {
+                    found.push((
+                        Rule::UnreachableAfterClearLoop,
+                        *span,
+                        "this loop is never entered, the cell is always 0 after the loop before it"
+                            .to_string(),
+                    ));
+                    spans.push(*span);
+                }
+
+                spans.extend(loops_after_loops(body, found));
+                previous_loop = true;
+            }
+        }
+    }
+
+    spans
+}
+
+fn comment_brackets(code: &str, tokens: &[Token], spans: &[Span], found: &mut Found) {
+    // the closest character on the same line that is not whitespace
+    let neighbor = |mut chars: Box<dyn Iterator<Item = char> + '_>| {
+        chars
+            .find(|c| !c.is_whitespace() || *c == '\n')
+            .filter(|c| *c != '\n')
+    };
+
+    for (token, span) in tokens.iter().zip(spans) {
+        if !matches!(token, Token::LoopStart | Token::LoopEnd) {
+            continue;
+        }
+
+        let before = neighbor(Box::new(code[..span.start].chars().rev()));
+        let after = neighbor(Box::new(code[span.end..].chars()));
+
+        let wordy = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric());
+        let suspicious = match token {
+            // `see [1` or `array[i`
+            Token::LoopStart => wordy(after) && (wordy(before) || before.is_none()),
+            // `1] and` or `i]`
+            _ => wordy(before) && (wordy(after) || after.is_none()),
+        };
+
+        if suspicious {
+            found.push((
+                Rule::SuspiciousUnmatchedLookingCommentBrackets,
+                *span,
+                format!(
+                    "`{}` looks like part of a comment, but it is an instruction",
+                    token.as_char()
+                ),
+            ));
+        }
+    }
+}
+
+/// Reports unmatched brackets, and returns whether there were none.
+fn unmatched_brackets(tokens: &[Token], spans: &[Span], found: &mut Found) -> bool {
+    let mut open = Vec::new();
+
+    for (token, span) in tokens.iter().zip(spans) {
+        match token {
+            Token::LoopStart => open.push(*span),
+            Token::LoopEnd if open.pop().is_none() => {
+                found.push((
+                    Rule::UnmatchedBracket,
+                    *span,
+                    "this `]` has no matching `[`".to_string(),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let balanced = open.is_empty() && !found.iter().any(|f| f.0 == Rule::UnmatchedBracket);
+
+    for span in open {
+        found.push((
+            Rule::UnmatchedBracket,
+            span,
+            "this `[` has no matching `]`".to_string(),
+        ));
+    }
+
+    balanced
+}
+
+fn trailing_dead_code<T: TokenExt>(segments: &[Segment<T>], found: &mut Found) {
+    let Some(Segment::Executable { code, .. }) = segments.last() else {
+        return;
+    };
+
+    let last_io = code
+        .tokens
+        .iter()
+        .rposition(|token| matches!(token.token(), Token::Read | Token::Write));
+    let dead = &code.spans[last_io.map_or(0, |i| i + 1)..];
+
+    if let (Some(first), Some(last)) = (dead.first(), dead.last()) {
+        found.push((
+            Rule::TrailingDeadCode,
+            first.to(*last),
+            "these instructions change nothing, the program ends without printing or reading after them"
+                .to_string(),
+        ));
+    }
+}
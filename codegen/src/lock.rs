@@ -0,0 +1,314 @@
+//! `bfgen.lock`, which a generated crate keeps to remember what it was
+//! generated from and what the generated files looked like. Before writing a
+//! crate, [`check`] makes sure the output directory is one that can be
+//! overwritten without losing anything: a new or empty directory, or a crate
+//! generated from the same source whose files nobody changed since. Files
+//! matching a `--keep` glob (see [`is_kept`]) are left to whoever changed them.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Result};
+
+/// The name of the lock file in a generated crate
+pub const LOCK_FILE: &str = "bfgen.lock";
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The FNV-1a hash of some bytes, as hexadecimal.
+fn hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    });
+    format!("{:016x}", hash)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// What a crate was generated from, and the files it was generated with
+pub struct Lock {
+    /// The name of the Brainfuck source file
+    pub source: String,
+    pub source_hash: String,
+    /// The arguments the code generator was run with
+    pub args: Vec<String>,
+    /// The hash of every generated file, by its path in the crate
+    pub files: BTreeMap<String, String>,
+}
+
+impl Lock {
+    /// The lock for a crate generated from `source` with `args`, whose
+    /// generated files are `files` (relative to `output`).
+    pub fn new(
+        output: &Path,
+        source: &str,
+        in_code: &str,
+        args: Vec<String>,
+        files: &[PathBuf],
+    ) -> Result<Self> {
+        let files = files
+            .iter()
+            .map(|file| {
+                let path = file.to_string_lossy().replace('\\', "/");
+                Ok((path, hash(&fs::read(output.join(file))?)))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            source: source.to_string(),
+            source_hash: hash(in_code.as_bytes()),
+            args,
+            files,
+        })
+    }
+
+    pub fn write(&self, output: &Path) -> Result<()> {
+        fs::write(
+            output.join(LOCK_FILE),
+            serde_json::to_string_pretty(self)? + "\n",
+        )?;
+        Ok(())
+    }
+
+    /// Keeps the hashes `previous` had for the files matching `keep`, which
+    /// were not written this time.
+    pub fn keep(&mut self, previous: &Lock, keep: &[String]) {
+        for (file, expected) in &previous.files {
+            if is_kept(keep, file) {
+                self.files
+                    .entry(file.clone())
+                    .or_insert_with(|| expected.clone());
+            }
+        }
+    }
+}
+
+/// Whether `glob` matches all of `path`. `?` matches any character and `*`
+/// any number of them, but not `/`, while `**` matches across directories.
+fn matches(glob: &[u8], path: &[u8]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => (0..=path.len())
+            .filter(|&i| i == 0 || path[i - 1] == b'/')
+            .any(|i| matches(rest, &path[i..])),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != b'/')
+            .any(|i| matches(rest, &path[i..])),
+        [b'?', rest @ ..] => path.first().is_some_and(|&c| c != b'/') && matches(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && matches(rest, &path[1..]),
+    }
+}
+
+/// Whether the file at `path` in a crate matches one of the `--keep` globs,
+/// itself or through one of the directories it is in.
+pub fn is_kept(keep: &[String], path: &str) -> bool {
+    let path = path.replace('\\', "/");
+    keep.iter().any(|glob| {
+        let glob = glob.trim_end_matches('/').as_bytes();
+        matches(glob, path.as_bytes())
+            || path
+                .match_indices('/')
+                .any(|(end, _)| matches(glob, &path.as_bytes()[..end]))
+    })
+}
+
+/// The lock of the crate in `output`, if it has one.
+pub fn read(output: &Path) -> Result<Option<Lock>> {
+    let path = output.join(LOCK_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    serde_json::from_str(&fs::read_to_string(&path)?)
+        .map(Some)
+        .map_err(|e| eyre!("failed to read {:?}: {}", path, e))
+}
+
+/// Makes sure that generating a crate from `source` into `output` does not
+/// overwrite anything that was not generated, or was changed by hand since
+/// without matching `keep`. Returns the lock of the crate already there.
+pub fn check(output: &Path, source: &str, keep: &[String]) -> Result<Option<Lock>> {
+    if !output.exists() || fs::read_dir(output)?.next().is_none() {
+        return Ok(None);
+    }
+
+    let Some(lock) = read(output)? else {
+        return Err(eyre!(
+            "{:?} is not empty and has no {}, so it was not generated from Brainfuck; pass \
+             `--force` to overwrite it",
+            output,
+            LOCK_FILE
+        ));
+    };
+
+    if lock.source != source {
+        return Err(eyre!(
+            "{:?} was generated from `{}`, not `{}`; pass `--force` to overwrite it",
+            output,
+            lock.source,
+            source
+        ));
+    }
+
+    // files that are gone have nothing left to lose
+    let changed: Vec<&str> = lock
+        .files
+        .iter()
+        .filter(|(file, expected)| {
+            !is_kept(keep, file)
+                && fs::read(output.join(file)).is_ok_and(|bytes| hash(&bytes) != **expected)
+        })
+        .map(|(file, _)| file.as_str())
+        .collect();
+    if !changed.is_empty() {
+        return Err(eyre!(
+            "{} in {:?} changed since {} generated; pass `--force` to overwrite {}",
+            changed.join(", "),
+            output,
+            if changed.len() == 1 {
+                "it was"
+            } else {
+                "they were"
+            },
+            if changed.len() == 1 { "it" } else { "them" }
+        ));
+    }
+
+    Ok(Some(lock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for `test`.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bfgen-lock-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A crate in `dir` as if generated from `hello.b`.
+    fn generated(dir: &Path) {
+        fs::write(dir.join("hello.b"), "+.").unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]").unwrap();
+        let files = [PathBuf::from("hello.b"), PathBuf::from("Cargo.toml")];
+        Lock::new(dir, "hello.b", "+.", Vec::new(), &files)
+            .unwrap()
+            .write(dir)
+            .unwrap();
+    }
+
+    #[test]
+    fn a_new_or_empty_directory_can_be_written() {
+        let dir = scratch("empty");
+        assert!(check(&dir, "hello.b", &[]).unwrap().is_none());
+        assert!(check(&dir.join("new"), "hello.b", &[]).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_directory_that_was_not_generated_is_refused() {
+        let dir = scratch("not-generated");
+        fs::write(dir.join("notes.txt"), "mine").unwrap();
+        let error = check(&dir, "hello.b", &[]).unwrap_err().to_string();
+        assert!(error.contains("has no bfgen.lock"), "{}", error);
+        assert!(error.contains("--force"), "{}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_crate_of_another_source_is_refused() {
+        let dir = scratch("other-source");
+        generated(&dir);
+        let error = check(&dir, "other.b", &[]).unwrap_err().to_string();
+        assert!(
+            error.contains("generated from `hello.b`, not `other.b`"),
+            "{}",
+            error
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_crate_with_changed_files_is_refused() {
+        let dir = scratch("changed");
+        generated(&dir);
+        assert_eq!(
+            check(&dir, "hello.b", &[]).unwrap().unwrap().source,
+            "hello.b"
+        );
+
+        // a deleted file has nothing to lose, a changed one does
+        fs::remove_file(dir.join("hello.b")).unwrap();
+        assert!(check(&dir, "hello.b", &[]).is_ok());
+        fs::write(dir.join("Cargo.toml"), "[package]\nedited = true").unwrap();
+        let error = check(&dir, "hello.b", &[]).unwrap_err().to_string();
+        assert!(error.starts_with("Cargo.toml in"), "{}", error);
+        assert!(
+            error.contains("changed since it was generated"),
+            "{}",
+            error
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keep_globs_match_files_and_directories() {
+        let keep = [
+            "tests/**".to_string(),
+            "*.md".to_string(),
+            "src/lib?.rs".to_string(),
+        ];
+        for path in ["tests/a.rs", "tests/deep/b.rs", "README.md", "src/lib2.rs"] {
+            assert!(is_kept(&keep, path), "{} is not kept", path);
+        }
+        for path in [
+            "src/main.rs",
+            "docs/README.md",
+            "src/lib.rs",
+            "testsuite.rs",
+        ] {
+            assert!(!is_kept(&keep, path), "{} is kept", path);
+        }
+
+        // a directory keeps everything in it, with or without a slash
+        assert!(is_kept(&["examples/".to_string()], "examples/a/b.rs"));
+        assert!(is_kept(&["**/*.rs".to_string()], "main.rs"));
+    }
+
+    #[test]
+    fn a_kept_file_changed_by_hand_is_not_refused() {
+        let dir = scratch("kept");
+        generated(&dir);
+        fs::write(dir.join("Cargo.toml"), "[package]\nedited = true").unwrap();
+        assert!(check(&dir, "hello.b", &[]).is_err());
+        assert!(check(&dir, "hello.b", &["Cargo.toml".to_string()]).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn the_hashes_of_kept_files_carry_over() {
+        let dir = scratch("carry-over");
+        generated(&dir);
+        let previous = read(&dir).unwrap().unwrap();
+
+        // only `hello.b` was written this time
+        let mut lock = Lock::new(
+            &dir,
+            "hello.b",
+            "+.",
+            Vec::new(),
+            &[PathBuf::from("hello.b")],
+        )
+        .unwrap();
+        lock.keep(&previous, &["Cargo.toml".to_string()]);
+        assert_eq!(lock.files, previous.files);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,20 +1,41 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    io::{stdout, ErrorKind, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::Instant,
+};
 
 use ascii::AsciiString;
-use clap::Parser;
-use color_eyre::eyre::{Context, Result};
-use generator::{BrainfuckToRust, CellSize, EofBehavior, OverflowBehavior, PointerSafety};
-use tracing_error::ErrorLayer;
-use tracing_subscriber::{prelude::*, EnvFilter};
+use clap::{Parser, ValueEnum};
+use color_eyre::eyre::{eyre, Context, Result};
+use gen_crate::Code;
+use generator::{
+    BrainfuckToRust, CellSize, CodegenStyle, EofBehavior, ExitCell, FlushPolicy, IoMode,
+    LoopExtraction, OverflowBehavior, PointerSafety, Profile,
+};
 
-#[macro_use]
-extern crate tracing;
 #[macro_use]
 extern crate serde;
 
-pub mod ast;
+use codegen::{
+    ast, ir,
+    lint::{lint, LintConfig},
+    pragma::{self, CellWidth, Eof, Flags, PragmaPolicy, SemanticsProfile},
+    verbosity::{self, VerbosityFlags},
+};
+
+pub mod float_ext;
 pub mod gen_crate;
 pub mod generator;
+pub mod lock;
+pub mod report;
+pub mod target;
+
+/// How big a program has to be, in bytes, to be generated a piece at a time
+/// (see [`generator::BrainfuckToRust::stream_body`])
+const STREAM_THRESHOLD: usize = 1 << 20;
 
 // `Repeated` vectorizes repeated operations.
 // Note that this does not improve performance
@@ -30,19 +51,314 @@ pub type File = ast::File<ast::Repeated>;
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Cli {
-    /// The Brainfuck source code file
+    /// The Brainfuck source code file (or, with `-e`, the program itself)
     pub input: PathBuf,
-    /// The directory to store the generated crate in
+    /// The directory to store the generated crate in, or the file to write
+    /// with `--emit ir` (`-` for stdout)
     pub output: PathBuf,
+    #[clap(short = 'e', long)]
+    /// Take INPUT as the program itself instead of the path of a file, for
+    /// one-liners
+    pub eval: bool,
+    #[clap(long, value_enum, default_value_t)]
+    /// What to generate
+    pub emit: Emit,
+    #[clap(long, value_name = "PROGRAM", conflicts_with = "emit")]
+    /// Pipe the IR (see `--emit ir`) to this program and write its output to OUTPUT instead of generating a crate
+    pub backend_cmd: Option<PathBuf>,
+    #[clap(
+        long = "backend-arg",
+        value_name = "ARG",
+        requires = "backend_cmd",
+        allow_hyphen_values = true
+    )]
+    /// An argument for the `--backend-cmd` program (can be given more than once)
+    pub backend_args: Vec<String>,
     #[clap(short, long)]
     /// Pass the generated source code through `rustfmt`
     pub format: bool,
+    #[clap(long, value_name = "N")]
+    /// Generate the code on this many threads, writing it out as it goes
+    /// instead of holding all of it in memory. Programs of at least 1 MiB are
+    /// generated this way anyway, on one thread per core
+    pub jobs: Option<usize>,
+    #[clap(long, conflicts_with = "format")]
+    /// Write `source-map.json` to the crate, leading from the lines of the
+    /// generated code back to the instructions they came from, for `bf
+    /// symbolize`. Every instruction's code starts a line of its own
+    pub source_map: bool,
+    #[clap(long, value_name = "BYTES", default_value_t = 8 << 20)]
+    /// Split generated code bigger than this into modules and leave it
+    /// unformatted, since rustc struggles with one enormous function
+    pub split_above: usize,
     #[clap(short, long)]
     /// Dump the parsed AST to this JSON file
     pub dump_ast: Option<PathBuf>,
-    #[clap(long)]
+    #[clap(long, group = "fixed")]
     /// Force the use of the given ASCII string as the input, rather than reading stdin
     pub fixed_input: Option<AsciiString>,
+    #[clap(long, value_name = "HEX", group = "fixed")]
+    /// Like `--fixed-input`, but the input is any bytes written in hexadecimal, e.g. `48690a00ff`
+    pub fixed_input_hex: Option<String>,
+    #[clap(long, value_name = "FILE", group = "fixed")]
+    /// Like `--fixed-input`, but the input is the contents of this file
+    pub fixed_input_file: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t)]
+    /// How `.` and `,` interpret cell values
+    pub io: IoMode,
+    #[clap(long, value_enum, default_value_t)]
+    /// When the program flushes its output. `per-write` suits interactive
+    /// programs and `on-exit` programs that write a lot
+    pub flush: FlushPolicy,
+    #[clap(long, value_enum, default_value_t)]
+    /// Which instructions the program is made of. `float-ext` adds `*` and
+    /// `/` and generates floating-point cells (experimental)
+    pub dialect: ast::Dialect,
+    #[clap(long, value_name = "BITS|bigint")]
+    /// How many bits a cell has (8, 16 or 32), or `bigint` for cells that
+    /// never overflow, like `@pragma cell-size` [default: 8]
+    pub cell_size: Option<CellWidth>,
+    #[clap(long, value_enum)]
+    /// What happens when the pointer moves off either end of the tape. `wrap`
+    /// is like `@pragma wrap-pointer` [default: none]
+    pub pointer_safety: Option<PointerSafety>,
+    #[clap(long, value_name = "unchanged|VALUE")]
+    /// What `,` stores at the end of the input, like `@pragma eof` [default: unchanged]
+    pub eof: Option<Eof>,
+    #[clap(long, value_enum)]
+    /// Generate the semantics of a well-known interpreter, for everything the
+    /// flags above do not say otherwise. Cells then wrap around in debug
+    /// builds too, and the pointer stays on the tape unless it wraps
+    pub semantics_profile: Option<SemanticsProfile>,
+    #[clap(long, value_enum, default_value_t)]
+    /// Whether the `@pragma` directives of the source or the flags above win
+    /// where they disagree, or whether that is an error
+    pub pragma_policy: PragmaPolicy,
+    #[clap(long, conflicts_with = "extract_loops_above")]
+    /// Move every top-level loop into its own function, so rustc does not have
+    /// to compile one enormous `main`
+    pub extract_loops: bool,
+    #[clap(long, value_name = "TOKENS")]
+    /// Move every loop (at any depth) made of at least this many tokens into its own function
+    pub extract_loops_above: Option<usize>,
+    #[clap(long)]
+    /// Let the generated program's arguments and environment change the memory
+    /// size, EOF behavior and input source, at a small performance cost
+    pub runtime_config: bool,
+    #[clap(long, conflicts_with_all = ["fixed", "resumable_input"])]
+    /// Let the generated program take an input file or `--input-str <text>` as
+    /// its argument, like the interpreter, and read stdin only without one
+    pub input_args: bool,
+    #[clap(long, value_name = "VAR", conflicts_with_all = ["fixed", "resumable_input"])]
+    /// Let the generated program read its input from this environment
+    /// variable instead of stdin, for when giving it stdin is awkward
+    pub input_env: Option<String>,
+    #[clap(
+        long,
+        value_name = "CELL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "current"
+    )]
+    /// Exit with the value this cell (`current` for the one under the pointer,
+    /// the default) has once the program ends. The cell goes after an `=`, so
+    /// the argument after the flag is never taken for it
+    pub exit_from_cell: Option<ExitCell>,
+    #[clap(long, value_name = "TRIPLE")]
+    /// Configure the generated crate to build for this target, e.g. `wasm32-wasip1`
+    /// or `aarch64-unknown-linux-musl`
+    pub target_triple: Option<String>,
+    #[clap(long)]
+    /// Return errors from a `run()` function instead of panicking, and exit with
+    /// a code for each kind of error
+    pub panic_free: bool,
+    #[clap(long, conflicts_with_all = ["fixed", "runtime_config"])]
+    /// Run the program on its own thread behind a `run()` that returns
+    /// `Paused::NeedsInput` with a continuation whenever it waits for input
+    pub resumable_input: bool,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = [
+            "extract_loops",
+            "extract_loops_above",
+            "runtime_config",
+            "resumable_input",
+        ]
+    )]
+    /// Generate straight-line code, or a `Program` whose `step` runs one op at
+    /// a time, trading speed for being able to pause and inspect it
+    pub codegen_style: CodegenStyle,
+    #[clap(long)]
+    /// With `--codegen-style state-machine`, let `bf attach` debug the program
+    /// when it is run with `BF_DEBUG_ADDR` set to an address to listen on
+    pub debug_server: bool,
+    #[clap(long, value_enum, default_value_t)]
+    /// What the generated crate is optimized for
+    pub profile: Profile,
+    #[clap(long)]
+    /// Put the program in a library whose `run()` runs it, with a `main.rs`
+    /// that only calls it, so other crates can depend on the generated one
+    pub lib: bool,
+    #[clap(long = "static")]
+    /// Build a fully static, stripped executable with LTO and `panic = "abort"`.
+    /// Implies `--build`, and targets musl unless `--target-triple` is given
+    pub static_binary: bool,
+    #[clap(long)]
+    /// Overwrite the output directory even if it was not generated from this
+    /// source, or its generated files were changed since (see `bfgen.lock`)
+    pub force: bool,
+    #[clap(long, value_name = "GLOB")]
+    /// Leave the files of the output directory matching a glob like
+    /// `tests/**` as they are when regenerating it. The generated code in
+    /// `src/` is always rewritten
+    pub keep: Vec<String>,
+    #[clap(long, value_name = "VERSION", default_value = "1.0.0")]
+    /// The version in the generated manifest
+    pub crate_version: String,
+    #[clap(long, value_name = "AUTHORS", value_delimiter = ',')]
+    /// The authors in the generated manifest, separated by commas
+    pub authors: Vec<String>,
+    #[clap(long, value_name = "SPDX")]
+    /// The license in the generated manifest, like `MIT OR Apache-2.0`.
+    /// Defaults to the `SPDX-License-Identifier` in the source, if it has one
+    pub license: Option<String>,
+    #[clap(long, value_name = "TEXT")]
+    /// The description in the generated manifest. Defaults to the comment on
+    /// the first line of the source, or says which file it was generated from
+    pub description: Option<String>,
+    #[clap(long)]
+    /// Print the warnings of `bf lint` before generating code
+    pub lint: bool,
+    #[clap(short, long)]
+    /// Build the generated crate with `cargo build --release`
+    pub build: bool,
+    #[clap(long)]
+    /// Print statistics about the generated code (and the build, with `--build`)
+    pub report: bool,
+    #[clap(long, value_name = "FILE")]
+    /// Write the statistics printed by `--report` to this JSON file
+    pub report_json: Option<PathBuf>,
+    #[clap(flatten)]
+    pub verbosity: VerbosityFlags,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Emit {
+    #[default]
+    /// A Rust crate
+    Rust,
+    /// The optimized program as JSON, for backends outside of this crate (see `codegen::ir`)
+    Ir,
+}
+
+impl Cli {
+    /// The Brainfuck program, read from INPUT or given with `-e`.
+    pub fn source(&self) -> Result<String> {
+        if self.eval {
+            return self
+                .input
+                .to_str()
+                .map(str::to_string)
+                .ok_or_else(|| eyre!("the program given with `-e` is not UTF-8"));
+        }
+        fs::read_to_string(&self.input)
+            .wrap_err_with(|| format!("failed to read the source file {:?}", self.input))
+    }
+
+    /// The name the source is copied into the generated crate under.
+    pub fn source_filename(&self) -> &str {
+        if self.eval {
+            return "program.b";
+        }
+        self.input.file_name().unwrap().to_str().unwrap()
+    }
+
+    /// The bytes given by `--fixed-input`, `--fixed-input-hex` or `--fixed-input-file`.
+    pub fn fixed_input(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(input) = &self.fixed_input {
+            return Ok(Some(input.as_bytes().to_vec()));
+        }
+
+        if let Some(hex) = &self.fixed_input_hex {
+            let digits = hex
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| {
+                    c.to_digit(16)
+                        .ok_or_else(|| eyre!("`{}` is not a hexadecimal digit", c))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if !digits.len().is_multiple_of(2) {
+                return Err(eyre!(
+                    "the fixed input has an odd number of hexadecimal digits"
+                ));
+            }
+
+            let bytes = digits
+                .chunks(2)
+                .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+                .collect();
+
+            return Ok(Some(bytes));
+        }
+
+        if let Some(path) = &self.fixed_input_file {
+            let bytes = fs::read(path)
+                .wrap_err_with(|| format!("failed to read the fixed input file {:?}", path))?;
+            return Ok(Some(bytes));
+        }
+
+        Ok(None)
+    }
+
+    /// The target the generated crate is configured for, if not the host.
+    pub fn target_triple(&self) -> Option<String> {
+        self.target_triple.clone().or_else(|| {
+            self.static_binary
+                .then(|| format!("{}-unknown-linux-musl", std::env::consts::ARCH))
+        })
+    }
+}
+
+/// Runs an external backend with the IR on its stdin, and returns its stdout.
+fn run_backend(program: &PathBuf, args: &[String], ir: &str) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("failed to start the backend {:?}", program))?;
+
+    // write from another thread, so a backend that prints before it has read
+    // everything cannot fill both pipes and wait forever
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let ir = ir.to_string();
+    let writer = thread::spawn(move || stdin.write_all(ir.as_bytes()));
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "the backend {:?} failed with {}",
+            program,
+            output.status
+        ));
+    }
+
+    // a backend that succeeded without reading everything did not need the rest
+    if let Ok(Err(e)) = writer.join() {
+        if e.kind() != ErrorKind::BrokenPipe {
+            return Err(eyre!(
+                "failed to write the IR to the backend {:?}: {}",
+                program,
+                e
+            ));
+        }
+    }
+
+    Ok(output.stdout)
 }
 
 fn main() -> Result<()> {
@@ -50,40 +366,189 @@ fn main() -> Result<()> {
 
     dotenvy::dotenv().ok();
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_target(false)
-                .with_line_number(false)
-                .with_file(true)
-                .compact(),
-        )
-        .with(EnvFilter::from_default_env())
-        .with(ErrorLayer::default())
-        .init();
+    verbosity::init(cli.verbosity.level());
 
     color_eyre::install()?;
 
-    let in_code = fs::read_to_string(&cli.input)?;
+    let in_code = cli.source()?;
 
-    let file: File = in_code.parse()?;
+    let file = File::parse_in(&in_code, cli.dialect);
+    if let Some(e) = pragma::parse_pragmas(&in_code).1.into_iter().next() {
+        return Err(eyre!("{:?}: {}", cli.input, e));
+    }
+    let flags = Flags {
+        wrap_pointer: cli.pointer_safety.map(|safety| {
+            let name = safety.to_possible_value().expect("not skipped");
+            (
+                safety == PointerSafety::Wrap,
+                format!("--pointer-safety {}", name.get_name()),
+            )
+        }),
+        cell_size: cli.cell_size,
+        eof: cli.eof,
+    };
+    let flags = match cli.semantics_profile {
+        Some(profile) => {
+            flags.or_profile(profile, &format!("--semantics-profile {}", profile.name()))
+        }
+        None => flags,
+    };
+    let (effective, warnings) =
+        pragma::resolve(&in_code, &flags, cli.pragma_policy, CellWidth::Bits(8))
+            .map_err(|e| eyre!("{:?}: {}", cli.input, e))?;
+    for warning in warnings {
+        verbosity::warn(format!("{:?}: {}", cli.input, warning));
+    }
+    verbosity::info(format!("{:?}: {}", cli.input, effective));
+
+    if cli.lint {
+        for diagnostic in lint(&in_code, &LintConfig::default()) {
+            eprintln!(
+                "{}: line {} column {}: {} [{}]",
+                diagnostic.severity,
+                diagnostic.span.line,
+                diagnostic.span.column,
+                diagnostic.message,
+                diagnostic.rule.name()
+            );
+        }
+    }
 
     if let Some(dump_ast) = &cli.dump_ast {
         fs::write(dump_ast, serde_json::to_string_pretty(&file)?)?;
     }
 
-    let out_code = BrainfuckToRust::builder()
-        .cell_size(CellSize::U8)
+    if cli.emit == Emit::Ir || cli.backend_cmd.is_some() {
+        let json = serde_json::to_string(&ir::Program::from_file(&file))?;
+
+        let generated = match &cli.backend_cmd {
+            Some(program) => run_backend(program, &cli.backend_args, &json)?,
+            None => format!("{}\n", json).into_bytes(),
+        };
+
+        if cli.output.as_os_str() == "-" {
+            stdout().write_all(&generated)?;
+        } else {
+            fs::write(&cli.output, generated)?;
+        }
+
+        return Ok(());
+    }
+
+    let loop_extraction = if let Some(min) = cli.extract_loops_above {
+        LoopExtraction::MinTokens(min)
+    } else if cli.extract_loops {
+        LoopExtraction::TopLevel
+    } else {
+        LoopExtraction::None
+    };
+
+    let generator = BrainfuckToRust::builder()
+        .cell_size(match effective.cell_size {
+            CellWidth::Bits(16) => CellSize::U16,
+            CellWidth::Bits(32) => CellSize::U32,
+            CellWidth::Bits(_) => CellSize::U8,
+            CellWidth::BigInt => CellSize::BigInt,
+        })
         .memory_size(30_000)
-        .pointer_safety(PointerSafety::None)
-        .overflow_behavior(OverflowBehavior::None)
-        .fixed_input(cli.fixed_input.clone())
-        .eof_behavior(EofBehavior::NoChange)
-        .build()
-        .generate(file)
-        .wrap_err("failed to generate Rust from Brainfuck")?;
-
-    gen_crate::generate_crate_for_code(&cli, &in_code, out_code)?;
+        .pointer_safety(match cli.pointer_safety {
+            _ if effective.wrap_pointer => PointerSafety::Wrap,
+            // like the interpreters the profiles stand for
+            None if cli.semantics_profile.is_some() => PointerSafety::Clamp,
+            // overridden by the pragma
+            Some(PointerSafety::Wrap) | None => PointerSafety::None,
+            Some(safety) => safety,
+        })
+        .overflow_behavior(if cli.semantics_profile.is_some() {
+            OverflowBehavior::Wrap
+        } else {
+            OverflowBehavior::None
+        })
+        .fixed_input(cli.fixed_input()?)
+        .eof_behavior(
+            effective
+                .eof
+                .map_or(EofBehavior::NoChange, EofBehavior::Fixed),
+        )
+        .io_mode(cli.io)
+        .loop_extraction(loop_extraction)
+        .runtime_config(cli.runtime_config)
+        .input_args(cli.input_args)
+        .input_env(cli.input_env.clone())
+        .exit_cell(cli.exit_from_cell)
+        .io_shim(cli.profile == Profile::MinSize)
+        .panic_free(cli.panic_free)
+        .flush(cli.flush)
+        .resumable_input(cli.resumable_input)
+        .style(cli.codegen_style)
+        .lib(cli.lib)
+        .debug_server(cli.debug_server)
+        .source_map(cli.source_map)
+        .build();
+
+    let streamed =
+        (cli.jobs.is_some() || in_code.len() >= STREAM_THRESHOLD) && generator.can_stream(&file);
+    let jobs = cli
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |jobs| jobs.get()));
+    let out_code = if streamed {
+        Code::Streamed {
+            generator: &generator,
+            file: &file,
+            jobs,
+        }
+    } else {
+        let code = generator
+            .generate(&file)
+            .wrap_err("failed to generate Rust from Brainfuck")?
+            .to_string();
+
+        // generated again in pieces, to split it into modules
+        if code.len() > cli.split_above && generator.can_stream(&file) {
+            Code::Streamed {
+                generator: &generator,
+                file: &file,
+                jobs,
+            }
+        } else {
+            Code::Text(code)
+        }
+    };
+
+    let generated_lines =
+        gen_crate::generate_crate_for_code(&cli, &generator, &in_code, out_code, &effective)?;
+
+    verbosity::info(format!(
+        "generated {} line{} of Rust into {:?}",
+        generated_lines,
+        if generated_lines == 1 { "" } else { "s" },
+        cli.output
+    ));
+
+    let compile_time = if cli.build || cli.static_binary {
+        let start = Instant::now();
+        gen_crate::build_crate(&cli.output)?;
+        verbosity::info(format!(
+            "built {:?} in {:.1}s",
+            cli.output,
+            start.elapsed().as_secs_f64()
+        ));
+        Some(start.elapsed())
+    } else {
+        None
+    };
+
+    if cli.report || cli.report_json.is_some() {
+        let report = report::Report::new(&in_code, &file, generated_lines, compile_time);
+
+        if cli.report {
+            print!("{}", report);
+        }
+
+        if let Some(path) = &cli.report_json {
+            fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        }
+    }
 
     Ok(())
 }
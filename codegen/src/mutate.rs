@@ -0,0 +1,185 @@
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::{
+    ast::{File, Repeated, Segment, Span, Token},
+    rng::Rng,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// A structured change to a program
+pub enum Mutation {
+    /// Exchange two neighboring instructions
+    SwapOps,
+    /// Turn `+` into `-`, `<` into `>` and so on
+    FlipOp,
+    /// Change how many times an instruction is repeated
+    PerturbCount,
+    /// Repeat the body of a loop inside it
+    DuplicateLoopBody,
+}
+
+impl Mutation {
+    pub const ALL: [Mutation; 4] = [
+        Mutation::SwapOps,
+        Mutation::FlipOp,
+        Mutation::PerturbCount,
+        Mutation::DuplicateLoopBody,
+    ];
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // no Brainfuck instructions, so this can be written into a program as a comment
+        let description = match self {
+            Mutation::SwapOps => "swapped two instructions",
+            Mutation::FlipOp => "flipped an instruction",
+            Mutation::PerturbCount => "changed a repeat count",
+            Mutation::DuplicateLoopBody => "duplicated a loop body",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A mutated copy of a program
+pub struct Mutant {
+    pub mutation: Mutation,
+    /// Where in the original source the mutation was made
+    pub span: Span,
+    pub file: File<Repeated>,
+}
+
+/// Applies one mutation of the given kind at a random place in the program, or
+/// returns `None` if the program has nowhere to apply it.
+pub fn mutate(file: &File<Repeated>, mutation: Mutation, rng: &mut Rng) -> Option<Mutant> {
+    let sites = count_sites(&file.segments, mutation);
+    if sites == 0 {
+        return None;
+    }
+
+    let mut file = file.clone();
+    let mut target = rng.below(sites);
+    let span = apply(&mut file.segments, mutation, &mut target, rng)?;
+
+    Some(Mutant {
+        mutation,
+        span,
+        file,
+    })
+}
+
+/// Whether a single token can be mutated
+fn is_site(token: &Repeated, mutation: Mutation) -> bool {
+    match mutation {
+        Mutation::FlipOp => matches!(
+            token.token,
+            Token::PointerAdd | Token::PointerSub | Token::ValueAdd | Token::ValueSub
+        ),
+        // the code generator only supports single reads
+        Mutation::PerturbCount => token.token != Token::Read,
+        Mutation::SwapOps | Mutation::DuplicateLoopBody => false,
+    }
+}
+
+/// The number of places a mutation can be applied
+fn count_sites(segments: &[Segment<Repeated>], mutation: Mutation) -> usize {
+    segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Executable { code, .. } => match mutation {
+                Mutation::SwapOps => code
+                    .tokens
+                    .windows(2)
+                    .filter(|pair| pair[0].token != pair[1].token)
+                    .count(),
+                _ => code.tokens.iter().filter(|t| is_site(t, mutation)).count(),
+            },
+            Segment::Loop { body, .. } => {
+                let own = (mutation == Mutation::DuplicateLoopBody && !body.is_empty()) as usize;
+                own + count_sites(body, mutation)
+            }
+        })
+        .sum()
+}
+
+/// Applies the mutation at the `target`th site, counting down as sites are
+/// passed, and returns its span.
+fn apply(
+    segments: &mut [Segment<Repeated>],
+    mutation: Mutation,
+    target: &mut usize,
+    rng: &mut Rng,
+) -> Option<Span> {
+    for segment in segments {
+        match segment {
+            Segment::Executable { code, .. } => {
+                for i in 0..code.tokens.len() {
+                    let site = match mutation {
+                        Mutation::SwapOps => code
+                            .tokens
+                            .get(i + 1)
+                            .is_some_and(|next| next.token != code.tokens[i].token),
+                        _ => is_site(&code.tokens[i], mutation),
+                    };
+
+                    if !site {
+                        continue;
+                    }
+
+                    if *target > 0 {
+                        *target -= 1;
+                        continue;
+                    }
+
+                    if mutation == Mutation::SwapOps {
+                        code.tokens.swap(i, i + 1);
+                        return Some(code.spans[i].to(code.spans[i + 1]));
+                    }
+
+                    let token = &mut code.tokens[i];
+                    match mutation {
+                        Mutation::FlipOp => {
+                            token.token = match token.token {
+                                Token::PointerAdd => Token::PointerSub,
+                                Token::PointerSub => Token::PointerAdd,
+                                Token::ValueAdd => Token::ValueSub,
+                                _ => Token::ValueAdd,
+                            };
+                        }
+                        Mutation::PerturbCount => {
+                            let delta = rng.below(3) + 1;
+                            token.count = if token.count > delta && rng.chance(1, 2) {
+                                token.count - delta
+                            } else {
+                                token.count + delta
+                            };
+                        }
+                        Mutation::SwapOps | Mutation::DuplicateLoopBody => unreachable!(),
+                    }
+
+                    return Some(code.spans[i]);
+                }
+            }
+            Segment::Loop { body, span } => {
+                if mutation == Mutation::DuplicateLoopBody && !body.is_empty() {
+                    if *target == 0 {
+                        let copy = body.clone();
+                        body.extend(copy);
+                        return Some(*span);
+                    }
+
+                    *target -= 1;
+                }
+
+                if let Some(span) = apply(body, mutation, target, rng) {
+                    return Some(span);
+                }
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,616 @@
+//! Semantics a program asks for in its own source, so it carries them with it
+//! instead of relying on the right flags being passed:
+//!
+//! ```text
+//! @pragma wrap-pointer      moving off either end of the tape wraps around
+//! @pragma cell-size 16      cells are 8, 16 or 32 bits wide, or `bigint`
+//! @pragma eof 0             `,` stores this value at the end of the input
+//! ```
+//!
+//! A pragma takes the rest of its line, which is never executed, so its name
+//! and any comment after it may contain instructions.
+//!
+//! Tools can also take the same semantics as flags ([`Flags`]). Where both
+//! say something different, the [`PragmaPolicy`] decides which one wins, or
+//! whether that is an error. A [`SemanticsProfile`] gives the flags of a
+//! well-known interpreter at once.
+
+use std::{fmt, ops::Range, str::FromStr};
+
+use clap::ValueEnum;
+use serde::{Deserializer, Serializer};
+use thiserror::Error;
+
+use crate::ast::Span;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// The semantics asked for by a program's pragmas. Anything not asked for is
+/// left to the tool running the program.
+pub struct Pragmas {
+    /// Whether moving the pointer off either end of the tape wraps around
+    pub wrap_pointer: bool,
+    pub cell_size: Option<CellWidth>,
+    /// What `,` stores at the end of the input, instead of leaving the cell
+    /// unchanged
+    pub eof: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How big the value of a cell can get
+pub enum CellWidth {
+    /// 8, 16 or 32 bits, wrapping around or not by the overflow behavior
+    Bits(u32),
+    /// Arbitrarily big, in either direction, so a cell never overflows
+    BigInt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What `,` does at the end of the input
+pub enum Eof {
+    Unchanged,
+    Value(u8),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The semantics given as flags, each `None` if not given
+pub struct Flags {
+    /// Whether the pointer wraps around, and the flag that said so, since
+    /// tools have their own
+    pub wrap_pointer: Option<(bool, String)>,
+    /// Given as `--cell-size`
+    pub cell_size: Option<CellWidth>,
+    /// Given as `--eof`
+    pub eof: Option<Eof>,
+}
+
+impl Flags {
+    /// The flags with the semantics of `profile` where they give none, `flag`
+    /// being how the profile was given, like `--profile classic`.
+    pub fn or_profile(self, profile: SemanticsProfile, flag: &str) -> Self {
+        Self {
+            wrap_pointer: self
+                .wrap_pointer
+                .or_else(|| Some((profile.wrap_pointer(), flag.to_string()))),
+            cell_size: self.cell_size.or(Some(CellWidth::Bits(8))),
+            eof: self.eof.or(Some(profile.eof())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// The semantics of a well-known interpreter, so that a program written for
+/// it takes a single flag. All of them have 8-bit cells that wrap around and
+/// the classic tape of 30000 cells
+pub enum SemanticsProfile {
+    /// `,` stores 0 at EOF, and the pointer stays on the tape, the most common
+    /// convention
+    Classic,
+    /// `,` leaves the cell unchanged at EOF, like Urban Müller's original
+    /// implementation
+    UrbanMueller,
+    /// `,` stores 0 at EOF, and the tape wraps around, so that no program can
+    /// move the pointer off it
+    Easy,
+    /// `,` leaves the cell unchanged at EOF, and the tape wraps around, so
+    /// that golfed programs can start by moving left
+    Golf,
+}
+
+impl SemanticsProfile {
+    /// Whether the pointer wraps around at either end of the tape, instead of
+    /// staying there
+    pub const fn wrap_pointer(&self) -> bool {
+        matches!(self, Self::Easy | Self::Golf)
+    }
+
+    /// What `,` does at the end of the input
+    pub const fn eof(&self) -> Eof {
+        match self {
+            Self::Classic | Self::Easy => Eof::Value(0),
+            Self::UrbanMueller | Self::Golf => Eof::Unchanged,
+        }
+    }
+
+    /// Its name, as given on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Classic => "classic",
+            Self::UrbanMueller => "urban-mueller",
+            Self::Easy => "easy",
+            Self::Golf => "golf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+/// Which semantics win where the pragmas and the flags disagree
+pub enum PragmaPolicy {
+    #[default]
+    /// The pragmas win, with a warning for each flag they override
+    Source,
+    /// The flags win, with a warning for each pragma they override
+    Flags,
+    /// Any disagreement is an error
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("`@pragma {pragma}` on line {line} conflicts with `{flag}`")]
+/// A pragma and a flag that disagree
+pub struct Conflict {
+    /// The pragma as written, like `eof 0`
+    pub pragma: String,
+    pub line: usize,
+    /// The flag as given, like `--eof 255`
+    pub flag: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// The semantics a program runs with in the end, recorded so the run can be
+/// reproduced
+pub struct Effective {
+    pub wrap_pointer: bool,
+    pub cell_size: CellWidth,
+    /// What `,` stores at the end of the input, `None` to leave the cell
+    /// unchanged
+    pub eof: Option<u8>,
+    pub policy: PragmaPolicy,
+}
+
+impl fmt::Display for CellWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bits(bits) => write!(f, "{}", bits),
+            Self::BigInt => write!(f, "bigint"),
+        }
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bigint" => Ok(Self::BigInt),
+            "8" | "16" | "32" => Ok(Self::Bits(s.parse().unwrap())),
+            _ => Err(format!("`{}` is not 8, 16, 32 or bigint", s)),
+        }
+    }
+}
+
+// a number of bits, or `"bigint"`
+impl serde::Serialize for CellWidth {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Bits(bits) => serializer.serialize_u32(*bits),
+            Self::BigInt => serializer.serialize_str("bigint"),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CellWidth {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bits(u32),
+            Name(String),
+        }
+
+        match <Repr as serde::Deserialize>::deserialize(deserializer)? {
+            Repr::Bits(bits) => bits.to_string().parse(),
+            Repr::Name(name) => name.parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Eof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unchanged => write!(f, "unchanged"),
+            Self::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// in the words of the directives
+impl fmt::Display for Effective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cell-size {}, wrap-pointer {}, eof {}",
+            self.cell_size,
+            if self.wrap_pointer { "on" } else { "off" },
+            self.eof.map_or(Eof::Unchanged, Eof::Value)
+        )
+    }
+}
+
+impl FromStr for Eof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unchanged" => Ok(Self::Unchanged),
+            _ => s
+                .parse()
+                .map(Self::Value)
+                .map_err(|_| format!("`{}` is not `unchanged` or a value from 0 to 255", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PragmaError {
+    #[error("line {line}: unknown pragma `{name}`")]
+    Unknown { name: String, line: usize },
+    #[error("line {line}: `@pragma {name}` needs {expected}")]
+    InvalidValue {
+        name: String,
+        expected: &'static str,
+        line: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An `@pragma` directive
+pub struct Directive {
+    pub name: String,
+    pub value: Option<String>,
+    /// The directive up to the end of its line
+    pub span: Span,
+}
+
+/// Finds the `@pragma <name> [<value>]` directives in the code.
+pub fn directives(code: &str) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    let mut start = 0;
+
+    for (i, line) in code.split('\n').enumerate() {
+        if let Some(at) = line.find("@pragma") {
+            let rest = &line[at + "@pragma".len()..];
+            let mut words = rest.split_whitespace();
+
+            // not part of a longer word like `@pragmas`
+            if let (true, Some(name)) = (rest.starts_with(char::is_whitespace), words.next()) {
+                directives.push(Directive {
+                    name: name.to_string(),
+                    value: words.next().map(str::to_string),
+                    span: Span {
+                        start: start + at,
+                        end: start + line.len(),
+                        line: i + 1,
+                        column: line[..at].chars().count() + 1,
+                    },
+                });
+            }
+        }
+
+        start += line.len() + 1;
+    }
+
+    directives
+}
+
+/// The byte ranges of the directives, which are not executed.
+pub fn skipped(code: &str) -> Vec<Range<usize>> {
+    directives(code)
+        .into_iter()
+        .map(|directive| directive.span.start..directive.span.end)
+        .collect()
+}
+
+/// Reads the pragmas of a program. Every directive that could be read is
+/// applied, later ones overriding earlier ones, along with the errors of the
+/// rest.
+pub fn parse_pragmas(code: &str) -> (Pragmas, Vec<PragmaError>) {
+    let mut pragmas = Pragmas::default();
+    let mut errors = Vec::new();
+
+    for directive in directives(code) {
+        let line = directive.span.line;
+        let invalid = |expected| PragmaError::InvalidValue {
+            name: directive.name.clone(),
+            expected,
+            line,
+        };
+
+        match directive.name.as_str() {
+            "wrap-pointer" => pragmas.wrap_pointer = true,
+            "cell-size" => match directive.value.as_deref().map(str::parse) {
+                Some(Ok(width)) => pragmas.cell_size = Some(width),
+                _ => errors.push(invalid("a cell size of 8, 16, 32 or bigint")),
+            },
+            "eof" => match directive.value.as_deref().map(str::parse) {
+                Some(Ok(value)) => pragmas.eof = Some(value),
+                _ => errors.push(invalid("a value from 0 to 255")),
+            },
+            _ => errors.push(PragmaError::Unknown {
+                name: directive.name.clone(),
+                line,
+            }),
+        }
+    }
+
+    (pragmas, errors)
+}
+
+/// Merges the pragmas of `code` with `flags` by `policy`, where `cell_size` is
+/// used if neither gives one. Returns the semantics to run with and a warning
+/// for every pragma or flag that was overridden. Unreadable pragmas are left
+/// to [`parse_pragmas`].
+pub fn resolve(
+    code: &str,
+    flags: &Flags,
+    policy: PragmaPolicy,
+    cell_size: CellWidth,
+) -> Result<(Effective, Vec<String>), Conflict> {
+    let (pragmas, _) = parse_pragmas(code);
+    let directives = directives(code);
+    let mut warnings = Vec::new();
+
+    // whether the flag wins over the pragma `name`, which says `pragma`
+    let mut flag_wins = |name: &str, pragma: String, flag: String| {
+        let conflict = Conflict {
+            pragma,
+            // the last directive of a kind is the one in effect
+            line: directives
+                .iter()
+                .rev()
+                .find(|directive| directive.name == name)
+                .map_or(0, |directive| directive.span.line),
+            flag,
+        };
+
+        match policy {
+            PragmaPolicy::Source => {
+                warnings.push(format!("{}, ignoring the flag", conflict));
+                Ok(false)
+            }
+            PragmaPolicy::Flags => {
+                warnings.push(format!("{}, ignoring the pragma", conflict));
+                Ok(true)
+            }
+            PragmaPolicy::Error => Err(conflict),
+        }
+    };
+
+    let wrap_pointer = match &flags.wrap_pointer {
+        Some((false, flag)) if pragmas.wrap_pointer => {
+            !flag_wins("wrap-pointer", "wrap-pointer".to_string(), flag.clone())?
+        }
+        Some((wrap, _)) => *wrap || pragmas.wrap_pointer,
+        None => pragmas.wrap_pointer,
+    };
+
+    let cell_size = match (pragmas.cell_size, flags.cell_size) {
+        (Some(pragma), Some(flag)) if pragma != flag => {
+            let cell_size = format!("cell-size {}", pragma);
+            if flag_wins("cell-size", cell_size, format!("--cell-size {}", flag))? {
+                flag
+            } else {
+                pragma
+            }
+        }
+        (pragma, flag) => pragma.or(flag).unwrap_or(cell_size),
+    };
+
+    let eof = match (pragmas.eof, flags.eof) {
+        (Some(pragma), Some(flag)) if Eof::Value(pragma) != flag => {
+            if flag_wins("eof", format!("eof {}", pragma), format!("--eof {}", flag))? {
+                flag
+            } else {
+                Eof::Value(pragma)
+            }
+        }
+        (Some(pragma), _) => Eof::Value(pragma),
+        (None, flag) => flag.unwrap_or(Eof::Unchanged),
+    };
+
+    let effective = Effective {
+        wrap_pointer,
+        cell_size,
+        eof: match eof {
+            Eof::Unchanged => None,
+            Eof::Value(value) => Some(value),
+        },
+        policy,
+    };
+    Ok((effective, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(wrap_pointer: Option<bool>, cell_size: Option<u32>, eof: Option<Eof>) -> Flags {
+        Flags {
+            wrap_pointer: wrap_pointer.map(|wrap| (wrap, "--wrap-pointer".to_string())),
+            cell_size: cell_size.map(CellWidth::Bits),
+            eof,
+        }
+    }
+
+    #[test]
+    fn later_directives_override_earlier_ones_and_bad_ones_are_reported() {
+        let code = "@pragma cell-size 16\n@pragma eof 7\n+\n@pragma cell-size 32\n\
+                    @pragma eof 256\n@pragma cell-size\n@pragma wrap\n@pragmas wrap\n";
+        let (pragmas, errors) = parse_pragmas(code);
+
+        assert_eq!(
+            pragmas,
+            Pragmas {
+                wrap_pointer: false,
+                cell_size: Some(CellWidth::Bits(32)),
+                eof: Some(7),
+            }
+        );
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "line 5: `@pragma eof` needs a value from 0 to 255",
+                "line 6: `@pragma cell-size` needs a cell size of 8, 16, 32 or bigint",
+                "line 7: unknown pragma `wrap`",
+            ]
+        );
+    }
+
+    #[test]
+    fn without_a_disagreement_every_policy_merges_the_same() {
+        let code = "@pragma eof 0\n+.";
+        for policy in [
+            PragmaPolicy::Source,
+            PragmaPolicy::Flags,
+            PragmaPolicy::Error,
+        ] {
+            let (effective, warnings) = resolve(
+                code,
+                &flags(Some(true), Some(16), Some(Eof::Value(0))),
+                policy,
+                CellWidth::Bits(8),
+            )
+            .unwrap();
+            assert_eq!(
+                effective,
+                Effective {
+                    wrap_pointer: true,
+                    cell_size: CellWidth::Bits(16),
+                    eof: Some(0),
+                    policy,
+                }
+            );
+            assert_eq!(warnings, Vec::<String>::new());
+        }
+
+        // and neither saying anything leaves the default
+        let (effective, _) = resolve(
+            "+.",
+            &Flags::default(),
+            PragmaPolicy::Error,
+            CellWidth::BigInt,
+        )
+        .unwrap();
+        assert_eq!(effective.cell_size, CellWidth::BigInt);
+        assert_eq!(effective.eof, None);
+        assert!(!effective.wrap_pointer);
+    }
+
+    #[test]
+    fn the_policy_picks_the_winner_of_a_disagreement() {
+        let code = "@pragma eof 0\n@pragma cell-size 16\n+.";
+        let given = flags(None, Some(32), Some(Eof::Unchanged));
+
+        let (source, warnings) =
+            resolve(code, &given, PragmaPolicy::Source, CellWidth::Bits(8)).unwrap();
+        assert_eq!(source.cell_size, CellWidth::Bits(16));
+        assert_eq!(source.eof, Some(0));
+        assert_eq!(
+            warnings,
+            [
+                "`@pragma cell-size 16` on line 2 conflicts with `--cell-size 32`, ignoring the flag",
+                "`@pragma eof 0` on line 1 conflicts with `--eof unchanged`, ignoring the flag",
+            ]
+        );
+
+        let (flags, warnings) =
+            resolve(code, &given, PragmaPolicy::Flags, CellWidth::Bits(8)).unwrap();
+        assert_eq!(flags.cell_size, CellWidth::Bits(32));
+        assert_eq!(flags.eof, None);
+        assert_eq!(
+            warnings,
+            [
+                "`@pragma cell-size 16` on line 2 conflicts with `--cell-size 32`, ignoring the pragma",
+                "`@pragma eof 0` on line 1 conflicts with `--eof unchanged`, ignoring the pragma",
+            ]
+        );
+
+        assert_eq!(
+            resolve(code, &given, PragmaPolicy::Error, CellWidth::Bits(8)),
+            Err(Conflict {
+                pragma: "cell-size 16".to_string(),
+                line: 2,
+                flag: "--cell-size 32".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn a_conflict_is_on_the_line_of_the_directive_in_effect() {
+        let code = "@pragma eof 1\n+\n@pragma eof 2\n.";
+        let conflict = resolve(
+            code,
+            &flags(None, None, Some(Eof::Value(3))),
+            PragmaPolicy::Error,
+            CellWidth::Bits(8),
+        )
+        .unwrap_err();
+        assert_eq!(
+            conflict.to_string(),
+            "`@pragma eof 2` on line 3 conflicts with `--eof 3`"
+        );
+    }
+
+    #[test]
+    fn only_a_flag_keeping_the_pointer_on_the_tape_conflicts_with_wrapping() {
+        let code = "@pragma wrap-pointer\n<.";
+
+        let (effective, warnings) = resolve(
+            code,
+            &flags(Some(true), None, None),
+            PragmaPolicy::Error,
+            CellWidth::Bits(8),
+        )
+        .unwrap();
+        assert!(effective.wrap_pointer);
+        assert_eq!(warnings, Vec::<String>::new());
+
+        // wrapping by the flag alone needs no pragma
+        let (effective, _) = resolve(
+            "<.",
+            &flags(Some(true), None, None),
+            PragmaPolicy::Error,
+            CellWidth::Bits(8),
+        )
+        .unwrap();
+        assert!(effective.wrap_pointer);
+
+        let keep = Flags {
+            wrap_pointer: Some((false, "--profile classic".to_string())),
+            ..Flags::default()
+        };
+        assert_eq!(
+            resolve(code, &keep, PragmaPolicy::Error, CellWidth::Bits(8))
+                .unwrap_err()
+                .to_string(),
+            "`@pragma wrap-pointer` on line 1 conflicts with `--profile classic`"
+        );
+        let (effective, _) = resolve(code, &keep, PragmaPolicy::Flags, CellWidth::Bits(8)).unwrap();
+        assert!(!effective.wrap_pointer);
+        let (effective, _) =
+            resolve(code, &keep, PragmaPolicy::Source, CellWidth::Bits(8)).unwrap();
+        assert!(effective.wrap_pointer);
+    }
+
+    #[test]
+    fn a_profile_only_fills_in_the_flags_not_given() {
+        let profiled = flags(None, None, None).or_profile(SemanticsProfile::Golf, "--profile golf");
+        assert_eq!(
+            profiled,
+            Flags {
+                wrap_pointer: Some((true, "--profile golf".to_string())),
+                cell_size: Some(CellWidth::Bits(8)),
+                eof: Some(Eof::Unchanged),
+            }
+        );
+
+        let given = flags(Some(false), Some(16), Some(Eof::Value(0)));
+        assert_eq!(
+            given
+                .clone()
+                .or_profile(SemanticsProfile::Golf, "--profile golf"),
+            given
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::rng::Rng;
+
+/// Random programs only use this many cells, starting at cell 0, so the
+/// pointer never reaches either end of the tape
+pub const CELLS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+/// What kind of program [`random_program`] generates
+pub struct RandomProgram {
+    /// Roughly how many instructions to generate
+    pub ops: usize,
+    /// How deeply loops can be nested
+    pub max_depth: usize,
+    /// Whether to use `,`
+    pub reads: bool,
+}
+
+/// Generates a random program that always halts. Every loop decrements the
+/// cell it starts on once per iteration, and its body returns the pointer to
+/// that cell without changing it otherwise, so no loop runs more than 255
+/// times.
+pub fn random_program(options: RandomProgram, rng: &mut Rng) -> String {
+    let mut generator = Generator {
+        options,
+        rng,
+        code: String::new(),
+        position: 0,
+        emitted: 0,
+        counters: Vec::new(),
+    };
+
+    generator.block(options.ops, 0);
+
+    generator.code
+}
+
+struct Generator<'a> {
+    options: RandomProgram,
+    rng: &'a mut Rng,
+    code: String,
+    /// The cell the pointer is on
+    position: usize,
+    /// The number of instructions generated so far
+    emitted: usize,
+    /// The cells of the loops being generated, which their bodies must not change
+    counters: Vec<usize>,
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, c: char) {
+        self.code.push(c);
+        self.emitted += 1;
+    }
+
+    /// Whether the cell under the pointer can be changed
+    fn writable(&self) -> bool {
+        !self.counters.contains(&self.position)
+    }
+
+    /// Generates instructions until `budget` more have been emitted.
+    fn block(&mut self, budget: usize, depth: usize) {
+        let end = self.emitted + budget;
+
+        while self.emitted < end {
+            match self.rng.below(10) {
+                0..=2 => {
+                    let left =
+                        self.position > 0 && (self.position == CELLS - 1 || self.rng.chance(1, 2));
+                    if left {
+                        self.position -= 1;
+                        self.emit('<');
+                    } else {
+                        self.position += 1;
+                        self.emit('>');
+                    }
+                }
+                3..=5 if self.writable() => {
+                    let op = if self.rng.chance(2, 3) { '+' } else { '-' };
+                    for _ in 0..self.rng.below(3) + 1 {
+                        self.emit(op);
+                    }
+                }
+                6 => self.emit('.'),
+                7 if self.options.reads && self.writable() => self.emit(','),
+                8 | 9
+                    if depth < self.options.max_depth
+                        && end - self.emitted >= 4
+                        && self.writable() =>
+                {
+                    let body = 1 + self.rng.below(end - self.emitted - 3);
+                    self.random_loop(body, depth);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn random_loop(&mut self, budget: usize, depth: usize) {
+        let start = self.position;
+        let decrement_first = self.rng.chance(1, 2);
+
+        self.emit('[');
+        if decrement_first {
+            self.emit('-');
+        }
+
+        self.counters.push(start);
+        self.block(budget, depth + 1);
+        self.counters.pop();
+
+        while self.position > start {
+            self.position -= 1;
+            self.emit('<');
+        }
+        while self.position < start {
+            self.position += 1;
+            self.emit('>');
+        }
+
+        if !decrement_first {
+            self.emit('-');
+        }
+        self.emit(']');
+    }
+}
@@ -0,0 +1,382 @@
+//! Which parts of a program touch which cells of the tape. A program (like
+//! one made by `bf link`) is split into regions by comments like
+//! `@region parser 10:20`, each owning the code up to the next one. The range
+//! (start included, end excluded) declares the cells the region may touch,
+//! and can be left out to only name the region. Following the pointer
+//! without running the program gives each region's cells, as long as every
+//! loop before it brings the pointer back to where it started.
+
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+use crate::{
+    ast::{Segment, Span, Token, TokenExt},
+    link::net_movement,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An `@region` directive
+pub struct Region {
+    pub name: String,
+    /// The cells it may touch, if declared
+    pub cells: Option<Range<isize>>,
+    /// The directive itself
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What the code of a region was found to touch
+pub struct RegionUsage {
+    pub region: Region,
+    /// Each cell the code reads or writes, with the first instruction that does
+    pub touched: BTreeMap<isize, Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionProblem {
+    /// The code of a region touches a cell outside the ones it declared
+    OutOfRange { region: String, cell: isize },
+    /// Two regions declared some of the same cells
+    DeclaredOverlap {
+        region: String,
+        other: String,
+        cells: Range<isize>,
+    },
+    /// Two regions touch the same cell
+    SharedCell {
+        region: String,
+        other: String,
+        cell: isize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionWarning {
+    pub problem: RegionProblem,
+    /// The directive or the instruction the warning is about
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What [`check`] found out about the regions of a program
+pub struct RegionReport {
+    pub regions: Vec<RegionUsage>,
+    /// The first loop that moves the pointer by an amount only known at
+    /// runtime, after which nothing is checked
+    pub lost_at: Option<Span>,
+    pub warnings: Vec<RegionWarning>,
+}
+
+impl fmt::Display for RegionProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange { region, cell } => write!(
+                f,
+                "region `{}` touches cell {}, outside of the cells it declared",
+                region, cell
+            ),
+            Self::DeclaredOverlap {
+                region,
+                other,
+                cells,
+            } => write!(
+                f,
+                "region `{}` declares cells {}:{}, which region `{}` declared too",
+                region, cells.start, cells.end, other
+            ),
+            Self::SharedCell {
+                region,
+                other,
+                cell,
+            } => write!(
+                f,
+                "region `{}` touches cell {}, which region `{}` touches too",
+                region, cell, other
+            ),
+        }
+    }
+}
+
+/// Parses a range of cells like `10:20`.
+fn parse_cells(s: &str) -> Option<Range<isize>> {
+    let (start, end) = s.split_once(':')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (start <= end).then_some(start..end)
+}
+
+/// Finds the `@region <name> [<start>:<end>]` directives in the code. Anything
+/// after them on their line is a comment.
+pub fn parse_regions(code: &str) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let mut start = 0;
+
+    for (i, line) in code.split('\n').enumerate() {
+        if let Some(at) = line.find("@region") {
+            let directive = &line[at..];
+            let mut words = directive["@region".len()..].split_whitespace();
+            // not part of a longer word like `@regions`
+            let separated = directive["@region".len()..].starts_with(char::is_whitespace);
+
+            if let (true, Some(name)) = (separated, words.next()) {
+                regions.push(Region {
+                    name: name.to_string(),
+                    cells: words.next().and_then(parse_cells),
+                    span: Span {
+                        start: start + at,
+                        end: start + line.len(),
+                        line: i + 1,
+                        column: line[..at].chars().count() + 1,
+                    },
+                });
+            }
+        }
+
+        start += line.len() + 1;
+    }
+
+    regions
+}
+
+/// A cell read or written by an instruction
+struct Access {
+    cell: isize,
+    span: Span,
+}
+
+/// Follows the pointer through segments, recording every access while its
+/// position is known. Returns the first loop it loses track of it in.
+fn walk<T: TokenExt>(
+    segments: &[Segment<T>],
+    pointer: &mut Option<isize>,
+    accesses: &mut Vec<Access>,
+) -> Option<Span> {
+    let mut lost = None;
+
+    for segment in segments {
+        match segment {
+            Segment::Executable { code, .. } => {
+                for (token, span) in code.tokens.iter().zip(&code.spans) {
+                    let Some(cell) = pointer else {
+                        continue;
+                    };
+
+                    match token.token() {
+                        Token::PointerAdd => *cell += token.count() as isize,
+                        Token::PointerSub => *cell -= token.count() as isize,
+                        _ => accesses.push(Access {
+                            cell: *cell,
+                            span: *span,
+                        }),
+                    }
+                }
+            }
+            Segment::Loop { body, span } => {
+                let Some(cell) = *pointer else {
+                    continue;
+                };
+                // the brackets read the cell
+                accesses.push(Access { cell, span: *span });
+
+                if net_movement(body) == Ok(0) {
+                    // every iteration starts on the same cell
+                    let found = walk(body, &mut Some(cell), accesses);
+                    lost = lost.or(found);
+                } else {
+                    lost = lost.or(Some(*span));
+                    *pointer = None;
+                }
+            }
+        }
+    }
+
+    lost
+}
+
+/// The lowest and highest cells the segments touch relative to where they
+/// start, if the pointer can be followed through all of them.
+pub fn footprint<T: TokenExt>(segments: &[Segment<T>]) -> Option<(isize, isize)> {
+    let mut accesses = Vec::new();
+    if walk(segments, &mut Some(0), &mut accesses).is_some() {
+        return None;
+    }
+
+    let cells = accesses.iter().map(|access| access.cell);
+    Some((cells.clone().min().unwrap_or(0), cells.max().unwrap_or(0)))
+}
+
+/// Works out which cells the code of each region touches and warns about
+/// regions touching cells outside of their declaration or each other's.
+pub fn check<T: TokenExt>(code: &str, segments: &[Segment<T>]) -> RegionReport {
+    let regions = parse_regions(code);
+    let mut usages: Vec<RegionUsage> = regions
+        .iter()
+        .map(|region| RegionUsage {
+            region: region.clone(),
+            touched: BTreeMap::new(),
+        })
+        .collect();
+
+    let mut accesses = Vec::new();
+    let lost_at = walk(segments, &mut Some(0), &mut accesses);
+
+    for access in accesses {
+        // the code before the first region belongs to none
+        let owner = regions.partition_point(|region| region.span.start <= access.span.start);
+        if let Some(usage) = owner.checked_sub(1).map(|i| &mut usages[i]) {
+            usage.touched.entry(access.cell).or_insert(access.span);
+        }
+    }
+
+    let mut warnings = Vec::new();
+
+    for (i, usage) in usages.iter().enumerate() {
+        let name = &usage.region.name;
+
+        if let Some(cells) = &usage.region.cells {
+            if let Some((cell, span)) = usage
+                .touched
+                .iter()
+                .filter(|(cell, _)| !cells.contains(cell))
+                .min_by_key(|(_, span)| span.start)
+            {
+                warnings.push(RegionWarning {
+                    problem: RegionProblem::OutOfRange {
+                        region: name.clone(),
+                        cell: *cell,
+                    },
+                    span: *span,
+                });
+            }
+        }
+
+        for other in &usages[..i] {
+            if let (Some(cells), Some(other_cells)) = (&usage.region.cells, &other.region.cells) {
+                let shared = cells.start.max(other_cells.start)..cells.end.min(other_cells.end);
+                if !shared.is_empty() {
+                    warnings.push(RegionWarning {
+                        problem: RegionProblem::DeclaredOverlap {
+                            region: name.clone(),
+                            other: other.region.name.clone(),
+                            cells: shared,
+                        },
+                        span: usage.region.span,
+                    });
+                    // sharing cells follows from that
+                    continue;
+                }
+            }
+
+            if let Some((cell, span)) = usage
+                .touched
+                .iter()
+                .filter(|(cell, _)| other.touched.contains_key(cell))
+                .min_by_key(|(_, span)| span.start)
+            {
+                warnings.push(RegionWarning {
+                    problem: RegionProblem::SharedCell {
+                        region: name.clone(),
+                        other: other.region.name.clone(),
+                        cell: *cell,
+                    },
+                    span: *span,
+                });
+            }
+        }
+    }
+
+    warnings.sort_by_key(|warning| warning.span.start);
+
+    RegionReport {
+        regions: usages,
+        lost_at,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{File, Repeated};
+
+    fn parse(code: &str) -> File<Repeated> {
+        match code.parse() {
+            Ok(file) => file,
+            Err(e) => match e {},
+        }
+    }
+
+    fn report(code: &str) -> RegionReport {
+        check(code, &parse(code).segments)
+    }
+
+    #[test]
+    fn regions_are_named_with_or_without_cells() {
+        let regions =
+            parse_regions("@region a 0:4\n+\n  @region b more words\n@regions c\n@region d 5:2");
+        let named: Vec<(&str, Option<Range<isize>>)> = regions
+            .iter()
+            .map(|region| (region.name.as_str(), region.cells.clone()))
+            .collect();
+        // a backwards range is no range
+        assert_eq!(named, [("a", Some(0..4)), ("b", None), ("d", None)]);
+        assert_eq!((regions[1].span.line, regions[1].span.column), (3, 3));
+    }
+
+    #[test]
+    fn a_footprint_follows_balanced_loops() {
+        assert_eq!(footprint(&parse("<+>>[-<+>]").segments), Some((-1, 1)));
+        assert_eq!(footprint(&parse("").segments), Some((0, 0)));
+        // after `[>]` the pointer could be anywhere
+        assert_eq!(footprint(&parse("+[>]+").segments), None);
+    }
+
+    #[test]
+    fn a_region_touching_cells_it_did_not_declare_is_reported() {
+        let report = report("@region a 0:2\n+>+>+");
+        assert_eq!(report.regions[0].touched.len(), 3);
+        assert_eq!(
+            report
+                .warnings
+                .iter()
+                .map(|warning| &warning.problem)
+                .collect::<Vec<_>>(),
+            [&RegionProblem::OutOfRange {
+                region: "a".into(),
+                cell: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn overlapping_regions_are_reported_once() {
+        // declared overlaps make touching the same cells beside the point
+        let report = report("@region a 0:3\n+>+\n@region b 2:5\n+<\n@region c\n-");
+        let problems: Vec<&RegionProblem> = report
+            .warnings
+            .iter()
+            .map(|warning| &warning.problem)
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                &RegionProblem::DeclaredOverlap {
+                    region: "b".into(),
+                    other: "a".into(),
+                    cells: 2..3,
+                },
+                &RegionProblem::SharedCell {
+                    region: "c".into(),
+                    other: "a".into(),
+                    cell: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_is_checked_after_the_pointer_is_lost() {
+        let report = report("@region a 0:1\n+[>]\n@region b 1:2\n>>>+");
+        assert_eq!(report.lost_at.map(|span| span.line), Some(2));
+        assert!(report.regions[1].touched.is_empty());
+        assert_eq!(report.warnings, []);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fmt, time::Duration};
+
+use codegen::ast::{count_tokens, File, Segment, Token, TokenExt, Tokens};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+/// The cells the pointer can reach, relative to the cell it starts at
+pub struct TapeUsage {
+    pub lowest: isize,
+    pub highest: isize,
+}
+
+#[derive(Debug, Serialize)]
+/// Statistics about a generated crate, for evaluating optimization options
+pub struct Report {
+    /// The number of Brainfuck instructions in the source
+    pub tokens_before: usize,
+    /// The number of tokens left after optimization
+    pub tokens_after: usize,
+    /// The number of lines in the generated `main.rs`
+    pub generated_lines: usize,
+    /// `None` if a loop moves the pointer by an amount only known at runtime
+    pub tape_usage: Option<TapeUsage>,
+    /// How long `cargo build --release` took, if the crate was built
+    pub compile_time_secs: Option<f64>,
+}
+
+impl Report {
+    pub fn new<T: TokenExt>(
+        in_code: &str,
+        file: &File<T>,
+        generated_lines: usize,
+        compile_time: Option<Duration>,
+    ) -> Self {
+        Self {
+            tokens_before: Token::tokenize(in_code).tokens.len(),
+            tokens_after: count_tokens(&file.segments),
+            generated_lines,
+            tape_usage: tape_usage(&file.segments),
+            compile_time_secs: compile_time.map(|time| time.as_secs_f64()),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Tokens before optimization: {}", self.tokens_before)?;
+        writeln!(f, "Tokens after optimization:  {}", self.tokens_after)?;
+        writeln!(f, "Generated lines of code:    {}", self.generated_lines)?;
+
+        match self.tape_usage {
+            Some(usage) => writeln!(
+                f,
+                "Estimated tape usage:       {} cells ({} to {} from the start)",
+                usage.highest - usage.lowest + 1,
+                usage.lowest,
+                usage.highest
+            )?,
+            None => writeln!(
+                f,
+                "Estimated tape usage:       unknown (a loop moves the pointer)"
+            )?,
+        }
+
+        if let Some(secs) = self.compile_time_secs {
+            writeln!(f, "Compile time:               {:.2}s", secs)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Follows the pointer through the program without running it. Loops that
+/// leave the pointer where it was are fine, any other loop makes the usage unknown.
+fn tape_usage<T: TokenExt>(segments: &[Segment<T>]) -> Option<TapeUsage> {
+    let mut offset = 0;
+    let mut usage = TapeUsage {
+        lowest: 0,
+        highest: 0,
+    };
+
+    walk_pointer(segments, &mut offset, &mut usage)?;
+
+    Some(usage)
+}
+
+fn walk_pointer<T: TokenExt>(
+    segments: &[Segment<T>],
+    offset: &mut isize,
+    usage: &mut TapeUsage,
+) -> Option<()> {
+    for segment in segments {
+        match segment {
+            Segment::Executable {
+                code: Tokens { tokens, .. },
+                ..
+            } => {
+                for token in tokens {
+                    match token.token() {
+                        Token::PointerAdd => *offset += token.count() as isize,
+                        Token::PointerSub => *offset -= token.count() as isize,
+                        _ => continue,
+                    }
+
+                    usage.lowest = usage.lowest.min(*offset);
+                    usage.highest = usage.highest.max(*offset);
+                }
+            }
+            Segment::Loop { body, .. } => {
+                let start = *offset;
+                walk_pointer(body, offset, usage)?;
+
+                if *offset != start {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(())
+}
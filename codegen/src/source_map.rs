@@ -0,0 +1,138 @@
+//! Source maps, which lead from the code generated for a program back to its
+//! Brainfuck source, so that a panic in a compiled program (an overflow in a
+//! debug build, or the pointer running off the tape) can be blamed on the
+//! instruction behind it. `bf symbolize` reads them.
+//!
+//! With `--source-map`, the code generator puts a [`marker`] in front of the
+//! code of every instruction and loop, and [`Placer::place`] turns the markers
+//! into line breaks as the code is written, noting the line each one ends up
+//! on. Every instruction's code then starts a line of its own, and a panic's
+//! line is enough to find its instruction.
+
+use crate::ast::Span;
+
+/// What the name of every marker starts with
+pub const MARKER: &str = "__bf_source_";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Where the code of each instruction of a program ended up
+pub struct SourceMap {
+    /// The Brainfuck source file, as copied into the generated crate
+    pub source: String,
+    /// In the order they were written, which is line by line for each file
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The code of an instruction, or of a run of them
+pub struct Entry {
+    /// The generated file, like `src/main.rs`
+    pub file: String,
+    /// The line of the file the code starts on
+    pub line: usize,
+    pub span: Span,
+}
+
+impl SourceMap {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// The code `line` of `file` belongs to: the last code starting on that
+    /// line or before it.
+    pub fn locate(&self, file: &str, line: usize) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.file == file)
+            .take_while(|entry| entry.line <= line)
+            .last()
+    }
+}
+
+/// The name of the marker for the code of the instructions at `span`.
+pub fn marker(span: Span) -> String {
+    format!(
+        "{}{}_{}_{}_{}",
+        MARKER, span.start, span.end, span.line, span.column
+    )
+}
+
+/// Reads the span back out of what follows [`MARKER`] in a marker, returning
+/// it and the length of the rest of the marker.
+fn parse_marker(rest: &str) -> Option<(Span, usize)> {
+    let len = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '_'))
+        .unwrap_or(rest.len());
+    let numbers: Vec<usize> = rest[..len]
+        .split('_')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+
+    match numbers[..] {
+        [start, end, line, column] => Some((
+            Span {
+                start,
+                end,
+                line,
+                column,
+            },
+            len,
+        )),
+        _ => None,
+    }
+}
+
+/// Places the markers of one generated file, a piece of code at a time.
+pub struct Placer {
+    file: String,
+    /// The line the next piece starts on
+    line: usize,
+}
+
+impl Placer {
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            line: 1,
+        }
+    }
+
+    /// Replaces the markers in the next piece of the file with line breaks,
+    /// adding an entry to `map` for each.
+    pub fn place(&mut self, code: &str, map: &mut SourceMap) -> String {
+        let mut out = String::with_capacity(code.len());
+        let mut rest = code;
+
+        while let Some(at) = rest.find(MARKER) {
+            let (before, marked) = rest.split_at(at);
+            self.line += before.matches('\n').count();
+            out.push_str(before);
+
+            let marked = &marked[MARKER.len()..];
+            match parse_marker(marked) {
+                Some((span, len)) => {
+                    self.line += 1;
+                    out.push('\n');
+                    map.entries.push(Entry {
+                        file: self.file.clone(),
+                        line: self.line,
+                        span,
+                    });
+                    rest = &marked[len..];
+                }
+                None => {
+                    out.push_str(MARKER);
+                    rest = marked;
+                }
+            }
+        }
+
+        self.line += rest.matches('\n').count();
+        out.push_str(rest);
+        out
+    }
+}
@@ -0,0 +1,136 @@
+use clap::ValueEnum;
+
+use crate::golf::{add_constant, run, Side};
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// How [`synthesize_print`] builds a program
+pub enum Strategy {
+    /// Print every byte from one cell, changing it by the difference to the
+    /// previous byte (with a multiplication loop when that is shorter)
+    SingleCell,
+    /// Set up several cells near the values that are printed with one
+    /// multiplication loop, then print each byte from the cell that is
+    /// cheapest to reach and adjust
+    MultiCell,
+    #[default]
+    /// Try every strategy and keep the shortest program
+    Best,
+}
+
+/// Generates a short Brainfuck program that prints `text`. The program starts
+/// on a zeroed tape and does not read input.
+pub fn synthesize_print(text: &str, strategy: Strategy) -> String {
+    synthesize_print_bytes(text.as_bytes(), strategy)
+}
+
+/// Like [`synthesize_print`], for output that is not necessarily UTF-8.
+pub fn synthesize_print_bytes(bytes: &[u8], strategy: Strategy) -> String {
+    match strategy {
+        Strategy::SingleCell => single_cell(bytes),
+        Strategy::MultiCell => multi_cell(bytes),
+        Strategy::Best => [single_cell(bytes), multi_cell(bytes)]
+            .into_iter()
+            .min_by_key(|program| program.len())
+            .unwrap_or_default(),
+    }
+}
+
+fn single_cell(bytes: &[u8]) -> String {
+    let mut program = String::new();
+    let mut value = 0u8;
+
+    for byte in bytes {
+        // cell 1 is never used, so it is always 0
+        program.push_str(&add_constant(byte.wrapping_sub(value), Side::Right));
+        program.push('.');
+        value = *byte;
+    }
+
+    program
+}
+
+fn multi_cell(bytes: &[u8]) -> String {
+    let mut distinct = bytes.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+
+    let mut best: Option<String> = None;
+
+    for cells in 1..=distinct.len().min(8) {
+        let centers = centers(&distinct, cells);
+
+        for times in 2..=16u8 {
+            let factors: Vec<u8> = centers
+                .iter()
+                .map(|center| ((*center as f64 / times as f64).round() as u8).max(1))
+                .collect();
+
+            let program = print_from(bytes, times, &factors);
+            if best.as_ref().is_none_or(|best| program.len() < best.len()) {
+                best = Some(program);
+            }
+        }
+    }
+
+    best.unwrap_or_default()
+}
+
+/// Splits the sorted bytes into `count` groups at the largest gaps between
+/// them, and returns the middle of each group.
+fn centers(distinct: &[u8], count: usize) -> Vec<u8> {
+    let mut gaps: Vec<usize> = (1..distinct.len()).collect();
+    gaps.sort_by_key(|&i| std::cmp::Reverse(distinct[i] - distinct[i - 1]));
+
+    let mut cuts: Vec<usize> = gaps.into_iter().take(count - 1).collect();
+    cuts.sort_unstable();
+
+    let mut centers = Vec::new();
+    let mut start = 0;
+    for end in cuts.into_iter().chain([distinct.len()]) {
+        centers.push(distinct[(start + end - 1) / 2]);
+        start = end;
+    }
+
+    centers
+}
+
+/// Sets cell `i + 1` to `times * factors[i]` with a loop counting down on
+/// cell 0, then prints each byte from whichever cell (including cell 0, which
+/// is 0 after the loop) costs the fewest instructions to move to and adjust.
+fn print_from(bytes: &[u8], times: u8, factors: &[u8]) -> String {
+    let mut program = "+".repeat(times as usize);
+    program.push('[');
+    for factor in factors {
+        program.push('>');
+        program.push_str(&"+".repeat(*factor as usize));
+    }
+    program.push_str(&"<".repeat(factors.len()));
+    program.push_str("-]");
+
+    let mut values: Vec<u8> = [0]
+        .into_iter()
+        .chain(factors.iter().map(|factor| times.wrapping_mul(*factor)))
+        .collect();
+    let mut pointer = 0usize;
+
+    for byte in bytes {
+        let cost =
+            |cell: usize| pointer.abs_diff(cell) + run(byte.wrapping_sub(values[cell])).len();
+        let cell = (0..values.len())
+            .min_by_key(|&cell| cost(cell))
+            .unwrap_or(0);
+
+        if cell > pointer {
+            program.push_str(&">".repeat(cell - pointer));
+        } else {
+            program.push_str(&"<".repeat(pointer - cell));
+        }
+        program.push_str(&run(byte.wrapping_sub(values[cell])));
+        program.push('.');
+
+        pointer = cell;
+        values[cell] = *byte;
+    }
+
+    program
+}
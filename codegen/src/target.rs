@@ -0,0 +1,53 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// The contents of a `.cargo/config.toml` that makes the generated crate
+/// build for `triple`, with the linker settings cross-compiling usually needs.
+/// With `static_link`, the C runtime is linked statically on every target that has one.
+pub fn cargo_config(triple: &str, static_link: bool) -> Result<String> {
+    let mut parts = triple.split('-');
+    let (Some(arch), Some(_)) = (parts.next(), parts.next()) else {
+        return Err(eyre!(
+            "`{}` is not a target triple, expected something like `x86_64-unknown-linux-musl`",
+            triple
+        ));
+    };
+
+    let cross = arch != std::env::consts::ARCH;
+
+    let mut config = format!(
+        "# Install the target first with `rustup target add {triple}`\n\n\
+         [build]\n\
+         target = \"{triple}\"\n"
+    );
+
+    let mut target = Vec::new();
+
+    if triple.starts_with("wasm32") {
+        if triple.contains("wasi") {
+            target.push("# run the program with a WASI runtime".to_string());
+            target.push("runner = \"wasmtime\"".to_string());
+        }
+    } else {
+        if static_link || triple.ends_with("-linux-musl") {
+            target.push("rustflags = [\"-C\", \"target-feature=+crt-static\"]".to_string());
+        }
+
+        if triple.ends_with("-linux-musl") && cross {
+            target.push(format!("linker = \"{}-linux-musl-gcc\"", arch));
+        } else if triple.ends_with("-linux-gnu") && cross {
+            target.push(format!("linker = \"{}-linux-gnu-gcc\"", arch));
+        } else if triple.ends_with("-windows-gnu") {
+            target.push(format!("linker = \"{}-w64-mingw32-gcc\"", arch));
+        }
+    }
+
+    if !target.is_empty() {
+        config.push_str(&format!("\n[target.{}]\n", triple));
+        for line in target {
+            config.push_str(&line);
+            config.push('\n');
+        }
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,124 @@
+//! How much `bf` and `bf-codegen` print to stderr besides their errors, picked
+//! with the same `-q`, `-v` and `-vv` flags in both. The level decides which
+//! of the [`warn`], [`status`] and [`info`] messages get through, and what
+//! the tracing subscriber shows.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use clap::{ArgAction, Args};
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{prelude::*, EnvFilter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors
+    Quiet,
+    /// Warnings and what a run stopped at, the default
+    Normal,
+    /// Also what the tools are doing, like the semantics a program runs with
+    Verbose,
+    /// Also every tracing event, for debugging the tools themselves
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, Default, Args)]
+pub struct VerbosityFlags {
+    #[clap(short, long, global = true, action = ArgAction::Count)]
+    /// Print more of what is going on to stderr, `-vv` for everything
+    pub verbose: u8,
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    /// Print nothing to stderr but errors
+    pub quiet: bool,
+}
+
+impl VerbosityFlags {
+    pub fn level(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+impl Verbosity {
+    /// The tracing filter for the level. Without `-q` or `-v`, `RUST_LOG` still
+    /// picks one.
+    pub fn filter(self) -> EnvFilter {
+        match self {
+            Self::Quiet => EnvFilter::new("error"),
+            Self::Normal => {
+                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"))
+            }
+            Self::Verbose => EnvFilter::new("info"),
+            Self::Debug => EnvFilter::new("trace"),
+        }
+    }
+
+    /// The flag giving this level, if it is not the default.
+    pub fn flag(self) -> Option<&'static str> {
+        match self {
+            Self::Quiet => Some("-q"),
+            Self::Normal => None,
+            Self::Verbose => Some("-v"),
+            Self::Debug => Some("-vv"),
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets the level for the rest of the process and installs the tracing
+/// subscriber with its filter.
+pub fn init(level: Verbosity) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_target(false)
+                .with_line_number(false)
+                .with_file(true)
+                .compact(),
+        )
+        .with(level.filter())
+        .with(ErrorLayer::default())
+        .init();
+}
+
+/// The level set with [`init`].
+pub fn level() -> Verbosity {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Debug,
+    }
+}
+
+/// Prints `warning: <message>` unless the level is [`Verbosity::Quiet`].
+pub fn warn(message: impl fmt::Display) {
+    if level() >= Verbosity::Normal {
+        eprintln!("warning: {}", message);
+    }
+}
+
+/// Prints a line saying where something stopped or what it is waiting for,
+/// unless the level is [`Verbosity::Quiet`].
+pub fn status(message: impl fmt::Display) {
+    if level() >= Verbosity::Normal {
+        eprintln!("{}", message);
+    }
+}
+
+/// Prints a line only with `-v` or `-vv`.
+pub fn info(message: impl fmt::Display) {
+    if level() >= Verbosity::Verbose {
+        eprintln!("{}", message);
+    }
+}
@@ -0,0 +1,114 @@
+//! `lint::fix` only deletes what changes nothing, keeps the comments and the
+//! lines where they were, and leaves nothing more to fix.
+
+use std::fs;
+
+use codegen::{
+    ast::{File, Repeated},
+    equiv::{self, Verdict},
+    lint::{fix, lint, LintConfig, Rule, Severity},
+};
+
+/// Long enough for every sample but mandelbrot.b and hanoi.bf to halt
+const MAX_STEPS: u64 = 10_000_000;
+
+fn fixed(code: &str) -> String {
+    fix(code, &LintConfig::default())
+}
+
+fn parse(code: &str) -> File<Repeated> {
+    match code.parse() {
+        Ok(file) => file,
+        Err(e) => match e {},
+    }
+}
+
+/// Checks everything a fix promises about `code`, returning the fixed code.
+fn round_trip(name: &str, code: &str) -> String {
+    let after = fixed(code);
+
+    assert_eq!(
+        fixed(&after),
+        after,
+        "fixing {} twice changed it again",
+        name
+    );
+    let left: Vec<_> = lint(&after, &LintConfig::default())
+        .into_iter()
+        .filter(|diagnostic| !diagnostic.fix.is_empty())
+        .collect();
+    assert!(left.is_empty(), "{} still has fixes: {:?}", name, left);
+
+    // only instructions are deleted
+    let comments = |code: &str| -> Vec<String> {
+        code.lines()
+            .map(|line| line.chars().filter(|c| !"+-<>[].,".contains(*c)).collect())
+            .collect()
+    };
+    assert_eq!(comments(&after), comments(code), "{} lost a comment", name);
+
+    let inputs = [b"".to_vec(), b"Hello, World!\n".to_vec(), b"12\n".to_vec()];
+    match equiv::check(&parse(code), &parse(&after), &inputs, MAX_STEPS) {
+        Verdict::Same { .. } | Verdict::Inconclusive { .. } => {}
+        verdict @ Verdict::Different { .. } => {
+            panic!("fixing {} changed what it prints: {:?}", name, verdict)
+        }
+    }
+
+    after
+}
+
+#[test]
+fn cancelling_adds_are_deleted_but_moves_are_not() {
+    assert_eq!(
+        round_trip("pairs", "add one +- then back\n+-+.\n"),
+        "add one  then back\n+.\n"
+    );
+    // `<` on the first cell does nothing, so `<>` may move the pointer
+    assert_eq!(round_trip("moves", "<>+."), "<>+.");
+}
+
+#[test]
+fn fixes_are_repeated_until_none_are_left() {
+    // removing the dead loop leaves `-` as trailing dead code
+    assert_eq!(round_trip("dead loop", "+[-][]-"), "+[-]");
+    assert_eq!(round_trip("dead loop", "+[-][]-."), "+[-]-.");
+}
+
+#[test]
+fn a_dead_loop_keeps_its_text() {
+    assert_eq!(
+        round_trip("dead loop", "+[-]\n[never entered so a comment]\n."),
+        "+[-]\nnever entered so a comment\n."
+    );
+}
+
+#[test]
+fn trailing_dead_code_is_deleted() {
+    assert_eq!(round_trip("trailing", "+.+++> done"), "+. done");
+}
+
+#[test]
+fn rules_that_are_off_are_not_fixed() {
+    let config = LintConfig {
+        overrides: vec![(Rule::TrailingDeadCode, Severity::Off)],
+    };
+    assert_eq!(fix("+.+-+", &config), "+.+");
+}
+
+#[test]
+fn fixing_the_samples_keeps_what_they_print() {
+    let dir = format!("{}/../sources.b", env!("CARGO_MANIFEST_DIR"));
+    let mut changed = 0;
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let code = fs::read_to_string(&path).unwrap();
+        if round_trip(&path.display().to_string(), &code) != code {
+            changed += 1;
+        }
+    }
+
+    // hanoi.bf and collatz.b, at least, have something to fix
+    assert!(changed >= 2);
+}
@@ -0,0 +1,79 @@
+//! Regenerating a crate over one that is already there, which `bfgen.lock`
+//! guards.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// A directory of its own for the crates of `test`.
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bfgen-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Generates a crate from `+++.` into `output`.
+fn generate(output: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_codegen"))
+        .arg("-e")
+        .arg("+++.")
+        .arg(output)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn a_changed_crate_is_only_overwritten_with_force() {
+    let dir = scratch("force");
+    let output = dir.join("out");
+    assert!(generate(&output, &[]).status.success());
+
+    // regenerating an untouched crate is fine
+    assert!(generate(&output, &[]).status.success());
+
+    let manifest = output.join("Cargo.toml");
+    let generated = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, "edited by hand").unwrap();
+    let refused = generate(&output, &[]);
+    assert!(!refused.status.success());
+    let error = String::from_utf8_lossy(&refused.stderr);
+    assert!(error.contains("Cargo.toml"), "{}", error);
+    assert!(error.contains("--force"), "{}", error);
+    assert_eq!(fs::read_to_string(&manifest).unwrap(), "edited by hand");
+
+    assert!(generate(&output, &["--force"]).status.success());
+    assert_eq!(fs::read_to_string(&manifest).unwrap(), generated);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn kept_files_survive_regenerating_the_crate() {
+    let dir = scratch("keep");
+    let output = dir.join("out");
+    assert!(generate(&output, &[]).status.success());
+
+    let readme = output.join("README.md");
+    fs::write(&readme, "my own readme").unwrap();
+    let test = output.join("tests").join("mine.rs");
+    fs::create_dir_all(test.parent().unwrap()).unwrap();
+    fs::write(&test, "#[test] fn mine() {}").unwrap();
+
+    let kept = generate(&output, &["--keep", "README.md", "--keep", "tests/**"]);
+    assert!(
+        kept.status.success(),
+        "{}",
+        String::from_utf8_lossy(&kept.stderr)
+    );
+    assert_eq!(fs::read_to_string(&readme).unwrap(), "my own readme");
+    assert!(test.exists());
+
+    // the lock still has the generated readme, so it needs `--keep` again
+    assert!(!generate(&output, &[]).status.success());
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,220 @@
+//! Assertions written into a program's comments, so a Brainfuck program can
+//! check its own state like a unit test:
+//!
+//! ```text
+//! ++++++++[>+++++++++<-]>  @assert cell[1] == 72
+//! .                        @assert-end ptr == 1
+//! ```
+//!
+//! An `@assert` is checked whenever execution reaches it, an `@assert-end`
+//! once the program halts. Either one takes the rest of its line, which is
+//! not executed while assertions are enabled. The subject is `cell[N]`, the
+//! current `cell` or the pointer `ptr`, compared with `==`, `!=`, `<`, `<=`,
+//! `>` or `>=` to a number or a character like `'H'`.
+
+use std::{cmp::Ordering, fmt};
+
+use crate::{cell::Cell, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subject {
+    Cell(usize),
+    /// The cell under the pointer
+    CurrentCell,
+    Pointer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    /// The position of the `@` in the code
+    pub start: usize,
+    /// The position right after the directive, at the end of its line
+    pub end: usize,
+    /// The line the directive is on, counting from 1
+    pub line: usize,
+    /// Whether it is only checked once the program halts
+    pub at_end: bool,
+    pub subject: Subject,
+    pub comparison: Comparison,
+    pub value: usize,
+    /// The condition as written
+    pub text: String,
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cell(cell) => write!(f, "cell[{}]", cell),
+            Self::CurrentCell => write!(f, "the current cell"),
+            Self::Pointer => write!(f, "the pointer"),
+        }
+    }
+}
+
+impl Comparison {
+    /// The operators as written
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    /// Whether the comparison holds for a value that compares to the
+    /// expected one by `ordering`.
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Self::Equal => ordering.is_eq(),
+            Self::NotEqual => ordering.is_ne(),
+            Self::Less => ordering.is_lt(),
+            Self::LessOrEqual => ordering.is_le(),
+            Self::Greater => ordering.is_gt(),
+            Self::GreaterOrEqual => ordering.is_ge(),
+        }
+    }
+}
+
+fn parse_subject(s: &str) -> Result<Subject> {
+    if s == "cell" {
+        return Ok(Subject::CurrentCell);
+    }
+    if s == "ptr" {
+        return Ok(Subject::Pointer);
+    }
+
+    let cell = s
+        .strip_prefix("cell[")
+        .and_then(|s| s.strip_suffix(']'))
+        .and_then(|cell| cell.trim().parse::<usize>().ok())
+        .ok_or_else(|| format!("`{}` is not `cell[N]`, `cell` or `ptr`", s))?;
+    Ok(Subject::Cell(cell))
+}
+
+fn parse_value(s: &str) -> Result<usize> {
+    let mut chars = s.chars();
+    if let (Some('\''), Some(c), Some('\''), None) =
+        (chars.next(), chars.next(), chars.next(), chars.next())
+    {
+        return Ok(c as usize);
+    }
+
+    s.parse()
+        .map_err(|_| format!("`{}` is not a number or a character like 'H'", s).into())
+}
+
+/// Parses the condition after `@assert`, like `cell[5] == 72`.
+fn parse_condition(text: &str) -> Result<(Subject, Comparison, usize)> {
+    let (at, operator, comparison) = Comparison::OPERATORS
+        .iter()
+        .filter_map(|(operator, comparison)| {
+            text.find(operator).map(|at| (at, *operator, *comparison))
+        })
+        // the first operator in the text, and the longest one there
+        .min_by_key(|(at, operator, _)| (*at, std::cmp::Reverse(operator.len())))
+        .ok_or_else(|| format!("`{}` has no comparison like `==`", text))?;
+
+    let subject = parse_subject(text[..at].trim())?;
+    let value = parse_value(text[at + operator.len()..].trim())?;
+
+    Ok((subject, comparison, value))
+}
+
+/// Finds every `@assert` and `@assert-end` directive in the code, failing on
+/// the first one that cannot be parsed. Positions are counted in characters,
+/// like the interpreter counts them.
+pub fn parse_assertions(code: &str) -> Result<Vec<Assertion>> {
+    let mut assertions = Vec::new();
+    let mut start = 0;
+
+    for (i, line) in code.split('\n').enumerate() {
+        let mut rest = line;
+        let mut offset = 0;
+
+        while let Some(at) = rest.find("@assert") {
+            let directive = &rest[at..];
+            let (at_end, condition) = if let Some(condition) = directive.strip_prefix("@assert-end")
+            {
+                (true, condition)
+            } else {
+                (false, &directive["@assert".len()..])
+            };
+
+            // part of a longer word like `@asserted`
+            if !condition.starts_with(char::is_whitespace) {
+                offset += at + 1;
+                rest = &rest[at + 1..];
+                continue;
+            }
+
+            let text = condition.trim().to_string();
+            let (subject, comparison, value) = parse_condition(&text)
+                .map_err(|e| format!("line {}: invalid assertion: {}", i + 1, e))?;
+
+            let position = start + line[..offset + at].chars().count();
+            assertions.push(Assertion {
+                start: position,
+                end: position + directive.chars().count(),
+                line: i + 1,
+                at_end,
+                subject,
+                comparison,
+                value,
+                text,
+            });
+            break;
+        }
+
+        start += line.chars().count() + 1;
+    }
+
+    Ok(assertions)
+}
+
+impl Assertion {
+    /// Checks the assertion against the state of a program, describing how it
+    /// failed if it does not hold.
+    pub fn check<C: Cell>(&self, memory: &[C], pointer: usize) -> Result {
+        let (ordering, actual) = match self.subject {
+            Subject::Cell(cell) => match memory.get(cell) {
+                Some(value) => (value.compare(self.value), value.to_string()),
+                None => {
+                    return Err(format!(
+                        "assertion `{}` on line {} is about cell {}, past the {} cells of memory",
+                        self.text,
+                        self.line,
+                        cell,
+                        memory.len()
+                    )
+                    .into())
+                }
+            },
+            Subject::CurrentCell => (
+                memory[pointer].compare(self.value),
+                memory[pointer].to_string(),
+            ),
+            Subject::Pointer => (pointer.cmp(&self.value), pointer.to_string()),
+        };
+
+        if self.comparison.holds(ordering) {
+            return Ok(());
+        }
+
+        Err(format!(
+            "assertion `{}` on line {} failed: {} is {}",
+            self.text, self.line, self.subject, actual
+        )
+        .into())
+    }
+}
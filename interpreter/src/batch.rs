@@ -0,0 +1,84 @@
+//! `bf batch`: runs many programs, each on its own input, through
+//! [`sandbox::run`], like a judge working through its submissions. The report
+//! of each run is printed as a line of JSON, in the order of the programs, and
+//! the metrics of all of them (see [`brainfuck_extended::metrics`]) can be
+//! written to a file for Prometheus, like the node exporter's textfile
+//! collector reads.
+
+use std::{
+    fs::{self, File},
+    io::{stdin, stdout, BufRead, BufReader, Write},
+    path::Path,
+    time::Duration,
+};
+
+use brainfuck_extended::{
+    metrics::RunMetrics,
+    sandbox::{self, SandboxLimits},
+    Result,
+};
+
+use crate::BatchOptions;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// A program to run, in the words of bf-server's `POST /run`
+struct Job {
+    code: String,
+    #[serde(default)]
+    input: String,
+}
+
+pub fn batch(options: BatchOptions) -> Result {
+    let defaults = SandboxLimits::default();
+    let limits = SandboxLimits {
+        max_steps: options.max_steps.unwrap_or(defaults.max_steps),
+        timeout: options
+            .timeout_ms
+            .map_or(defaults.timeout, Duration::from_millis),
+        max_output: options.max_output.unwrap_or(defaults.max_output),
+        io_mode: options.io,
+        ..defaults
+    };
+
+    let jobs: Box<dyn BufRead> = match &options.jobs {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        )),
+        None => Box::new(stdin().lock()),
+    };
+
+    let mut metrics = RunMetrics::new("bf batch", "batch");
+    let mut out = stdout().lock();
+    for (i, line) in jobs.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let job: Job = serde_json::from_str(&line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+
+        let report = sandbox::run(&job.code, job.input.as_bytes(), &limits);
+        metrics.record(&report);
+        writeln!(out, "{}", serde_json::to_string(&report)?)?;
+    }
+    out.flush()?;
+
+    if let Some(path) = &options.metrics {
+        let mut text = String::new();
+        metrics.render(&mut text, &[]);
+        write_atomically(path, &text)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the file next to where it goes first, so that nothing reading it
+/// ever sees half of it.
+fn write_atomically(path: &Path, text: &str) -> Result {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, text)?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
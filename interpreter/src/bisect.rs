@@ -0,0 +1,292 @@
+//! Optimization fuel, for finding the transformation of the bytecode compiler
+//! behind a miscompile. Every transformation (merging a run of `+` or `>`,
+//! turning a loop into a clear, scan or copy op, fusing a superinstruction)
+//! takes one unit of [`OptFuel`], and once it runs out the rest of the program
+//! is compiled as is. `bf bisect-opt` then [`bisect`]s the fuel: with none the
+//! program does what each instruction says, and the first amount of fuel
+//! after which it does something else points at the transformation to blame.
+//!
+//! The transformations also belong to [`Pass`]es, which the compiler can
+//! stop after, to see what each one does to a program.
+//!
+//! Hot loops (see [`crate::hot`]) are run at once while the program runs, so
+//! they take no fuel and stay off during a bisection. Running with
+//! `--hot-loop-threshold 0` rules them out.
+
+use std::fmt;
+
+use crate::{
+    bytecode::{Machine, Program, Status},
+    fusion::{Fusion, FusionTable},
+    pragma::Effective,
+    Dispatch, Result,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+/// The kinds of transformations, in the order the compiler applies them. The
+/// program after a pass is the one compiled with only the transformations of
+/// that pass and the ones before it
+pub enum Pass {
+    /// None at all, an op for every instruction
+    Parse,
+    /// Merging runs of `+`/`-` and `<`/`>` into single ops
+    Merge,
+    /// Turning loops like `[-]` into clear ops
+    Clear,
+    /// Turning loops like `[->+<]` into copy ops
+    Copy,
+    /// Turning loops like `[>]` into scan ops
+    Scan,
+    #[default]
+    /// Fusing superinstructions (see [`crate::fusion`])
+    Fuse,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Pass::Parse,
+        Pass::Merge,
+        Pass::Clear,
+        Pass::Copy,
+        Pass::Scan,
+        Pass::Fuse,
+    ];
+
+    /// Its name, as given to `--dump-ir-after`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Parse => "parse",
+            Pass::Merge => "merge",
+            Pass::Clear => "clear",
+            Pass::Copy => "copy",
+            Pass::Scan => "scan",
+            Pass::Fuse => "fuse",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A change the bytecode compiler makes to the ops it reads
+pub enum Transformation {
+    /// An `add` or `move` merged into the one before it
+    Merge,
+    /// A loop like `[-]` turned into a clear op
+    Clear,
+    /// A clear op merged into the clear op two cells to its left
+    MergeClears,
+    /// A loop like `[>]` turned into a scan op
+    Scan,
+    /// A loop like `[->+<]` turned into a copy op
+    Copy,
+    Fuse(Fusion),
+}
+
+impl fmt::Display for Transformation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Merge => write!(f, "merging an add or move into the one before it"),
+            Self::Clear => write!(f, "turning a loop like `[-]` into a clear op"),
+            Self::MergeClears => write!(f, "merging a clear op into the one before it"),
+            Self::Scan => write!(f, "turning a loop like `[>]` into a scan op"),
+            Self::Copy => write!(f, "turning a loop like `[->+<]` into a copy op"),
+            Self::Fuse(fusion) => write!(f, "fusing `{}` into a superinstruction", fusion),
+        }
+    }
+}
+
+impl Transformation {
+    /// The pass it belongs to
+    pub fn pass(&self) -> Pass {
+        match self {
+            Self::Merge => Pass::Merge,
+            Self::Clear | Self::MergeClears => Pass::Clear,
+            Self::Copy => Pass::Copy,
+            Self::Scan => Pass::Scan,
+            Self::Fuse(_) => Pass::Fuse,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// How many transformations the bytecode compiler may apply
+pub struct OptFuel {
+    /// How many it may apply in total, `None` for any number
+    pub limit: Option<u64>,
+    /// The last pass whose transformations it applies
+    pub until: Pass,
+    /// How many it applied so far
+    pub used: u64,
+    /// The last one it applied, and the source position of the ops it changed
+    pub last: Option<(Transformation, usize)>,
+}
+
+impl OptFuel {
+    /// Fuel for at most `limit` transformations, or any number with `None`
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Takes the fuel for a transformation of the ops at `position`, returning
+    /// whether there was any left.
+    pub(crate) fn apply(&mut self, transformation: Transformation, position: usize) -> bool {
+        if transformation.pass() > self.until || self.limit.is_some_and(|limit| self.used >= limit)
+        {
+            return false;
+        }
+
+        self.used += 1;
+        self.last = Some((transformation, position));
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What a run of a program did, for comparing runs
+pub struct Outcome {
+    pub output: Vec<u8>,
+    /// How the run ended, like `halted` or the error it failed with
+    pub end: String,
+    /// Whether it halted or failed, rather than running out of fuel
+    pub finished: bool,
+}
+
+impl Outcome {
+    /// Runs `program` on a copy of `machine` for up to `fuel` ops.
+    pub fn of(program: &Program, machine: &Machine, fuel: u64) -> Self {
+        Self::with_dispatch(program, machine, Dispatch::Match, fuel)
+    }
+
+    /// Like [`Outcome::of`], but with `dispatch`.
+    pub fn with_dispatch(
+        program: &Program,
+        machine: &Machine,
+        dispatch: Dispatch,
+        fuel: u64,
+    ) -> Self {
+        let mut machine = machine.clone();
+        let mut left = fuel;
+        let mut output = Vec::new();
+
+        let (end, finished) = loop {
+            let status = machine.run(program, dispatch, &mut left);
+            output.append(&mut machine.output);
+
+            match status {
+                Ok(Status::Output) => {}
+                Ok(Status::Halted) => break ("halted".to_string(), true),
+                Ok(Status::NeedsInput) => break ("needed more input".to_string(), true),
+                Ok(Status::OutOfFuel) => {
+                    break (format!("did not halt within {} ops", fuel), false)
+                }
+                Err(e) => break (format!("failed: {}", e), true),
+            }
+        };
+
+        Self {
+            output,
+            end,
+            finished,
+        }
+    }
+
+    /// Whether two runs may be of programs that do the same. A run that was
+    /// cut short only has to have printed the start of what the other did,
+    /// since fewer ops get further with the same fuel.
+    pub fn agrees(&self, other: &Self) -> bool {
+        match (self.finished, other.finished) {
+            (true, true) => self == other,
+            (false, true) => other.output.starts_with(&self.output),
+            (true, false) => self.output.starts_with(&other.output),
+            (false, false) => {
+                self.output.starts_with(&other.output) || other.output.starts_with(&self.output)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The first transformation that changes what a program does
+pub struct Culprit {
+    /// How many transformations come before it, plus one
+    pub number: u64,
+    pub transformation: Transformation,
+    /// The source position of the ops it changed
+    pub position: usize,
+    /// What the program did without it, and with it
+    pub before: Outcome,
+    pub after: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The result of [`bisect`]
+pub struct Bisection {
+    /// How many transformations the compiler applies with unlimited fuel
+    pub total: u64,
+    /// `None` if the program does the same with all of them as with none
+    pub culprit: Option<Culprit>,
+}
+
+/// Compiles `code` with `fuel`, returning what the fuel was used for.
+fn compile(
+    code: &str,
+    fusions: &FusionTable,
+    semantics: &Effective,
+    limit: Option<u64>,
+) -> Result<(Program, OptFuel)> {
+    let mut fuel = OptFuel::new(limit);
+    let program = Program::compile_with_fuel(code, fusions, Vec::new(), semantics, &mut fuel)?;
+    Ok((program, fuel))
+}
+
+/// Binary-searches the optimization fuel for the first transformation after
+/// which `code` does something else on `machine` than it does
+/// unoptimized, running it for up to `fuel` ops each time. This assumes that
+/// once the program goes wrong, more transformations do not set it right.
+pub fn bisect(
+    code: &str,
+    fusions: &FusionTable,
+    semantics: &Effective,
+    machine: &Machine,
+    fuel: u64,
+) -> Result<Bisection> {
+    let outcome = |limit| -> Result<Outcome> {
+        let (program, _) = compile(code, fusions, semantics, Some(limit))?;
+        Ok(Outcome::of(&program, machine, fuel))
+    };
+
+    let total = compile(code, fusions, semantics, None)?.1.used;
+    let reference = outcome(0)?;
+    if outcome(total)?.agrees(&reference) {
+        return Ok(Bisection {
+            total,
+            culprit: None,
+        });
+    }
+
+    // the program does the same with `same` transformations, but not `differs`
+    let (mut same, mut differs) = (0, total);
+    while differs - same > 1 {
+        let middle = same + (differs - same) / 2;
+        if outcome(middle)?.agrees(&reference) {
+            same = middle;
+        } else {
+            differs = middle;
+        }
+    }
+
+    let (_, used) = compile(code, fusions, semantics, Some(differs))?;
+    let (transformation, position) = used.last.expect("at least one was applied");
+    Ok(Bisection {
+        total,
+        culprit: Some(Culprit {
+            number: differs,
+            transformation,
+            position,
+            before: outcome(same)?,
+            after: outcome(differs)?,
+        }),
+    })
+}
@@ -0,0 +1,77 @@
+//! Precomputed pairs of matching `[` and `]`.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
+pub struct BracketTable {
+    /// For every position in the code, the position of the matching bracket
+    /// if there is a matched bracket there
+    matches: Vec<Option<usize>>,
+}
+
+impl BracketTable {
+    /// Pairs up the brackets in `code`. Unmatched brackets are left without a match.
+    pub fn new(code: &[char]) -> Self {
+        Self::skipping(code, &[])
+    }
+
+    /// Like [`BracketTable::new`], but leaves out the brackets within the
+    /// `skipped` ranges (sorted by their start), like the text of the
+    /// directives the interpreter passes over.
+    pub fn skipping(code: &[char], skipped: &[Range<usize>]) -> Self {
+        let mut matches = vec![None; code.len()];
+        let mut open = Vec::new();
+        let mut skipped = skipped.iter().peekable();
+
+        for (pos, c) in code.iter().enumerate() {
+            while skipped.next_if(|range| range.end <= pos).is_some() {}
+            if skipped.peek().is_some_and(|range| range.contains(&pos)) {
+                continue;
+            }
+
+            match c {
+                '[' => open.push(pos),
+                ']' => {
+                    if let Some(start) = open.pop() {
+                        matches[start] = Some(pos);
+                        matches[pos] = Some(start);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self { matches }
+    }
+
+    /// The position of the bracket matching the one at `pos`, if any.
+    pub fn matching(&self, pos: usize) -> Option<usize> {
+        self.matches.get(pos).copied().flatten()
+    }
+
+    /// The positions of the `[` and `]` of the innermost loop containing `pos`.
+    /// A bracket counts as part of the loop it delimits.
+    pub fn enclosing_loop(&self, pos: usize) -> Option<(usize, usize)> {
+        if let Some(other) = self.matching(pos) {
+            return Some((pos.min(other), pos.max(other)));
+        }
+
+        // the nearest `[` to the left whose loop extends past `pos`
+        let mut start = pos.min(self.matches.len());
+        while start > 0 {
+            start -= 1;
+
+            if let Some(end) = self.matches[start] {
+                if end > pos {
+                    return Some((start, end));
+                }
+                if end < start {
+                    // skip over the whole loop that ends here
+                    start = end;
+                }
+            }
+        }
+
+        None
+    }
+}
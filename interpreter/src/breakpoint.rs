@@ -0,0 +1,61 @@
+use regex::Regex;
+
+#[derive(Debug, Clone)]
+/// What the program's cumulative output is matched against
+pub enum OutputPattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl std::fmt::Display for OutputPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Substring(substring) => write!(f, "{:?}", substring),
+            Self::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Fires the first time the program's cumulative output matches a pattern.
+pub struct OutputBreakpoint {
+    pub pattern: OutputPattern,
+    /// How many bytes of the output have already been searched
+    checked: usize,
+    hit: bool,
+}
+
+impl OutputBreakpoint {
+    pub fn new(pattern: OutputPattern) -> Self {
+        Self {
+            pattern,
+            checked: 0,
+            hit: false,
+        }
+    }
+
+    /// Returns `true` if `output` matches for the first time.
+    pub fn check(&mut self, output: &str) -> bool {
+        if self.hit || output.len() == self.checked {
+            return false;
+        }
+
+        let matched = match &self.pattern {
+            OutputPattern::Substring(substring) => {
+                // only the new bytes (plus enough of the old ones to complete
+                // a match that straddles them) need to be searched
+                let mut start = self.checked.saturating_sub(substring.len());
+                while !output.is_char_boundary(start) {
+                    start -= 1;
+                }
+                output[start..].contains(substring.as_str())
+            }
+            OutputPattern::Regex(regex) => regex.is_match(output),
+        };
+
+        self.checked = output.len();
+        self.hit = matched;
+
+        matched
+    }
+}
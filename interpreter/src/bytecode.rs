@@ -0,0 +1,1221 @@
+//! A faster engine for running programs without the debugger. The source is
+//! compiled into bytecode once: runs of `+`/`-` and `<`/`>` become single ops,
+//! brackets know where their partner is and common loops (clearing cells,
+//! scanning for a 0 cell and adding a cell to others) run in one op. Unlike the
+//! step-by-step interpreter, `[` skips its loop when the cell is 0.
+
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use crate::{
+    assertion::Assertion,
+    bisect::{OptFuel, Transformation},
+    cell::CellValue,
+    fusion::{fuse, FusionTable},
+    hot::{LoopEffect, LoopState, HOT_LOOP_THRESHOLD},
+    interpreter::{read_byte, read_number, Read},
+    pragma::{read_pragmas, skipped, CellWidth, Effective},
+    scan::{find_zero, rfind_zero},
+    IoMode, Result, RuntimeError, MEMORY_SIZE,
+};
+
+/// How much output is collected before [`Machine::run`] returns it
+pub const OUTPUT_CHUNK: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Add to the current cell, wrapping around
+    Add(u8),
+    /// Move the pointer, stopping at either end of the tape
+    Move(isize),
+    Write,
+    Read,
+    /// Jump past the matching [`Op::JumpIfNotZero`] if the cell is 0
+    JumpIfZero(usize),
+    /// Jump back past the matching [`Op::JumpIfZero`] unless the cell is 0
+    JumpIfNotZero(usize),
+    /// Set `cells` cells to 0 with loops like `[-]>[-]>[-]`: starting at the
+    /// current cell, each one is cleared by adding `add` (1 or 255) until it is
+    /// 0, and the pointer moves right onto the next one
+    Clear {
+        cells: usize,
+        add: u8,
+    },
+    /// Start a loop like `[>]` or `[<<]`, whose body is a single move by
+    /// `step`: find the first 0 cell in that direction and jump to `end` past
+    /// the loop. If the search would run into the end of the tape, the body
+    /// runs normally instead
+    Scan {
+        step: isize,
+        end: usize,
+    },
+    /// Start a loop like `[->+>++<<]`, described by [`Program::copies`] at
+    /// this index, by adding the loop's cell times each factor to the other
+    /// cells at once. If the body would move past the end of the tape, it runs
+    /// normally instead
+    Copy(usize),
+    // the superinstructions of `crate::fusion`, which run several of the ops
+    // above in one go
+    AddMove(u8, isize),
+    MoveAdd(isize, u8),
+    AddMoveAdd(u8, isize, u8),
+    MoveAddMove(isize, u8, isize),
+    /// A clear loop of one cell followed by adding `value`
+    Set {
+        add: u8,
+        value: u8,
+    },
+    SetMove {
+        add: u8,
+        value: u8,
+        offset: isize,
+    },
+    MoveJumpIfZero(isize, usize),
+    MoveJumpIfNotZero(isize, usize),
+    AddJumpIfNotZero(u8, usize),
+    /// Check the `@assert` directive described by [`Program::assertions`] at
+    /// this index
+    Assert(usize),
+    /// The end of the program
+    Halt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instr {
+    pub op: Op,
+    /// How many source instructions the op stands for, so steps are counted
+    /// like the interpreter counts them
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A loop whose body only uses `+`, `-`, `<` and `>`, returns to the loop's
+/// cell and changes it by exactly 1
+pub struct CopyLoop {
+    /// The cells the body changes, relative to the loop's cell, and how much
+    /// one iteration adds to each
+    pub targets: Vec<(isize, u8)>,
+    /// What one iteration adds to the loop's cell, 1 or 255
+    pub add: u8,
+    /// The lowest and highest offset the body moves to
+    pub lowest: isize,
+    pub highest: isize,
+    /// The source instructions in one iteration, counting the `]`
+    pub iteration_steps: u64,
+    /// The op after the loop
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub instrs: Vec<Instr>,
+    /// The loops started by [`Op::Copy`]
+    pub copies: Vec<CopyLoop>,
+    /// The assertions checked by [`Op::Assert`] and when the program halts
+    pub assertions: Vec<Assertion>,
+    /// The position in the source of the first instruction of each op, and
+    /// the length of the source for [`Op::Halt`]
+    pub positions: Vec<usize>,
+    /// What [`Op::Read`] stores at the end of the input, from `@pragma eof`
+    pub eof: Option<u8>,
+    /// Whether the pointer wraps around the ends of the tape instead of
+    /// stopping there, from `@pragma wrap-pointer`
+    pub wrap_pointer: bool,
+}
+
+impl Program {
+    /// Compiles the source into bytecode with the default superinstructions,
+    /// failing on unmatched brackets and on `@pragma` directives (see
+    /// [`crate::pragma`]) it cannot honor.
+    pub fn compile(code: &str) -> Result<Self> {
+        Self::compile_with(code, &FusionTable::default())
+    }
+
+    /// Compiles the source into bytecode, fusing the sequences in `fusions`.
+    pub fn compile_with(code: &str, fusions: &FusionTable) -> Result<Self> {
+        Self::compile_with_assertions(code, fusions, Vec::new())
+    }
+
+    /// Like [`Program::compile_with`], but checks these `@assert` directives
+    /// of the code (see [`crate::assertion`]) instead of executing them.
+    pub fn compile_with_assertions(
+        code: &str,
+        fusions: &FusionTable,
+        assertions: Vec<Assertion>,
+    ) -> Result<Self> {
+        let semantics = read_pragmas(code)?;
+        Self::compile_with_semantics(code, fusions, assertions, &semantics)
+    }
+
+    /// Like [`Program::compile_with_assertions`], but runs with `semantics`
+    /// instead of the ones the pragmas ask for, like after merging them with
+    /// flags (see [`crate::pragma::resolve`]).
+    pub fn compile_with_semantics(
+        code: &str,
+        fusions: &FusionTable,
+        assertions: Vec<Assertion>,
+        semantics: &Effective,
+    ) -> Result<Self> {
+        Self::compile_with_fuel(
+            code,
+            fusions,
+            assertions,
+            semantics,
+            &mut OptFuel::default(),
+        )
+    }
+
+    /// Like [`Program::compile_with_semantics`], but only applies as many
+    /// transformations as `fuel` allows (see [`crate::bisect`]).
+    pub fn compile_with_fuel(
+        code: &str,
+        fusions: &FusionTable,
+        assertions: Vec<Assertion>,
+        semantics: &Effective,
+        fuel: &mut OptFuel,
+    ) -> Result<Self> {
+        if semantics.cell_size == CellWidth::BigInt {
+            return Err("big-integer cells only run on the step-by-step interpreter".into());
+        }
+
+        let mut instrs: Vec<Instr> = Vec::new();
+        let mut positions = Vec::new();
+        let mut copies = Vec::new();
+        let mut open = Vec::new();
+        let mut directives = assertions.iter().enumerate().peekable();
+        let mut pragmas_skipped = skipped(code).into_iter().peekable();
+        let mut skip_until = 0;
+
+        for (pos, c) in code.chars().enumerate() {
+            if pos < skip_until {
+                continue;
+            }
+
+            if let Some(pragma) = pragmas_skipped.next_if(|range| range.start == pos) {
+                skip_until = pragma.end;
+                continue;
+            }
+
+            if let Some((index, assertion)) = directives.next_if(|(_, a)| a.start == pos) {
+                skip_until = assertion.end;
+                if !assertion.at_end {
+                    // never merged into the ops around it, so a loop
+                    // containing it is never run at once either
+                    instrs.push(Instr {
+                        op: Op::Assert(index),
+                        count: 0,
+                    });
+                    positions.push(pos);
+                }
+                continue;
+            }
+
+            let op = match c {
+                '+' => Op::Add(1),
+                '-' => Op::Add(u8::MAX),
+                '>' => Op::Move(1),
+                '<' => Op::Move(-1),
+                '.' => Op::Write,
+                ',' => Op::Read,
+                '[' => {
+                    open.push(instrs.len());
+                    // patched once the matching `]` is found
+                    Op::JumpIfZero(0)
+                }
+                ']' => {
+                    let start = open.pop().ok_or("unmatched ]")?;
+                    let end = instrs.len() + 1;
+
+                    let bracket = positions[start];
+                    if let [Instr {
+                        op: Op::Add(add @ (1 | u8::MAX)),
+                        count: 1,
+                    }] = instrs[start + 1..]
+                    {
+                        if fuel.apply(Transformation::Clear, bracket) {
+                            instrs.truncate(start);
+                            positions.truncate(start);
+                            push_clear(&mut instrs, &mut positions, add, bracket, fuel);
+                            continue;
+                        }
+                    }
+
+                    let copy = copy_loop(&instrs[start + 1..], end)
+                        .filter(|_| fuel.apply(Transformation::Copy, bracket));
+                    instrs[start].op = match copy {
+                        Some(copy) => {
+                            copies.push(copy);
+                            Op::Copy(copies.len() - 1)
+                        }
+                        None => match instrs[start + 1..] {
+                            [Instr {
+                                op: Op::Move(step), ..
+                            }] if step != 0 && fuel.apply(Transformation::Scan, bracket) => {
+                                Op::Scan { step, end }
+                            }
+                            _ => Op::JumpIfZero(end),
+                        },
+                    };
+                    Op::JumpIfNotZero(start + 1)
+                }
+                _ => continue,
+            };
+
+            // jumps only ever land right after an op that starts or ends a
+            // loop, which is never merged, so every jump target stays the
+            // start of an op
+            match (instrs.last_mut().map(|last| (last.op, last)), op) {
+                (Some((Op::Add(a), last)), Op::Add(b))
+                    if fuel.apply(Transformation::Merge, pos) =>
+                {
+                    last.op = Op::Add(a.wrapping_add(b));
+                    last.count += 1;
+                }
+                (Some((Op::Move(a), last)), Op::Move(b))
+                    if fuel.apply(Transformation::Merge, pos) =>
+                {
+                    last.op = Op::Move(a + b);
+                    last.count += 1;
+                }
+                _ => {
+                    instrs.push(Instr { op, count: 1 });
+                    positions.push(pos);
+                }
+            }
+        }
+
+        if !open.is_empty() {
+            return Err("unmatched [".into());
+        }
+
+        instrs.push(Instr {
+            op: Op::Halt,
+            count: 0,
+        });
+        positions.push(code.chars().count());
+
+        let (instrs, positions) = fuse(instrs, &positions, &mut copies, fusions, fuel);
+
+        Ok(Self {
+            instrs,
+            copies,
+            assertions,
+            positions,
+            eof: semantics.eof,
+            wrap_pointer: semantics.wrap_pointer,
+        })
+    }
+}
+
+/// Appends a clear loop (starting at `position` in the source), merging it
+/// into a clear loop of the same kind right before a single `>` if `fuel`
+/// allows.
+fn push_clear(
+    instrs: &mut Vec<Instr>,
+    positions: &mut Vec<usize>,
+    add: u8,
+    position: usize,
+    fuel: &mut OptFuel,
+) {
+    if let [.., Instr {
+        op: Op::Clear {
+            cells,
+            add: previous,
+        },
+        count,
+    }, Instr {
+        op: Op::Move(1),
+        count: 1,
+    }] = instrs[..]
+    {
+        if previous == add && fuel.apply(Transformation::MergeClears, position) {
+            instrs.pop();
+            instrs.pop();
+            positions.pop();
+            instrs.push(Instr {
+                op: Op::Clear {
+                    cells: cells + 1,
+                    add,
+                },
+                count: count + 4,
+            });
+            return;
+        }
+    }
+
+    instrs.push(Instr {
+        op: Op::Clear { cells: 1, add },
+        count: 3,
+    });
+    positions.push(position);
+}
+
+/// Describes the loop with this body, if it is a [`CopyLoop`].
+fn copy_loop(body: &[Instr], end: usize) -> Option<CopyLoop> {
+    let mut deltas: Vec<(isize, u8)> = Vec::new();
+    let mut offset = 0isize;
+    let mut lowest = 0;
+    let mut highest = 0;
+    let mut iteration_steps = 1;
+
+    for instr in body {
+        match instr.op {
+            Op::Add(n) => match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta = delta.wrapping_add(n),
+                None => deltas.push((offset, n)),
+            },
+            Op::Move(n) => {
+                offset += n;
+                lowest = lowest.min(offset);
+                highest = highest.max(offset);
+            }
+            _ => return None,
+        }
+        iteration_steps += instr.count as u64;
+    }
+
+    let add = deltas
+        .iter()
+        .find(|(o, _)| *o == 0)
+        .map_or(0, |(_, delta)| *delta);
+    if offset != 0 || !matches!(add, 1 | u8::MAX) {
+        return None;
+    }
+
+    Some(CopyLoop {
+        targets: deltas
+            .into_iter()
+            .filter(|(o, delta)| *o != 0 && *delta != 0)
+            .collect(),
+        add,
+        lowest,
+        highest,
+        iteration_steps,
+        end,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why [`Machine::run`] returned
+pub enum Status {
+    Halted,
+    /// A `,` is waiting for more input, see [`Machine::push_input`]
+    NeedsInput,
+    /// A line (or [`OUTPUT_CHUNK`] bytes) of output is ready in [`Machine::output`]
+    Output,
+    /// The given number of ops were executed
+    OutOfFuel,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// How the machine finds the code for the next op
+pub enum Dispatch {
+    #[default]
+    /// One `match` on the op in a loop
+    Match,
+    /// Each op is resolved to its handler function ahead of time, and the loop
+    /// only calls the next handler
+    Threaded,
+    /// Like `threaded`, but each handler tail-calls the next one, so there is
+    /// no loop at all. Needs the `tail-calls` feature (and a nightly compiler)
+    TailCall,
+}
+
+impl Dispatch {
+    /// The dispatchers this build supports
+    pub fn available() -> Vec<Dispatch> {
+        let mut available = vec![Dispatch::Match, Dispatch::Threaded];
+        if cfg!(feature = "tail-calls") {
+            available.push(Dispatch::TailCall);
+        }
+        available
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub memory: Box<[u8]>,
+    pub pointer: usize,
+    /// The index of the next op
+    pub pc: usize,
+    pub input: Vec<u8>,
+    pub input_pos: usize,
+    pub input_closed: bool,
+    pub io_mode: IoMode,
+    /// Bytes written since the caller last took them
+    pub output: Vec<u8>,
+    /// The number of source instructions executed so far
+    pub steps: u64,
+    /// How many times a loop jumps back before its remaining iterations are
+    /// run at once where possible (see `crate::hot`), or 0 to never do that
+    pub hot_loop_threshold: u32,
+    /// What is known about the loop ending at each op
+    loops: Vec<LoopState>,
+}
+
+/// What an op tells the dispatch loop to do next
+enum Flow {
+    Next,
+    Stop(Status),
+}
+
+impl Machine {
+    pub fn new(io_mode: IoMode) -> Self {
+        Self {
+            memory: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            pointer: 0,
+            pc: 0,
+            input: Vec::new(),
+            input_pos: 0,
+            input_closed: false,
+            io_mode,
+            output: Vec::new(),
+            steps: 0,
+            hot_loop_threshold: HOT_LOOP_THRESHOLD,
+            loops: Vec::new(),
+        }
+    }
+
+    /// Gives the tape `cells` cells instead of [`MEMORY_SIZE`], all 0.
+    pub fn resize_memory(&mut self, cells: usize) {
+        self.memory = vec![0; cells.max(1)].into_boxed_slice();
+        self.pointer = 0;
+    }
+
+    /// The last cell of the tape.
+    pub fn max_pointer(&self) -> usize {
+        self.memory.len() - 1
+    }
+
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// How many bytes of the input have not been read yet.
+    pub fn pending_input(&self) -> usize {
+        self.input.len() - self.input_pos
+    }
+
+    /// Forgets the input that was read already, so that a machine fed for a
+    /// long time does not hold on to all of it.
+    pub fn drop_read_input(&mut self) {
+        self.input.drain(..self.input_pos);
+        self.input_pos = 0;
+    }
+
+    /// Executes ops until the program halts, needs input or has output ready,
+    /// or `fuel` (which goes down by one for every op) runs out. Failing ops
+    /// raise [`RuntimeError`]s.
+    pub fn run(&mut self, program: &Program, dispatch: Dispatch, fuel: &mut u64) -> Result<Status> {
+        if self.hot_loop_threshold > 0 && self.loops.len() != program.instrs.len() {
+            self.loops = vec![LoopState::Counting(0); program.instrs.len()];
+        }
+
+        match dispatch {
+            Dispatch::Match => self
+                .run_match(program, fuel)
+                .map_err(|e| self.failed(program, e)),
+            Dispatch::Threaded => self
+                .run_threaded(program, fuel)
+                .map_err(|e| self.failed(program, e)),
+            Dispatch::TailCall => self.run_tail_call(program, fuel),
+        }
+    }
+
+    /// Describes an error raised by the current op, which is still the one
+    /// that failed.
+    fn failed(
+        &self,
+        program: &Program,
+        e: Box<dyn std::error::Error>,
+    ) -> Box<dyn std::error::Error> {
+        RuntimeError {
+            message: e.to_string(),
+            position: program.positions[self.pc],
+            pointer: self.pointer,
+            cell: CellValue::Byte(self.memory[self.pointer]),
+        }
+        .into()
+    }
+
+    fn run_match(&mut self, program: &Program, fuel: &mut u64) -> Result<Status> {
+        while *fuel > 0 {
+            *fuel -= 1;
+
+            let instr = program.instrs[self.pc];
+            let flow = match instr.op {
+                Op::Add(_) => add(self, program, instr),
+                Op::Move(_) => move_pointer(self, program, instr),
+                Op::Write => write(self, program, instr),
+                Op::Read => read(self, program, instr),
+                Op::JumpIfZero(_) => jump_if_zero(self, program, instr),
+                Op::JumpIfNotZero(_) => jump_if_not_zero(self, program, instr),
+                Op::Clear { .. } => clear(self, program, instr),
+                Op::Scan { .. } => scan(self, program, instr),
+                Op::Copy(_) => copy(self, program, instr),
+                Op::AddMove(..) => add_move(self, program, instr),
+                Op::MoveAdd(..) => move_add(self, program, instr),
+                Op::AddMoveAdd(..) => add_move_add(self, program, instr),
+                Op::MoveAddMove(..) => move_add_move(self, program, instr),
+                Op::Set { .. } => set(self, program, instr),
+                Op::SetMove { .. } => set_move(self, program, instr),
+                Op::MoveJumpIfZero(..) => move_jump_if_zero(self, program, instr),
+                Op::MoveJumpIfNotZero(..) => move_jump_if_not_zero(self, program, instr),
+                Op::AddJumpIfNotZero(..) => add_jump_if_not_zero(self, program, instr),
+                Op::Assert(_) => assert(self, program, instr),
+                Op::Halt => halt(self, program, instr),
+            }?;
+
+            if let Flow::Stop(status) = flow {
+                return Ok(status);
+            }
+        }
+
+        Ok(Status::OutOfFuel)
+    }
+
+    fn run_threaded(&mut self, program: &Program, fuel: &mut u64) -> Result<Status> {
+        let code = threaded(program);
+
+        while *fuel > 0 {
+            *fuel -= 1;
+
+            let next = &code[self.pc];
+            if let Flow::Stop(status) = (next.handler)(self, program, next.instr)? {
+                return Ok(status);
+            }
+        }
+
+        Ok(Status::OutOfFuel)
+    }
+
+    #[cfg(feature = "tail-calls")]
+    fn run_tail_call(&mut self, program: &Program, fuel: &mut u64) -> Result<Status> {
+        tail::run(self, program, fuel).map_err(|e| self.failed(program, e))
+    }
+
+    #[cfg(not(feature = "tail-calls"))]
+    fn run_tail_call(&mut self, _program: &Program, _fuel: &mut u64) -> Result<Status> {
+        Err(
+            "this build does not support the tail-call dispatcher, enable the `tail-calls` feature"
+                .into(),
+        )
+    }
+
+    /// Jumps back to `target` from the `]` at `pc`, or runs all of the loop's
+    /// remaining iterations at once once it is hot and simple enough.
+    #[inline(always)]
+    fn jump_back(&mut self, program: &Program, target: usize) {
+        let Some(state) = self.loops.get_mut(self.pc) else {
+            self.pc = target;
+            return;
+        };
+
+        self.pc = match state {
+            LoopState::Specialized(effect) => {
+                match effect.run(&mut self.memory[..], self.pointer) {
+                    Some(steps) => {
+                        self.steps += steps;
+                        self.pc + 1
+                    }
+                    // it never ends, or is too close to an end of the tape this time
+                    None => target,
+                }
+            }
+            LoopState::Counting(count) => {
+                *count += 1;
+                if *count >= self.hot_loop_threshold {
+                    *state = match LoopEffect::analyze(program, target, self.pc) {
+                        Some(effect) => LoopState::Specialized(effect),
+                        None => LoopState::Generic,
+                    };
+                }
+                target
+            }
+            LoopState::Generic => target,
+        };
+    }
+
+    /// The cell `offset` cells away from the pointer, which is where the
+    /// pointer stops at either end of the tape unless the program wraps it
+    /// around.
+    #[inline(always)]
+    fn moved(&self, program: &Program, offset: isize) -> usize {
+        if program.wrap_pointer {
+            (self.pointer as isize + offset).rem_euclid(self.memory.len() as isize) as usize
+        } else {
+            self.pointer
+                .saturating_add_signed(offset)
+                .min(self.max_pointer())
+        }
+    }
+
+    /// Enters the loop started at `pc` (or skips it if the cell is 0) like a
+    /// plain `[` would, for loop ops that cannot take their shortcut.
+    #[inline(always)]
+    fn enter_loop(&mut self, end: usize) -> Flow {
+        self.steps += 1;
+        self.pc = if self.memory[self.pointer] == 0 {
+            end
+        } else {
+            self.pc + 1
+        };
+        Flow::Next
+    }
+}
+
+/// Executes one kind of op. Every dispatcher shares these, so they only differ
+/// in how they get from one op to the next.
+type Handler = fn(&mut Machine, &Program, Instr) -> Result<Flow>;
+
+#[inline(always)]
+fn add(m: &mut Machine, _: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Add(n) = instr.op else { unreachable!() };
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(n);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn move_pointer(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Move(n) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    m.pointer = m.moved(program, n);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn write(m: &mut Machine, _: &Program, instr: Instr) -> Result<Flow> {
+    m.steps += instr.count as u64;
+    m.pc += 1;
+
+    let value = m.memory[m.pointer];
+    match m.io_mode {
+        IoMode::Text => m.output.push(value),
+        IoMode::Numeric => m.output.extend(format!("{} ", value).bytes()),
+    }
+
+    if value == b'\n' || m.output.len() >= OUTPUT_CHUNK {
+        return Ok(Flow::Stop(Status::Output));
+    }
+
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn read(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let read = match m.io_mode {
+        IoMode::Text => read_byte(&m.input, &mut m.input_pos, m.input_closed),
+        IoMode::Numeric => read_number(&m.input, &mut m.input_pos, m.input_closed)?,
+    };
+
+    match read {
+        Read::Value(value) => m.memory[m.pointer] = value,
+        Read::Eof => {
+            if let Some(value) = program.eof {
+                m.memory[m.pointer] = value;
+            }
+        }
+        // the `,` runs again once there is input
+        Read::Pending => return Ok(Flow::Stop(Status::NeedsInput)),
+    }
+
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn jump_if_zero(m: &mut Machine, _: &Program, instr: Instr) -> Result<Flow> {
+    let Op::JumpIfZero(end) = instr.op else {
+        unreachable!()
+    };
+    Ok(m.enter_loop(end))
+}
+
+#[inline(always)]
+fn jump_if_not_zero(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::JumpIfNotZero(target) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    if m.memory[m.pointer] != 0 {
+        m.jump_back(program, target);
+    } else {
+        m.pc += 1;
+    }
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn clear(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Clear { cells, add } = instr.op else {
+        unreachable!()
+    };
+
+    if program.wrap_pointer && m.pointer + cells > m.memory.len() {
+        // the loops go on from the first cell, and may come back around to
+        // the ones cleared already
+        let mut steps = cells as u64 - 1;
+        for index in 0..cells {
+            let cell = &mut m.memory[(m.pointer + index) % m.memory.len()];
+            steps += clear_steps(*cell, add);
+            *cell = 0;
+        }
+
+        m.steps += steps;
+        m.pointer = (m.pointer + cells - 1) % m.memory.len();
+        m.pc += 1;
+        return Ok(Flow::Next);
+    }
+
+    // the pointer stops at the end of the tape, where the remaining loops
+    // find the last cell already cleared
+    let last = (m.pointer + cells - 1).min(m.max_pointer());
+    let cleared = &mut m.memory[m.pointer..=last];
+
+    // the `>` between the loops, and the loops on the last cell that is
+    // already 0 when the pointer stops at the end of the tape
+    let mut steps = cells as u64 - 1 + (cells - cleared.len()) as u64;
+    for value in cleared.iter() {
+        steps += clear_steps(*value, add);
+    }
+    cleared.fill(0);
+
+    m.steps += steps;
+    m.pointer = last;
+    m.pc += 1;
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn scan(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Scan { step, end } = instr.op else {
+        unreachable!()
+    };
+    if m.memory[m.pointer] == 0 {
+        return Ok(m.enter_loop(end));
+    }
+
+    let found = match step {
+        1 => find_zero(&m.memory[m.pointer..]).map(|i| m.pointer + i),
+        -1 => rfind_zero(&m.memory[..=m.pointer]),
+        _ => {
+            let mut pointer = m.pointer;
+            loop {
+                match pointer.checked_add_signed(step) {
+                    Some(next) if next <= m.max_pointer() => pointer = next,
+                    _ => break None,
+                }
+                if m.memory[pointer] == 0 {
+                    break Some(pointer);
+                }
+            }
+        }
+    };
+
+    let Some(found) = found else {
+        return Ok(m.enter_loop(end));
+    };
+
+    // the `[` and then the body and `]` of every iteration, which may have
+    // been fused into one op
+    let iterations = (found.abs_diff(m.pointer) / step.unsigned_abs()) as u64;
+    let iteration_steps: u64 = program.instrs[m.pc + 1..end]
+        .iter()
+        .map(|instr| instr.count as u64)
+        .sum();
+    m.steps += 1 + iterations * iteration_steps;
+    m.pointer = found;
+    m.pc = end;
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn copy(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Copy(index) = instr.op else {
+        unreachable!()
+    };
+    let copy = &program.copies[index];
+
+    let value = m.memory[m.pointer];
+    let in_bounds = m.pointer as isize + copy.lowest >= 0
+        && m.pointer as isize + copy.highest <= m.max_pointer() as isize;
+    if value == 0 || !in_bounds {
+        return Ok(m.enter_loop(copy.end));
+    }
+
+    // never 0, since the value is not
+    let iterations = if copy.add == u8::MAX {
+        value
+    } else {
+        value.wrapping_neg()
+    };
+    for (offset, delta) in &copy.targets {
+        let cell = &mut m.memory[m.pointer.wrapping_add_signed(*offset)];
+        *cell = cell.wrapping_add(delta.wrapping_mul(iterations));
+    }
+    m.memory[m.pointer] = 0;
+
+    m.steps += 1 + iterations as u64 * copy.iteration_steps;
+    m.pc = copy.end;
+    Ok(Flow::Next)
+}
+
+/// The steps a loop like `[-]` (or `[+]` if `add` is 1) takes to clear a cell
+#[inline(always)]
+pub(crate) fn clear_steps(value: u8, add: u8) -> u64 {
+    let iterations = match (value, add) {
+        (0, _) => 0,
+        (value, u8::MAX) => value as u64,
+        (value, _) => 256 - value as u64,
+    };
+    1 + 2 * iterations
+}
+
+#[inline(always)]
+fn add_move(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::AddMove(n, offset) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(n);
+    m.pointer = m.moved(program, offset);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn move_add(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::MoveAdd(offset, n) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    m.pointer = m.moved(program, offset);
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(n);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn add_move_add(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::AddMoveAdd(first, offset, second) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(first);
+    m.pointer = m.moved(program, offset);
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(second);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn move_add_move(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::MoveAddMove(first, n, second) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pc += 1;
+    m.pointer = m.moved(program, first);
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(n);
+    m.pointer = m.moved(program, second);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn set(m: &mut Machine, _: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Set { add, value } = instr.op else {
+        unreachable!()
+    };
+    // the count includes the clear loop's three instructions
+    m.steps += instr.count as u64 - 3 + clear_steps(m.memory[m.pointer], add);
+    m.pc += 1;
+    m.memory[m.pointer] = value;
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn set_move(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::SetMove { add, value, offset } = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64 - 3 + clear_steps(m.memory[m.pointer], add);
+    m.pc += 1;
+    m.memory[m.pointer] = value;
+    m.pointer = m.moved(program, offset);
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn move_jump_if_zero(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::MoveJumpIfZero(offset, end) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pointer = m.moved(program, offset);
+    m.pc = if m.memory[m.pointer] == 0 {
+        end
+    } else {
+        m.pc + 1
+    };
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn move_jump_if_not_zero(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::MoveJumpIfNotZero(offset, target) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.pointer = m.moved(program, offset);
+    if m.memory[m.pointer] != 0 {
+        m.jump_back(program, target);
+    } else {
+        m.pc += 1;
+    }
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn add_jump_if_not_zero(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::AddJumpIfNotZero(n, target) = instr.op else {
+        unreachable!()
+    };
+    m.steps += instr.count as u64;
+    m.memory[m.pointer] = m.memory[m.pointer].wrapping_add(n);
+    if m.memory[m.pointer] != 0 {
+        m.jump_back(program, target);
+    } else {
+        m.pc += 1;
+    }
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn assert(m: &mut Machine, program: &Program, instr: Instr) -> Result<Flow> {
+    let Op::Assert(index) = instr.op else {
+        unreachable!()
+    };
+    program.assertions[index].check(&m.memory[..], m.pointer)?;
+    m.pc += 1;
+    Ok(Flow::Next)
+}
+
+#[inline(always)]
+fn halt(m: &mut Machine, program: &Program, _: Instr) -> Result<Flow> {
+    for assertion in program
+        .assertions
+        .iter()
+        .filter(|assertion| assertion.at_end)
+    {
+        assertion.check(&m.memory[..], m.pointer)?;
+    }
+    Ok(Flow::Stop(Status::Halted))
+}
+
+/// The handler for each kind of op
+fn handler(op: Op) -> Handler {
+    match op {
+        Op::Add(_) => add,
+        Op::Move(_) => move_pointer,
+        Op::Write => write,
+        Op::Read => read,
+        Op::JumpIfZero(_) => jump_if_zero,
+        Op::JumpIfNotZero(_) => jump_if_not_zero,
+        Op::Clear { .. } => clear,
+        Op::Scan { .. } => scan,
+        Op::Copy(_) => copy,
+        Op::AddMove(..) => add_move,
+        Op::MoveAdd(..) => move_add,
+        Op::AddMoveAdd(..) => add_move_add,
+        Op::MoveAddMove(..) => move_add_move,
+        Op::Set { .. } => set,
+        Op::SetMove { .. } => set_move,
+        Op::MoveJumpIfZero(..) => move_jump_if_zero,
+        Op::MoveJumpIfNotZero(..) => move_jump_if_not_zero,
+        Op::AddJumpIfNotZero(..) => add_jump_if_not_zero,
+        Op::Assert(_) => assert,
+        Op::Halt => halt,
+    }
+}
+
+/// An op together with the function that executes it
+#[derive(Clone, Copy)]
+struct Threaded {
+    handler: Handler,
+    instr: Instr,
+}
+
+/// Resolves every op to its handler ahead of time.
+fn threaded(program: &Program) -> Vec<Threaded> {
+    program
+        .instrs
+        .iter()
+        .map(|instr| Threaded {
+            handler: handler(instr.op),
+            instr: *instr,
+        })
+        .collect()
+}
+
+#[cfg(feature = "tail-calls")]
+mod tail {
+    use super::{
+        add, add_jump_if_not_zero, add_move, add_move_add, assert, clear, copy, halt,
+        jump_if_not_zero, jump_if_zero, move_add, move_add_move, move_jump_if_not_zero,
+        move_jump_if_zero, move_pointer, read, scan, set, set_move, write, Flow, Instr, Machine,
+        Op, Program, Status,
+    };
+    use crate::Result;
+
+    // a struct, because a function pointer type cannot refer to itself
+    #[derive(Clone, Copy)]
+    struct Handler(fn(&mut Machine, &Program, &[Link], &mut u64) -> Result<Status>);
+
+    #[derive(Clone, Copy)]
+    struct Link {
+        handler: Handler,
+        instr: Instr,
+    }
+
+    /// Generates a handler that executes one kind of op and then tail-calls
+    /// the handler of the next op.
+    macro_rules! handler {
+        ($name:ident, $op:ident) => {
+            fn $name(
+                m: &mut Machine,
+                program: &Program,
+                code: &[Link],
+                fuel: &mut u64,
+            ) -> Result<Status> {
+                if *fuel == 0 {
+                    return Ok(Status::OutOfFuel);
+                }
+                *fuel -= 1;
+
+                match $op(m, program, code[m.pc].instr)? {
+                    Flow::Next => {
+                        let next = code[m.pc].handler.0;
+                        become next(m, program, code, fuel)
+                    }
+                    Flow::Stop(status) => Ok(status),
+                }
+            }
+        };
+    }
+
+    handler!(tail_add, add);
+    handler!(tail_move_pointer, move_pointer);
+    handler!(tail_write, write);
+    handler!(tail_read, read);
+    handler!(tail_jump_if_zero, jump_if_zero);
+    handler!(tail_jump_if_not_zero, jump_if_not_zero);
+    handler!(tail_clear, clear);
+    handler!(tail_scan, scan);
+    handler!(tail_copy, copy);
+    handler!(tail_add_move, add_move);
+    handler!(tail_move_add, move_add);
+    handler!(tail_add_move_add, add_move_add);
+    handler!(tail_move_add_move, move_add_move);
+    handler!(tail_set, set);
+    handler!(tail_set_move, set_move);
+    handler!(tail_move_jump_if_zero, move_jump_if_zero);
+    handler!(tail_move_jump_if_not_zero, move_jump_if_not_zero);
+    handler!(tail_add_jump_if_not_zero, add_jump_if_not_zero);
+    handler!(tail_assert, assert);
+    handler!(tail_halt, halt);
+
+    pub fn run(m: &mut Machine, program: &Program, fuel: &mut u64) -> Result<Status> {
+        let code: Vec<Link> = program
+            .instrs
+            .iter()
+            .map(|instr| Link {
+                handler: Handler(match instr.op {
+                    Op::Add(_) => tail_add,
+                    Op::Move(_) => tail_move_pointer,
+                    Op::Write => tail_write,
+                    Op::Read => tail_read,
+                    Op::JumpIfZero(_) => tail_jump_if_zero,
+                    Op::JumpIfNotZero(_) => tail_jump_if_not_zero,
+                    Op::Clear { .. } => tail_clear,
+                    Op::Scan { .. } => tail_scan,
+                    Op::Copy(_) => tail_copy,
+                    Op::AddMove(..) => tail_add_move,
+                    Op::MoveAdd(..) => tail_move_add,
+                    Op::AddMoveAdd(..) => tail_add_move_add,
+                    Op::MoveAddMove(..) => tail_move_add_move,
+                    Op::Set { .. } => tail_set,
+                    Op::SetMove { .. } => tail_set_move,
+                    Op::MoveJumpIfZero(..) => tail_move_jump_if_zero,
+                    Op::MoveJumpIfNotZero(..) => tail_move_jump_if_not_zero,
+                    Op::AddJumpIfNotZero(..) => tail_add_jump_if_not_zero,
+                    Op::Assert(_) => tail_assert,
+                    Op::Halt => tail_halt,
+                }),
+                instr: *instr,
+            })
+            .collect();
+
+        (code[m.pc].handler.0)(m, program, &code, fuel)
+    }
+}
+
+/// Runs a copy of the machine with each available dispatcher for up to `fuel`
+/// ops, and returns how long each one took for the steps it executed.
+pub fn benchmark(
+    program: &Program,
+    machine: &Machine,
+    fuel: u64,
+) -> Result<Vec<(Dispatch, u64, Duration)>> {
+    let mut results = Vec::new();
+
+    for dispatch in Dispatch::available() {
+        let mut best: Option<(u64, Duration)> = None;
+
+        // the fastest of a few runs, to smooth over noise
+        for _ in 0..3 {
+            let mut copy = machine.clone();
+            let start = Instant::now();
+            let mut left = fuel;
+
+            // output is thrown away, so only the run itself is timed
+            while copy.run(program, dispatch, &mut left)? == Status::Output {
+                copy.output.clear();
+            }
+
+            let elapsed = start.elapsed();
+            if best.is_none_or(|(_, time)| elapsed < time) {
+                best = Some((copy.steps - machine.steps, elapsed));
+            }
+        }
+
+        if let Some((steps, time)) = best {
+            results.push((dispatch, steps, time));
+        }
+    }
+
+    Ok(results)
+}
+
+/// Picks the dispatcher that executes the start of the program fastest.
+pub fn fastest(program: &Program, machine: &Machine) -> Result<Dispatch> {
+    let results = benchmark(program, machine, 1_000_000)?;
+
+    Ok(results
+        .into_iter()
+        .max_by(|(_, a_steps, a_time), (_, b_steps, b_time)| {
+            let rate = |steps: u64, time: Duration| steps as f64 / time.as_secs_f64().max(1e-9);
+            rate(*a_steps, *a_time).total_cmp(&rate(*b_steps, *b_time))
+        })
+        .map_or(Dispatch::Match, |(dispatch, _, _)| dispatch))
+}
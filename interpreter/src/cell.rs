@@ -0,0 +1,434 @@
+//! The cells of the tape. They are bytes that wrap around, unless a program
+//! asks for `@pragma cell-size bigint`, when they are integers that never
+//! overflow and can go below 0, like the big-integer cells of bf-codegen.
+//! [`Cell`] is what an instruction can do to a cell of either kind, and
+//! [`Memory`] is a tape of one kind or the other, so the debugger, dumps,
+//! step hashes and checkpoints work with both.
+
+use std::{cmp::Ordering, fmt, hash::Hash, ops::RangeBounds};
+
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use serde::{Deserializer, Serializer};
+
+use crate::pragma::CellWidth;
+
+/// A cell of the tape
+pub trait Cell: Clone + Default + fmt::Debug + fmt::Display + Eq + Hash {
+    /// Adds `n`, wrapping around if the cell does, the same as adding one at
+    /// a time would
+    fn add(&mut self, n: isize);
+
+    fn is_zero(&self) -> bool;
+
+    /// The cell holding a byte, which `,` read or `@pragma eof` asks for
+    fn from_byte(byte: u8) -> Self;
+
+    /// The cell holding a number `,` read with `--io numeric`, wrapped around
+    /// into the cell, or `None` if it is not a number that fits. Bytes take
+    /// any number that fits in an `i64`, like the generated code does.
+    fn parse(number: &str) -> Option<Self>;
+
+    /// What `.` writes in text mode, or `None` if the cell does not hold a
+    /// byte
+    fn to_byte(&self) -> Option<u8>;
+
+    /// How the cell compares to a value of an `@assert`
+    fn compare(&self, value: usize) -> Ordering;
+
+    /// Feeds what identifies the value to a hash, a byte being itself
+    fn fingerprint(&self, feed: &mut impl FnMut(&[u8]));
+
+    /// The bytes the value holds on the heap
+    fn heap_size(&self) -> usize {
+        0
+    }
+
+    fn value(&self) -> CellValue;
+}
+
+impl Cell for u8 {
+    fn add(&mut self, n: isize) {
+        *self = self.wrapping_add(n as u8);
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte
+    }
+
+    fn parse(number: &str) -> Option<Self> {
+        number
+            .parse::<i64>()
+            .ok()
+            .map(|value| value.rem_euclid(256) as u8)
+    }
+
+    fn to_byte(&self) -> Option<u8> {
+        Some(*self)
+    }
+
+    fn compare(&self, value: usize) -> Ordering {
+        (*self as usize).cmp(&value)
+    }
+
+    fn fingerprint(&self, feed: &mut impl FnMut(&[u8])) {
+        feed(&[*self]);
+    }
+
+    fn value(&self) -> CellValue {
+        CellValue::Byte(*self)
+    }
+}
+
+impl Cell for BigInt {
+    fn add(&mut self, n: isize) {
+        *self += n;
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        BigInt::from(byte)
+    }
+
+    fn parse(number: &str) -> Option<Self> {
+        number.parse().ok()
+    }
+
+    fn to_byte(&self) -> Option<u8> {
+        self.to_u8()
+    }
+
+    fn compare(&self, value: usize) -> Ordering {
+        self.cmp(&BigInt::from(value))
+    }
+
+    fn fingerprint(&self, feed: &mut impl FnMut(&[u8])) {
+        // the length first, so that the values of two cells cannot run
+        // together the same way as two other ones
+        let bytes = self.to_signed_bytes_le();
+        feed(&(bytes.len() as u64).to_le_bytes());
+        feed(&bytes);
+    }
+
+    fn heap_size(&self) -> usize {
+        self.bits().div_ceil(64) as usize * 8
+    }
+
+    fn value(&self) -> CellValue {
+        CellValue::BigInt(self.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The value of a cell of either kind
+pub enum CellValue {
+    Byte(u8),
+    BigInt(BigInt),
+}
+
+impl CellValue {
+    /// What `.` writes in text mode, or `None` if the cell does not hold a
+    /// byte
+    pub fn to_byte(&self) -> Option<u8> {
+        match self {
+            Self::Byte(byte) => Some(*byte),
+            Self::BigInt(value) => value.to_byte(),
+        }
+    }
+
+    /// The lowest 8 bits, in two's complement for negative values, which is
+    /// all of it an exit status keeps
+    pub fn low_byte(&self) -> u8 {
+        match self {
+            Self::Byte(byte) => *byte,
+            Self::BigInt(value) => value.to_signed_bytes_le()[0],
+        }
+    }
+
+    /// The value, or the closest `i64` to it
+    pub fn saturating_i64(&self) -> i64 {
+        match self {
+            Self::Byte(byte) => *byte as i64,
+            Self::BigInt(value) => {
+                value
+                    .to_i64()
+                    .unwrap_or(if value.sign() == num_bigint::Sign::Minus {
+                        i64::MIN
+                    } else {
+                        i64::MAX
+                    })
+            }
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Byte(byte) => *byte == 0,
+            Self::BigInt(value) => Zero::is_zero(value),
+        }
+    }
+
+    /// The value after adding `n`, and whether it wrapped around on the way.
+    pub fn added(&self, n: isize) -> (Self, bool) {
+        match self {
+            Self::Byte(byte) => {
+                let mut after = *byte;
+                after.add(n);
+                let wrapped = (*byte as isize + n) != after as isize;
+                (Self::Byte(after), wrapped)
+            }
+            Self::BigInt(value) => (Self::BigInt(value + n), false),
+        }
+    }
+}
+
+// with the width and fill of the formatter, like `{:03}`
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Byte(byte) => fmt::Display::fmt(byte, f),
+            Self::BigInt(value) => fmt::Display::fmt(value, f),
+        }
+    }
+}
+
+// a byte as a number, and a big integer as a string of its digits, since
+// JSON numbers only go so far in most readers
+impl serde::Serialize for CellValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Byte(byte) => serializer.serialize_u8(*byte),
+            Self::BigInt(value) => serializer.collect_str(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A tape, or a part of one, of either kind of cell
+pub enum Memory {
+    Bytes(Vec<u8>),
+    BigInt(Vec<BigInt>),
+}
+
+/// Evaluates `$body` with `$cells` bound to the cells of `$memory`, whichever
+/// kind they are, for code that works on any [`Cell`].
+macro_rules! with_cells {
+    ($memory:expr, $cells:ident => $body:expr) => {
+        match $memory {
+            $crate::cell::Memory::Bytes($cells) => $body,
+            $crate::cell::Memory::BigInt($cells) => $body,
+        }
+    };
+}
+
+pub(crate) use with_cells;
+
+impl Memory {
+    /// A tape of `cells` cells that are all 0. Anything but big integers is
+    /// 8 bits wide, which the hosts check with
+    /// [`crate::pragma::read_pragmas`] first.
+    pub fn new(width: CellWidth, cells: usize) -> Self {
+        match width {
+            CellWidth::BigInt => Self::BigInt(vec![BigInt::default(); cells]),
+            CellWidth::Bits(_) => Self::Bytes(vec![0; cells]),
+        }
+    }
+
+    pub fn width(&self) -> CellWidth {
+        match self {
+            Self::Bytes(_) => CellWidth::Bits(8),
+            Self::BigInt(_) => CellWidth::BigInt,
+        }
+    }
+
+    /// What kind of cells these are, like `8-bit cells`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Bytes(_) => "8-bit cells",
+            Self::BigInt(_) => "big-integer cells",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        with_cells!(self, cells => cells.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, cell: usize) -> CellValue {
+        with_cells!(self, cells => cells[cell].value())
+    }
+
+    pub fn is_zero(&self, cell: usize) -> bool {
+        with_cells!(self, cells => Cell::is_zero(&cells[cell]))
+    }
+
+    pub fn add(&mut self, cell: usize, n: isize) {
+        with_cells!(self, cells => cells[cell].add(n))
+    }
+
+    pub fn set_byte(&mut self, cell: usize, byte: u8) {
+        with_cells!(self, cells => cells[cell] = Cell::from_byte(byte))
+    }
+
+    /// What `.` writes in text mode, or `None` if the cell does not hold a
+    /// byte
+    pub fn to_byte(&self, cell: usize) -> Option<u8> {
+        with_cells!(self, cells => cells[cell].to_byte())
+    }
+
+    /// A copy of the cells in `range`, which must be on the tape.
+    pub fn window(&self, range: impl RangeBounds<usize>) -> Self {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        match self {
+            Self::Bytes(cells) => Self::Bytes(cells[range].to_vec()),
+            Self::BigInt(cells) => Self::BigInt(cells[range].to_vec()),
+        }
+    }
+
+    /// Overwrites the cells from `start` on with `window`, which has to be
+    /// cells of the same kind.
+    pub fn replace(&mut self, start: usize, window: &Self) {
+        match (self, window) {
+            (Self::Bytes(cells), Self::Bytes(window)) => {
+                cells[start..start + window.len()].copy_from_slice(window)
+            }
+            (Self::BigInt(cells), Self::BigInt(window)) => {
+                cells[start..start + window.len()].clone_from_slice(window)
+            }
+            _ => panic!("replaced cells with cells of another kind"),
+        }
+    }
+
+    /// Roughly how many bytes the cells take.
+    pub fn size(&self) -> usize {
+        with_cells!(self, cells => size_of_val(&cells[..])
+            + cells.iter().map(Cell::heap_size).sum::<usize>())
+    }
+}
+
+impl From<Vec<u8>> for Memory {
+    fn from(cells: Vec<u8>) -> Self {
+        Self::Bytes(cells)
+    }
+}
+
+// bytes as numbers, as the tape always was, and big integers as strings of
+// their digits
+impl serde::Serialize for Memory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Bytes(cells) => cells.serialize(serializer),
+            Self::BigInt(cells) => serializer.collect_seq(cells.iter().map(ToString::to_string)),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Memory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bytes(Vec<u8>),
+            BigInt(Vec<String>),
+        }
+
+        match <Repr as serde::Deserialize>::deserialize(deserializer)? {
+            Repr::Bytes(cells) => Ok(Self::Bytes(cells)),
+            Repr::BigInt(cells) => cells
+                .iter()
+                .map(|cell| {
+                    cell.parse()
+                        .map_err(|_| format!("`{}` is not the value of a cell", cell))
+                })
+                .collect::<Result<_, _>>()
+                .map(Self::BigInt)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_wrap_around_and_big_integers_do_not() {
+        let mut memory = Memory::new(CellWidth::Bits(8), 2);
+        memory.add(0, -1);
+        memory.add(1, 300);
+        assert_eq!(memory, Memory::Bytes(vec![255, 44]));
+
+        let mut memory = Memory::new(CellWidth::BigInt, 2);
+        memory.add(0, -1);
+        memory.add(1, 300);
+        memory.add(1, isize::MAX);
+        memory.add(1, isize::MAX);
+        assert_eq!(memory.get(0).to_string(), "-1");
+        assert_eq!(memory.get(1).to_string(), "18446744073709551914");
+        assert!(!memory.is_zero(0));
+        assert_eq!(memory.to_byte(0), None);
+    }
+
+    #[test]
+    fn numbers_are_read_into_the_cell() {
+        assert_eq!(u8::parse("-1"), Some(255));
+        assert_eq!(u8::parse("99999999999999999999"), None);
+        assert_eq!(BigInt::parse("-1"), Some(BigInt::from(-1)));
+        assert_eq!(
+            BigInt::parse("99999999999999999999").map(|value| value.to_string()),
+            Some("99999999999999999999".to_string())
+        );
+        assert_eq!(BigInt::parse("1.5"), None);
+    }
+
+    #[test]
+    fn the_value_of_a_cell_is_formatted_and_wrapped_like_a_number() {
+        let big = CellValue::BigInt(BigInt::from(-2));
+        assert_eq!(format!("{:03}", CellValue::Byte(7)), "007");
+        assert_eq!(format!("{:>3}", big), " -2");
+        assert_eq!(big.low_byte(), 254);
+        assert_eq!(big.saturating_i64(), -2);
+        assert_eq!(
+            CellValue::BigInt(BigInt::from(u64::MAX)).saturating_i64(),
+            i64::MAX
+        );
+        assert_eq!(CellValue::Byte(255).added(1), (CellValue::Byte(0), true));
+        assert_eq!(big.added(-1), (CellValue::BigInt(BigInt::from(-3)), false));
+    }
+
+    #[test]
+    fn a_tape_of_either_kind_round_trips_through_json() {
+        let mut big = Memory::new(CellWidth::BigInt, 3);
+        big.add(0, -5);
+        big.add(2, isize::MAX);
+        big.add(2, isize::MAX);
+        for memory in [Memory::Bytes(vec![1, 2, 255]), big] {
+            let json = serde_json::to_string(&memory).unwrap();
+            assert_eq!(serde_json::from_str::<Memory>(&json).unwrap(), memory);
+        }
+        assert_eq!(
+            serde_json::to_string(&Memory::Bytes(vec![1, 2])).unwrap(),
+            "[1,2]"
+        );
+        assert!(serde_json::from_str::<Memory>("[\"1\", \"x\"]").is_err());
+    }
+
+    #[test]
+    fn a_window_replaces_the_cells_it_was_taken_from() {
+        let mut memory = Memory::Bytes(vec![1, 2, 3, 4]);
+        let mut window = memory.window(1..3);
+        window.add(0, 10);
+        memory.replace(1, &window);
+        assert_eq!(memory, Memory::Bytes(vec![1, 12, 3, 4]));
+    }
+}
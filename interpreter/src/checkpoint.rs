@@ -0,0 +1,173 @@
+//! Checkpoints of the interpreter written to disk, so a run that takes hours
+//! can be resumed after a crash or a reboot instead of starting over. A
+//! checkpoint holds everything the program can observe: the tape, where it is
+//! in the code, the input it was given and how much of it it read, and the
+//! output so far. Recorded history like step hashes is left out.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{cell::Memory, hash::fnv1a, BrainfuckInterpreter, ExecutionStats, Result};
+
+/// Bumped whenever the fields of [`SavedState`] change
+const VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The state of the interpreter at some step
+pub struct SavedState {
+    pub version: u32,
+    /// The FNV-1a hash of the code, so a checkpoint is only resumed by the
+    /// program that wrote it
+    pub code_hash: u64,
+    pub steps: u64,
+    /// The tape, as numbers for bytes and strings of digits for big integers
+    pub memory: Memory,
+    pub pointer: usize,
+    pub code_pos: usize,
+    pub input: Vec<u8>,
+    pub input_pos: usize,
+    pub input_closed: bool,
+    pub output: String,
+    pub pending_output: Vec<u8>,
+    pub stats: ExecutionStats,
+}
+
+#[derive(Debug, Clone)]
+/// Where and how often checkpoints are written. The newest one is always at
+/// `path`, the ones before it at `path.1`, `path.2` and so on.
+pub struct DiskCheckpoints {
+    pub path: PathBuf,
+    pub interval: u64,
+    /// How many checkpoints to keep, including the newest
+    pub keep: usize,
+    /// The step of the last checkpoint written
+    last: Option<u64>,
+}
+
+fn code_hash(code: &[char]) -> u64 {
+    fnv1a(code.iter().collect::<String>().as_bytes())
+}
+
+impl BrainfuckInterpreter {
+    pub fn save_state(&self) -> SavedState {
+        SavedState {
+            version: VERSION,
+            code_hash: code_hash(&self.code),
+            steps: self.steps,
+            memory: self.memory.clone(),
+            pointer: self.pointer,
+            code_pos: self.code_pos,
+            input: self.input.clone(),
+            input_pos: self.input_pos,
+            input_closed: self.input_closed,
+            output: self.output.clone(),
+            pending_output: self.pending_output.clone(),
+            stats: self.stats,
+        }
+    }
+
+    /// Continues from a saved state, replacing the input and output with the
+    /// ones in it. Fails if it was saved by a different program.
+    pub fn restore_state(&mut self, state: SavedState) -> Result {
+        if state.version != VERSION {
+            return Err(format!(
+                "the checkpoint is of version {}, but this interpreter reads version {}",
+                state.version, VERSION
+            )
+            .into());
+        }
+        if state.code_hash != code_hash(&self.code) {
+            return Err("the checkpoint was saved by a different program".into());
+        }
+        if state.memory.width() != self.memory.width() {
+            return Err(format!(
+                "the checkpoint has {}, but this run has {}",
+                state.memory.kind(),
+                self.memory.kind()
+            )
+            .into());
+        }
+        if state.memory.len() != self.memory.len() {
+            return Err(format!(
+                "the checkpoint has {} cells of memory, but this run has {}",
+                state.memory.len(),
+                self.memory.len()
+            )
+            .into());
+        }
+        if state.code_pos > self.code.len() {
+            return Err("the checkpoint is corrupt".into());
+        }
+
+        self.memory = state.memory;
+        self.steps = state.steps;
+        self.previous_steps = state.steps;
+        self.pointer = state.pointer;
+        self.seek(state.code_pos);
+        self.input = state.input;
+        self.input_pos = state.input_pos;
+        self.input_closed = state.input_closed;
+        self.output = state.output;
+        self.pending_output = state.pending_output;
+        self.stats = state.stats;
+
+        Ok(())
+    }
+}
+
+impl SavedState {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&text)
+            .map_err(|e| format!("{} is not a checkpoint: {}", path.display(), e).into())
+    }
+}
+
+/// The path with `.<suffix>` appended, like `run.checkpoint.1`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+impl DiskCheckpoints {
+    pub fn new(path: PathBuf, interval: u64, keep: usize) -> Self {
+        Self {
+            path,
+            interval: interval.max(1),
+            keep: keep.max(1),
+            last: None,
+        }
+    }
+
+    /// Writes a checkpoint if one is due at the interpreter's current step,
+    /// moving the older ones down. Returns whether it wrote one.
+    pub fn save_if_due(&mut self, i: &BrainfuckInterpreter) -> Result<bool> {
+        if !i.passed_multiple(self.interval) || self.last == Some(i.steps) {
+            return Ok(false);
+        }
+        self.last = Some(i.steps);
+
+        // so a crash while writing never leaves a broken checkpoint behind
+        let temporary = with_suffix(&self.path, "tmp");
+        fs::write(&temporary, serde_json::to_string(&i.save_state())?)?;
+
+        // the oldest one falls off the end
+        for n in (1..self.keep).rev() {
+            let older = if n == 1 {
+                self.path.clone()
+            } else {
+                with_suffix(&self.path, &(n - 1).to_string())
+            };
+            if older.exists() {
+                fs::rename(&older, with_suffix(&self.path, &n.to_string()))?;
+            }
+        }
+
+        fs::rename(&temporary, &self.path)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,86 @@
+//! Files that are compressed with zstd as they are written (and decompressed
+//! as they are read) when their name ends in `.zst`, since the traces and
+//! dumps of a long run get huge otherwise.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use brainfuck_extended::Result;
+
+/// A file being written, compressed or not
+pub enum Output {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+/// Whether a file is written compressed
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+/// Creates the file, compressing what is written to it if it is named `*.zst`.
+pub fn create(path: &Path) -> Result<Output> {
+    let file = BufWriter::new(File::create(path)?);
+
+    if is_compressed(path) {
+        Ok(Output::Zstd(zstd::Encoder::new(
+            file,
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?))
+    } else {
+        Ok(Output::Plain(file))
+    }
+}
+
+/// Writes the bytes to the file like [`std::fs::write`], compressed if it is
+/// named `*.zst`.
+pub fn write(path: &Path, bytes: &[u8]) -> Result {
+    let mut output = create(path)?;
+    output.write_all(bytes)?;
+    output.finish()
+}
+
+/// Reads the whole file like [`std::fs::read`], decompressing it if it is
+/// named `*.zst`.
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if is_compressed(path) {
+        Ok(zstd::decode_all(&bytes[..])?)
+    } else {
+        Ok(bytes)
+    }
+}
+
+impl Output {
+    /// Writes whatever is buffered, and ends the compressed stream. Dropping
+    /// the file without this may leave it truncated.
+    pub fn finish(self) -> Result {
+        let mut file = match self {
+            Self::Plain(file) => file,
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! `bf conformance`: runs a suite of small programs on the edge cases where
+//! Brainfuck implementations differ (what `,` does at the end of the input,
+//! how big a cell gets, what happens left of the first cell), and where they
+//! tend to go wrong (loops that are skipped or nested, characters that are
+//! not instructions). The step-by-step interpreter and every dispatcher of
+//! the bytecode engine run each case, and what they do is compared with the
+//! [`REFERENCES`], the semantics programs are commonly written for, to tell
+//! which flags a program ported from elsewhere needs.
+
+use std::fmt;
+
+use brainfuck_extended::{
+    bisect::Outcome,
+    bytecode::{Machine, Program},
+    interpreter::MEMORY_SIZE,
+    pragma::{CellWidth, Effective, Eof, PragmaPolicy, SemanticsProfile},
+    BrainfuckInterpreter, Dispatch, FusionTable, IoMode, OutputMode, StepResult,
+};
+use clap::ValueEnum;
+
+/// How many steps a case may take, far more than any of them needs
+const MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// One of the properties the probes find out
+pub enum Property {
+    /// What `-` does to a cell that is 0
+    Underflow,
+    /// Whether 256 `+` take a cell back to 0
+    CellSize,
+    /// What `,` does at the end of the input
+    Eof,
+    /// What `<` does on the first cell
+    LeftEdge,
+    /// Whether the tape has the classic 30000 cells
+    TapeSize,
+}
+
+#[derive(Debug, Clone)]
+/// What a case checks
+pub enum Check {
+    /// Every implementation prints this
+    Prints(&'static [u8]),
+    /// Implementations differ here, and what they print tells how
+    Probe(Property),
+}
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub name: &'static str,
+    pub code: String,
+    pub input: &'static [u8],
+    pub check: Check,
+}
+
+/// The cases of the suite, the ones every implementation agrees on first.
+pub fn suite() -> Vec<Case> {
+    let case = |name, code: &str, input, check| Case {
+        name,
+        code: code.to_string(),
+        input,
+        check,
+    };
+
+    vec![
+        case(
+            "cells keep their values when the pointer moves",
+            "+>++>+++<<.>.>.",
+            b"",
+            Check::Prints(b"\x01\x02\x03"),
+        ),
+        case(
+            "a loop runs until its cell is 0",
+            "+++[>++<-]>.",
+            b"",
+            Check::Prints(b"\x06"),
+        ),
+        case(
+            "a loop on a cell that is 0 is skipped",
+            "[.[.]+.]+.",
+            b"",
+            Check::Prints(b"\x01"),
+        ),
+        case(
+            "a skipped loop does not read the input it would have",
+            "[,.],.",
+            b"ab",
+            Check::Prints(b"a"),
+        ),
+        case(
+            "nested loops",
+            "++[>++[>++<-]<-]>>.",
+            b"",
+            Check::Prints(b"\x08"),
+        ),
+        case(
+            "deeply nested loops",
+            "+[[[[[[[[[[[[[[[[-]]]]]]]]]]]]]]]]+.",
+            b"",
+            Check::Prints(b"\x01"),
+        ),
+        case(
+            "a loop ending on another cell",
+            ">+>+[<]>.",
+            b"",
+            Check::Prints(b"\x01"),
+        ),
+        case(
+            "characters that are not instructions are ignored",
+            "+ text! #with$ %punctuation& ;and' \"digits\" 0123 ?+.",
+            b"",
+            Check::Prints(b"\x02"),
+        ),
+        case(
+            "`,` reads a byte at a time, newlines included",
+            ",.,.,.",
+            b"a\nb",
+            Check::Prints(b"a\nb"),
+        ),
+        case(
+            "`-` on a cell that is 0",
+            "-.",
+            b"",
+            Check::Probe(Property::Underflow),
+        ),
+        case(
+            "256 `+` on a cell",
+            "++++++++[>++++++++++++++++++++++++++++++++<-]>>+<[>-<[-]]>.",
+            b"",
+            Check::Probe(Property::CellSize),
+        ),
+        case(
+            "`,` at the end of the input",
+            "+++++,.",
+            b"",
+            Check::Probe(Property::Eof),
+        ),
+        case(
+            "`<` on the first cell",
+            "+<.",
+            b"",
+            Check::Probe(Property::LeftEdge),
+        ),
+        case(
+            "moving to cell 29999",
+            &format!("{}+.", ">".repeat(29_999)),
+            b"",
+            Check::Probe(Property::TapeSize),
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Semantics a program can be written for
+pub struct Reference {
+    pub name: &'static str,
+    /// How many bits a cell has, all of them wrapping around
+    pub cell_bits: u32,
+    /// What `,` stores at the end of the input, `None` to leave the cell
+    /// unchanged
+    pub eof: Option<u8>,
+    pub wrap_pointer: bool,
+    /// The `--profile` with these semantics, if there is one
+    pub profile: Option<SemanticsProfile>,
+}
+
+impl Reference {
+    const fn profile(name: &'static str, profile: SemanticsProfile) -> Self {
+        Self {
+            name,
+            cell_bits: 8,
+            eof: match profile.eof() {
+                Eof::Value(value) => Some(value),
+                Eof::Unchanged => None,
+            },
+            wrap_pointer: profile.wrap_pointer(),
+            profile: Some(profile),
+        }
+    }
+}
+
+/// The semantics programs are commonly written for, all with a tape of 30000
+/// cells: those of the [`SemanticsProfile`]s, and a few only `bf-codegen` or
+/// the flags have.
+pub const REFERENCES: &[Reference] = &[
+    Reference::profile(
+        "the most common convention, where `,` stores 0 at EOF",
+        SemanticsProfile::Classic,
+    ),
+    Reference::profile(
+        "Urban Müller's original implementation, where `,` leaves the cell unchanged at EOF",
+        SemanticsProfile::UrbanMueller,
+    ),
+    Reference::profile(
+        "a tape that wraps around, with EOF as 0",
+        SemanticsProfile::Easy,
+    ),
+    Reference::profile(
+        "a tape that wraps around, with `,` leaving the cell unchanged at EOF",
+        SemanticsProfile::Golf,
+    ),
+    Reference {
+        name: "EOF as -1, like a C implementation storing what `getchar()` returns",
+        cell_bits: 8,
+        eof: Some(255),
+        wrap_pointer: false,
+        profile: None,
+    },
+    Reference {
+        name: "16-bit cells with EOF as 0",
+        cell_bits: 16,
+        eof: Some(0),
+        wrap_pointer: false,
+        profile: None,
+    },
+];
+
+/// What runs the cases
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runner {
+    Interpreter,
+    Bytecode(Dispatch),
+}
+
+impl fmt::Display for Runner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interpreter => write!(f, "the interpreter"),
+            Self::Bytecode(dispatch) => write!(
+                f,
+                "the `{}` bytecode engine",
+                dispatch
+                    .to_possible_value()
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl Runner {
+    /// Everything in this build that can run programs.
+    pub fn available() -> Vec<Runner> {
+        let mut runners = vec![Runner::Interpreter];
+        runners.extend(Dispatch::available().into_iter().map(Runner::Bytecode));
+        runners
+    }
+
+    /// Runs a case with `semantics`.
+    pub fn run(&self, case: &Case, semantics: &Effective) -> Outcome {
+        match self {
+            Self::Interpreter => run_interpreter(case, semantics),
+            Self::Bytecode(dispatch) => {
+                let program = match Program::compile_with_semantics(
+                    &case.code,
+                    &FusionTable::default(),
+                    Vec::new(),
+                    semantics,
+                ) {
+                    Ok(program) => program,
+                    Err(e) => {
+                        return Outcome {
+                            output: Vec::new(),
+                            end: format!("failed to compile: {}", e),
+                            finished: true,
+                        }
+                    }
+                };
+                let mut machine = Machine::new(IoMode::Text);
+                machine.push_input(case.input);
+                machine.close_input();
+                Outcome::with_dispatch(&program, &machine, *dispatch, MAX_STEPS)
+            }
+        }
+    }
+}
+
+fn run_interpreter(case: &Case, semantics: &Effective) -> Outcome {
+    let mut i = BrainfuckInterpreter::new(&case.code, OutputMode::Utf8, IoMode::Text);
+    i.pragmas.wrap_pointer = semantics.wrap_pointer;
+    i.pragmas.eof = semantics.eof;
+    i.record_output_steps();
+    i.push_input(case.input);
+    i.close_input();
+
+    let (end, finished) = loop {
+        match i.step() {
+            Ok(StepResult::Halted) => break ("halted".to_string(), true),
+            Ok(StepResult::NeedsInput) => break ("needed more input".to_string(), true),
+            Ok(StepResult::Continue) => {}
+            Err(e) => break (format!("failed: {}", e), true),
+        }
+        if i.steps >= MAX_STEPS {
+            break (format!("did not halt within {} steps", MAX_STEPS), false);
+        }
+    };
+
+    Outcome {
+        output: i
+            .output_log
+            .unwrap_or_default()
+            .iter()
+            .map(|record| record.byte)
+            .collect(),
+        end,
+        finished,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The semantics the probes found, as far as any of the [`REFERENCES`] could
+/// have them
+pub struct Observed {
+    /// Whether `-` takes a cell from 0 to 255
+    pub underflow_wraps: bool,
+    /// Whether 256 `+` take a cell back to 0
+    pub eight_bits: bool,
+    /// What `,` stores at EOF, `Some(None)` if it leaves the cell unchanged
+    pub eof: Option<Option<u8>>,
+    /// Whether the pointer wraps around at the first cell, `None` if it does
+    /// neither that nor stay there
+    pub wrap_pointer: Option<bool>,
+    pub tape_30000: bool,
+}
+
+impl Observed {
+    /// Notes what a probe of `property` found out.
+    pub fn record(&mut self, property: Property, outcome: &Outcome) {
+        let halted = outcome.end == "halted";
+        let byte = outcome.output.first().copied().filter(|_| halted);
+        match property {
+            Property::Underflow => self.underflow_wraps = byte == Some(255),
+            Property::CellSize => self.eight_bits = byte == Some(1),
+            Property::Eof => self.eof = byte.map(|value| (value != 5).then_some(value)),
+            Property::LeftEdge => {
+                self.wrap_pointer = match byte {
+                    Some(1) => Some(false),
+                    Some(0) => Some(true),
+                    _ => None,
+                }
+            }
+            Property::TapeSize => self.tape_30000 = byte == Some(1),
+        }
+    }
+
+    /// What differs from `reference`, as the flags that would change it, or
+    /// what cannot be changed.
+    pub fn differences(&self, reference: &Reference) -> Vec<String> {
+        let mut differences = Vec::new();
+        if reference.cell_bits != 8 {
+            differences.push(format!(
+                "{}-bit cells, which only `bf-codegen --cell-size {}` has",
+                reference.cell_bits, reference.cell_bits
+            ));
+        } else if !(self.underflow_wraps && self.eight_bits) {
+            differences.push("8-bit cells that wrap around".to_string());
+        }
+        if self.eof != Some(reference.eof) {
+            differences.push(match reference.eof {
+                Some(value) => format!("`--eof {}`", value),
+                None => "`--eof unchanged`".to_string(),
+            });
+        }
+        if self.wrap_pointer != Some(reference.wrap_pointer) {
+            differences.push(if reference.wrap_pointer {
+                "`--pointer wrap`".to_string()
+            } else {
+                "`--pointer clamp`".to_string()
+            });
+        }
+        if !self.tape_30000 {
+            differences.push("a tape of 30000 cells".to_string());
+        }
+        differences
+    }
+}
+
+/// What a probe found out, in words.
+pub fn describe(property: Property, outcome: &Outcome) -> String {
+    if outcome.end != "halted" {
+        return match property {
+            Property::Underflow => format!("cells do not go below 0 (it {})", outcome.end),
+            Property::LeftEdge => {
+                format!(
+                    "there is nothing left of the first cell (it {})",
+                    outcome.end
+                )
+            }
+            Property::TapeSize => {
+                format!("the tape is shorter than 30000 cells (it {})", outcome.end)
+            }
+            _ => format!("it {}", outcome.end),
+        };
+    }
+
+    match (property, outcome.output.first().copied()) {
+        (Property::Underflow, Some(255)) => "cells wrap around from 0 to 255".to_string(),
+        (Property::CellSize, Some(1)) => "cells have 8 bits".to_string(),
+        (Property::CellSize, Some(0)) => "cells have more than 8 bits".to_string(),
+        (Property::Eof, Some(5)) => "`,` leaves the cell unchanged at EOF".to_string(),
+        (Property::Eof, Some(255)) => "`,` stores -1 (255) at EOF".to_string(),
+        (Property::Eof, Some(value)) => format!("`,` stores {} at EOF", value),
+        (Property::LeftEdge, Some(1)) => "the pointer stays on the first cell".to_string(),
+        (Property::LeftEdge, Some(0)) => {
+            format!("the pointer wraps around to cell {}", MEMORY_SIZE - 1)
+        }
+        (Property::TapeSize, Some(1)) => "the tape has at least 30000 cells".to_string(),
+        (_, Some(value)) => format!("it printed {}", value),
+        (_, None) => "it printed nothing".to_string(),
+    }
+}
+
+/// The semantics to run the suite with, from the flags.
+pub fn semantics(eof: Option<u8>, wrap_pointer: bool) -> Effective {
+    Effective {
+        wrap_pointer,
+        cell_size: CellWidth::Bits(8),
+        eof,
+        policy: PragmaPolicy::Flags,
+    }
+}
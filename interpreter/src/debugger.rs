@@ -0,0 +1,1310 @@
+use std::{
+    io::{stdout, Stdout, Write},
+    ops::Range,
+    process::{self, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::TryRecvError,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use brainfuck_extended::{
+    bytecode::Program,
+    cell::Memory,
+    pragma::{CellWidth, Pragmas},
+    FusionTable, IoMode, OutputMode, Result,
+};
+use crossterm::{
+    cursor,
+    event::{poll, read, Event, KeyCode},
+    execute,
+    style::{Color, Stylize},
+    terminal::{self, size},
+};
+
+use crate::{
+    disasm::{compile, disassemble, Line},
+    dump::Dump,
+    engine::{self, Command, EngineHandle, Snapshot, View},
+    explain::explain,
+    history::{sparkline, History},
+};
+
+/// Whether [`setup_terminal`] took over the terminal, so that
+/// [`cleanup_terminal`] only gives it back then
+static TERMINAL_SET_UP: AtomicBool = AtomicBool::new(false);
+
+pub fn setup_terminal() {
+    TERMINAL_SET_UP.store(true, Ordering::Relaxed);
+    let mut stdout = stdout();
+
+    execute!(stdout, terminal::EnterAlternateScreen).unwrap();
+    execute!(stdout, cursor::Hide).unwrap();
+
+    // Needed for when ytop is run in a TTY since TTYs don't actually have an alternate screen.
+    // Must be executed after attempting to enter the alternate screen so that it only clears the
+    // 		primary screen if we are running in a TTY.
+    // If not running in a TTY, then we just end up clearing the alternate screen which should have
+    // 		no effect.
+    execute!(stdout, terminal::Clear(terminal::ClearType::All)).unwrap();
+
+    terminal::enable_raw_mode().unwrap();
+}
+
+pub fn cleanup_terminal() {
+    if !TERMINAL_SET_UP.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let mut stdout = stdout();
+
+    // Needed for when ytop is run in a TTY since TTYs don't actually have an alternate screen.
+    // Must be executed before attempting to leave the alternate screen so that it only modifies the
+    // 		primary screen if we are running in a TTY.
+    // If not running in a TTY, then we just end up modifying the alternate screen which should have
+    // 		no effect.
+    execute!(stdout, cursor::MoveTo(0, 0)).unwrap();
+    execute!(stdout, terminal::Clear(terminal::ClearType::All)).unwrap();
+
+    execute!(stdout, terminal::LeaveAlternateScreen).unwrap();
+    execute!(stdout, cursor::Show).unwrap();
+
+    terminal::disable_raw_mode().unwrap();
+}
+
+/// What the status bar says about the program being debugged
+pub struct ProgramInfo {
+    /// Usually the file name of the source code
+    pub name: String,
+    pub output_mode: OutputMode,
+    pub io_mode: IoMode,
+    pub pragmas: Pragmas,
+    /// What the bytecode ops stepped through with `i` fuse
+    pub fusions: FusionTable,
+    /// How many cells the tape has
+    pub memory_size: usize,
+}
+
+impl ProgramInfo {
+    /// What kind of cells the tape has
+    fn cells(&self) -> &'static str {
+        match self.pragmas.cell_size {
+            Some(CellWidth::BigInt) => "big-integer cells",
+            _ => "8-bit cells",
+        }
+    }
+
+    /// A short description of how the program's instructions behave
+    fn dialect(&self) -> String {
+        let io = match self.io_mode {
+            IoMode::Text => "text",
+            IoMode::Numeric => "numeric",
+        };
+        let output = match self.output_mode {
+            OutputMode::Ascii => "ascii",
+            OutputMode::Utf8 => "utf8",
+        };
+        let pointer = if self.pragmas.wrap_pointer {
+            "wrapping"
+        } else {
+            "clamped"
+        };
+        let eof = match self.pragmas.eof {
+            Some(value) => format!(", EOF {}", value),
+            None => String::new(),
+        };
+
+        format!("{} I/O, {} output, {} pointer{}", io, output, pointer, eof)
+    }
+}
+
+#[derive(Default)]
+/// How the debugger gets the user's attention when execution stops on its own
+pub struct Alerts {
+    /// Ring the terminal bell when a stop condition is hit
+    pub bell: bool,
+    /// A shell command run whenever a stop condition is hit or the program halts
+    pub command: Option<String>,
+}
+
+pub struct Debugger {
+    stdout: Stdout,
+    program: ProgramInfo,
+    pub paused: bool,
+    pub halted: bool,
+    /// A message shown above the status lines, e.g. why execution paused
+    pub status: Option<String>,
+    pub alerts: Alerts,
+    /// When a stop condition was last hit, which flashes the status bar
+    flash: Option<Instant>,
+    /// The flash phase the status bar was last drawn in
+    flash_drawn: Option<bool>,
+    /// The index of the output byte selected with `<`/`>`, whose step is shown
+    output_selection: Option<usize>,
+    size: (u16, u16),
+
+    last_op_reset: SystemTime,
+    last_op_steps: u64,
+    last_ops_per_second: u64,
+
+    /// The cells shown in the memory pane
+    memory_range: Range<usize>,
+    /// Whether the code pane is colored by instruction class
+    color: bool,
+    /// Whether the pane explaining the next instruction is shown
+    explain: bool,
+    /// Whether the pane graphing the history of the cell and pointer is shown
+    show_history: bool,
+    history: History,
+    /// Whether the memory pane turns the page whenever the pointer leaves it
+    follow_pointer: bool,
+
+    /// The execution speed limit in instructions per second, `None` if unlimited
+    speed: Option<u64>,
+    /// When the last frame was requested and whether its snapshot has arrived yet
+    last_frame: (Instant, bool),
+    /// How long the program has been running, not counting the current stretch
+    run_time: Duration,
+    /// When the program was last continued, if it is running
+    running_since: Option<Instant>,
+    /// The bytecode ops stepped through instead of single instructions, if
+    /// toggled on with `i`
+    ops: Option<Ops>,
+}
+
+/// The bytecode of the program, for stepping one op at a time
+struct Ops {
+    lines: Vec<Line>,
+    program: Arc<Program>,
+}
+
+impl Ops {
+    /// The op the instruction at `pos` is part of, and the position the op
+    /// after it starts at
+    fn at(&self, pos: usize) -> Option<(&Line, usize)> {
+        let index = self.lines.iter().rposition(|line| line.position <= pos)?;
+        let end = self
+            .lines
+            .get(index + 1)
+            .map_or(usize::MAX, |next| next.position);
+        Some((&self.lines[index], end))
+    }
+}
+
+/// The smallest terminal the panes fit in
+const MIN_WIDTH: u16 = 40;
+const MIN_HEIGHT: u16 = 26;
+
+/// The number of lines at the bottom taken by the status bar
+const STATUS_BAR_HEIGHT: u16 = 3;
+
+/// How long the status bar flashes after a stop condition is hit
+const FLASH_DURATION: Duration = Duration::from_millis(1500);
+/// How long each on and off phase of the flash lasts
+const FLASH_PERIOD: Duration = Duration::from_millis(250);
+
+/// How often the UI redraws while the program is running
+const TARGET_FPS: u32 = 30;
+/// The slowest selectable execution speed, in instructions per second
+const MIN_SPEED: u64 = 1;
+/// Speeds above this are treated as unlimited
+const MAX_SPEED: u64 = 1 << 24;
+
+/// Every key binding, as listed by the help overlay
+const KEY_BINDINGS: &[(&str, &str)] = &[
+    ("c", "continue execution (while paused)"),
+    ("p", "pause execution (while running)"),
+    (
+        "any other letter / arrow",
+        "step one instruction, or one op with `i` (while paused)",
+    ),
+    ("i", "toggle stepping one bytecode op at a time"),
+    (
+        "< / >",
+        "select an output byte to see its step (while paused)",
+    ),
+    (
+        "Up / Down",
+        "double / halve the execution speed (while running)",
+    ),
+    ("PgUp / PgDn", "previous / next page of memory"),
+    ("g", "jump the memory view to a cell"),
+    ("f", "toggle following the pointer in the memory view"),
+    ("e", "toggle the pane explaining the next instruction"),
+    ("h", "toggle the pane graphing the cell and pointer history"),
+    (
+        ":",
+        "run a command: dump <start>..<end> [file] [hex|raw|rust]",
+    ),
+    ("?", "show this help"),
+    ("q", "quit"),
+];
+
+/// The color of an instruction in the code pane
+fn instruction_color(c: char) -> Color {
+    match c {
+        '>' | '<' => Color::Cyan,
+        '+' | '-' => Color::Green,
+        '.' | ',' => Color::Magenta,
+        '[' | ']' => Color::Yellow,
+        _ => Color::DarkGrey,
+    }
+}
+
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        cleanup_terminal();
+    }
+}
+
+struct Bounds {
+    pub start: usize,
+    pub end: usize,
+    pub rel: u16,
+}
+
+impl Debugger {
+    pub fn new(program: ProgramInfo, color: bool, explain: bool, history: bool) -> Result<Self> {
+        setup_terminal();
+
+        let mut stdout = stdout();
+        stdout.flush()?;
+
+        let size = size()?;
+
+        Ok(Self {
+            stdout,
+            program,
+            paused: true,
+            halted: false,
+            status: None,
+            alerts: Alerts::default(),
+            flash: None,
+            flash_drawn: None,
+            output_selection: None,
+            size,
+            last_op_reset: SystemTime::now(),
+            last_op_steps: 0,
+            last_ops_per_second: 0,
+            memory_range: 0..size.0 as usize / 4,
+            color,
+            explain,
+            show_history: history,
+            history: History::default(),
+            follow_pointer: true,
+            speed: None,
+            last_frame: (Instant::now(), true),
+            run_time: Duration::ZERO,
+            running_since: None,
+            ops: None,
+        })
+    }
+
+    /// Calculates the region of the buffer which should be displayed.
+    ///
+    /// `width`: The width of the resulting rendered text (**in characters**)
+    /// `buf_len`: The length of the buffer to be rendered
+    /// `pos`: The position of the cursor in the buffer
+    fn region_bounds(width: u16, buf_len: usize, pos: usize) -> Bounds {
+        let width = width as usize;
+
+        let buf_start = pos.saturating_sub(width / 2);
+        let buf_end = (buf_start + width / 2).min(buf_len);
+
+        let pos_rel = pos - buf_start;
+
+        Bounds {
+            start: buf_start,
+            end: buf_end,
+            rel: pos_rel as u16,
+        }
+    }
+
+    // fn region_bounds(width: u16, buf_len: usize, pos: usize) -> Bounds {
+    //     let width = width as usize;
+
+    //     let buf_start = pos.saturating_sub(width / 2);
+
+    //     let pos_rel = pos - buf_start;
+
+    //     let missing_left = width / 2 - pos_rel;
+
+    //     let buf_end = (buf_start + width / 2 + missing_left).min(buf_len);
+
+    //     Bounds {
+    //         start: buf_start,
+    //         end: buf_end,
+    //         rel: pos_rel as u16,
+    //     }
+    // }
+
+    fn draw_region(
+        &mut self,
+        label: &str,
+        (px, py): (u16, u16),
+        width: u16,
+        buf: impl AsRef<str>,
+        pos: usize,
+    ) -> Result {
+        execute!(self.stdout, cursor::MoveTo(px, py))?;
+        print!("{}:", label);
+
+        // slice by characters so multibyte output never splits a char boundary
+        let buf = buf.as_ref().chars().collect::<Vec<_>>();
+
+        let Bounds { start, end, rel } = Self::region_bounds(width, buf.len(), pos);
+
+        execute!(self.stdout, cursor::MoveTo(px, py + 1))?;
+        print!("{}", buf[start..end].iter().collect::<String>());
+
+        execute!(self.stdout, cursor::MoveTo(px + rel, py + 2))?;
+        print!("^");
+
+        Ok(())
+    }
+
+    /// Draws the code pane, highlighting the bracket matching the current one
+    /// and dimming everything outside the innermost loop being executed.
+    fn draw_code(&mut self, (px, py): (u16, u16), width: u16, view: &View) -> Result {
+        let pos = view.code_pos;
+        // with op stepping, the op being executed is underlined
+        let op = self.ops.as_ref().and_then(|ops| ops.at(pos));
+
+        execute!(self.stdout, cursor::MoveTo(px, py))?;
+        match op {
+            Some((line, _)) => print!(
+                "{:width$}",
+                format!("Code (op {:04}: {}):", line.offset, line.op),
+                width = width as usize
+            ),
+            None => print!("{:width$}", "Code:", width = width as usize),
+        }
+        let op = op.map(|(line, end)| line.position..end);
+
+        let Bounds { start, end, rel } = Self::region_bounds(width, view.code.len(), pos);
+
+        let matching = view
+            .code
+            .get(pos)
+            .filter(|c| matches!(c, '[' | ']'))
+            .and_then(|_| view.brackets.matching(pos));
+        let current_loop = view.brackets.enclosing_loop(pos);
+
+        execute!(self.stdout, cursor::MoveTo(px, py + 1))?;
+        for (i, c) in view.code[start..end].iter().enumerate() {
+            let i = start + i;
+            let c = if *c == '\n' { ' ' } else { *c };
+
+            let mut styled = c.stylize();
+            if self.color {
+                styled = styled.with(instruction_color(c));
+            }
+
+            if Some(i) == matching {
+                styled = styled.reverse();
+            } else if current_loop.is_some_and(|(open, close)| i < open || i > close) {
+                styled = styled.dim();
+            }
+            if op.as_ref().is_some_and(|op| op.contains(&i)) {
+                styled = styled.underlined();
+            }
+
+            print!("{}", styled);
+        }
+
+        execute!(self.stdout, cursor::MoveTo(px + rel, py + 2))?;
+        print!("^");
+
+        Ok(())
+    }
+
+    fn draw_memory(
+        &mut self,
+        (px, py): (u16, u16),
+        width: u16,
+        memory: &Memory,
+        pointer: usize,
+    ) -> Result {
+        let cell_count = ((width / 4) as usize).max(1);
+
+        if self.follow_pointer && !self.memory_range.contains(&pointer) {
+            // frames are rendered at a fixed rate, so the pointer may have moved
+            // any distance since the last one; turn to the page it is on
+            self.memory_range.start = pointer - pointer % cell_count;
+        }
+
+        // the terminal may also have been resized since the range was calculated
+        let cells = self.program.memory_size;
+        self.memory_range.start = self.memory_range.start.min(cells - 1);
+        self.memory_range.end = (self.memory_range.start + cell_count).min(cells);
+
+        execute!(self.stdout, cursor::MoveTo(px, py))?;
+        if self.follow_pointer {
+            print!("Memory:");
+        } else {
+            print!(
+                "Memory (cells {}-{}, press f to follow the pointer):",
+                self.memory_range.start,
+                self.memory_range.end - 1
+            );
+        }
+
+        execute!(self.stdout, cursor::MoveTo(px, py + 1))?;
+
+        let region = self
+            .memory_range
+            .clone()
+            .map(|cell| format!("{:03}", memory.get(cell)))
+            .collect::<Vec<_>>();
+
+        // big integers can take more than 3 digits, so the line is cut off at
+        // the edge and the caret goes under wherever the cell ended up
+        print!(
+            "{}",
+            region
+                .join(" ")
+                .chars()
+                .take(width as usize)
+                .collect::<String>()
+        );
+
+        if self.memory_range.contains(&pointer) {
+            let rel: usize = region[..pointer - self.memory_range.start]
+                .iter()
+                .map(|cell| cell.len() + 1)
+                .sum();
+            if rel < width as usize {
+                execute!(self.stdout, cursor::MoveTo(px + rel as u16, py + 2))?;
+                print!("^");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scrolls the memory pane by whole pages and stops following the pointer.
+    fn page_memory(&mut self, pages: isize) {
+        let page = self.memory_range.len().max(1) as isize;
+        let start = self.memory_range.start as isize + pages * page;
+        let last_page = (self.program.memory_size as isize - 1) / page * page;
+
+        self.memory_range.start = start.clamp(0, last_page) as usize;
+        self.follow_pointer = false;
+    }
+
+    /// Asks for a cell index and moves the memory pane to the page containing it.
+    fn jump_to_cell(&mut self, view: &View) -> Result {
+        let Some(line) = self.prompt_line("Jump to cell (Esc cancels):")? else {
+            return Ok(());
+        };
+
+        match line.trim().parse::<usize>() {
+            Ok(cell) if cell < self.program.memory_size => {
+                let page = self.memory_range.len().max(1);
+                self.memory_range.start = cell - cell % page;
+                self.follow_pointer = false;
+                self.status = None;
+            }
+            _ => {
+                self.status = Some(format!(
+                    "Not a cell index: {:?} (memory has {} cells)",
+                    line.trim(),
+                    self.program.memory_size
+                ));
+            }
+        }
+
+        self.render(view)
+    }
+
+    /// Reads a command at a `:` prompt and runs it. Only `dump` exists so
+    /// far, which works like the `--debug-script` command of the same name.
+    fn run_command(&mut self, view: &View) -> Result {
+        let Some(line) = self.prompt_line(":")? else {
+            return Ok(());
+        };
+
+        let line = line.trim();
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+        let result = match name {
+            "dump" => Dump::parse(args).and_then(|dump| {
+                let written = dump.write(&view.memory)?;
+                Ok((dump, written))
+            }),
+            _ => Err(format!("Unknown command: {:?}", name).into()),
+        };
+
+        match result {
+            Ok((dump, None)) => {
+                self.status = Some(format!(
+                    "Wrote cells {}..{} to {}",
+                    dump.range.start,
+                    dump.range.end,
+                    dump.path.unwrap_or_default().display()
+                ));
+            }
+            Ok((dump, Some(written))) => {
+                let mut lines: Vec<String> = String::from_utf8_lossy(&written)
+                    .lines()
+                    .map(str::to_string)
+                    .collect();
+                lines.splice(
+                    0..0,
+                    [
+                        format!("Cells {}..{}", dump.range.start, dump.range.end),
+                        String::new(),
+                    ],
+                );
+                lines.extend([String::new(), "Press any key to close".to_string()]);
+                self.show_overlay(&lines)?;
+            }
+            Err(e) => {
+                self.status = Some(e.to_string());
+            }
+        }
+
+        self.render(view)
+    }
+
+    /// Redraws every pane without handling any key presses.
+    fn render(&mut self, view: &View) -> Result {
+        execute!(self.stdout, terminal::Clear(terminal::ClearType::All))?;
+
+        if self.size.0 < MIN_WIDTH || self.size.1 < MIN_HEIGHT {
+            return self.render_too_small();
+        }
+
+        self.draw_region(
+            "Input",
+            (0, 0),
+            self.size.0,
+            view.input
+                .iter()
+                .map(|b| {
+                    if b.is_ascii_graphic() {
+                        *b as char
+                    } else {
+                        ' '
+                    }
+                })
+                .collect::<String>(),
+            view.input_pos,
+        )?;
+
+        self.draw_memory((0, 6), self.size.0, &view.memory, view.pointer)?;
+
+        let output_log = &view.output_log;
+        let (output_label, output_pos) = match self.output_selection {
+            Some(selection) if selection < output_log.len() => {
+                let record = output_log[selection];
+                let bytes = output_log[..selection]
+                    .iter()
+                    .map(|record| record.byte)
+                    .collect::<Vec<_>>();
+                (
+                    format!(
+                        "Output (byte {} = {:?}, written at step {})",
+                        selection, record.byte as char, record.step
+                    ),
+                    String::from_utf8_lossy(&bytes).chars().count(),
+                )
+            }
+            _ => ("Output".to_string(), view.output.chars().count()),
+        };
+
+        self.draw_region(
+            &output_label,
+            (0, 12),
+            self.size.0,
+            &view.output,
+            output_pos,
+        )?;
+
+        self.draw_code((0, 16), self.size.0, view)?;
+
+        if self.explain {
+            execute!(self.stdout, cursor::MoveTo(0, 20))?;
+            print!("Next:");
+            execute!(self.stdout, cursor::MoveTo(0, 21))?;
+            let explanation = explain(view, self.program.io_mode, &self.program.pragmas);
+            print!(
+                "{}",
+                explanation
+                    .chars()
+                    .take(self.size.0 as usize)
+                    .collect::<String>()
+            );
+        }
+
+        if self.show_history {
+            // below the explanation if there is room for both
+            let row = if self.explain { 23 } else { 20 };
+            if row + 1 < self.size.1 - STATUS_BAR_HEIGHT - 1 {
+                self.draw_history((0, row), self.size.0)?;
+            }
+        }
+
+        if let Some(status) = &self.status {
+            execute!(
+                self.stdout,
+                cursor::MoveTo(0, self.size.1 - STATUS_BAR_HEIGHT - 1)
+            )?;
+            print!("{}", status);
+        }
+
+        self.draw_status_bar(view)?;
+
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Draws a sparkline of the recent values of the cell under the pointer and
+    /// one of the pointer, each scaled to the range it covered.
+    fn draw_history(&mut self, (px, py): (u16, u16), width: u16) -> Result {
+        let rows = [
+            (
+                "Cell",
+                self.history.cells.iter().copied().collect::<Vec<_>>(),
+            ),
+            (
+                "Pointer",
+                self.history
+                    .pointers
+                    .iter()
+                    .map(|pointer| *pointer as i64)
+                    .collect::<Vec<_>>(),
+            ),
+        ];
+
+        for (row, (label, values)) in rows.into_iter().enumerate() {
+            // the label and range take up to 24 characters
+            let (line, low, high) =
+                sparkline(values.into_iter(), (width as usize).saturating_sub(24));
+            execute!(self.stdout, cursor::MoveTo(px, py + row as u16))?;
+            print!("{:<8} {} {}-{}", format!("{}:", label), line, low, high);
+        }
+
+        Ok(())
+    }
+
+    /// Draws the bottom lines: what is being run, how far along it is and
+    /// the latest step hash.
+    fn draw_status_bar(&mut self, view: &View) -> Result {
+        let state = if self.halted {
+            "Halted"
+        } else if self.paused {
+            "Paused"
+        } else {
+            "Running"
+        };
+
+        let elapsed = self.elapsed().as_secs();
+        let speed = match self.speed {
+            Some(speed) => format!("{}/s", speed),
+            None => "unlimited".to_string(),
+        };
+
+        let hash = match view.hash {
+            Some(hash) => format!(" Hash at step {}: {:016x}", hash.step, hash.hash),
+            None => " Hash: none published yet".to_string(),
+        };
+        let mut hash = format!("{} | Memory: {}", hash, view.memory_usage);
+        if let Some(phase) = &view.phase {
+            hash.push_str(&format!(
+                " | Phase: {} ({} steps, {} bytes out, {:.1}s)",
+                phase.name, phase.steps, phase.output_bytes, phase.time_secs
+            ));
+        }
+
+        let lines = [
+            format!(
+                " {} | {} | {} | {} cells",
+                self.program.name,
+                self.program.dialect(),
+                self.program.cells(),
+                self.program.memory_size
+            ),
+            format!(
+                " {} | {:02}:{:02}:{:02} | Steps: {} | Pos: {} | Pointer: {} | Speed: {} | Ops/s: {}",
+                state,
+                elapsed / 3600,
+                elapsed / 60 % 60,
+                elapsed % 60,
+                view.steps,
+                view.code_pos,
+                view.pointer,
+                speed,
+                self.last_ops_per_second
+            ),
+            hash,
+        ];
+
+        self.flash_drawn = self.flash_phase();
+        let highlighted = self.flash_drawn == Some(true);
+
+        let width = self.size.0 as usize;
+        for (row, line) in lines.iter().enumerate() {
+            let line = format!("{:width$}", line.chars().take(width).collect::<String>());
+            execute!(
+                self.stdout,
+                cursor::MoveTo(0, self.size.1 - STATUS_BAR_HEIGHT + row as u16)
+            )?;
+            if highlighted {
+                print!("{}", line.black().on_yellow());
+            } else {
+                print!("{}", line.reverse());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the flashing status bar is highlighted right now, or `None` if
+    /// it is not flashing.
+    fn flash_phase(&self) -> Option<bool> {
+        let elapsed = self.flash?.elapsed();
+        (elapsed < FLASH_DURATION)
+            .then(|| (elapsed.as_millis() / FLASH_PERIOD.as_millis()).is_multiple_of(2))
+    }
+
+    /// Flashes the status bar, and rings the bell if enabled, because a stop
+    /// condition was hit.
+    fn alert(&mut self) -> Result {
+        self.flash = Some(Instant::now());
+
+        if self.alerts.bell {
+            print!("\x07");
+            self.stdout.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the `--notify-cmd`, if any, with the event in its environment.
+    /// It runs in the background, and its output is discarded so it cannot
+    /// draw over the panes.
+    fn notify(&mut self, event: &str, reason: Option<&str>, steps: u64) {
+        let Some(command) = &self.alerts.command else {
+            return;
+        };
+
+        let child = process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("BF_EVENT", event)
+            .env("BF_REASON", reason.unwrap_or_default())
+            .env("BF_STEPS", steps.to_string())
+            .env("BF_PROGRAM", &self.program.name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        match child {
+            Ok(mut child) => {
+                // reap it whenever it exits
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => {
+                self.status = Some(format!("Failed to run --notify-cmd: {}", e));
+            }
+        }
+    }
+
+    /// How long the program has spent running, excluding pauses.
+    fn elapsed(&self) -> Duration {
+        self.run_time
+            + self
+                .running_since
+                .map(|since| since.elapsed())
+                .unwrap_or_default()
+    }
+
+    /// Pauses or resumes the clock shown in the status bar along with `paused`.
+    /// Switches between stepping single instructions and bytecode ops.
+    fn toggle_ops(&mut self, view: &View) {
+        if self.ops.take().is_some() {
+            self.status = Some("Stepping one instruction at a time".to_string());
+            return;
+        }
+
+        let code: String = view.code.iter().collect();
+        match compile(&code, &self.program.fusions) {
+            Ok(program) => {
+                let lines = disassemble(&program, &code);
+                self.status = Some(format!(
+                    "Stepping one bytecode op at a time ({} ops), checked against the bytecode engine",
+                    lines.len()
+                ));
+                self.ops = Some(Ops {
+                    lines,
+                    program: Arc::new(program),
+                });
+            }
+            Err(e) => self.status = Some(format!("Cannot step by op: {}", e)),
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+
+        if paused {
+            if let Some(since) = self.running_since.take() {
+                self.run_time += since.elapsed();
+            }
+        } else {
+            self.running_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Draws a box of text over the middle of the screen and waits for a key to dismiss it.
+    fn show_overlay(&mut self, lines: &[String]) -> Result {
+        let inner_width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(self.size.0.saturating_sub(4) as usize);
+        let height = lines.len().min(self.size.1.saturating_sub(2) as usize);
+
+        let col = self.size.0.saturating_sub(inner_width as u16 + 4) / 2;
+        let row = self.size.1.saturating_sub(height as u16 + 2) / 2;
+
+        let border = format!("+{}+", "-".repeat(inner_width + 2));
+
+        execute!(self.stdout, cursor::MoveTo(col, row))?;
+        print!("{}", border);
+
+        for (i, line) in lines.iter().take(height).enumerate() {
+            let line = line.chars().take(inner_width).collect::<String>();
+            execute!(self.stdout, cursor::MoveTo(col, row + 1 + i as u16))?;
+            print!("| {:inner_width$} |", line);
+        }
+
+        execute!(self.stdout, cursor::MoveTo(col, row + 1 + height as u16))?;
+        print!("{}", border);
+
+        self.stdout.flush()?;
+
+        loop {
+            match read()? {
+                Event::Key(_) => break,
+                Event::Resize(width, height) => {
+                    // the panes are redrawn with the new layout once the overlay closes
+                    self.size = (width, height);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the key bindings and how the debugger is configured.
+    fn show_help(&mut self, view: &View) -> Result {
+        let mut lines = vec!["Keys".to_string(), String::new()];
+
+        let key_width = KEY_BINDINGS
+            .iter()
+            .map(|(key, _)| key.len())
+            .max()
+            .unwrap_or(0);
+        for (key, description) in KEY_BINDINGS {
+            lines.push(format!("{:key_width$}  {}", key, description));
+        }
+
+        lines.extend([
+            String::new(),
+            "Configuration".to_string(),
+            String::new(),
+            format!("Program: {}", self.program.name),
+            format!("Dialect: {}", self.program.dialect()),
+            format!(
+                "Memory: {} {}",
+                self.program.memory_size,
+                self.program.cells()
+            ),
+            format!(
+                "Speed: {}",
+                match self.speed {
+                    Some(speed) => format!("{} instructions/s", speed),
+                    None => "unlimited".to_string(),
+                }
+            ),
+            format!(
+                "Follow pointer: {}",
+                if self.follow_pointer { "on" } else { "off" }
+            ),
+            format!("Colors: {}", if self.color { "on" } else { "off" }),
+            format!("Explanations: {}", if self.explain { "on" } else { "off" }),
+            format!("History: {}", if self.show_history { "on" } else { "off" }),
+            String::new(),
+            "Press any key to close".to_string(),
+        ]);
+
+        self.show_overlay(&lines)?;
+        self.render(view)
+    }
+
+    /// Shown instead of the panes when they would not fit in the terminal.
+    fn render_too_small(&mut self) -> Result {
+        let lines = [
+            "Terminal too small".to_string(),
+            format!(
+                "{}x{}, need {}x{}",
+                self.size.0, self.size.1, MIN_WIDTH, MIN_HEIGHT
+            ),
+        ];
+
+        for (row, line) in lines.iter().enumerate() {
+            let col = self.size.0.saturating_sub(line.len() as u16) / 2;
+            let row = (self.size.1 / 2 + row as u16).saturating_sub(1);
+            execute!(self.stdout, cursor::MoveTo(col, row))?;
+            print!("{}", line);
+        }
+
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Reads a line typed at the prompt row, returning `None` if it was
+    /// cancelled with Esc.
+    fn prompt_line(&mut self, message: &str) -> Result<Option<String>> {
+        let mut line = String::new();
+
+        execute!(self.stdout, cursor::Show)?;
+
+        let result = loop {
+            execute!(
+                self.stdout,
+                cursor::MoveTo(0, self.size.1.saturating_sub(STATUS_BAR_HEIGHT + 1)),
+                terminal::Clear(terminal::ClearType::CurrentLine)
+            )?;
+            print!("{} {}", message, line);
+            self.stdout.flush()?;
+
+            let key = match read()? {
+                Event::Key(key) => key,
+                Event::Resize(width, height) => {
+                    // the panes are redrawn with the new layout once the prompt closes
+                    self.size = (width, height);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match key.code {
+                KeyCode::Enter => {
+                    break Some(line);
+                }
+                KeyCode::Esc => {
+                    break None;
+                }
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Char(c) => {
+                    line.push(c);
+                }
+                _ => {}
+            }
+        };
+
+        execute!(self.stdout, cursor::Hide)?;
+
+        Ok(result)
+    }
+
+    /// Asks the user for more input once the program blocks on `,` with the
+    /// input exhausted. Returns `None` if the user signalled EOF instead.
+    fn prompt_input(&mut self) -> Result<Option<String>> {
+        let line =
+            self.prompt_line("Program needs input (Enter sends the line, Esc sends EOF):")?;
+
+        Ok(line.map(|line| line + "\n"))
+    }
+
+    /// Recalculates the op/s counter once every second.
+    fn update_ops_per_second(&mut self, steps: u64) -> Result {
+        let now = SystemTime::now();
+        if now.duration_since(self.last_op_reset)? > Duration::from_secs(1) {
+            // rewinding moves the step count backwards
+            self.last_ops_per_second = steps.saturating_sub(self.last_op_steps);
+            self.last_op_steps = steps;
+            self.last_op_reset = now;
+        }
+
+        Ok(())
+    }
+
+    /// Brings the view up to date and samples its history.
+    fn apply(&mut self, view: &mut View, snapshot: Snapshot) {
+        view.apply(snapshot);
+        self.history.record(view);
+    }
+
+    /// Drives the UI until the user quits, sending commands to the
+    /// interpreter thread and rendering the state it reports back.
+    pub fn run(&mut self, engine: &EngineHandle, view: &mut View) -> Result {
+        let frame_time = Duration::from_secs(1) / TARGET_FPS;
+
+        self.render(view)?;
+        self.show_overlay(&[
+            "brainfuck-extended debugger".to_string(),
+            String::new(),
+            format!("Debugging {}, starting paused.", self.program.name),
+            "Step with any letter, continue with c, pause with p".to_string(),
+            "and quit with q. Press ? at any time for every key.".to_string(),
+            String::new(),
+            "Press any key to start".to_string(),
+        ])?;
+        self.render(view)?;
+
+        loop {
+            let mut dirty = false;
+
+            loop {
+                let event = match engine.events.try_recv() {
+                    Ok(event) => event,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        return Err("the interpreter thread stopped unexpectedly".into());
+                    }
+                };
+
+                dirty = true;
+
+                match event {
+                    engine::Event::Snapshot(snapshot) => {
+                        self.apply(view, snapshot);
+                        self.last_frame.1 = true;
+                    }
+                    engine::Event::Paused(snapshot, reason) => {
+                        self.apply(view, snapshot);
+                        self.set_paused(true);
+                        if let Some(reason) = reason {
+                            self.status = Some(format!("Paused: {}", reason));
+                        }
+                    }
+                    engine::Event::Stopped(snapshot, reason) => {
+                        self.apply(view, snapshot);
+                        self.set_paused(true);
+                        self.alert()?;
+                        self.notify("stopped", Some(&reason), view.steps);
+                        self.status = Some(format!("Paused: {}", reason));
+                    }
+                    engine::Event::NeedsInput(snapshot) => {
+                        self.apply(view, snapshot);
+                        self.render(view)?;
+
+                        match self.prompt_input()? {
+                            Some(line) => engine.send(Command::PushInput(line.into_bytes())),
+                            None => engine.send(Command::CloseInput),
+                        }
+
+                        // retry the `,` that asked for input
+                        if self.paused {
+                            engine.send(Command::Step);
+                        } else {
+                            engine.send(Command::Continue);
+                        }
+                    }
+                    engine::Event::Halted(snapshot, reason) => {
+                        self.apply(view, snapshot);
+                        self.set_paused(true);
+                        self.halted = true;
+                        if reason.is_some() {
+                            self.alert()?;
+                        }
+                        self.notify("halted", reason.as_deref(), view.steps);
+                        self.status = Some(match reason {
+                            Some(reason) => format!(
+                                "Halted: {} (press r to rewind, : for a command, any other key to quit)",
+                                reason
+                            ),
+                            None => "Halted (press r to rewind, : for a command, any other key to quit)".to_string(),
+                        });
+                    }
+                    engine::Event::Notice(notice) => {
+                        self.status = Some(notice);
+                    }
+                    engine::Event::Failed(e) => {
+                        return Err(e.into());
+                    }
+                    engine::Event::Crashed(e) => {
+                        return Err(e.into());
+                    }
+                }
+            }
+
+            // redraw whenever the flashing status bar changes phase
+            if self.flash_phase() != self.flash_drawn {
+                dirty = true;
+            }
+
+            if dirty {
+                self.update_ops_per_second(view.steps)?;
+                self.render(view)?;
+            }
+
+            // request the next frame once the previous one arrived, so a slow
+            // terminal never queues up snapshots
+            let since_frame = self.last_frame.0.elapsed();
+            if !self.paused && self.last_frame.1 && since_frame >= frame_time {
+                engine.send(Command::RequestSnapshot);
+                self.last_frame = (Instant::now(), false);
+            }
+
+            if !poll(
+                frame_time
+                    .saturating_sub(since_frame)
+                    .max(Duration::from_millis(1)),
+            )? {
+                continue;
+            }
+
+            let key = match read()? {
+                Event::Key(key) => key,
+                Event::Resize(width, height) => {
+                    self.size = (width, height);
+                    self.render(view)?;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if self.halted {
+                if key.code == KeyCode::Char(':') {
+                    self.run_command(view)?;
+                    continue;
+                }
+                if key.code != KeyCode::Char('r') {
+                    break Ok(());
+                }
+
+                self.halted = false;
+                engine.send(Command::Rewind);
+                continue;
+            }
+
+            // keys that only move the memory pane work whether or not the program is running
+            match key.code {
+                KeyCode::PageUp => {
+                    self.page_memory(-1);
+                    self.render(view)?;
+                    continue;
+                }
+                KeyCode::PageDown => {
+                    self.page_memory(1);
+                    self.render(view)?;
+                    continue;
+                }
+                KeyCode::Char('g') => {
+                    self.jump_to_cell(view)?;
+                    continue;
+                }
+                KeyCode::Char('f') => {
+                    self.follow_pointer = !self.follow_pointer;
+                    self.render(view)?;
+                    continue;
+                }
+                KeyCode::Char('e') => {
+                    self.explain = !self.explain;
+                    self.render(view)?;
+                    continue;
+                }
+                KeyCode::Char('h') => {
+                    self.show_history = !self.show_history;
+                    self.render(view)?;
+                    continue;
+                }
+                KeyCode::Char('i') => {
+                    self.toggle_ops(view);
+                    self.render(view)?;
+                    continue;
+                }
+                KeyCode::Char('?') => {
+                    self.show_help(view)?;
+                    continue;
+                }
+                KeyCode::Char(':') => {
+                    self.run_command(view)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if self.paused {
+                match key.code {
+                    KeyCode::Char('q') => {
+                        break Ok(());
+                    }
+                    KeyCode::Char('c') => {
+                        self.set_paused(false);
+                        self.status = None;
+                        engine.send(Command::Continue);
+                    }
+                    KeyCode::Char('r') => {
+                        engine.send(Command::Rewind);
+                    }
+                    KeyCode::Char('<') => {
+                        self.output_selection = match self.output_selection {
+                            Some(selection) => Some(selection.saturating_sub(1)),
+                            None => view.output_log.len().checked_sub(1),
+                        };
+                        self.render(view)?;
+                    }
+                    KeyCode::Char('>') => {
+                        self.output_selection = self
+                            .output_selection
+                            .map(|selection| selection + 1)
+                            .filter(|selection| *selection < view.output_log.len());
+                        self.render(view)?;
+                    }
+                    KeyCode::Char(_)
+                    | KeyCode::Left
+                    | KeyCode::Right
+                    | KeyCode::Up
+                    | KeyCode::Down => {
+                        engine.send(match &self.ops {
+                            Some(ops) => Command::StepOp(ops.program.clone()),
+                            None => Command::Step,
+                        });
+                    }
+                    _ => {}
+                }
+            } else {
+                match key.code {
+                    KeyCode::Char('q') => {
+                        break Ok(());
+                    }
+                    KeyCode::Char('p') => {
+                        engine.send(Command::Pause);
+                    }
+                    KeyCode::Up => {
+                        self.speed = self
+                            .speed
+                            .map(|speed| speed.saturating_mul(2))
+                            .filter(|speed| *speed <= MAX_SPEED);
+                        engine.send(Command::SetSpeed(self.speed));
+                        self.render(view)?;
+                    }
+                    KeyCode::Down => {
+                        self.speed = Some(match self.speed {
+                            Some(speed) => (speed / 2).max(MIN_SPEED),
+                            None => MAX_SPEED,
+                        });
+                        engine.send(Command::SetSpeed(self.speed));
+                        self.render(view)?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! A readable listing of the bytecode a program compiles to (see
+//! [`brainfuck_extended::bytecode`]), for `bf disasm` and for stepping the
+//! debugger one op at a time. Every op gets a line like
+//!
+//! ```text
+//!   0003  1:10     4  add 1; move 1 (fused)                 +>
+//! > 0004  1:14     1  jump-if-not-zero -> 0002              ]
+//! ```
+//!
+//! with its offset, where it starts in the source, how many instructions it
+//! stands for and the instructions themselves. A `>` marks the ops something
+//! jumps to.
+//!
+//! [`passes`] lists the ops after each pass of the compiler instead (see
+//! [`brainfuck_extended::bisect::Pass`]), and [`diff`] shows what a pass
+//! changed.
+//!
+//! When the debugger steps by op, each op runs on the bytecode engine as well
+//! as the interpreter (see [`crate::engine::Command::StepOp`]), so a fused op or
+//! loop op that does not do what its instructions do stands out.
+
+use std::{fmt, str::FromStr};
+
+use brainfuck_extended::{
+    bisect::{OptFuel, Pass},
+    bytecode::{Op, Program},
+    pragma::{read_pragmas, skipped},
+    FusionTable, Result,
+};
+
+use crate::report::line_and_column;
+
+/// How many instructions of the source are shown for an op
+const MAX_SOURCE: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An op of the bytecode and where it came from
+pub struct Line {
+    pub offset: usize,
+    /// The position of its first instruction in the source
+    pub position: usize,
+    pub line: usize,
+    pub column: usize,
+    /// How many instructions it stands for
+    pub steps: u32,
+    pub op: String,
+    /// The op with jump targets as where they are in the source, which does
+    /// not change when ops before them are merged
+    pub anchored: String,
+    /// Whether something jumps to it
+    pub target: bool,
+    /// Its instructions in the source, shortened if there are many
+    pub source: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04}  {:<7} {:>5}  {:<38} {}",
+            if self.target { '>' } else { ' ' },
+            self.offset,
+            format!("{}:{}", self.line, self.column),
+            self.steps,
+            self.op,
+            self.source
+        )
+    }
+}
+
+/// An add as the signed amount it makes up.
+fn signed(add: u8) -> i16 {
+    add as i8 as i16
+}
+
+/// Describes an op, with jump targets as `jump` describes them.
+fn describe(op: Op, program: &Program, jump: &dyn Fn(usize) -> String) -> String {
+    match op {
+        Op::Add(add) => format!("add {}", signed(add)),
+        Op::Move(offset) => format!("move {}", offset),
+        Op::Write => "write".to_string(),
+        Op::Read => "read".to_string(),
+        Op::JumpIfZero(end) => format!("jump-if-zero -> {}", jump(end)),
+        Op::JumpIfNotZero(target) => format!("jump-if-not-zero -> {}", jump(target)),
+        Op::Clear { cells, add } => format!("clear {} by {}", cells, signed(add)),
+        Op::Scan { step, end } => format!("scan {} -> {}", step, jump(end)),
+        Op::Copy(index) => {
+            let copy = &program.copies[index];
+            let targets: Vec<String> = copy
+                .targets
+                .iter()
+                .map(|(offset, factor)| format!("{}*{}", offset, signed(*factor)))
+                .collect();
+            format!("copy {} -> {}", targets.join(" "), jump(copy.end))
+        }
+        Op::AddMove(add, offset) => format!("add {}; move {} (fused)", signed(add), offset),
+        Op::MoveAdd(offset, add) => format!("move {}; add {} (fused)", offset, signed(add)),
+        Op::AddMoveAdd(first, offset, second) => format!(
+            "add {}; move {}; add {} (fused)",
+            signed(first),
+            offset,
+            signed(second)
+        ),
+        Op::MoveAddMove(first, add, second) => format!(
+            "move {}; add {}; move {} (fused)",
+            first,
+            signed(add),
+            second
+        ),
+        Op::Set { add, value } => {
+            format!("clear 1 by {}; add {} (fused)", signed(add), signed(value))
+        }
+        Op::SetMove { add, value, offset } => format!(
+            "clear 1 by {}; add {}; move {} (fused)",
+            signed(add),
+            signed(value),
+            offset
+        ),
+        Op::MoveJumpIfZero(offset, end) => {
+            format!("move {}; jump-if-zero -> {} (fused)", offset, jump(end))
+        }
+        Op::MoveJumpIfNotZero(offset, target) => {
+            format!(
+                "move {}; jump-if-not-zero -> {} (fused)",
+                offset,
+                jump(target)
+            )
+        }
+        Op::AddJumpIfNotZero(add, target) => format!(
+            "add {}; jump-if-not-zero -> {} (fused)",
+            signed(add),
+            jump(target)
+        ),
+        Op::Assert(index) => format!("assert {}", program.assertions[index].text),
+        Op::Halt => "halt".to_string(),
+    }
+}
+
+/// The offsets something jumps to, or lands on after a loop op.
+fn targets(program: &Program) -> Vec<bool> {
+    let mut targets = vec![false; program.instrs.len() + 1];
+
+    for instr in &program.instrs {
+        let target = match instr.op {
+            Op::JumpIfZero(end) | Op::Scan { end, .. } | Op::MoveJumpIfZero(_, end) => end,
+            Op::JumpIfNotZero(target)
+            | Op::MoveJumpIfNotZero(_, target)
+            | Op::AddJumpIfNotZero(_, target) => target,
+            Op::Copy(index) => program.copies[index].end,
+            _ => continue,
+        };
+        targets[target] = true;
+    }
+
+    targets
+}
+
+/// Lists the ops of `program`, which was compiled from `code`.
+pub fn disassemble(program: &Program, code: &str) -> Vec<Line> {
+    let chars: Vec<char> = code.chars().collect();
+    let directives = skipped(code);
+    let targets = targets(program);
+
+    program
+        .instrs
+        .iter()
+        .enumerate()
+        .map(|(offset, instr)| {
+            let position = program.positions[offset];
+            let end = program
+                .positions
+                .get(offset + 1)
+                .copied()
+                .unwrap_or(chars.len());
+            let (line, column) = line_and_column(&chars, position);
+
+            // an assertion's text is part of its op
+            let mut source: String = match instr.op {
+                Op::Assert(_) => String::new(),
+                _ => (position..end.max(position))
+                    .filter(|&i| !directives.iter().any(|range| range.contains(&i)))
+                    .map(|i| chars[i])
+                    .filter(|c| "+-<>.,[]".contains(*c))
+                    .collect(),
+            };
+            if source.chars().count() > MAX_SOURCE {
+                source = source.chars().take(MAX_SOURCE - 1).collect();
+                source.push('…');
+            }
+
+            Line {
+                offset,
+                position,
+                line,
+                column,
+                steps: instr.count,
+                op: describe(instr.op, program, &|target| format!("{:04}", target)),
+                anchored: describe(instr.op, program, &|target| {
+                    let (line, column) = line_and_column(&chars, program.positions[target]);
+                    format!("{}:{}", line, column)
+                }),
+                target: targets[offset],
+                source,
+            }
+        })
+        .collect()
+}
+
+/// Compiles `code` with `fusions`.
+pub fn compile(code: &str, fusions: &FusionTable) -> Result<Program> {
+    compile_with_fuel(code, fusions, &mut OptFuel::default())
+}
+
+/// Like [`compile`], with only the transformations `fuel` allows.
+fn compile_with_fuel(code: &str, fusions: &FusionTable, fuel: &mut OptFuel) -> Result<Program> {
+    let semantics = read_pragmas(code)?;
+    Program::compile_with_fuel(code, fusions, Vec::new(), &semantics, fuel)
+}
+
+/// Compiles `code` with `fusions` and `fuel` and lists its ops.
+pub fn listing(code: &str, fusions: &FusionTable, fuel: &mut OptFuel) -> Result<Vec<Line>> {
+    Ok(disassemble(&compile_with_fuel(code, fusions, fuel)?, code))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The passes to dump the program after, for `--dump-ir-after`
+pub enum DumpAfter {
+    All,
+    Pass(Pass),
+}
+
+impl FromStr for DumpAfter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            _ => Pass::ALL
+                .into_iter()
+                .find(|pass| pass.name() == s)
+                .map(Self::Pass)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = Pass::ALL.iter().map(Pass::name).collect();
+                    format!("`{}` is not `all` or one of {}", s, names.join(", "))
+                }),
+        }
+    }
+}
+
+/// Lists the ops of `code` after every pass, compiling it with `fusions`
+/// and at most `limit` transformations.
+pub fn passes(
+    code: &str,
+    fusions: &FusionTable,
+    limit: Option<u64>,
+) -> Result<Vec<(Pass, Vec<Line>)>> {
+    Pass::ALL
+        .into_iter()
+        .map(|pass| {
+            let mut fuel = OptFuel {
+                until: pass,
+                ..OptFuel::new(limit)
+            };
+            Ok((pass, listing(code, fusions, &mut fuel)?))
+        })
+        .collect()
+}
+
+/// The ops of a pass that changed the ops of the one `before` it, each with
+/// the ops it replaced, as `-` and `+` lines under the position in the
+/// source. Passes only ever replace ops by ones starting where the first of
+/// them did, so the ops line up by their positions.
+pub fn diff(before: &[Line], after: &[Line]) -> String {
+    let mut out = String::new();
+    let mut old = before.iter().peekable();
+
+    for (i, line) in after.iter().enumerate() {
+        let end = after.get(i + 1).map_or(usize::MAX, |next| next.position);
+        let mut replaced = Vec::new();
+        while let Some(previous) = old.next_if(|previous| previous.position < end) {
+            replaced.push(previous);
+        }
+
+        let same = matches!(
+            replaced.as_slice(),
+            [previous] if previous.anchored == line.anchored && previous.steps == line.steps
+        );
+        if same {
+            continue;
+        }
+
+        out.push_str(&format!("@@ {}:{} @@\n", line.line, line.column));
+        for previous in replaced {
+            out.push_str(&format!("-{}\n", previous.to_string().trim_end()));
+        }
+        out.push_str(&format!("+{}\n", line.to_string().trim_end()));
+    }
+
+    out
+}
@@ -0,0 +1,190 @@
+//! Writes a slice of the tape in a few formats, for getting the data
+//! structures a program built in memory out of it.
+
+use std::{ops::Range, path::PathBuf};
+
+use brainfuck_extended::{cell::Memory, Result};
+use clap::ValueEnum;
+use num_bigint::BigInt;
+
+use crate::compress;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// How dumped cells are written
+pub enum DumpFormat {
+    #[default]
+    /// 16 cells per line in hex after the index of the first one, and as text
+    /// (big integers go 8 to a line, without the text)
+    Hex,
+    /// The cells as bytes, or big integers in decimal, one per line
+    Raw,
+    /// A Rust array literal, of strings of digits for big integers
+    Rust,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A `dump` command: which cells to write, where and how
+pub struct Dump {
+    pub range: Range<usize>,
+    /// `None` to show the dump instead of writing it to a file
+    pub path: Option<PathBuf>,
+    pub format: DumpFormat,
+}
+
+/// Parses a range of cells like `10..20` or `10..=19`.
+pub fn parse_range(s: &str) -> Result<Range<usize>> {
+    let invalid = || format!("`{}` is not a range of cells like `10..20`", s);
+
+    let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+    let start: usize = start.trim().parse().map_err(|_| invalid())?;
+    let end: usize = match end.strip_prefix('=') {
+        Some(end) => end.trim().parse::<usize>().map_err(|_| invalid())? + 1,
+        None => end.trim().parse().map_err(|_| invalid())?,
+    };
+
+    if start > end {
+        return Err(format!("the range {} ends before it starts", s).into());
+    }
+
+    Ok(start..end)
+}
+
+impl Dump {
+    /// Without a format, a file gets the raw bytes and anything else hex.
+    pub fn new(range: Range<usize>, path: Option<PathBuf>, format: Option<DumpFormat>) -> Self {
+        let format = format.unwrap_or(if path.is_some() {
+            DumpFormat::Raw
+        } else {
+            DumpFormat::Hex
+        });
+
+        Self {
+            range,
+            path,
+            format,
+        }
+    }
+
+    /// Parses the arguments of `dump <start>..<end> [file] [format]`.
+    pub fn parse(args: &str) -> Result<Self> {
+        let mut args = args.split_whitespace();
+        let range = parse_range(args.next().ok_or("`dump` expects a range of cells")?)?;
+
+        let mut path = None;
+        let mut format = None;
+        for arg in args {
+            match DumpFormat::from_str(arg, true) {
+                Ok(parsed) if format.is_none() => format = Some(parsed),
+                _ if path.is_none() && format.is_none() => path = Some(PathBuf::from(arg)),
+                _ => {
+                    return Err(
+                        format!("unexpected `{}` after the dump's file and format", arg).into(),
+                    )
+                }
+            }
+        }
+
+        Ok(Self::new(range, path, format))
+    }
+
+    /// The cells of `memory` in the range, in the format.
+    pub fn format(&self, memory: &Memory) -> Result<Vec<u8>> {
+        if self.range.end > memory.len() {
+            return Err(format!(
+                "the range {}..{} is not within the {} cells of memory",
+                self.range.start,
+                self.range.end,
+                memory.len()
+            )
+            .into());
+        }
+        Ok(match memory {
+            Memory::Bytes(cells) => {
+                format_cells(&cells[self.range.clone()], self.range.start, self.format)
+            }
+            Memory::BigInt(cells) => {
+                format_big_cells(&cells[self.range.clone()], self.range.start, self.format)
+            }
+        })
+    }
+
+    /// Writes the dump to its file, or returns it if it has none.
+    pub fn write(&self, memory: &Memory) -> Result<Option<Vec<u8>>> {
+        let dump = self.format(memory)?;
+        match &self.path {
+            Some(path) => {
+                compress::write(path, &dump)?;
+                Ok(None)
+            }
+            None => Ok(Some(dump)),
+        }
+    }
+}
+
+/// Formats `cells`, the first of which is the cell at index `start`.
+pub fn format_cells(cells: &[u8], start: usize, format: DumpFormat) -> Vec<u8> {
+    match format {
+        DumpFormat::Raw => cells.to_vec(),
+        DumpFormat::Hex => {
+            let mut dump = String::new();
+            for (i, line) in cells.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|cell| format!("{:02x}", cell)).collect();
+                let text: String = line
+                    .iter()
+                    .map(|cell| {
+                        if cell.is_ascii_graphic() || *cell == b' ' {
+                            *cell as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                dump.push_str(&format!(
+                    "{:05}: {:<47}  {}\n",
+                    start + i * 16,
+                    hex.join(" "),
+                    text
+                ));
+            }
+            dump.into_bytes()
+        }
+        DumpFormat::Rust => {
+            let mut dump = "[\n".to_string();
+            for line in cells.chunks(12) {
+                let cells: Vec<String> =
+                    line.iter().map(|cell| format!("0x{:02x},", cell)).collect();
+                dump.push_str(&format!("    {}\n", cells.join(" ")));
+            }
+            dump.push_str("]\n");
+            dump.into_bytes()
+        }
+    }
+}
+
+/// Formats big-integer `cells` like [`format_cells`], with a minus sign in
+/// front of the negative ones.
+pub fn format_big_cells(cells: &[BigInt], start: usize, format: DumpFormat) -> Vec<u8> {
+    let mut dump = String::new();
+    match format {
+        DumpFormat::Raw => {
+            for cell in cells {
+                dump.push_str(&format!("{}\n", cell));
+            }
+        }
+        DumpFormat::Hex => {
+            for (i, line) in cells.chunks(8).enumerate() {
+                let hex: Vec<String> = line.iter().map(|cell| format!("{:x}", cell)).collect();
+                dump.push_str(&format!("{:05}: {}\n", start + i * 8, hex.join(" ")));
+            }
+        }
+        DumpFormat::Rust => {
+            dump.push_str("[\n");
+            for line in cells.chunks(8) {
+                let cells: Vec<String> = line.iter().map(|cell| format!("\"{}\",", cell)).collect();
+                dump.push_str(&format!("    {}\n", cells.join(" ")));
+            }
+            dump.push_str("]\n");
+        }
+    }
+    dump.into_bytes()
+}
@@ -0,0 +1,698 @@
+//! Runs the interpreter on its own thread and talks to the debugger UI over
+//! channels, so rendering and key handling never stall execution.
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use brainfuck_extended::{
+    bytecode::{Dispatch, Machine, Op, Program},
+    cell::Memory,
+    checkpoint::DiskCheckpoints,
+    usage::MEMORY_CHECK_INTERVAL,
+    BracketTable, BrainfuckInterpreter, MemoryUsage, OutputRecord, PhaseStats, RuntimeError,
+    StepHash, StepResult,
+};
+
+use crate::stop::{Stop, StopConditions};
+
+/// The most instructions [`Command::StepOp`] executes before giving up
+const MAX_OP_STEPS: u64 = 1 << 24;
+
+/// Sent from the UI to the interpreter thread
+pub enum Command {
+    /// Execute a single instruction, then pause
+    Step,
+    /// Execute the bytecode op of this program that starts at the code
+    /// pointer, on the bytecode engine and the interpreter, then pause with a
+    /// message if the two ended up in different states. Away from the start
+    /// of an op, or at one doing I/O, only the interpreter runs, up to the
+    /// start of the next op
+    StepOp(Arc<Program>),
+    Continue,
+    Pause,
+    /// Send a snapshot of the current state
+    RequestSnapshot,
+    /// Limit execution to this many instructions per second (`None` for no limit)
+    SetSpeed(Option<u64>),
+    PushInput(Vec<u8>),
+    CloseInput,
+    /// Go back to just before the most recent stop condition was hit, then pause
+    Rewind,
+    /// Stop before executing the instruction at this code position
+    SetBreakpoint(usize),
+    ClearBreakpoint(usize),
+    Quit,
+}
+
+/// Sent from the interpreter thread to the UI
+pub enum Event {
+    /// The state at the time of a [`Command::RequestSnapshot`]
+    Snapshot(Snapshot),
+    /// Execution paused, optionally with a message about why
+    Paused(Snapshot, Option<String>),
+    /// Execution paused because a stop condition was hit
+    Stopped(Snapshot, String),
+    /// A `,` is waiting for [`Command::PushInput`] or [`Command::CloseInput`]
+    NeedsInput(Snapshot),
+    /// The program finished, optionally with a stop condition hit at the very end
+    Halted(Snapshot, Option<String>),
+    /// Something the user should know about that does not stop execution
+    Notice(String),
+    /// The engine failed
+    Failed(String),
+    /// The program failed at an instruction
+    Crashed(RuntimeError),
+}
+
+/// The interpreter state needed to render the debugger. Output and input are
+/// only sent from where the previous snapshot left off.
+pub struct Snapshot {
+    pub memory: Memory,
+    pub pointer: usize,
+    pub code_pos: usize,
+    pub steps: u64,
+    pub input_pos: usize,
+    pub input_start: usize,
+    pub input: Vec<u8>,
+    pub output_start: usize,
+    pub output: String,
+    pub output_log_start: usize,
+    pub output_log: Vec<OutputRecord>,
+    pub hash: Option<StepHash>,
+    pub memory_usage: MemoryUsage,
+    /// The `@phase` execution is in, with its totals so far
+    pub phase: Option<PhaseStats>,
+}
+
+/// The UI's copy of the interpreter state, kept up to date with snapshots
+pub struct View {
+    pub memory: Memory,
+    pub pointer: usize,
+    pub code: Vec<char>,
+    pub brackets: BracketTable,
+    pub code_pos: usize,
+    pub steps: u64,
+    pub input: Vec<u8>,
+    pub input_pos: usize,
+    pub output: String,
+    pub output_log: Vec<OutputRecord>,
+    /// The most recently published step hash
+    pub hash: Option<StepHash>,
+    /// What the interpreter thread holds on to, including its checkpoints
+    pub memory_usage: MemoryUsage,
+    pub phase: Option<PhaseStats>,
+}
+
+impl View {
+    /// The view of a run of `code`, whose loops are `brackets`, on `memory`,
+    /// before the first snapshot.
+    pub fn new(code: Vec<char>, brackets: BracketTable, memory: Memory) -> Self {
+        Self {
+            memory,
+            pointer: 0,
+            brackets,
+            code,
+            code_pos: 0,
+            steps: 0,
+            input: Vec::new(),
+            input_pos: 0,
+            output: String::new(),
+            output_log: Vec::new(),
+            hash: None,
+            memory_usage: MemoryUsage::default(),
+            phase: None,
+        }
+    }
+
+    pub fn apply(&mut self, snapshot: Snapshot) {
+        self.memory = snapshot.memory;
+        self.pointer = snapshot.pointer;
+        self.code_pos = snapshot.code_pos;
+        self.steps = snapshot.steps;
+        self.input_pos = snapshot.input_pos;
+        self.hash = snapshot.hash;
+        self.memory_usage = snapshot.memory_usage;
+        self.phase = snapshot.phase;
+
+        self.input.truncate(snapshot.input_start);
+        self.input.extend(snapshot.input);
+        self.output.truncate(snapshot.output_start);
+        self.output.push_str(&snapshot.output);
+        self.output_log.truncate(snapshot.output_log_start);
+        self.output_log.extend(snapshot.output_log);
+    }
+}
+
+/// The UI's end of the protocol
+pub struct EngineHandle {
+    pub commands: Sender<Command>,
+    pub events: Receiver<Event>,
+    pub(crate) thread: JoinHandle<(BrainfuckInterpreter, Vec<String>)>,
+}
+
+impl EngineHandle {
+    pub fn send(&self, command: Command) {
+        // the engine only hangs up after a failure, which the UI hears about separately
+        self.commands.send(command).ok();
+    }
+
+    /// Stops the interpreter thread and hands the interpreter back, along with
+    /// every [`Event::Notice`] it sent.
+    pub fn join(self) -> (BrainfuckInterpreter, Vec<String>) {
+        self.send(Command::Quit);
+        self.thread.join().expect("interpreter thread panicked")
+    }
+}
+
+#[derive(Default)]
+/// How much of each growing buffer the UI has already been sent
+pub(crate) struct Sent {
+    input: usize,
+    pub(crate) output: usize,
+    pub(crate) output_log: usize,
+}
+
+impl Sent {
+    /// The state of `i` to send to the UI, with only what it has not seen yet
+    /// of the input and output. `checkpoint_bytes` is counted in its memory usage.
+    pub(crate) fn snapshot(
+        &mut self,
+        i: &BrainfuckInterpreter,
+        checkpoint_bytes: usize,
+    ) -> Snapshot {
+        let output_log = i.output_log.as_deref().unwrap_or_default();
+
+        let snapshot = Snapshot {
+            memory: i.memory.clone(),
+            pointer: i.pointer,
+            code_pos: i.code_pos,
+            steps: i.steps,
+            input_pos: i.input_pos,
+            input_start: self.input,
+            input: i.input[self.input..].to_vec(),
+            output_start: self.output,
+            output: i.output[self.output..].to_string(),
+            output_log_start: self.output_log,
+            // the log is gone if it was dropped to save memory
+            output_log: output_log
+                .get(self.output_log..)
+                .unwrap_or_default()
+                .to_vec(),
+            hash: i.step_hasher.as_ref().and_then(|hasher| hasher.latest()),
+            memory_usage: MemoryUsage {
+                checkpoints: checkpoint_bytes,
+                ..i.memory_usage()
+            },
+            phase: i.phases.current(i.steps, i.stats.output_bytes),
+        };
+
+        self.input = i.input.len();
+        self.output = i.output.len();
+        self.output_log = output_log.len();
+
+        snapshot
+    }
+}
+
+/// The full state at some step, restored when rewinding
+struct Checkpoint {
+    interpreter: BrainfuckInterpreter,
+    stop: StopConditions,
+}
+
+struct Engine {
+    interpreter: BrainfuckInterpreter,
+    stop: StopConditions,
+    events: Sender<Event>,
+
+    /// Taken every `checkpoint_interval` steps, in order of their step count
+    checkpoints: Vec<Checkpoint>,
+    checkpoint_interval: u64,
+    /// The bytes taken by `checkpoints`
+    checkpoint_bytes: usize,
+    /// How many bytes the state may take before history is dropped
+    max_memory: Option<usize>,
+    /// Where the state is saved to disk, if it is
+    disk: Option<DiskCheckpoints>,
+    /// Every notice sent so far
+    notices: Vec<String>,
+    /// The code positions to stop at
+    breakpoints: BTreeSet<usize>,
+    /// The step at which a stop condition was most recently hit
+    last_stop: Option<u64>,
+
+    running: bool,
+    /// The maximum number of instructions per second, if throttled
+    speed: Option<u64>,
+    /// When the current throttled stretch of execution began, and the step count at that time
+    throttle_start: (Instant, u64),
+
+    sent: Sent,
+    /// Runs the ops stepped with [`Command::StepOp`], keeping what it knows
+    /// about hot loops between them
+    machine: Option<Machine>,
+}
+
+pub fn spawn(
+    interpreter: BrainfuckInterpreter,
+    stop: StopConditions,
+    checkpoint_interval: u64,
+    max_memory: Option<usize>,
+    disk: Option<DiskCheckpoints>,
+) -> EngineHandle {
+    let (commands_tx, commands_rx) = channel();
+    let (events_tx, events_rx) = channel();
+
+    let thread = thread::spawn(move || {
+        let mut engine = Engine {
+            interpreter,
+            stop,
+            events: events_tx,
+            checkpoints: Vec::new(),
+            checkpoint_interval: checkpoint_interval.max(1),
+            checkpoint_bytes: 0,
+            max_memory,
+            disk,
+            notices: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            last_stop: None,
+            running: false,
+            speed: None,
+            throttle_start: (Instant::now(), 0),
+            sent: Sent::default(),
+            machine: None,
+        };
+        engine.run(commands_rx);
+        (engine.interpreter, engine.notices)
+    });
+
+    EngineHandle {
+        commands: commands_tx,
+        events: events_rx,
+        thread,
+    }
+}
+
+impl Engine {
+    fn snapshot(&mut self) -> Snapshot {
+        self.sent.snapshot(&self.interpreter, self.checkpoint_bytes)
+    }
+
+    fn send(&self, event: Event) {
+        // the UI going away means the session is over
+        self.events.send(event).ok();
+    }
+
+    fn pause(&mut self, reason: Option<String>) {
+        self.running = false;
+        let snapshot = self.snapshot();
+        self.send(Event::Paused(snapshot, reason));
+    }
+
+    fn stopped(&mut self, stop: Stop) {
+        self.running = false;
+        let snapshot = self.snapshot();
+        self.send(Event::Stopped(snapshot, stop.into_reason()));
+    }
+
+    /// Applies a command. Returns `false` once the engine should exit.
+    fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::Step => {
+                self.running = false;
+                self.execute();
+            }
+            Command::StepOp(program) => {
+                self.step_op(&program);
+            }
+            Command::Continue => {
+                self.running = true;
+                self.reset_throttle();
+            }
+            Command::Pause => {
+                self.pause(None);
+            }
+            Command::RequestSnapshot => {
+                let snapshot = self.snapshot();
+                self.send(Event::Snapshot(snapshot));
+            }
+            Command::SetSpeed(speed) => {
+                self.speed = speed;
+                self.reset_throttle();
+            }
+            Command::PushInput(bytes) => {
+                self.interpreter.push_input(&bytes);
+            }
+            Command::CloseInput => {
+                self.interpreter.close_input();
+            }
+            Command::Rewind => {
+                self.rewind();
+            }
+            Command::SetBreakpoint(pos) => {
+                self.breakpoints.insert(pos);
+            }
+            Command::ClearBreakpoint(pos) => {
+                self.breakpoints.remove(&pos);
+            }
+            Command::Quit => return false,
+        }
+
+        true
+    }
+
+    /// Saves the current state if a checkpoint is due, along with the one on
+    /// disk.
+    fn checkpoint(&mut self) {
+        let steps = self.interpreter.steps;
+
+        if let Some(disk) = &mut self.disk {
+            if let Err(e) = disk.save_if_due(&self.interpreter) {
+                let notice = format!(
+                    "could not write the checkpoint to {}: {}",
+                    disk.path.display(),
+                    e
+                );
+                self.notices.push(notice.clone());
+                self.send(Event::Notice(notice));
+            }
+        }
+
+        // a resumed run starts wherever the checkpoint was taken
+        let due = (self.checkpoints.is_empty()
+            || self.interpreter.passed_multiple(self.checkpoint_interval))
+            && self
+                .checkpoints
+                .last()
+                .is_none_or(|checkpoint| checkpoint.interpreter.steps < steps);
+
+        if due {
+            self.checkpoint_bytes += self.interpreter.memory_usage().total();
+            self.checkpoints.push(Checkpoint {
+                interpreter: self.interpreter.clone(),
+                stop: self.stop.clone(),
+            });
+        }
+    }
+
+    /// Keeps the state under the memory limit by dropping the oldest
+    /// checkpoints (except the initial state) and then the interpreter's own
+    /// history. Returns `false` if that was not enough.
+    fn fit_memory(&mut self) -> bool {
+        let Some(limit) = self.max_memory else {
+            return true;
+        };
+
+        let over = |engine: &Self| {
+            engine.interpreter.memory_usage().total() + engine.checkpoint_bytes > limit
+        };
+
+        let mut dropped = 0;
+        while over(self) && self.checkpoints.len() > 1 {
+            let checkpoint = self.checkpoints.remove(1);
+            self.checkpoint_bytes -= checkpoint.interpreter.memory_usage().total();
+            dropped += 1;
+        }
+
+        let mut notices = Vec::new();
+        if dropped > 0 {
+            notices.push(format!("{} checkpoints", dropped));
+        }
+
+        match self.interpreter.fit_memory(limit, self.checkpoint_bytes) {
+            Ok(history) => notices.extend(history.into_iter().map(String::from)),
+            Err(e) => {
+                self.send(Event::Failed(e.to_string()));
+                return false;
+            }
+        }
+
+        if !notices.is_empty() {
+            let notice = format!("over --max-rss, dropped {}", notices.join(" and "));
+            self.notices.push(notice.clone());
+            self.send(Event::Notice(notice));
+        }
+
+        true
+    }
+
+    /// Restores the nearest checkpoint before the last stop and executes
+    /// forward to the step just before it.
+    fn rewind(&mut self) {
+        let Some(last_stop) = self.last_stop else {
+            self.pause(Some("no breakpoint has been hit yet".to_string()));
+            return;
+        };
+
+        let target = last_stop.saturating_sub(1);
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.interpreter.steps <= target)
+            .expect("the initial state is always checkpointed");
+
+        // input is only ever appended to, so keep everything received since the checkpoint
+        let input = std::mem::take(&mut self.interpreter.input);
+        let input_closed = self.interpreter.input_closed;
+
+        self.interpreter = checkpoint.interpreter.clone();
+        self.stop = checkpoint.stop.clone();
+        self.interpreter.input = input;
+        self.interpreter.input_closed = input_closed;
+
+        // up to the instruction that got to the stop, which may run more than
+        // one step
+        while self.interpreter.steps + self.interpreter.next_len() <= target {
+            // the original run already got past this point, so nothing can go differently
+            if !matches!(self.interpreter.step(), Ok(StepResult::Continue)) {
+                break;
+            }
+            // keep the stop conditions in sync so they can be hit again
+            self.stop.check(&self.interpreter);
+        }
+
+        // the output is now a prefix of what the UI was sent
+        self.sent.output = self.sent.output.min(self.interpreter.output.len());
+        self.sent.output_log = self
+            .sent
+            .output_log
+            .min(self.interpreter.output_log.as_ref().map_or(0, Vec::len));
+
+        self.pause(Some(format!(
+            "rewound to step {}, just before the last breakpoint was hit",
+            self.interpreter.steps
+        )));
+    }
+
+    fn reset_throttle(&mut self) {
+        self.throttle_start = (Instant::now(), self.interpreter.steps);
+    }
+
+    /// How long to wait before the next instruction to stay under the speed limit.
+    fn throttle_delay(&self) -> Option<Duration> {
+        let speed = self.speed?;
+        let (start, start_steps) = self.throttle_start;
+
+        let due =
+            Duration::from_secs_f64((self.interpreter.steps - start_steps) as f64 / speed as f64);
+        due.checked_sub(start.elapsed())
+    }
+
+    fn run(&mut self, commands: Receiver<Command>) {
+        let snapshot = self.snapshot();
+        self.send(Event::Paused(snapshot, None));
+
+        loop {
+            let command = if self.running {
+                if let Some(delay) = self.throttle_delay() {
+                    // wait for the next instruction to be due, but stay responsive
+                    match commands.recv_timeout(delay) {
+                        Ok(command) => Some(command),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                } else {
+                    match commands.try_recv() {
+                        Ok(command) => Some(command),
+                        Err(TryRecvError::Empty) => None,
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+            } else {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                }
+            };
+
+            if let Some(command) = command {
+                if !self.handle(command) {
+                    return;
+                }
+            } else if !self.execute() {
+                // stay alive until the UI quits so the final state can be inspected
+                self.running = false;
+            }
+        }
+    }
+
+    /// Checks the stop conditions and breakpoints, remembering when one was hit.
+    fn check_stop(&mut self) -> Option<Stop> {
+        let stop = self.stop.check(&self.interpreter).or_else(|| {
+            self.breakpoints
+                .range(self.interpreter.next_span())
+                .next()
+                .map(|pos| Stop::Break(format!("breakpoint at position {}", pos)))
+        });
+        if stop.is_some() {
+            self.last_stop = Some(self.interpreter.steps);
+        }
+        stop
+    }
+
+    /// Executes the op starting at the code pointer (see [`Command::StepOp`]).
+    /// Stopping for any other reason is reported as usual, and an op that
+    /// never ends on the interpreter, like `[>]` on a tape without a 0, is
+    /// paused after [`MAX_OP_STEPS`].
+    fn step_op(&mut self, program: &Program) {
+        let i = &self.interpreter;
+        let op = program.positions.partition_point(|&pos| pos < i.code_pos);
+        let at_start = program.positions.get(op) == Some(&i.code_pos);
+
+        // the position the op ends at, if the bytecode engine ran it
+        let end = match program.instrs.get(op).map(|instr| instr.op) {
+            Some(Op::Write | Op::Read | Op::Assert(_) | Op::Halt) | None => None,
+            Some(_) if at_start => match &i.memory {
+                Memory::Bytes(cells) => {
+                    let machine = self.machine.get_or_insert_with(|| {
+                        let mut machine = Machine::new(i.io_mode);
+                        machine.resize_memory(cells.len());
+                        machine
+                    });
+                    machine.memory.copy_from_slice(cells);
+                    machine.pointer = i.pointer;
+                    machine.pc = op;
+                    // a failing op fails on the interpreter as well
+                    machine
+                        .run(program, Dispatch::Match, &mut 1)
+                        .ok()
+                        .map(|_| program.positions[machine.pc])
+                }
+                // the bytecode engine has no big-integer cells to run it on
+                Memory::BigInt(_) => None,
+            },
+            Some(_) => None,
+        };
+        // without one, the op ends at the start of any op
+        let done = |pos: usize| match end {
+            Some(end) => pos == end,
+            None => program.positions.binary_search(&pos).is_ok(),
+        };
+
+        // keep `execute` from pausing after every instruction
+        self.running = true;
+        for _ in 0..MAX_OP_STEPS {
+            if !self.execute() {
+                self.running = false;
+                return;
+            }
+            if done(self.interpreter.code_pos) {
+                self.running = false;
+                let reason = end.and_then(|_| self.disagreement(op));
+                self.pause(reason);
+                return;
+            }
+        }
+
+        self.running = false;
+        self.pause(Some(format!(
+            "the op did not finish within {} instructions",
+            MAX_OP_STEPS
+        )));
+    }
+
+    /// How the state the bytecode engine left after running op `op` differs
+    /// from the interpreter's, if it does.
+    fn disagreement(&self, op: usize) -> Option<String> {
+        let machine = self.machine.as_ref()?;
+        let i = &self.interpreter;
+        let Memory::Bytes(cells) = &i.memory else {
+            return None;
+        };
+
+        let difference = if machine.pointer != i.pointer {
+            format!(
+                "the pointer is at {} there, not {}",
+                machine.pointer, i.pointer
+            )
+        } else {
+            let cell = (0..cells.len()).find(|&cell| machine.memory[cell] != cells[cell])?;
+            format!(
+                "cell {} is {} there, not {}",
+                cell, machine.memory[cell], cells[cell]
+            )
+        };
+        Some(format!(
+            "op {:04} ran differently on the bytecode engine: {}",
+            op, difference
+        ))
+    }
+
+    /// Executes one instruction and reports anything the UI needs to know.
+    /// Returns `false` if execution cannot continue.
+    fn execute(&mut self) -> bool {
+        self.checkpoint();
+
+        if self.interpreter.passed_multiple(MEMORY_CHECK_INTERVAL) && !self.fit_memory() {
+            return false;
+        }
+
+        let result = match self.interpreter.step() {
+            Ok(result) => result,
+            Err(e) => {
+                self.send(match e.downcast::<RuntimeError>() {
+                    Ok(e) => Event::Crashed(*e),
+                    Err(e) => Event::Failed(e.to_string()),
+                });
+                return false;
+            }
+        };
+
+        match result {
+            StepResult::Continue => {}
+            StepResult::NeedsInput => {
+                let snapshot = self.snapshot();
+                self.send(Event::NeedsInput(snapshot));
+                // wait for the input, then retry the same instruction
+                self.running = false;
+                return false;
+            }
+            StepResult::Halted => {
+                let reason = self.check_stop().map(Stop::into_reason);
+                let snapshot = self.snapshot();
+                self.send(Event::Halted(snapshot, reason));
+                return false;
+            }
+        }
+
+        if let Some(stop) = self.check_stop() {
+            self.stopped(stop);
+            return false;
+        }
+
+        if !self.running {
+            // a single step always reports where it ended up
+            self.pause(None);
+        }
+
+        true
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The first place where the output differs from the expected output
+pub struct Mismatch {
+    /// The byte offset into the output
+    pub offset: usize,
+    /// `None` if the output is longer than expected
+    pub expected: Option<u8>,
+    /// `None` if the program halted before producing this byte
+    pub actual: Option<u8>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |byte: Option<u8>| match byte {
+            Some(byte) => format!("{:?}", byte as char),
+            None => "end of output".to_string(),
+        };
+
+        write!(
+            f,
+            "output diverged from expected output at byte {}: expected {}, got {}",
+            self.offset,
+            describe(self.expected),
+            describe(self.actual)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Compares the output against an expected output as it is produced.
+pub struct Expectation {
+    pub expected: Vec<u8>,
+    /// How many bytes of the output have already been compared
+    checked: usize,
+    diverged: bool,
+}
+
+impl Expectation {
+    pub fn new(expected: Vec<u8>) -> Self {
+        Self {
+            expected,
+            checked: 0,
+            diverged: false,
+        }
+    }
+
+    /// Compares the bytes produced since the last call. Only the first
+    /// mismatch is ever reported.
+    pub fn check(&mut self, output: &[u8]) -> Option<Mismatch> {
+        if self.diverged {
+            return None;
+        }
+
+        for (offset, actual) in output.iter().enumerate().skip(self.checked) {
+            let expected = self.expected.get(offset).copied();
+            if expected != Some(*actual) {
+                self.diverged = true;
+                return Some(Mismatch {
+                    offset,
+                    expected,
+                    actual: Some(*actual),
+                });
+            }
+        }
+
+        self.checked = output.len();
+
+        None
+    }
+
+    /// Checks that a finished program produced all of the expected output.
+    pub fn finish(&mut self, output: &[u8]) -> Option<Mismatch> {
+        if let Some(mismatch) = self.check(output) {
+            return Some(mismatch);
+        }
+
+        if self.diverged || output.len() >= self.expected.len() {
+            return None;
+        }
+
+        self.diverged = true;
+        Some(Mismatch {
+            offset: output.len(),
+            expected: Some(self.expected[output.len()]),
+            actual: None,
+        })
+    }
+
+    /// Renders the line containing a mismatch from both outputs, with a caret
+    /// under the first differing byte.
+    pub fn context(&self, output: &[u8], mismatch: &Mismatch) -> String {
+        const BEFORE: usize = 40;
+        const AFTER: usize = 20;
+
+        let offset = mismatch.offset;
+
+        let line = output[..offset].iter().filter(|b| **b == b'\n').count() + 1;
+        let line_start = output[..offset]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map(|pos| pos + 1)
+            .unwrap_or(0);
+        let start = line_start.max(offset.saturating_sub(BEFORE));
+        let column = offset - line_start + 1;
+
+        let escape = |bytes: &[u8]| String::from_utf8_lossy(bytes).escape_debug().to_string();
+
+        let prefix = escape(&output[start..offset]);
+        let expected = escape(&self.expected[start..(offset + AFTER).min(self.expected.len())]);
+        let actual = escape(&output[start..(offset + AFTER).min(output.len())]);
+
+        format!(
+            "{} (line {}, column {})\nexpected: {}\n  actual: {}\n          {}^",
+            mismatch,
+            line,
+            column,
+            expected,
+            actual,
+            " ".repeat(prefix.chars().count())
+        )
+    }
+}
@@ -0,0 +1,142 @@
+//! Plain-English descriptions of what the next instruction will do, for
+//! people learning Brainfuck with the debugger.
+
+use brainfuck_extended::{pragma::Pragmas, IoMode};
+
+use crate::engine::View;
+
+/// Describes the effect of executing the instruction under the code pointer.
+pub fn explain(view: &View, io_mode: IoMode, pragmas: &Pragmas) -> String {
+    let Some(&c) = view.code.get(view.code_pos) else {
+        return "The program has finished.".to_string();
+    };
+
+    let cell = view.pointer;
+    let value = view.memory.get(cell);
+    let last = view.memory.len() - 1;
+
+    match c {
+        '>' => {
+            if cell < last {
+                format!(
+                    "Move the pointer right from cell {} to cell {}.",
+                    cell,
+                    cell + 1
+                )
+            } else if pragmas.wrap_pointer {
+                format!(
+                    "Move the pointer right from the last cell, {}, around to cell 0.",
+                    cell
+                )
+            } else {
+                format!(
+                    "Keep the pointer at cell {}, since it is the last cell.",
+                    cell
+                )
+            }
+        }
+        '<' => {
+            if cell > 0 {
+                format!(
+                    "Move the pointer left from cell {} to cell {}.",
+                    cell,
+                    cell - 1
+                )
+            } else if pragmas.wrap_pointer {
+                format!(
+                    "Move the pointer left from cell 0 around to the last cell, {}.",
+                    last
+                )
+            } else {
+                "Keep the pointer at cell 0, since it is the first cell.".to_string()
+            }
+        }
+        '+' => {
+            let (after, wrapped) = value.added(1);
+            let wrap = if wrapped { ", wrapping around" } else { "" };
+            format!(
+                "Increment cell {} from {} to {}{}.",
+                cell, value, after, wrap
+            )
+        }
+        '-' => {
+            let (after, wrapped) = value.added(-1);
+            let wrap = if wrapped { ", wrapping around" } else { "" };
+            format!(
+                "Decrement cell {} from {} to {}{}.",
+                cell, value, after, wrap
+            )
+        }
+        '.' => match (io_mode, value.to_byte()) {
+            (IoMode::Text, Some(byte)) => format!(
+                "Write cell {} ({}, {:?}) to the output.",
+                cell, byte, byte as char
+            ),
+            (IoMode::Text, None) => format!(
+                "Fail, since cell {} holds {}, which is not a byte to write.",
+                cell, value
+            ),
+            (IoMode::Numeric, _) => {
+                format!("Write cell {} to the output as the number {}.", cell, value)
+            }
+        },
+        ',' => match (io_mode, view.input.get(view.input_pos)) {
+            (IoMode::Text, Some(byte)) => format!(
+                "Read the next input byte ({}, {:?}) into cell {}.",
+                byte, *byte as char, cell
+            ),
+            (IoMode::Numeric, Some(_)) => {
+                format!("Read the next number from the input into cell {}.", cell)
+            }
+            (_, None) => match pragmas.eof {
+                Some(eof) => format!(
+                    "Read from the input into cell {}. The input is used up, so more is asked \
+                     for, or cell {} is set to {} at EOF.",
+                    cell, cell, eof
+                ),
+                None => format!(
+                    "Read from the input into cell {}. The input is used up, so more is asked \
+                     for, or cell {} is left unchanged at EOF.",
+                    cell, cell
+                ),
+            },
+        },
+        '[' => {
+            let end = view.brackets.matching(view.code_pos);
+            match end {
+                Some(_) if !value.is_zero() => format!(
+                    "Enter the loop because cell {} is nonzero ({}).",
+                    cell, value
+                ),
+                Some(end) => format!(
+                    "Skip the loop, past the ] at position {}, because cell {} is zero.",
+                    end, cell
+                ),
+                None => "This [ has no matching ].".to_string(),
+            }
+        }
+        ']' => {
+            let start = view.brackets.matching(view.code_pos);
+            match start {
+                Some(start) if !value.is_zero() => format!(
+                    "Jump back to position {} because cell {} is nonzero ({}).",
+                    start + 1,
+                    cell,
+                    value
+                ),
+                Some(_) => format!("Leave the loop because cell {} is zero.", cell),
+                None => "This ] has no matching [.".to_string(),
+            }
+        }
+        '@' if view.code[view.code_pos..]
+            .iter()
+            .copied()
+            .take(7)
+            .eq("@pragma".chars()) =>
+        {
+            "Skip the @pragma directive, the rest of its line is not executed.".to_string()
+        }
+        c if c.is_whitespace() => "Skip whitespace, it is not an instruction.".to_string(),
+        c => format!("Skip {:?}, it is a comment, not an instruction.", c),
+    }
+}
@@ -0,0 +1,303 @@
+//! The peephole pass that fuses common sequences of bytecode ops into
+//! superinstructions, so they take one dispatch instead of several. Which
+//! sequences are fused, and which wins where they overlap, comes from a
+//! [`FusionTable`], which can be loaded from a file like the one
+//! [`profile`] prints, one pattern per line:
+//!
+//! ```text
+//! # fused first where patterns overlap
+//! add move add
+//! move jump-if-not-zero
+//! ```
+
+use std::fmt;
+
+use crate::{
+    bisect::{OptFuel, Transformation},
+    bytecode::{CopyLoop, Dispatch, Instr, Machine, Op, Program, Status},
+    Result,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A sequence of ops that has a superinstruction
+pub enum Fusion {
+    AddMove,
+    MoveAdd,
+    AddMoveAdd,
+    MoveAddMove,
+    /// `[-]+++`, a clear loop followed by an add
+    Set,
+    SetMove,
+    /// A move followed by the start of a loop, which checks the new cell
+    MoveJumpIfZero,
+    MoveJumpIfNotZero,
+    AddJumpIfNotZero,
+}
+
+impl Fusion {
+    pub const ALL: [Fusion; 9] = [
+        Fusion::AddMove,
+        Fusion::MoveAdd,
+        Fusion::AddMoveAdd,
+        Fusion::MoveAddMove,
+        Fusion::Set,
+        Fusion::SetMove,
+        Fusion::MoveJumpIfZero,
+        Fusion::MoveJumpIfNotZero,
+        Fusion::AddJumpIfNotZero,
+    ];
+
+    /// The kinds of ops that are fused, in order
+    pub fn pattern(&self) -> &'static [&'static str] {
+        match self {
+            Fusion::AddMove => &["add", "move"],
+            Fusion::MoveAdd => &["move", "add"],
+            Fusion::AddMoveAdd => &["add", "move", "add"],
+            Fusion::MoveAddMove => &["move", "add", "move"],
+            Fusion::Set => &["clear", "add"],
+            Fusion::SetMove => &["clear", "add", "move"],
+            Fusion::MoveJumpIfZero => &["move", "jump-if-zero"],
+            Fusion::MoveJumpIfNotZero => &["move", "jump-if-not-zero"],
+            Fusion::AddJumpIfNotZero => &["add", "jump-if-not-zero"],
+        }
+    }
+
+    /// The superinstruction for these ops, if they match the pattern
+    fn fuse(&self, parts: &[Instr]) -> Option<Op> {
+        let ops: Vec<Op> = parts.iter().map(|part| part.op).collect();
+
+        Some(match (self, ops.as_slice()) {
+            (Fusion::AddMove, [Op::Add(add), Op::Move(offset)]) => Op::AddMove(*add, *offset),
+            (Fusion::MoveAdd, [Op::Move(offset), Op::Add(add)]) => Op::MoveAdd(*offset, *add),
+            (Fusion::AddMoveAdd, [Op::Add(first), Op::Move(offset), Op::Add(second)]) => {
+                Op::AddMoveAdd(*first, *offset, *second)
+            }
+            (Fusion::MoveAddMove, [Op::Move(first), Op::Add(add), Op::Move(second)]) => {
+                Op::MoveAddMove(*first, *add, *second)
+            }
+            (Fusion::Set, [Op::Clear { cells: 1, add }, Op::Add(value)]) => Op::Set {
+                add: *add,
+                value: *value,
+            },
+            (Fusion::SetMove, [Op::Clear { cells: 1, add }, Op::Add(value), Op::Move(offset)]) => {
+                Op::SetMove {
+                    add: *add,
+                    value: *value,
+                    offset: *offset,
+                }
+            }
+            (Fusion::MoveJumpIfZero, [Op::Move(offset), Op::JumpIfZero(end)]) => {
+                Op::MoveJumpIfZero(*offset, *end)
+            }
+            (Fusion::MoveJumpIfNotZero, [Op::Move(offset), Op::JumpIfNotZero(target)]) => {
+                Op::MoveJumpIfNotZero(*offset, *target)
+            }
+            (Fusion::AddJumpIfNotZero, [Op::Add(add), Op::JumpIfNotZero(target)]) => {
+                Op::AddJumpIfNotZero(*add, *target)
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Fusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern().join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The sequences to fuse, tried in order at every op
+pub struct FusionTable {
+    pub fusions: Vec<Fusion>,
+}
+
+impl Default for FusionTable {
+    /// Longer patterns first, so they are not split up by shorter ones
+    fn default() -> Self {
+        Self {
+            fusions: vec![
+                Fusion::SetMove,
+                Fusion::AddMoveAdd,
+                Fusion::MoveAddMove,
+                Fusion::Set,
+                Fusion::MoveJumpIfNotZero,
+                Fusion::AddJumpIfNotZero,
+                Fusion::MoveJumpIfZero,
+                Fusion::AddMove,
+                Fusion::MoveAdd,
+            ],
+        }
+    }
+}
+
+impl FusionTable {
+    /// A table that fuses nothing
+    pub fn none() -> Self {
+        Self {
+            fusions: Vec::new(),
+        }
+    }
+
+    /// Parses a table with one pattern per line, like `add move`. Empty lines
+    /// and everything after a `#` are ignored.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut fusions = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let kinds: Vec<&str> = line.split_whitespace().collect();
+            if kinds.is_empty() {
+                continue;
+            }
+
+            let fusion = Fusion::ALL
+                .into_iter()
+                .find(|fusion| fusion.pattern() == kinds.as_slice())
+                .ok_or_else(|| {
+                    format!(
+                        "line {}: there is no superinstruction for `{}`",
+                        i + 1,
+                        kinds.join(" ")
+                    )
+                })?;
+            fusions.push(fusion);
+        }
+
+        Ok(Self { fusions })
+    }
+}
+
+/// Which ops something jumps to (or the dispatch loop lands on after a loop
+/// op), since those cannot be fused into the op before them.
+fn jump_targets(instrs: &[Instr], copies: &[CopyLoop]) -> Vec<bool> {
+    let mut targets = vec![false; instrs.len() + 1];
+
+    for (i, instr) in instrs.iter().enumerate() {
+        match instr.op {
+            Op::JumpIfZero(end) => targets[end] = true,
+            Op::JumpIfNotZero(target) => targets[target] = true,
+            Op::Scan { end, .. } => {
+                targets[end] = true;
+                targets[i + 1] = true;
+            }
+            Op::Copy(index) => {
+                targets[copies[index].end] = true;
+                targets[i + 1] = true;
+            }
+            _ => {}
+        }
+    }
+
+    targets
+}
+
+/// Fuses the ops with every pattern of the table that fits, as long as `fuel`
+/// allows, rewriting the jumps to match. A superinstruction is at the source
+/// position of its first op.
+pub(crate) fn fuse(
+    instrs: Vec<Instr>,
+    positions: &[usize],
+    copies: &mut [CopyLoop],
+    table: &FusionTable,
+    fuel: &mut OptFuel,
+) -> (Vec<Instr>, Vec<usize>) {
+    let targets = jump_targets(&instrs, copies);
+    let mut fused = Vec::new();
+    let mut fused_positions = Vec::new();
+    // where each op ended up, for the ones that can be jumped to
+    let mut moved = vec![0; instrs.len() + 1];
+
+    let mut i = 0;
+    while i < instrs.len() {
+        moved[i] = fused.len();
+
+        let superinstruction = table
+            .fusions
+            .iter()
+            .find_map(|fusion| {
+                let end = i + fusion.pattern().len();
+                if end > instrs.len() || targets[i + 1..end].contains(&true) {
+                    return None;
+                }
+
+                let parts = &instrs[i..end];
+                let op = fusion.fuse(parts)?;
+                Some((*fusion, op, parts))
+            })
+            .filter(|(fusion, ..)| fuel.apply(Transformation::Fuse(*fusion), positions[i]))
+            .map(|(_, op, parts)| (op, parts));
+
+        fused_positions.push(positions[i]);
+        match superinstruction {
+            Some((op, parts)) => {
+                fused.push(Instr {
+                    op,
+                    count: parts.iter().map(|part| part.count).sum(),
+                });
+                i += parts.len();
+            }
+            None => {
+                fused.push(instrs[i]);
+                i += 1;
+            }
+        }
+    }
+    moved[instrs.len()] = fused.len();
+
+    for instr in &mut fused {
+        match &mut instr.op {
+            Op::JumpIfZero(target)
+            | Op::JumpIfNotZero(target)
+            | Op::Scan { end: target, .. }
+            | Op::MoveJumpIfZero(_, target)
+            | Op::MoveJumpIfNotZero(_, target)
+            | Op::AddJumpIfNotZero(_, target) => *target = moved[*target],
+            _ => {}
+        }
+    }
+
+    for copy in copies {
+        copy.end = moved[copy.end];
+    }
+
+    (fused, fused_positions)
+}
+
+/// Runs the unfused program and counts how often each pattern would have run
+/// as a superinstruction, most frequent first. Printing the patterns in this
+/// order gives a table tuned for the program.
+pub fn profile(program: &Program, machine: &Machine, fuel: u64) -> Result<Vec<(Fusion, u64)>> {
+    let mut machine = machine.clone();
+    let mut hits = vec![0u64; program.instrs.len()];
+
+    for _ in 0..fuel {
+        hits[machine.pc] += 1;
+
+        match machine.run(program, Dispatch::Match, &mut 1)? {
+            Status::Output => machine.output.clear(),
+            Status::OutOfFuel => {}
+            Status::Halted | Status::NeedsInput => break,
+        }
+    }
+
+    let targets = jump_targets(&program.instrs, &program.copies);
+    let mut counts: Vec<(Fusion, u64)> = Fusion::ALL
+        .into_iter()
+        .map(|fusion| {
+            let len = fusion.pattern().len();
+            let count = (0..program.instrs.len().saturating_sub(len - 1))
+                .filter(|&i| {
+                    !targets[i + 1..i + len].contains(&true)
+                        && fusion.fuse(&program.instrs[i..i + len]).is_some()
+                })
+                .map(|i| hits[i])
+                .sum();
+            (fusion, count)
+        })
+        .collect();
+
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    Ok(counts)
+}
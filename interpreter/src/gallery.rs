@@ -0,0 +1,40 @@
+//! Example programs bundled into the binary, so there is always something to
+//! try the interpreter and debugger on.
+
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub code: &'static str,
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        name: "hello",
+        description: "Prints a greeting",
+        code: include_str!("../../sources.b/hello.b"),
+    },
+    Example {
+        name: "cat",
+        description: "Copies its input to its output",
+        code: include_str!("../../sources.b/cat.b"),
+    },
+    Example {
+        name: "rot13",
+        description: "Applies ROT13 to its input",
+        code: include_str!("../../sources.b/rot13.b"),
+    },
+    Example {
+        name: "fibonacci",
+        description: "Prints the Fibonacci numbers that fit in a cell",
+        code: include_str!("../../sources.b/fibonacci.b"),
+    },
+    Example {
+        name: "mandelbrot",
+        description: "Renders the Mandelbrot set as ASCII art (takes a while)",
+        code: include_str!("../../sources.b/mandelbrot.b"),
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
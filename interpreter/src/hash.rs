@@ -0,0 +1,97 @@
+//! A cheap fingerprint of the execution so far, for comparing two runs
+//! without diffing full traces.
+
+use crate::cell::Cell;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The FNV-1a hash of some bytes, the same on every platform.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The rolling hash at some step
+pub struct StepHash {
+    pub step: u64,
+    pub hash: u64,
+}
+
+#[derive(Debug, Clone)]
+/// Folds the pointer and every cell written since the last publish into a
+/// rolling hash every `interval` steps. Two runs that publish the same hash at
+/// the same step went through the same states at each publish (barring collisions).
+pub struct StepHasher {
+    interval: u64,
+    hash: u64,
+    /// Whether each cell was written since the last publish
+    dirty: Vec<bool>,
+    /// The written cells in the order they were first written
+    dirty_cells: Vec<usize>,
+    /// Every hash published so far
+    pub history: Vec<StepHash>,
+}
+
+impl StepHasher {
+    /// A hasher for a tape of `cells` cells.
+    pub fn new(interval: u64, cells: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            hash: FNV_OFFSET,
+            dirty: vec![false; cells],
+            dirty_cells: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Records that the cell at `pointer` was written.
+    pub fn mark(&mut self, pointer: usize) {
+        if !self.dirty[pointer] {
+            self.dirty[pointer] = true;
+            self.dirty_cells.push(pointer);
+        }
+    }
+
+    /// Publishes a new hash if a multiple of the interval is in `before + 1`
+    /// to `step`, the steps of the last instruction.
+    pub fn update<C: Cell>(&mut self, before: u64, step: u64, pointer: usize, memory: &[C]) {
+        if step / self.interval == before / self.interval {
+            return;
+        }
+
+        self.feed(&step.to_le_bytes());
+        self.feed(&(pointer as u64).to_le_bytes());
+
+        for cell in std::mem::take(&mut self.dirty_cells) {
+            self.dirty[cell] = false;
+            self.feed(&(cell as u64).to_le_bytes());
+            memory[cell].fingerprint(&mut |bytes| self.feed(bytes));
+        }
+
+        self.history.push(StepHash {
+            step,
+            hash: self.hash,
+        });
+    }
+
+    /// The most recently published hash.
+    pub fn latest(&self) -> Option<StepHash> {
+        self.history.last().copied()
+    }
+
+    /// The bytes taken by the record of written cells, not counting the history
+    pub fn buffer_size(&self) -> usize {
+        self.dirty.len() + self.dirty_cells.capacity() * std::mem::size_of::<usize>()
+    }
+
+    /// FNV-1a, so hashes are stable across platforms and builds
+    fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Recent values of the current cell and the pointer, drawn as sparklines so
+//! counting loops and pointer sweeps stand out.
+
+use std::collections::VecDeque;
+
+use crate::engine::View;
+
+/// How many samples are kept of each value
+pub const HISTORY_LENGTH: usize = 512;
+
+/// The bars of a sparkline, from lowest to highest
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The values seen every time the debugger's view was updated: every step
+/// while stepping, every frame while running
+#[derive(Default)]
+pub struct History {
+    /// The value of the cell under the pointer, or the closest `i64` to a big
+    /// integer
+    pub cells: VecDeque<i64>,
+    pub pointers: VecDeque<usize>,
+    /// The step of the latest sample, so a view that did not move on is not
+    /// sampled twice
+    last_step: Option<u64>,
+}
+
+impl History {
+    pub fn record(&mut self, view: &View) {
+        if self.last_step == Some(view.steps) {
+            return;
+        }
+        self.last_step = Some(view.steps);
+
+        push(
+            &mut self.cells,
+            view.memory.get(view.pointer).saturating_i64(),
+        );
+        push(&mut self.pointers, view.pointer);
+    }
+}
+
+/// Appends `value`, dropping the oldest sample once the buffer is full.
+fn push<T>(buffer: &mut VecDeque<T>, value: T) {
+    if buffer.len() == HISTORY_LENGTH {
+        buffer.pop_front();
+    }
+    buffer.push_back(value);
+}
+
+/// The last `width` of `values` as bars scaled between the lowest and highest
+/// of them, along with that range.
+pub fn sparkline(values: impl ExactSizeIterator<Item = i64>, width: usize) -> (String, i64, i64) {
+    let skip = values.len().saturating_sub(width);
+    let values: Vec<i64> = values.skip(skip).collect();
+
+    let low = values.iter().copied().min().unwrap_or(0);
+    let high = values.iter().copied().max().unwrap_or(0);
+
+    let line = values
+        .iter()
+        .map(|value| {
+            if high == low {
+                BARS[0]
+            } else {
+                // in an `i128`, where the range of any two `i64`s fits
+                let bar = (*value as i128 - low as i128) * (BARS.len() - 1) as i128
+                    / (high as i128 - low as i128);
+                BARS[bar as usize]
+            }
+        })
+        .collect();
+
+    (line, low, high)
+}
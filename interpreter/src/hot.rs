@@ -0,0 +1,216 @@
+//! A light runtime tier for the bytecode engine. Every loop counts how often
+//! it jumps back, and once that passes the machine's threshold its body is
+//! analyzed: if it only adds, clears and moves, and returns to the loop's
+//! cell, the net effect of one iteration is worked out once, and from then on
+//! every time the loop is reached, the remaining iterations happen at once,
+//! solving for how many there are.
+
+use crate::bytecode::{clear_steps, Instr, Op, Program};
+
+/// How many times a loop jumps back before it is specialized, by default
+pub const HOT_LOOP_THRESHOLD: u32 = 64;
+
+#[derive(Debug, Clone)]
+/// What is known about the loop ending at an op
+pub enum LoopState {
+    /// Jumped back this many times
+    Counting(u32),
+    Specialized(LoopEffect),
+    /// The body does something that cannot be done at once
+    Generic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What one iteration does to a cell
+enum CellEffect {
+    Add(u8),
+    /// Cleared, and then changed by this much
+    Set(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A clear loop in the body on a cell that is not yet set in the iteration
+struct Clear {
+    offset: isize,
+    /// What the body added to the cell before the clear
+    before: u8,
+    /// What the clear loop adds each time, 1 or 255
+    add: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The net effect of one iteration of a loop
+pub struct LoopEffect {
+    cells: Vec<(isize, CellEffect)>,
+    clears: Vec<Clear>,
+    /// The steps of one iteration, except for the clear loops in `clears`
+    steps: u64,
+    lowest: isize,
+    highest: isize,
+}
+
+impl LoopEffect {
+    /// Analyzes the loop whose body starts at `start` and whose `]` is the op
+    /// at `back_edge`.
+    pub(crate) fn analyze(program: &Program, start: usize, back_edge: usize) -> Option<Self> {
+        let mut effect = Self {
+            cells: Vec::new(),
+            clears: Vec::new(),
+            steps: 0,
+            lowest: 0,
+            highest: 0,
+        };
+        let mut offset = 0isize;
+
+        for Instr { op, count } in &program.instrs[start..=back_edge] {
+            effect.steps += *count as u64;
+
+            match *op {
+                Op::Add(n) => effect.add(offset, n),
+                Op::Move(n) => effect.move_by(&mut offset, n),
+                Op::Clear { cells, add } => {
+                    for i in 0..cells {
+                        if i > 0 {
+                            effect.move_by(&mut offset, 1);
+                        }
+                        effect.clear(offset, add);
+                    }
+                }
+                Op::AddMove(n, by) => {
+                    effect.add(offset, n);
+                    effect.move_by(&mut offset, by);
+                }
+                Op::MoveAdd(by, n) => {
+                    effect.move_by(&mut offset, by);
+                    effect.add(offset, n);
+                }
+                Op::AddMoveAdd(first, by, second) => {
+                    effect.add(offset, first);
+                    effect.move_by(&mut offset, by);
+                    effect.add(offset, second);
+                }
+                Op::MoveAddMove(first, n, second) => {
+                    effect.move_by(&mut offset, first);
+                    effect.add(offset, n);
+                    effect.move_by(&mut offset, second);
+                }
+                Op::Set { add, value } => {
+                    effect.clear(offset, add);
+                    effect.add(offset, value);
+                }
+                Op::SetMove {
+                    add,
+                    value,
+                    offset: by,
+                } => {
+                    effect.clear(offset, add);
+                    effect.add(offset, value);
+                    effect.move_by(&mut offset, by);
+                }
+                // the `]` itself, which may be fused with what comes before it
+                Op::JumpIfNotZero(_) => {}
+                Op::MoveJumpIfNotZero(by, _) => effect.move_by(&mut offset, by),
+                Op::AddJumpIfNotZero(n, _) => effect.add(offset, n),
+                _ => return None,
+            }
+        }
+
+        let counter = effect.cell(0);
+        match counter {
+            Some(CellEffect::Add(n)) if offset == 0 && n != 0 => Some(effect),
+            _ => None,
+        }
+    }
+
+    fn cell(&self, offset: isize) -> Option<CellEffect> {
+        self.cells
+            .iter()
+            .find(|(o, _)| *o == offset)
+            .map(|(_, effect)| *effect)
+    }
+
+    fn set_cell(&mut self, offset: isize, effect: CellEffect) {
+        match self.cells.iter_mut().find(|(o, _)| *o == offset) {
+            Some((_, existing)) => *existing = effect,
+            None => self.cells.push((offset, effect)),
+        }
+    }
+
+    fn add(&mut self, offset: isize, n: u8) {
+        let effect = match self.cell(offset) {
+            None => CellEffect::Add(n),
+            Some(CellEffect::Add(m)) => CellEffect::Add(m.wrapping_add(n)),
+            Some(CellEffect::Set(m)) => CellEffect::Set(m.wrapping_add(n)),
+        };
+        self.set_cell(offset, effect);
+    }
+
+    fn move_by(&mut self, offset: &mut isize, n: isize) {
+        *offset += n;
+        self.lowest = self.lowest.min(*offset);
+        self.highest = self.highest.max(*offset);
+    }
+
+    fn clear(&mut self, offset: isize, add: u8) {
+        // the `[-]` is counted by `clear_steps`
+        self.steps -= 3;
+
+        match self.cell(offset) {
+            // the value is the same in every iteration
+            Some(CellEffect::Set(value)) => self.steps += clear_steps(value, add),
+            before => self.clears.push(Clear {
+                offset,
+                before: match before {
+                    Some(CellEffect::Add(n)) => n,
+                    _ => 0,
+                },
+                add,
+            }),
+        }
+        self.set_cell(offset, CellEffect::Set(0));
+    }
+
+    /// Runs every remaining iteration of the loop on the cell at `pointer`,
+    /// which is not 0, returning how many steps they took. Returns `None`
+    /// (and changes nothing) if the loop never ends or would move off the
+    /// tape.
+    pub(crate) fn run(&self, memory: &mut [u8], pointer: usize) -> Option<u64> {
+        if (pointer as isize) + self.lowest < 0
+            || (pointer as isize) + self.highest >= memory.len() as isize
+        {
+            return None;
+        }
+
+        let Some(CellEffect::Add(step)) = self.cell(0) else {
+            return None;
+        };
+        let value = memory[pointer];
+        let iterations =
+            (1..=256u64).find(|n| value.wrapping_add(step.wrapping_mul(*n as u8)) == 0)?;
+
+        let cell = |offset: isize| pointer.wrapping_add_signed(offset);
+
+        let mut steps = iterations * self.steps;
+        for clear in &self.clears {
+            // the first iteration clears whatever is in the cell, the others
+            // what the iteration before left there
+            let first = memory[cell(clear.offset)].wrapping_add(clear.before);
+            steps += clear_steps(first, clear.add);
+
+            if let Some(CellEffect::Set(value)) = self.cell(clear.offset) {
+                steps +=
+                    (iterations - 1) * clear_steps(value.wrapping_add(clear.before), clear.add);
+            }
+        }
+
+        for (offset, effect) in &self.cells {
+            let cell = &mut memory[cell(*offset)];
+            *cell = match effect {
+                CellEffect::Add(n) => cell.wrapping_add(n.wrapping_mul(iterations as u8)),
+                CellEffect::Set(value) => *value,
+            };
+        }
+
+        Some(steps)
+    }
+}
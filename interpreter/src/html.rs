@@ -0,0 +1,182 @@
+//! The standalone HTML report written by `--export-html`, to share a run with
+//! someone without a terminal: the source colored by how often each
+//! instruction ran, the output, the stats and, with `--html-replay`, a player
+//! stepping through the sampled trace.
+
+use std::time::Duration;
+
+use brainfuck_extended::{trace::TraceEvent, BrainfuckInterpreter};
+
+use crate::summary::ExitReason;
+
+const REPORT_TEMPLATE: &str = include_str!("./report.html.TEMPLATE");
+const REPLAY_TEMPLATE: &str = include_str!("./replay.js.TEMPLATE");
+
+/// How many heat classes the template has, besides `h0` for never
+const HEAT_LEVELS: u64 = 8;
+
+/// `text` with the characters that mean something in HTML escaped, and `%`,
+/// so that nothing from the program is taken for a placeholder of the template.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '%' => escaped.push_str("&#37;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The heat class of a character that ran `count` times, on a log scale up
+/// to `max`, so that the few instructions of a hot loop do not wash out the
+/// rest.
+fn level(count: u64, max: u64) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    let scaled = ((count as f64).ln_1p() / (max as f64).ln_1p() * HEAT_LEVELS as f64).ceil();
+    (scaled as u64).clamp(1, HEAT_LEVELS)
+}
+
+/// The code, with every character that ran in a span of its own (with the
+/// `c<position>` id the player looks for) and the rest as it is.
+fn source(code: &[char], heat: &[u64]) -> String {
+    let max = heat.iter().copied().max().unwrap_or(0);
+    let mut html = String::new();
+    let mut never = String::new();
+
+    for (position, (c, &count)) in code.iter().zip(heat).enumerate() {
+        if count == 0 || c.is_whitespace() {
+            never.push(*c);
+            continue;
+        }
+        if !never.is_empty() {
+            html.push_str(&format!(r#"<span class="h0">{}</span>"#, escape(&never)));
+            never.clear();
+        }
+        html.push_str(&format!(
+            r#"<span id="c{}" class="h{}" title="{}: ran {} time{}">{}</span>"#,
+            position,
+            level(count, max),
+            position,
+            count,
+            if count == 1 { "" } else { "s" },
+            escape(&c.to_string())
+        ));
+    }
+    if !never.is_empty() {
+        html.push_str(&format!(r#"<span class="h0">{}</span>"#, escape(&never)));
+    }
+
+    html
+}
+
+/// The player for the sampled steps, as `[step, position, pointer, cell,
+/// phase]`s.
+fn player(i: &BrainfuckInterpreter) -> String {
+    let Some(tracer) = i
+        .tracer
+        .as_ref()
+        .filter(|tracer| !tracer.records.is_empty())
+    else {
+        return "<p>No step was sampled, see <code>--trace-sample</code>.</p>".to_string();
+    };
+
+    let samples: Vec<_> = tracer
+        .records
+        .iter()
+        .map(|record| {
+            let phase = match &record.event {
+                TraceEvent::Sample(_) => None,
+                TraceEvent::Phase(name) => Some(name.as_str()),
+            };
+            (
+                record.step,
+                record.position,
+                record.pointer,
+                record.cell.clone(),
+                phase,
+            )
+        })
+        .collect();
+    // a phase name could end the script early, or look like a placeholder
+    let samples = serde_json::to_string(&samples)
+        .expect("samples are serializable")
+        .replace('<', "\\u003c")
+        .replace('%', "\\u0025");
+
+    REPLAY_TEMPLATE.replace("%%SAMPLES%%", &samples)
+}
+
+fn describe(exit: &ExitReason) -> String {
+    match exit {
+        ExitReason::Halted => "halted".to_string(),
+        ExitReason::Stopped { reason } => format!("stopped: {}", reason),
+        ExitReason::Quit => "quit before the program halted".to_string(),
+        ExitReason::Failed { error } => format!("failed: {}", error),
+    }
+}
+
+/// The report of the run of the program called `name` that `i` went through,
+/// with the player if `replay`.
+pub fn render(
+    name: &str,
+    i: &BrainfuckInterpreter,
+    exit: &ExitReason,
+    wall_time: Duration,
+    replay: bool,
+) -> String {
+    let heat = i.heat.clone().unwrap_or_else(|| vec![0; i.code.len()]);
+
+    let summary = [
+        ("Exit", describe(exit)),
+        ("Steps", i.steps.to_string()),
+        ("Time", format!("{:.3}s", wall_time.as_secs_f64())),
+        ("Output bytes", i.stats.output_bytes.to_string()),
+    ]
+    .iter()
+    .map(|(label, value)| format!("<tr><th>{}</th><td>{}</td></tr>", label, escape(value)))
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    let phases = i.phases.report(i.steps, i.stats.output_bytes);
+    let phases = if phases.is_empty() {
+        String::new()
+    } else {
+        let rows: String = phases
+            .iter()
+            .map(|phase| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3}s</td></tr>\n",
+                    escape(&phase.name),
+                    phase.entered,
+                    phase.steps,
+                    phase.output_bytes,
+                    phase.time_secs
+                )
+            })
+            .collect();
+        format!(
+            "<h2>Phases</h2>\n<table>\n<tr><th>Phase</th><th>Entered</th><th>Steps</th>\
+             <th>Output bytes</th><th>Time</th></tr>\n{}</table>",
+            rows
+        )
+    };
+
+    REPORT_TEMPLATE
+        .replace("%%NAME%%", &escape(name))
+        .replace("%%SUMMARY%%", &summary)
+        .replace(
+            "%%PLAYER%%",
+            &if replay { player(i) } else { String::new() },
+        )
+        .replace("%%OUTPUT%%", &escape(&i.output))
+        .replace("%%STATS%%", &escape(&i.stats.to_string()))
+        .replace("%%PHASES%%", &phases)
+        .replace("%%SOURCE%%", &source(&i.code, &heat))
+}
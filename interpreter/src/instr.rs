@@ -0,0 +1,157 @@
+//! The code lowered to the instructions the interpreter dispatches on, instead
+//! of matching every character and looking up its bracket or directive on
+//! every step. A run of the same one of `+-<>` becomes a single [`Instr::Add`]
+//! or [`Instr::Move`], brackets know the index of their partner and comments
+//! are dropped. The characters stay around for showing the source, and the
+//! position in the code where each instruction starts is kept next to it, so
+//! the debugger, the breakpoints and `--trace` still point at the source.
+
+use std::ops::Range;
+
+use crate::{Assertion, BracketTable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    /// A run of `+`, or of `-` if it is negative, as long as the run
+    Add(isize),
+    /// A run of `>`, or of `<` if it is negative, as long as the run
+    Move(isize),
+    /// `.`
+    Write,
+    /// `,`
+    Read,
+    /// `[`, with the index of its `]`
+    Open(usize),
+    /// `]`, with the index of its `[`
+    Close(usize),
+    /// A `[` without a partner, which fails when it runs
+    UnmatchedOpen,
+    /// A `]` without a partner, which fails when it runs
+    UnmatchedClose,
+    /// A directive, passed over in a single step
+    Skip,
+    /// An `@assert` directive, with its index in the assertions
+    Assert(usize),
+    /// A `#` or an `@` that is not a directive, which does nothing but is
+    /// still a step, since it may mark a phase for `--trace` and the phases
+    Marker,
+}
+
+impl Instr {
+    /// How many characters of the code the instruction stands for, which is
+    /// also how many steps it takes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(self) -> usize {
+        match self {
+            Self::Add(n) | Self::Move(n) => n.unsigned_abs(),
+            _ => 1,
+        }
+    }
+
+    /// Whether the instruction writes the cell under the pointer, when it
+    /// does not fail.
+    pub fn writes_cell(self) -> bool {
+        matches!(self, Self::Add(_) | Self::Read)
+    }
+}
+
+/// Pairs up the brackets of `code` the way running it does: the ones in the
+/// `directives` and the conditions of the `assertions` (both sorted by
+/// position) are not part of the code.
+pub fn brackets(
+    code: &[char],
+    directives: &[Range<usize>],
+    assertions: &[Assertion],
+) -> BracketTable {
+    let mut skipped = directives.to_vec();
+    skipped.extend(
+        assertions
+            .iter()
+            .map(|assertion| assertion.start..assertion.end),
+    );
+    skipped.sort_by_key(|range| range.start);
+    BracketTable::skipping(code, &skipped)
+}
+
+/// Lowers `code`, passing over the `directives` and the `assertions` (both
+/// sorted by position) the way running it does. Returns the instructions, the
+/// position in the code of each one followed by the length of the code, where
+/// execution ends, and the comment characters dropped before each one
+/// followed by those after the last one.
+pub fn lower(
+    code: &[char],
+    directives: &[Range<usize>],
+    assertions: &[Assertion],
+) -> (Vec<Instr>, Vec<usize>, Vec<u64>) {
+    let brackets = brackets(code, directives, assertions);
+
+    let mut instrs = Vec::new();
+    let mut positions = Vec::new();
+    let mut comments = Vec::new();
+    let mut dropped = 0;
+    // the instructions of the `[`s whose `]` is still to come
+    let mut open = Vec::new();
+    let mut pos = 0;
+
+    while pos < code.len() {
+        let c = code[pos];
+        let start = pos;
+        pos += 1;
+
+        let instr = match c {
+            '+' | '-' | '>' | '<' => {
+                while code.get(pos) == Some(&c) {
+                    pos += 1;
+                }
+                let run = (pos - start) as isize;
+                match c {
+                    '+' => Instr::Add(run),
+                    '-' => Instr::Add(-run),
+                    '>' => Instr::Move(run),
+                    _ => Instr::Move(-run),
+                }
+            }
+            '.' => Instr::Write,
+            ',' => Instr::Read,
+            '[' if brackets.matching(start).is_some() => {
+                open.push(instrs.len());
+                // patched once its `]` is lowered
+                Instr::Open(0)
+            }
+            '[' => Instr::UnmatchedOpen,
+            ']' if brackets.matching(start).is_some() => {
+                let partner = open.pop().expect("every matched `]` has its `[` before it");
+                instrs[partner] = Instr::Open(instrs.len());
+                Instr::Close(partner)
+            }
+            ']' => Instr::UnmatchedClose,
+            '@' => {
+                if let Ok(i) = directives.binary_search_by_key(&start, |directive| directive.start)
+                {
+                    pos = directives[i].end;
+                    Instr::Skip
+                } else if let Ok(i) =
+                    assertions.binary_search_by_key(&start, |assertion| assertion.start)
+                {
+                    pos = assertions[i].end;
+                    Instr::Assert(i)
+                } else {
+                    Instr::Marker
+                }
+            }
+            '#' => Instr::Marker,
+            _ => {
+                dropped += 1;
+                continue;
+            }
+        };
+
+        instrs.push(instr);
+        positions.push(start);
+        comments.push(std::mem::take(&mut dropped));
+    }
+    positions.push(code.len());
+    comments.push(dropped);
+
+    (instrs, positions, comments)
+}
@@ -0,0 +1,842 @@
+use std::{fmt, ops::Range};
+
+use ascii::ToAsciiChar;
+use clap::ValueEnum;
+
+use crate::{
+    assertion::{parse_assertions, Assertion},
+    cell::{with_cells, Cell, CellValue, Memory},
+    instr::{self, Instr},
+    memo::Memo,
+    pragma::{self, CellWidth, Pragmas},
+    trace::Tracer,
+    BracketTable, ExecutionStats, Phases, Result, StepHasher,
+};
+
+/// How many cells the tape has, unless a run asks for another size with
+/// [`BrainfuckInterpreter::resize_memory`]
+pub const MEMORY_SIZE: usize = 30_000;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// How the bytes written by `.` are turned into output text
+pub enum OutputMode {
+    #[default]
+    /// Every byte must be an ASCII character
+    Ascii,
+    /// Consecutive bytes are decoded as UTF-8, invalid sequences become U+FFFD
+    Utf8,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// How `.` and `,` interpret cell values
+pub enum IoMode {
+    #[default]
+    /// `.` writes the cell as a byte and `,` reads a single byte
+    Text,
+    /// `.` writes the cell as a decimal number followed by a space and
+    /// `,` parses the next whitespace-delimited integer
+    Numeric,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The outcome of executing a single instruction
+pub enum StepResult {
+    /// An instruction was executed and the program can continue
+    Continue,
+    /// A `,` was reached while the input is exhausted but not closed. Nothing
+    /// was executed; supply more input with [`BrainfuckInterpreter::push_input`]
+    /// (or signal EOF with [`BrainfuckInterpreter::close_input`]) and step again.
+    NeedsInput,
+    /// The end of the program was reached
+    Halted,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// The result of trying to read a value for `,`
+pub(crate) enum Read<T = u8> {
+    Value(T),
+    Eof,
+    Pending,
+}
+
+#[derive(Clone)]
+pub struct BrainfuckInterpreter {
+    /// The tape, of bytes unless the code asks for `@pragma cell-size bigint`
+    pub memory: Memory,
+    pub pointer: usize,
+    pub input: Vec<u8>,
+    pub input_pos: usize,
+    /// Whether the host promised that no more input will be pushed
+    pub input_closed: bool,
+    pub code: Vec<char>,
+    /// The position in the code of the next instruction, which
+    /// [`BrainfuckInterpreter::seek`] moves
+    pub code_pos: usize,
+    /// The code lowered to instructions (see [`crate::instr`]), which is what
+    /// runs
+    pub instrs: Vec<Instr>,
+    /// The position in the code of each instruction, and the length of the
+    /// code after the last one
+    pub positions: Vec<usize>,
+    /// The comment characters passed over before each instruction, and after
+    /// the last one
+    pub comments: Vec<u64>,
+    /// The index of the next instruction
+    pub pc: usize,
+
+    pub output: String,
+    pub output_mode: OutputMode,
+    pub io_mode: IoMode,
+    /// Written bytes that do not form a complete UTF-8 sequence yet
+    pub pending_output: Vec<u8>,
+
+    /// The number of instructions executed so far, counting every character
+    /// of a run of `+-<>`
+    pub steps: u64,
+    /// What `steps` was before the last step
+    pub previous_steps: u64,
+    /// What those instructions did
+    pub stats: ExecutionStats,
+    /// Every byte written by `.` together with the step that produced it, if
+    /// enabled with [`BrainfuckInterpreter::record_output_steps`]
+    pub output_log: Option<Vec<OutputRecord>>,
+    /// Every read of `,` together with its step, if enabled with
+    /// [`BrainfuckInterpreter::record_input_steps`]
+    pub input_log: Option<Vec<InputRecord>>,
+    /// A rolling hash of the execution, if enabled with
+    /// [`BrainfuckInterpreter::record_step_hashes`]
+    pub step_hasher: Option<StepHasher>,
+    /// The `@assert` directives in the code, sorted by position, if enabled
+    /// with [`BrainfuckInterpreter::enable_assertions`]
+    pub assertions: Vec<Assertion>,
+    /// The sampled steps and phase markers, if enabled with
+    /// [`BrainfuckInterpreter::record_trace`]
+    pub tracer: Option<Tracer>,
+    /// How many times each character of the code ran, if enabled with
+    /// [`BrainfuckInterpreter::record_heat`]
+    pub heat: Option<Vec<u64>>,
+    /// The `@phase` markers passed so far (see [`crate::phase`])
+    pub phases: Phases,
+    /// The recorded runs of pure loops, if enabled with
+    /// [`BrainfuckInterpreter::enable_memoization`]
+    pub memo: Option<Memo>,
+    /// The semantics asked for by the code's `@pragma` directives
+    pub pragmas: Pragmas,
+    /// Where those directives are, sorted by position
+    pub directives: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An error raised by an instruction while the program runs, along with where
+/// it happened, so it can be shown in the source
+pub struct RuntimeError {
+    pub message: String,
+    /// The position in the code of the instruction that failed, which is the
+    /// length of the code for the `@assert-end` checks
+    pub position: usize,
+    pub pointer: usize,
+    /// The value of the cell under the pointer
+    pub cell: CellValue,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A single byte written by `.`
+pub struct OutputRecord {
+    /// The (zero-based) step that wrote the byte
+    pub step: u64,
+    pub byte: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A single read of `,`
+pub struct InputRecord {
+    /// The (zero-based) step that read
+    pub step: u64,
+    /// Where the bytes it took up are in the input, which with `--io numeric`
+    /// include the whitespace before the number
+    pub start: usize,
+    pub end: usize,
+    /// Whether it hit EOF instead of reading a value
+    pub eof: bool,
+}
+
+impl BrainfuckInterpreter {
+    /// Sets up a run of `code` with the `@pragma` directives that could be
+    /// read. Hosts should reject the others with
+    /// [`crate::pragma::read_pragmas`] first.
+    pub fn new(code: &str, output_mode: OutputMode, io_mode: IoMode) -> Self {
+        let pragmas = codegen::pragma::parse_pragmas(code).0;
+        let chars = code.chars().collect::<Vec<_>>();
+        let directives = pragma::skipped(code);
+        let (instrs, positions, comments) = instr::lower(&chars, &directives, &[]);
+
+        Self {
+            memory: Memory::new(cell_width(&pragmas), MEMORY_SIZE),
+            pointer: 0,
+            input: Vec::new(),
+            input_pos: 0,
+            input_closed: false,
+            code: chars,
+            code_pos: 0,
+            instrs,
+            positions,
+            comments,
+            pc: 0,
+            output: String::new(),
+            output_mode,
+            io_mode,
+            pending_output: Vec::new(),
+            steps: 0,
+            previous_steps: 0,
+            stats: ExecutionStats::default(),
+            output_log: None,
+            input_log: None,
+            step_hasher: None,
+            assertions: Vec::new(),
+            tracer: None,
+            heat: None,
+            phases: Phases::new(code),
+            memo: None,
+            pragmas,
+            directives,
+        }
+    }
+
+    /// Lowers the code again, after the assertions changed.
+    fn lower(&mut self) {
+        (self.instrs, self.positions, self.comments) =
+            instr::lower(&self.code, &self.directives, &self.assertions);
+        self.seek(self.code_pos);
+        // which brackets are loops can change with the assertions
+        if self.memo.is_some() {
+            self.memo = Some(Memo::new(&self.code, &self.brackets()));
+        }
+    }
+
+    /// The pairs of brackets that run as loops, leaving out those in the
+    /// directives and the assertions.
+    pub fn brackets(&self) -> BracketTable {
+        instr::brackets(&self.code, &self.directives, &self.assertions)
+    }
+
+    /// Moves the code pointer to the first instruction at or after `position`,
+    /// which is the end of the code past the last one.
+    pub fn seek(&mut self, position: usize) {
+        self.pc = self.positions.partition_point(|&start| start < position);
+        self.code_pos = self.positions[self.pc];
+    }
+
+    /// The positions in the code where a breakpoint stops execution before
+    /// the next instruction: its own characters and the comments before it.
+    pub fn next_span(&self) -> Range<usize> {
+        let end = |index: usize| match self.instrs.get(index) {
+            Some(instr) => self.positions[index] + instr.len(),
+            None => self.code.len(),
+        };
+        let start = match self.pc {
+            0 => 0,
+            pc => end(pc - 1),
+        };
+        start..end(self.pc)
+    }
+
+    /// How many steps the next instruction takes, unless it replays a loop
+    /// (see [`crate::memo`]).
+    pub fn next_len(&self) -> u64 {
+        self.instrs
+            .get(self.pc)
+            .map_or(1, |instr| instr.len() as u64)
+    }
+
+    /// Whether the last step got [`BrainfuckInterpreter::steps`] to or past
+    /// a multiple of `interval`, which a step of a whole run can do without
+    /// landing on it.
+    pub fn passed_multiple(&self, interval: u64) -> bool {
+        self.steps / interval > self.previous_steps / interval
+    }
+
+    /// Gives the tape `cells` cells instead of [`MEMORY_SIZE`], all 0, of the
+    /// width the pragmas ask for. Call it before the program runs and before
+    /// [`BrainfuckInterpreter::record_step_hashes`].
+    pub fn resize_memory(&mut self, cells: usize) {
+        self.memory = Memory::new(cell_width(&self.pragmas), cells.max(1));
+        self.pointer = 0;
+    }
+
+    /// The last cell of the tape.
+    pub fn max_pointer(&self) -> usize {
+        self.memory.len() - 1
+    }
+
+    /// Starts checking the `@assert` directives in the code (see
+    /// [`crate::assertion`]), which are then skipped instead of executed.
+    pub fn enable_assertions(&mut self) -> Result {
+        self.assertions = parse_assertions(&self.code.iter().collect::<String>())?;
+        self.lower();
+        Ok(())
+    }
+
+    /// Starts recording the step at which each output byte is written.
+    pub fn record_output_steps(&mut self) {
+        self.output_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Starts recording the step at which each `,` reads, and what.
+    pub fn record_input_steps(&mut self) {
+        self.input_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Starts publishing a rolling hash of the pointer and written cells every
+    /// `interval` steps.
+    pub fn record_step_hashes(&mut self, interval: u64) {
+        self.step_hasher
+            .get_or_insert_with(|| StepHasher::new(interval, self.memory.len()));
+    }
+
+    /// Starts recording every `interval`th step and every phase marker (see
+    /// [`crate::trace`]).
+    pub fn record_trace(&mut self, interval: u64) {
+        if self.tracer.is_none() {
+            self.tracer = Some(Tracer::new(&self.code.iter().collect::<String>(), interval));
+        }
+    }
+
+    /// Starts counting how many times each character of the code runs. The
+    /// loops replayed by memoization are not counted.
+    pub fn record_heat(&mut self) {
+        self.heat.get_or_insert_with(|| vec![0; self.code.len()]);
+    }
+
+    /// Appends bytes to the end of the input stream.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    /// Marks the input as finished, so reading past the end of it hits EOF
+    /// instead of returning [`StepResult::NeedsInput`].
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    pub fn halted(&self) -> bool {
+        self.pc >= self.instrs.len()
+    }
+
+    /// Steps until the program halts or needs more input, returning why along
+    /// with the stats of the whole execution so far.
+    pub fn run(&mut self) -> Result<(StepResult, ExecutionStats)> {
+        loop {
+            match self.step()? {
+                StepResult::Continue => {}
+                result => break Ok((result, self.stats)),
+            }
+        }
+    }
+
+    /// Appends a byte written by `.` to the output according to the output mode.
+    fn write_byte(&mut self, byte: u8) -> Result {
+        self.stats.output_bytes += 1;
+
+        if let Some(output_log) = &mut self.output_log {
+            output_log.push(OutputRecord {
+                step: self.steps,
+                byte,
+            });
+        }
+
+        match self.output_mode {
+            OutputMode::Ascii => {
+                self.output.push(byte.to_ascii_char()?.as_char());
+            }
+            OutputMode::Utf8 => {
+                self.pending_output.push(byte);
+                decode_utf8(&mut self.pending_output, &mut self.output);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces a trailing incomplete UTF-8 sequence once no more bytes can arrive.
+    fn finish_output(&mut self) {
+        if !self.pending_output.is_empty() {
+            self.output.push(char::REPLACEMENT_CHARACTER);
+            self.pending_output.clear();
+        }
+    }
+
+    /// Executes the instruction under the code pointer. Failures are
+    /// [`RuntimeError`]s.
+    pub fn step(&mut self) -> Result<StepResult> {
+        self.execute().map_err(|e| {
+            RuntimeError {
+                message: e.to_string(),
+                position: self.code_pos,
+                pointer: self.pointer,
+                cell: self.memory.get(self.pointer),
+            }
+            .into()
+        })
+    }
+
+    fn execute(&mut self) -> Result<StepResult> {
+        if self.halted() {
+            return Ok(StepResult::Halted);
+        }
+
+        let position = self.code_pos;
+        let instr = self.instrs[self.pc];
+        let pointer = self.pointer;
+
+        // a loop that is skipped is not worth recording
+        if matches!(instr, Instr::Open(_))
+            && !self.memory.is_zero(self.pointer)
+            && self.replay_loop()
+        {
+            return self.finish_step();
+        }
+
+        let mut next = self.pc + 1;
+
+        match instr {
+            Instr::Add(n) => {
+                // the run length wraps around the same way its steps would
+                self.memory.add(self.pointer, n);
+            }
+            Instr::Move(n) => {
+                self.pointer = if self.pragmas.wrap_pointer {
+                    (self.pointer as isize + n).rem_euclid(self.memory.len() as isize) as usize
+                } else {
+                    self.pointer
+                        .saturating_add_signed(n)
+                        .min(self.max_pointer())
+                };
+            }
+            Instr::Write => match self.io_mode {
+                IoMode::Text => {
+                    let byte = self.memory.to_byte(self.pointer).ok_or_else(|| {
+                        format!(
+                            "cannot write {}, it is not a byte",
+                            self.memory.get(self.pointer)
+                        )
+                    })?;
+                    self.write_byte(byte)?;
+                }
+                IoMode::Numeric => {
+                    for byte in format!("{} ", self.memory.get(self.pointer)).bytes() {
+                        self.write_byte(byte)?;
+                    }
+                }
+            },
+            Instr::Read => {
+                let input_pos = self.input_pos;
+                let read = with_cells!(&mut self.memory, cells => read_cell(
+                    &mut cells[self.pointer],
+                    self.io_mode,
+                    &self.input,
+                    &mut self.input_pos,
+                    self.input_closed,
+                    self.pragmas.eof,
+                )?);
+                if read == Read::Pending {
+                    return Ok(StepResult::NeedsInput);
+                }
+                if let Some(input_log) = &mut self.input_log {
+                    input_log.push(InputRecord {
+                        step: self.steps,
+                        start: input_pos,
+                        end: self.input_pos,
+                        eof: matches!(read, Read::Eof),
+                    });
+                }
+                self.stats.input_bytes += (self.input_pos - input_pos) as u64;
+            }
+            Instr::Open(end) => {
+                if self.memory.is_zero(self.pointer) {
+                    next = end + 1;
+                }
+            }
+            Instr::Close(start) => {
+                if !self.memory.is_zero(self.pointer) {
+                    next = start + 1;
+                    self.stats.loop_iterations += 1;
+                }
+            }
+            Instr::UnmatchedOpen => return Err("unmatched [".into()),
+            Instr::UnmatchedClose => return Err("unmatched ]".into()),
+            // pragmas take effect before the program starts
+            Instr::Skip => {}
+            Instr::Assert(i) => {
+                let assertion = &self.assertions[i];
+                if !assertion.at_end {
+                    with_cells!(&self.memory, cells => assertion.check(cells, self.pointer))?;
+                }
+            }
+            Instr::Marker => {}
+        }
+
+        // the comments before the instruction, and after the last one once
+        // the program ends
+        self.stats.comments += self.comments[self.pc];
+        if next == self.instrs.len() {
+            self.stats.comments += self.comments[next];
+        }
+        self.pc = next;
+        self.code_pos = self.positions[next];
+
+        // what the step did in terms of the source, for the stats and the views
+        let c = self.code[position];
+        let len = instr.len();
+        self.previous_steps = self.steps;
+        self.steps += len as u64;
+        self.stats.count(c, len as u64);
+        let highest = match instr {
+            // it went past the last cell on the way
+            Instr::Move(n) if pointer as isize + n > self.max_pointer() as isize => {
+                self.max_pointer()
+            }
+            Instr::Move(n) if self.pragmas.wrap_pointer && (pointer as isize) + n < 0 => {
+                self.max_pointer()
+            }
+            _ => self.pointer,
+        };
+        self.stats.max_pointer = self.stats.max_pointer.max(highest);
+
+        if let Some(step_hasher) = &mut self.step_hasher {
+            if instr.writes_cell() {
+                step_hasher.mark(self.pointer);
+            }
+            with_cells!(&self.memory, cells => step_hasher.update(
+                self.previous_steps,
+                self.steps,
+                self.pointer,
+                cells,
+            ));
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.observe(
+                self.previous_steps,
+                self.steps,
+                position,
+                c,
+                self.pointer,
+                &self.memory,
+            );
+        }
+
+        if let Some(heat) = &mut self.heat {
+            for count in &mut heat[position..position + len] {
+                *count += 1;
+            }
+        }
+
+        if c == '@' {
+            self.phases
+                .pass(position, self.steps, self.stats.output_bytes);
+        }
+
+        self.record_loop(position);
+
+        self.finish_step()
+    }
+
+    /// Wraps up the run if the last step halted it.
+    fn finish_step(&mut self) -> Result<StepResult> {
+        if self.halted() {
+            self.finish_output();
+            self.phases.finish(self.steps, self.stats.output_bytes);
+            for assertion in self.assertions.iter().filter(|assertion| assertion.at_end) {
+                with_cells!(&self.memory, cells => assertion.check(cells, self.pointer))?;
+            }
+            return Ok(StepResult::Halted);
+        }
+
+        Ok(StepResult::Continue)
+    }
+}
+
+/// The width of the cells `pragmas` ask for, which is a byte unless they ask
+/// for big integers, since the hosts reject the other widths.
+fn cell_width(pragmas: &Pragmas) -> CellWidth {
+    match pragmas.cell_size {
+        Some(CellWidth::BigInt) => CellWidth::BigInt,
+        _ => CellWidth::Bits(8),
+    }
+}
+
+/// Reads a value for `,` into `cell` like [`read_byte`] or [`read_number`],
+/// storing `eof` instead at the end of the input if the program asked for a
+/// value, and otherwise leaving the cell alone.
+fn read_cell<C: Cell>(
+    cell: &mut C,
+    io_mode: IoMode,
+    input: &[u8],
+    input_pos: &mut usize,
+    closed: bool,
+    eof: Option<u8>,
+) -> Result<Read<()>> {
+    let read = match io_mode {
+        IoMode::Text => match read_byte(input, input_pos, closed) {
+            Read::Value(byte) => Read::Value(C::from_byte(byte)),
+            Read::Eof => Read::Eof,
+            Read::Pending => Read::Pending,
+        },
+        IoMode::Numeric => read_number(input, input_pos, closed)?,
+    };
+
+    Ok(match read {
+        Read::Value(value) => {
+            *cell = value;
+            Read::Value(())
+        }
+        Read::Eof => {
+            if let Some(value) = eof {
+                *cell = C::from_byte(value);
+            }
+            Read::Eof
+        }
+        Read::Pending => Read::Pending,
+    })
+}
+
+/// Moves every complete (or definitely invalid) UTF-8 sequence from `pending`
+/// into `output`, leaving only an incomplete tail.
+pub fn decode_utf8(pending: &mut Vec<u8>, output: &mut String) {
+    loop {
+        match std::str::from_utf8(pending) {
+            Ok(valid) => {
+                output.push_str(valid);
+                pending.clear();
+                break;
+            }
+            Err(e) => {
+                let valid_up_to = e.valid_up_to();
+                // the prefix was just validated by `from_utf8`
+                let valid = std::str::from_utf8(&pending[..valid_up_to]).unwrap();
+                output.push_str(valid);
+
+                match e.error_len() {
+                    Some(len) => {
+                        output.push(char::REPLACEMENT_CHARACTER);
+                        pending.drain(..valid_up_to + len);
+                    }
+                    None => {
+                        // the sequence may still be completed by the next write
+                        pending.drain(..valid_up_to);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads the next byte of the input.
+pub(crate) fn read_byte(input: &[u8], input_pos: &mut usize, closed: bool) -> Read {
+    if let Some(byte) = input.get(*input_pos) {
+        *input_pos += 1;
+        Read::Value(*byte)
+    } else if closed {
+        Read::Eof
+    } else {
+        Read::Pending
+    }
+}
+
+/// Parses the next whitespace-delimited integer from the input, wrapping it
+/// into the range of a cell like the generated code does, so `-1` is the byte
+/// 255 and `300` is 44. A number for a byte has to fit in an `i64`, and one at
+/// the very end of the input is only complete once the input is closed.
+pub(crate) fn read_number<C: Cell>(
+    input: &[u8],
+    input_pos: &mut usize,
+    closed: bool,
+) -> Result<Read<C>> {
+    let mut start = *input_pos;
+
+    while input
+        .get(start)
+        .is_some_and(|byte| byte.is_ascii_whitespace())
+    {
+        start += 1;
+    }
+
+    let mut end = start;
+
+    while input
+        .get(end)
+        .is_some_and(|byte| !byte.is_ascii_whitespace())
+    {
+        end += 1;
+    }
+
+    if end == input.len() && !closed {
+        return Ok(Read::Pending);
+    }
+
+    *input_pos = end;
+
+    if start == end {
+        return Ok(Read::Eof);
+    }
+
+    let token = String::from_utf8_lossy(&input[start..end]);
+    let value = C::parse(&token).ok_or_else(|| format!("invalid numeric input `{}`", token))?;
+
+    Ok(Read::Value(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `writes` one after the other, returning the text and what is
+    /// still pending.
+    fn decoded(writes: &[&[u8]]) -> (String, Vec<u8>) {
+        let mut pending = Vec::new();
+        let mut output = String::new();
+        for write in writes {
+            pending.extend_from_slice(write);
+            decode_utf8(&mut pending, &mut output);
+        }
+        (output, pending)
+    }
+
+    #[test]
+    fn a_sequence_split_across_writes_waits_for_the_rest() {
+        assert_eq!(decoded(&[b"a\xc3"]), ("a".to_string(), vec![0xc3]));
+        assert_eq!(
+            decoded(&[b"a\xc3", b"\xa9"]),
+            ("a\u{e9}".to_string(), vec![])
+        );
+        assert_eq!(
+            decoded(&[b"\xf0\x9f", b"\xa6", b"\x80!"]),
+            ("\u{1f980}!".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn invalid_bytes_are_replaced_and_decoding_goes_on() {
+        assert_eq!(decoded(&[b"a\xffb"]), ("a\u{fffd}b".to_string(), vec![]));
+        // a sequence cut short by a byte that cannot continue it
+        assert_eq!(
+            decoded(&[b"\xe2\x82", b"a"]),
+            ("\u{fffd}a".to_string(), vec![])
+        );
+        assert_eq!(
+            decoded(&[b"\x80\x80"]),
+            ("\u{fffd}\u{fffd}".to_string(), vec![])
+        );
+    }
+
+    /// Reads every number of `input`, closed, returning the values and the
+    /// position after each one.
+    fn numbers(input: &str) -> Vec<(Read, usize)> {
+        let mut input_pos = 0;
+        let mut reads = Vec::new();
+        loop {
+            let read = read_number(input.as_bytes(), &mut input_pos, true).unwrap();
+            let eof = read == Read::Eof;
+            reads.push((read, input_pos));
+            if eof {
+                return reads;
+            }
+        }
+    }
+
+    #[test]
+    fn numbers_are_separated_by_any_whitespace() {
+        assert_eq!(
+            numbers(" 1\t22\n\n+3 "),
+            [
+                (Read::Value(1), 2),
+                (Read::Value(22), 5),
+                (Read::Value(3), 9),
+                (Read::Eof, 10),
+            ]
+        );
+        assert_eq!(numbers(""), [(Read::Eof, 0)]);
+    }
+
+    #[test]
+    fn numbers_wrap_into_a_cell() {
+        let values: Vec<Read> = numbers("255 256 300 -1 -256 -257 9223372036854775807")
+            .into_iter()
+            .map(|(read, _)| read)
+            .collect();
+        assert_eq!(
+            values,
+            [
+                Read::Value(255),
+                Read::Value(0),
+                Read::Value(44),
+                Read::Value(255),
+                Read::Value(0),
+                Read::Value(255),
+                Read::Value(255),
+                Read::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn big_integers_are_read_whole() {
+        let mut input_pos = 0;
+        let input = b"-1 99999999999999999999 x";
+        let mut read = || read_number::<num_bigint::BigInt>(input, &mut input_pos, true);
+        assert_eq!(read().unwrap(), Read::Value((-1).into()));
+        assert_eq!(
+            read().unwrap(),
+            Read::Value("99999999999999999999".parse().unwrap())
+        );
+        assert!(read()
+            .unwrap_err()
+            .to_string()
+            .contains("invalid numeric input"));
+    }
+
+    #[test]
+    fn what_is_not_an_i64_is_an_error() {
+        for input in ["abc", "1.5", "0x10", "9223372036854775808", "\u{e9}"] {
+            let error = read_number::<u8>(input.as_bytes(), &mut 0, true).unwrap_err();
+            assert!(
+                error.to_string().contains("invalid numeric input"),
+                "{}: {}",
+                input,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn a_number_at_the_end_of_open_input_may_not_be_complete_yet() {
+        let mut input_pos = 0;
+        assert_eq!(
+            read_number::<u8>(b" 12", &mut input_pos, false).unwrap(),
+            Read::Pending
+        );
+        assert_eq!(input_pos, 0);
+        assert_eq!(
+            read_number::<u8>(b" 12 ", &mut input_pos, false).unwrap(),
+            Read::Value(12)
+        );
+        assert_eq!(input_pos, 3);
+        assert_eq!(
+            read_number::<u8>(b" 12 ", &mut input_pos, false).unwrap(),
+            Read::Pending
+        );
+        assert_eq!(
+            read_number::<u8>(b" 12 ", &mut input_pos, true).unwrap(),
+            Read::Eof
+        );
+    }
+}
@@ -0,0 +1,47 @@
+#![cfg_attr(feature = "tail-calls", feature(explicit_tail_calls))]
+#![cfg_attr(feature = "tail-calls", allow(incomplete_features))]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+#[macro_use]
+extern crate serde;
+
+pub mod assertion;
+pub mod bisect;
+pub mod brackets;
+pub mod breakpoint;
+pub mod bytecode;
+pub mod cell;
+pub mod checkpoint;
+pub mod expect;
+pub mod fusion;
+pub mod hash;
+pub mod hot;
+pub mod instr;
+pub mod interpreter;
+pub mod memo;
+pub mod metrics;
+pub mod phase;
+pub mod pragma;
+pub mod sandbox;
+pub mod scan;
+pub mod stats;
+pub mod trace;
+pub mod usage;
+
+pub use assertion::Assertion;
+pub use brackets::BracketTable;
+pub use breakpoint::{OutputBreakpoint, OutputPattern};
+pub use bytecode::Dispatch;
+pub use expect::{Expectation, Mismatch};
+pub use fusion::FusionTable;
+pub use hash::{StepHash, StepHasher};
+pub use interpreter::{
+    BrainfuckInterpreter, InputRecord, IoMode, OutputMode, OutputRecord, RuntimeError, StepResult,
+    MEMORY_SIZE,
+};
+pub use phase::{PhaseStats, Phases};
+pub use stats::ExecutionStats;
+pub use trace::{TraceRecord, Tracer};
+pub use usage::MemoryUsage;
+
+pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -0,0 +1,187 @@
+//! `bf lsp`: a language server speaking the Language Server Protocol over
+//! stdin and stdout, so that editors show the diagnostics of `bf lint` while
+//! a program is edited. Documents are synced whole, and each version is
+//! linted again and its diagnostics published. Nothing else is offered.
+
+use std::io::{stdin, stdout, BufRead, Write};
+
+use brainfuck_extended::Result;
+use codegen::lint::{lint, Diagnostic, LintConfig, Severity};
+use serde_json::{json, Value};
+
+/// The error code of a request for a method the server does not have
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Reads the next message, or `None` once the client closed stdin.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+
+    // the headers end with an empty line
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                let value = value.trim();
+                length = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| format!("`{}` is not a content length", value))?,
+                );
+            }
+        }
+    }
+
+    let length = length.ok_or("a message has no Content-Length header")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// The position of a byte offset in the code as LSP counts it: the line, and
+/// the UTF-16 code units before the offset on it.
+fn position(code: &str, offset: usize) -> Value {
+    let before = &code[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+fn to_lsp(code: &str, diagnostic: &Diagnostic) -> Value {
+    // notes are shown as information, since LSP has no notes
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Note | Severity::Off => 3,
+    };
+
+    json!({
+        "range": {
+            "start": position(code, diagnostic.span.start),
+            "end": position(code, diagnostic.span.end),
+        },
+        "severity": severity,
+        "code": diagnostic.rule.name(),
+        "source": "bf lint",
+        "message": diagnostic.message,
+    })
+}
+
+/// Publishes the diagnostics of `code` for the document at `uri`, or clears
+/// them without any code.
+fn publish(
+    output: &mut impl Write,
+    uri: &Value,
+    code: Option<&str>,
+    config: &LintConfig,
+) -> Result {
+    let diagnostics: Vec<Value> = match code {
+        Some(code) => lint(code, config)
+            .iter()
+            .map(|diagnostic| to_lsp(code, diagnostic))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    write_message(
+        output,
+        &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }),
+    )
+}
+
+/// Serves one client on stdin and stdout until it exits, linting with
+/// `config`.
+pub fn serve(config: LintConfig) -> Result {
+    let mut input = stdin().lock();
+    let mut output = stdout().lock();
+    let mut shut_down = false;
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let document = &params["textDocument"];
+
+        let result = match method {
+            "initialize" => json!({
+                // the whole document is sent on every change
+                "capabilities": { "textDocumentSync": 1 },
+                "serverInfo": { "name": "bf lsp" },
+            }),
+            "shutdown" => {
+                shut_down = true;
+                Value::Null
+            }
+            "exit" if shut_down => return Ok(()),
+            "exit" => return Err("the client exited without shutting the server down".into()),
+            "textDocument/didOpen" => {
+                publish(
+                    &mut output,
+                    &document["uri"],
+                    document["text"].as_str(),
+                    &config,
+                )?;
+                continue;
+            }
+            "textDocument/didChange" => {
+                // only the last change matters when each is the whole document
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if text.is_some() {
+                    publish(&mut output, &document["uri"], text, &config)?;
+                }
+                continue;
+            }
+            "textDocument/didClose" => {
+                publish(&mut output, &document["uri"], None, &config)?;
+                continue;
+            }
+            // requests need an answer, but other notifications can be ignored
+            _ if message.get("id").is_some() => {
+                write_message(
+                    &mut output,
+                    &json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("`bf lsp` does not handle {}", method),
+                        },
+                    }),
+                )?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        write_message(
+            &mut output,
+            &json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }),
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,25 +1,982 @@
 use std::{
     fs,
-    io::{stdout, Stdout, Write},
+    io::{self, stderr, stdin, stdout, BufRead, IsTerminal, Write},
     ops::Range,
+    path::{Path, PathBuf},
     process::exit,
-    time::{Duration, SystemTime},
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use ascii::{AsAsciiStr, AsciiChar, ToAsciiChar};
-use crossterm::{
-    cursor,
-    event::{poll, read, Event, KeyCode},
-    execute,
-    terminal::{self, size},
+use ascii::ToAsciiChar;
+use brainfuck_extended::{
+    assertion::parse_assertions,
+    bisect::{self, OptFuel, Outcome as BisectOutcome, Pass},
+    bytecode::{self, Machine, Status},
+    cell::Memory,
+    checkpoint::{DiskCheckpoints, SavedState},
+    fusion, hot,
+    interpreter::decode_utf8,
+    pragma::{self, CellWidth, Effective, Eof, Flags, PragmaPolicy, SemanticsProfile},
+    sandbox::{self, SandboxLimits},
+    trace::{parse_sample, TraceEvent},
+    usage::MEMORY_CHECK_INTERVAL,
+    BracketTable, BrainfuckInterpreter, Dispatch, FusionTable, IoMode, OutputMode, Result,
+    RuntimeError, StepResult, MEMORY_SIZE,
 };
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use codegen::{
+    analysis::{self, analyze},
+    ast::{self, Dialect, File, Repeated, Span},
+    equiv::{self, Run, Verdict},
+    eval::{Evaluator, Outcome},
+    golf,
+    link::{link, Module},
+    lint::{self, lint, Diagnostic, LintConfig, Rule, Severity},
+    mutate::Mutation,
+    random::{random_program, RandomProgram},
+    regions,
+    rng::Rng,
+    synth::{synthesize_print, synthesize_print_bytes, Strategy},
+    verbosity::{self, Verbosity, VerbosityFlags},
+};
+use conformance::{Check, Observed, Runner};
+use debugger::{cleanup_terminal, Alerts, Debugger, ProgramInfo};
+use disasm::DumpAfter;
+use dump::{parse_range, Dump, DumpFormat};
+use engine::View;
+use regex::Regex;
+use script::{parse_script, run_script};
+use stop::{Stop, StopConditions};
+use summary::{ExitReason, Summary};
+use symbolize::Symbolizer;
+use testing::Limits;
+
+mod batch;
+mod compress;
+mod conformance;
+mod debugger;
+mod disasm;
+mod dump;
+mod engine;
+mod explain;
+mod gallery;
+mod history;
+mod html;
+mod lsp;
+mod mutation;
+mod pipe;
+mod remote;
+mod report;
+mod script;
+mod stop;
+mod summary;
+mod symbolize;
+mod testing;
+mod transcript;
+mod watch;
+
+#[macro_use]
+extern crate serde;
+
+#[derive(Debug, Parser)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(required = true)]
+    /// The Brainfuck source code file (or, with `-e`, the program itself)
+    pub code: Option<PathBuf>,
+    #[clap(short = 'e', long)]
+    /// Take the code argument as the program itself instead of the path of a
+    /// file, for one-liners
+    pub eval: bool,
+    #[clap(flatten)]
+    pub run: RunOptions,
+    #[clap(flatten)]
+    pub verbosity: VerbosityFlags,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a program, the same as without a subcommand
+    Run {
+        /// The Brainfuck source code file (or, with `-e`, the program itself)
+        code: PathBuf,
+        #[clap(short = 'e', long)]
+        /// Take the code argument as the program itself instead of the path of
+        /// a file, for one-liners
+        eval: bool,
+        #[clap(flatten)]
+        run: Box<RunOptions>,
+    },
+    /// Browse and run the example programs bundled with the interpreter
+    Examples {
+        #[clap(subcommand)]
+        command: ExamplesCommand,
+    },
+    /// Write structurally mutated copies of a program, for mutation testing
+    Mutate(MutateOptions),
+    /// Print a random program that always halts, for differential testing
+    GenRandom(GenRandomOptions),
+    /// Check whether two programs print the same output for the same input
+    Equiv(EquivOptions),
+    /// Report what can be worked out about a program without running it on any input
+    Analyze {
+        /// The Brainfuck source code file
+        code: PathBuf,
+        #[clap(long, value_name = "STEPS", default_value_t = analysis::DEFAULT_BUDGET)]
+        /// Stop evaluating exact values after this many steps
+        budget: u64,
+        #[clap(long)]
+        /// Instead, report which cells the code of each `@region` touches and
+        /// whether regions touch cells outside of their own
+        regions: bool,
+    },
+    /// Check a program for likely mistakes
+    Lint(LintOptions),
+    /// Serve the diagnostics of `lint` to an editor, as a language server on
+    /// stdin and stdout
+    Lsp {
+        #[clap(long = "rule", value_name = "RULE=SEVERITY", value_parser = parse_rule_severity)]
+        /// Change how serious a rule is, like `lint --rule`
+        rules: Vec<(Rule, Severity)>,
+    },
+    /// Report how short a program gets with each shrinking transform
+    Golf {
+        /// The Brainfuck source code file
+        code: PathBuf,
+        #[clap(long)]
+        /// If the program always prints the same thing, also try a program that just prints it
+        search_print: bool,
+        #[clap(short, long, value_name = "FILE")]
+        /// Write the shortest program found to this file
+        output: Option<PathBuf>,
+    },
+    /// Compare how fast each dispatcher of the bytecode engine runs a program
+    Bench {
+        /// The Brainfuck source code file
+        code: PathBuf,
+        #[clap(long, value_name = "FILE")]
+        /// A file whose contents are used as the program's input
+        input: Option<PathBuf>,
+        #[clap(long, value_name = "OPS", default_value_t = 100_000_000)]
+        /// Stop each run after this many bytecode ops
+        fuel: u64,
+        #[clap(long, value_name = "FILE")]
+        /// Fuse the op sequences listed in this file instead of the default ones
+        fusions: Option<PathBuf>,
+        #[clap(long, value_name = "ITERATIONS", default_value_t = hot::HOT_LOOP_THRESHOLD)]
+        /// After how many iterations to try running the rest of a loop at once,
+        /// 0 to never do that
+        hot_loop_threshold: u32,
+        #[clap(long, conflicts_with = "fusions")]
+        /// Instead of timing the dispatchers, print how often each sequence of
+        /// ops would run as a superinstruction, as a fusion table for `--fusions`
+        profile: bool,
+    },
+    /// Print the bytecode a program compiles to, with offsets, fused ops and
+    /// jump targets
+    Disasm(DisasmOptions),
+    /// Find the first transformation of the bytecode compiler that changes
+    /// what a program does, by binary-searching `--opt-fuel`
+    BisectOpt {
+        /// The Brainfuck source code file
+        code: PathBuf,
+        #[clap(long, value_name = "FILE")]
+        /// A file whose contents are used as the program's input
+        input: Option<PathBuf>,
+        #[clap(long, value_name = "OPS", default_value_t = 100_000_000)]
+        /// Stop each run after this many bytecode ops
+        fuel: u64,
+        #[clap(long, value_name = "FILE")]
+        /// Fuse the op sequences listed in this file instead of the default ones
+        fusions: Option<PathBuf>,
+    },
+    /// Debug a program compiled with `--codegen-style state-machine --debug-server`
+    Attach(AttachOptions),
+    /// Translate the locations in the output of a program compiled with
+    /// `bf-codegen --source-map`, like where it panicked, back to its source
+    Symbolize {
+        /// The `source-map.json` of the generated crate
+        map: PathBuf,
+        /// A file with the output to translate, instead of stdin
+        input: Option<PathBuf>,
+    },
+    /// Print a file written by `--transcript` as a timeline of the input read
+    /// and the output written
+    Transcript {
+        /// The transcript file
+        file: PathBuf,
+    },
+    /// Run the `*_test.b` files and report which pass
+    Test(TestOptions),
+    /// Run a suite of classic edge cases, like what `,` does at EOF, and report
+    /// which of the semantics programs are commonly written for this build
+    /// matches with the given flags
+    Conformance(ConformanceOptions),
+    /// Check that the tests of a program fail for mutated copies of it
+    MutationTest(MutationTestOptions),
+    /// Run a program again whenever its source or input changes
+    Watch(WatchOptions),
+    /// Concatenate programs into one, giving each its own region of the tape
+    Link(LinkOptions),
+    /// Run programs like a shell pipeline, each one's output the next one's
+    /// input, in one process
+    Pipe(PipeOptions),
+    /// Run many programs, each on its own input, in the sandbox, and report
+    /// how each went and what they did together
+    Batch(BatchOptions),
+    /// Print a short program that prints the given text
+    Textgen {
+        #[clap(required_unless_present = "file")]
+        /// The text to print
+        text: Option<String>,
+        #[clap(long, value_name = "FILE", conflicts_with = "text")]
+        /// Print the contents of this file instead
+        file: Option<PathBuf>,
+        #[clap(long, value_enum, default_value_t)]
+        /// How to build the program
+        strategy: Strategy,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct LintOptions {
+    /// The Brainfuck source code file
+    pub code: PathBuf,
+    #[clap(long, value_enum, default_value_t)]
+    /// How to print the diagnostics
+    pub format: LintFormat,
+    #[clap(long = "rule", value_name = "RULE=SEVERITY", value_parser = parse_rule_severity)]
+    /// Change how serious a rule is (off, note, warning or error), can be given more than once
+    pub rules: Vec<(Rule, Severity)>,
+    #[clap(long)]
+    /// Apply the safe fixes to the file, print them as a unified diff and
+    /// report what is left on stderr
+    pub fix: bool,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LintFormat {
+    #[default]
+    /// One diagnostic per line
+    Text,
+    /// A JSON array of diagnostics
+    Json,
+}
+
+fn parse_rule_severity(s: &str) -> std::result::Result<(Rule, Severity), String> {
+    let (rule, severity) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected RULE=SEVERITY, found `{}`", s))?;
+
+    Ok((
+        Rule::from_str(rule, true)?,
+        Severity::from_str(severity, true)?,
+    ))
+}
+
+fn parse_dump_range(s: &str) -> std::result::Result<Range<usize>, String> {
+    parse_range(s).map_err(|e| e.to_string())
+}
+
+fn parse_trace_sample(s: &str) -> std::result::Result<u64, String> {
+    parse_sample(s).map_err(|e| e.to_string())
+}
+
+fn parse_memory_size(s: &str) -> std::result::Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("the tape needs at least one cell".to_string()),
+        Ok(cells) => Ok(cells),
+        Err(_) => Err(format!("`{}` is not a number of cells", s)),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DisasmOptions {
+    /// The Brainfuck source code file
+    pub code: PathBuf,
+    #[clap(long, value_name = "FILE")]
+    /// Fuse the op sequences listed in this file instead of the default ones
+    pub fusions: Option<PathBuf>,
+    #[clap(long, conflicts_with = "fusions")]
+    /// Do not fuse any ops
+    pub no_fusions: bool,
+    #[clap(long, value_name = "COUNT")]
+    /// Apply at most this many transformations while compiling
+    pub opt_fuel: Option<u64>,
+    #[clap(long, value_name = "PASS|all")]
+    /// Also write the bytecode after this pass (parse, merge, clear, copy,
+    /// scan or fuse), or after every one, to numbered files like
+    /// `prog.03-clear.ir`
+    pub dump_ir_after: Option<DumpAfter>,
+    #[clap(long, value_name = "DIR", default_value = ".")]
+    /// Where `--dump-ir-after` writes its files
+    pub dump_ir_dir: PathBuf,
+    #[clap(long)]
+    /// Instead of the bytecode, print what each pass changed
+    pub dump_ir_diff: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct EquivOptions {
+    /// The first Brainfuck source code file
+    pub a: PathBuf,
+    /// The second Brainfuck source code file
+    pub b: PathBuf,
+    #[clap(long, value_name = "FILE")]
+    /// Compare the programs on the contents of this file (can be given more than once)
+    pub input: Vec<PathBuf>,
+    #[clap(long, value_name = "TEXT")]
+    /// Compare the programs on this text (can be given more than once)
+    pub input_text: Vec<String>,
+    #[clap(long, value_name = "COUNT", default_value_t = 32)]
+    /// Also compare the programs on this many random inputs
+    pub random_inputs: usize,
+    #[clap(long, value_name = "BYTES", default_value_t = 16)]
+    /// The maximum length of the random inputs
+    pub max_input_length: usize,
+    #[clap(long, default_value_t = 0)]
+    /// The seed for the random inputs
+    pub seed: u64,
+    #[clap(long, value_name = "STEPS", default_value_t = 10_000_000)]
+    /// Give up on a run after this many steps
+    pub max_steps: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct GenRandomOptions {
+    #[clap(long, default_value_t = 100)]
+    /// Roughly how many instructions to generate
+    pub ops: usize,
+    #[clap(long, default_value_t = 0)]
+    /// The seed for the program, so it can be generated again
+    pub seed: u64,
+    #[clap(long, default_value_t = 3)]
+    /// How deeply loops can be nested
+    pub max_depth: usize,
+    #[clap(long)]
+    /// Do not read input with `,`
+    pub no_input: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AttachOptions {
+    /// The address the program listens on, as given in `BF_DEBUG_ADDR`
+    pub address: String,
+    /// The Brainfuck source code file the program was compiled from
+    pub code: PathBuf,
+    #[clap(long, value_enum, default_value_t)]
+    /// How the bytes written by `.` are turned into output text
+    pub output_mode: OutputMode,
+    #[clap(
+        long,
+        value_name = "SUBSTRING",
+        conflicts_with = "break_on_output_regex"
+    )]
+    /// Pause as soon as the output contains this string
+    pub break_on_output: Option<String>,
+    #[clap(long, value_name = "REGEX")]
+    /// Pause as soon as the output matches this regex
+    pub break_on_output_regex: Option<Regex>,
+    #[clap(long, value_name = "FILE")]
+    /// Compare the output against this file, pausing at the first difference
+    pub expect: Option<PathBuf>,
+    #[clap(long)]
+    /// Start the debugger with the pane explaining each instruction open
+    pub explain: bool,
+    #[clap(long)]
+    /// Start the debugger with the pane graphing the cell and pointer history open
+    pub history: bool,
+    #[clap(long)]
+    /// Do not color the debugger's code pane
+    pub no_color: bool,
+    #[clap(long)]
+    /// Ring the terminal bell when a breakpoint or `--expect` pauses the debugger
+    pub bell: bool,
+    #[clap(long, value_name = "COMMAND")]
+    /// Run this shell command whenever a breakpoint or `--expect` pauses the
+    /// debugger or the program halts, e.g. to send a desktop notification
+    pub notify_cmd: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct TestOptions {
+    #[clap(default_value = ".")]
+    /// The test files, or directories to search for `*_test.b` and `*_test.bf` files
+    pub paths: Vec<PathBuf>,
+    #[clap(long, value_name = "STEPS", default_value_t = 10_000_000)]
+    /// Fail a test that does not halt within this many steps
+    pub max_steps: u64,
+    #[clap(long, value_name = "SECONDS", default_value_t = 10.0)]
+    /// Fail a test that does not halt within this many seconds
+    pub timeout: f64,
+}
+
+#[derive(Debug, Args)]
+pub struct ConformanceOptions {
+    #[clap(long, value_enum)]
+    /// What happens when the pointer moves off either end of the tape
+    /// [default: clamp]
+    pub pointer: Option<PointerMode>,
+    #[clap(long, value_name = "unchanged|VALUE")]
+    /// What `,` stores at the end of the input [default: unchanged]
+    pub eof: Option<Eof>,
+    #[clap(long, value_enum, visible_alias = "semantics-profile")]
+    /// Run the suite with the semantics of a well-known interpreter, for
+    /// everything `--pointer` and `--eof` do not say otherwise
+    pub profile: Option<SemanticsProfile>,
+}
+
+#[derive(Debug, Args)]
+pub struct MutationTestOptions {
+    /// The Brainfuck source code file to mutate
+    pub program: PathBuf,
+    #[clap(default_value = ".")]
+    /// Where to search for its tests, which name it with `@program`
+    pub paths: Vec<PathBuf>,
+    #[clap(short = 'n', long, default_value_t = 20)]
+    /// How many mutants to test
+    pub count: usize,
+    #[clap(long, default_value_t = 0)]
+    /// The seed for choosing mutations, so runs can be reproduced
+    pub seed: u64,
+    #[clap(long = "mutation", value_enum, value_name = "MUTATION")]
+    /// Only apply this kind of mutation (can be given more than once). Defaults to all of them
+    pub mutations: Vec<Mutation>,
+    #[clap(long, value_name = "STEPS", default_value_t = 10_000_000)]
+    /// Fail a test that does not halt within this many steps
+    pub max_steps: u64,
+    #[clap(long, value_name = "SECONDS", default_value_t = 10.0)]
+    /// Fail a test that does not halt within this many seconds
+    pub timeout: f64,
+}
+
+#[derive(Debug, Args)]
+pub struct LinkOptions {
+    #[clap(required = true, value_name = "MODULE[@OFFSET]", value_parser = parse_module)]
+    /// The Brainfuck source code files to link in order, each followed by the
+    /// cell its region of the tape starts at (0 by default)
+    pub modules: Vec<(PathBuf, usize)>,
+    #[clap(short, long, value_name = "FILE")]
+    /// Write the linked program to this file instead of stdout
+    pub output: Option<PathBuf>,
+    #[clap(long)]
+    /// Move the pointer back to cell 0 after each module, so the offsets are
+    /// cells rather than relative to where the previous module left the pointer
+    pub glue: bool,
+}
 
-const MEMORY_SIZE: usize = 30_000;
-const MAX_POINTER: usize = MEMORY_SIZE - 1;
-const WRAPPING: bool = false;
-const DEBUG: bool = true;
+fn parse_module(s: &str) -> std::result::Result<(PathBuf, usize), String> {
+    match s.rsplit_once('@') {
+        Some((path, offset)) => {
+            let offset = offset
+                .parse()
+                .map_err(|_| format!("`{}` is not a cell to start the module at", offset))?;
+            Ok((PathBuf::from(path), offset))
+        }
+        None => Ok((PathBuf::from(s), 0)),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PipeOptions {
+    #[clap(required = true)]
+    /// The Brainfuck source code files, in the order the data flows through them
+    pub programs: Vec<PathBuf>,
+    #[clap(short, long, value_name = "FILE")]
+    /// A file whose contents are the first program's input. Without it, input
+    /// is read from stdin
+    pub input: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t = Engine::Auto)]
+    /// Which bytecode engine runs the programs
+    pub engine: Engine,
+    #[clap(long, value_name = "BYTES", default_value_t = 64 * 1024)]
+    /// Pause a program while this many bytes of its output wait for the next
+    /// one to read them, counted in chunks of 4096 bytes, which a line of
+    /// output takes a whole one of
+    pub buffer: usize,
+    #[clap(long, value_name = "ITERATIONS", default_value_t = hot::HOT_LOOP_THRESHOLD)]
+    /// After how many iterations the bytecode engine tries to run the rest of
+    /// a loop at once, 0 to never do that
+    pub hot_loop_threshold: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct BatchOptions {
+    /// A file of one JSON object per line, each a program to run like the
+    /// body of bf-server's `POST /run`: `{"code": "...", "input": "..."}`,
+    /// `input` being optional. Without it, the programs are read from stdin
+    pub jobs: Option<PathBuf>,
+    #[clap(long, value_name = "FILE")]
+    /// Write the metrics of the runs to this file in the Prometheus text
+    /// format, the same ones as bf-server's `GET /metrics`
+    pub metrics: Option<PathBuf>,
+    #[clap(long, value_name = "STEPS")]
+    /// The most instructions each program may execute, instead of the
+    /// sandbox's default
+    pub max_steps: Option<u64>,
+    #[clap(long, value_name = "MS")]
+    /// How long each program may take, instead of the sandbox's default
+    pub timeout_ms: Option<u64>,
+    #[clap(long, value_name = "BYTES")]
+    /// How much output each program may write, instead of the sandbox's
+    /// default
+    pub max_output: Option<usize>,
+    #[clap(long, value_enum, default_value_t)]
+    /// How `.` and `,` interpret cell values
+    pub io: IoMode,
+}
 
-pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
+#[derive(Debug, Args)]
+pub struct WatchOptions {
+    /// The Brainfuck source code file
+    pub program: PathBuf,
+    /// A file whose contents are used as the program's input. Without it,
+    /// input is read from stdin
+    pub input: Option<PathBuf>,
+    #[clap(long, value_name = "FILE")]
+    /// Also run again when this file changes, like a config file the `--cmd`
+    /// reads (can be given more than once)
+    pub also: Vec<PathBuf>,
+    #[clap(long, value_enum, default_value_t = Engine::Auto)]
+    /// Which bytecode engine runs the program
+    pub engine: Engine,
+    #[clap(long, value_name = "COMMAND")]
+    /// Run this shell command instead of the program, e.g. to generate and
+    /// build a crate from it. It gets `BF_PROGRAM` and `BF_INPUT` in its environment
+    pub cmd: Option<String>,
+    #[clap(long)]
+    /// Clear the terminal before each run
+    pub clear: bool,
+    #[clap(long)]
+    /// After each run, show how its output differs from the previous run's
+    pub diff: bool,
+    #[clap(long, value_name = "START..END", value_parser = parse_dump_range, conflicts_with = "cmd")]
+    /// Also show how the cells in this range differ from the previous run's
+    /// once the program stops
+    pub diff_tape: Option<Range<usize>>,
+    #[clap(long)]
+    /// Do not color the diffs
+    pub no_color: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MutateOptions {
+    /// The Brainfuck source code file
+    pub code: PathBuf,
+    #[clap(short = 'n', long, default_value_t = 10)]
+    /// How many mutants to produce
+    pub count: usize,
+    #[clap(long, default_value_t = 0)]
+    /// The seed for choosing mutations, so runs can be reproduced
+    pub seed: u64,
+    #[clap(long = "mutation", value_enum, value_name = "MUTATION")]
+    /// Only apply this kind of mutation (can be given more than once). Defaults to all of them
+    pub mutations: Vec<Mutation>,
+    #[clap(long, value_name = "STEPS")]
+    /// Only keep mutants that still halt within this many steps
+    pub halts_within: Option<u64>,
+    #[clap(long, value_name = "FILE")]
+    /// The input to run mutants with for `--halts-within`
+    pub input: Option<PathBuf>,
+    #[clap(long, value_name = "DIR")]
+    /// Write each mutant to `DIR/mutant-<n>.b` instead of printing one per line
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExamplesCommand {
+    /// List the bundled examples
+    List,
+    /// Print the source code of an example
+    Show { name: String },
+    /// Run an example, taking the same options as running a file
+    Run {
+        name: String,
+        #[clap(flatten)]
+        run: Box<RunOptions>,
+    },
+}
+
+// everything that controls how a program is run, shared by the subcommands that run one
+#[derive(Debug, Args)]
+pub struct RunOptions {
+    /// A file whose contents are used as the program's input. Without it,
+    /// input is requested whenever the program runs out of it
+    pub input: Option<PathBuf>,
+    #[clap(long, value_name = "VAR", conflicts_with = "input")]
+    /// Use the contents of this environment variable as the program's input,
+    /// for when giving it stdin is awkward
+    pub input_env: Option<String>,
+    #[clap(long, value_name = "TEXT", conflicts_with_all = ["input", "input_env"])]
+    /// Use this text as the program's input, like the code generator's
+    /// `--fixed-input`, for quick experiments with `-e`
+    pub fixed_input: Option<String>,
+    #[clap(
+        long,
+        value_name = "CELL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "current"
+    )]
+    /// Exit with the value this cell (`current` for the one under the pointer,
+    /// the default) has once the program halts. The cell goes after an `=`, so
+    /// the argument after the flag is never taken for it
+    pub exit_from_cell: Option<ExitCell>,
+    #[clap(long, value_enum, default_value_t)]
+    /// How the bytes written by `.` are turned into output text
+    pub output_mode: OutputMode,
+    #[clap(long, value_enum, default_value_t)]
+    /// How `.` and `,` interpret cell values
+    pub io: IoMode,
+    #[clap(long, value_enum)]
+    /// What happens when the pointer moves off either end of the tape. `wrap`
+    /// is like `@pragma wrap-pointer` [default: clamp]
+    pub pointer: Option<PointerMode>,
+    #[clap(long, conflicts_with = "pointer")]
+    /// Wrap the pointer around the ends of the tape, the same as `--pointer wrap`
+    pub wrap_pointer: bool,
+    #[clap(
+        long,
+        value_name = "CELLS",
+        default_value_t = MEMORY_SIZE,
+        value_parser = parse_memory_size
+    )]
+    /// How many cells the tape has
+    pub memory_size: usize,
+    #[clap(long, conflicts_with = "engine")]
+    /// Run the step-by-step interpreter headless instead of in the debugger,
+    /// printing the output as it goes and reading input from stdin
+    pub no_debugger: bool,
+    #[clap(long, value_name = "unchanged|VALUE")]
+    /// What `,` stores at the end of the input, like `@pragma eof` [default: unchanged]
+    pub eof: Option<Eof>,
+    #[clap(long, value_enum, visible_alias = "semantics-profile")]
+    /// Run with the semantics of a well-known interpreter, for everything
+    /// `--pointer` and `--eof` do not say otherwise
+    pub profile: Option<SemanticsProfile>,
+    #[clap(long, value_enum, default_value_t)]
+    /// Whether the `@pragma` directives of the program or the flags above win
+    /// where they disagree, or whether that is an error
+    pub pragma_policy: PragmaPolicy,
+    #[clap(
+        long,
+        value_name = "SUBSTRING",
+        conflicts_with = "break_on_output_regex"
+    )]
+    /// Stop (or pause the debugger) as soon as the output contains this string
+    pub break_on_output: Option<String>,
+    #[clap(long, value_name = "REGEX")]
+    /// Stop (or pause the debugger) as soon as the output matches this regex
+    pub break_on_output_regex: Option<Regex>,
+    #[clap(long, value_name = "FILE")]
+    /// Compare the output against this file as it is produced, pausing the
+    /// debugger (or aborting a headless run) at the first difference
+    pub expect: Option<PathBuf>,
+    #[clap(long, value_name = "FILE")]
+    /// Write every output byte and the step that produced it to this JSONL file
+    pub output_timestamps: Option<PathBuf>,
+    #[clap(long, value_name = "FILE")]
+    /// Write the input the program read and the output it wrote, in the order
+    /// it did, with their steps, to this JSONL file (see `bf transcript`)
+    pub transcript: Option<PathBuf>,
+    #[clap(long, value_name = "FILE")]
+    /// Write the rolling hash of the pointer and written cells, published every
+    /// `--hash-interval` steps, to this JSONL file
+    pub step_hashes: Option<PathBuf>,
+    #[clap(long, value_name = "STEPS", default_value_t = 10_000)]
+    /// How often the step hash is published (and shown in the debugger)
+    pub hash_interval: u64,
+    #[clap(long, value_name = "FILE")]
+    /// Write every `--trace-sample`th step, and a phase marker whenever a `#`
+    /// or an `@phase <name>` comment runs, to this JSONL file
+    pub trace: Option<PathBuf>,
+    #[clap(long, value_name = "1/N", default_value = "1/10000", value_parser = parse_trace_sample)]
+    /// Which steps `--trace` records
+    pub trace_sample: u64,
+    #[clap(long, value_name = "STEPS", default_value_t = 1_000_000)]
+    /// How often the debugger saves the full state, so it can rewind to just
+    /// before a breakpoint by replaying at most this many steps
+    pub checkpoint_interval: u64,
+    #[clap(long, value_name = "STEPS")]
+    /// Write the full state to `--checkpoint-file` every this many steps, so a
+    /// long run can be continued with `--resume` after a crash
+    pub checkpoint_every: Option<u64>,
+    #[clap(long, value_name = "FILE", requires = "checkpoint_every")]
+    /// Where `--checkpoint-every` writes, with older checkpoints moved to
+    /// `FILE.1`, `FILE.2` and so on. Defaults to `<program>.checkpoint`
+    pub checkpoint_file: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value_t = 3,
+        requires = "checkpoint_every"
+    )]
+    /// How many checkpoints `--checkpoint-every` keeps on disk
+    pub checkpoint_keep: usize,
+    #[clap(long, value_name = "FILE")]
+    /// Continue from a checkpoint written by `--checkpoint-every`, with the
+    /// input and output it saved
+    pub resume: Option<PathBuf>,
+    #[clap(long, value_name = "MB")]
+    /// Keep the interpreter's own memory (tape, output, checkpoints and
+    /// recorded history) under this many megabytes, dropping the oldest
+    /// history first and stopping only if that is not enough
+    pub max_rss: Option<usize>,
+    #[clap(long)]
+    /// Print how many of each instruction ran, how often loops repeated, the
+    /// bytes read and written and the highest cell reached once the program stops
+    pub stats: bool,
+    #[clap(long, value_name = "FILE")]
+    /// Write why the run ended, the stats, any warnings, the time and memory
+    /// it took and a hash of the output to this JSON file
+    pub summary_json: Option<PathBuf>,
+    #[clap(long, value_name = "FILE")]
+    /// Write a standalone HTML report of the run to this file: the source
+    /// colored by how often each instruction ran, the output and the stats
+    pub export_html: Option<PathBuf>,
+    #[clap(long, requires = "export_html")]
+    /// Add a player to the `--export-html` report that steps through the
+    /// steps `--trace-sample` picks
+    pub html_replay: bool,
+    #[clap(long, value_name = "START..END", value_parser = parse_dump_range)]
+    /// Once the program stops, write the cells in this range (`..=` includes
+    /// the end) to stdout, or to `--dump-file`
+    pub dump_range: Option<Range<usize>>,
+    #[clap(long, value_name = "FILE", requires = "dump_range")]
+    /// Write the cells of `--dump-range` to this file instead
+    pub dump_file: Option<PathBuf>,
+    #[clap(long, value_enum, requires = "dump_range")]
+    /// How to write the cells of `--dump-range`. Defaults to raw bytes in a
+    /// file and hex on stdout
+    pub dump_format: Option<DumpFormat>,
+    #[clap(long, value_name = "FILE")]
+    /// Run the debugger commands in this file (`break`, `step`, `continue`,
+    /// `dump`, ...) instead of the interactive debugger
+    pub debug_script: Option<PathBuf>,
+    #[clap(long)]
+    /// Start the debugger with the pane explaining each instruction open
+    pub explain: bool,
+    #[clap(long)]
+    /// Start the debugger with the pane graphing the cell and pointer history open
+    pub history: bool,
+    #[clap(long)]
+    /// Do not color the debugger's code pane
+    pub no_color: bool,
+    #[clap(long)]
+    /// Ring the terminal bell when a breakpoint or `--expect` pauses the debugger
+    pub bell: bool,
+    #[clap(long, value_name = "COMMAND")]
+    /// Run this shell command whenever a breakpoint or `--expect` pauses the
+    /// debugger or the program halts, e.g. to send a desktop notification
+    pub notify_cmd: Option<String>,
+    #[clap(long)]
+    /// Print the warnings of `bf lint` before running the program
+    pub lint: bool,
+    #[clap(long)]
+    /// Check the `@assert cell[5] == 72` and `@assert-end ...` directives in
+    /// the program's comments, failing the run at the first one that does not hold
+    pub assertions: bool,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["engine", "memoize", "sandbox"]
+    )]
+    /// Which instructions the program is made of. Only `standard` runs here:
+    /// `float-ext` needs floating-point cells, which only bf-codegen has
+    pub dialect: Dialect,
+    #[clap(long, conflicts_with_all = ["step_hashes", "trace", "export_html"])]
+    /// Record the runs of loops that end where they started and do no I/O,
+    /// and replay them at once whenever they start from the same cells again
+    /// (experimental)
+    pub memoize: bool,
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "engine",
+            "assertions",
+            "memoize",
+            "fusions",
+            "opt_fuel",
+            "dump_range",
+            "pointer",
+            "eof",
+            "profile",
+            "exit_from_cell",
+            "memory_size",
+            "wrap_pointer",
+            "no_debugger",
+        ]
+    )]
+    /// Run the program with the limits the library's `sandbox` module puts on
+    /// untrusted programs, printing why it failed as JSON to stderr
+    pub sandbox: bool,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = [
+            "break_on_output",
+            "break_on_output_regex",
+            "expect",
+            "output_timestamps",
+            "transcript",
+            "step_hashes",
+            "trace",
+            "max_rss",
+            "stats",
+            "summary_json",
+            "export_html",
+            "debug_script",
+            "checkpoint_every",
+            "resume",
+            "sandbox",
+            "memoize",
+        ]
+    )]
+    /// What runs the program. Anything but `interpreter` runs it headless on
+    /// the bytecode engine, without the debugger
+    pub engine: Engine,
+    #[clap(long, value_name = "FILE")]
+    /// Fuse the op sequences listed in this file (like `bf bench --profile`
+    /// prints) into superinstructions instead of the default ones, also for
+    /// stepping through the ops in the debugger
+    pub fusions: Option<PathBuf>,
+    #[clap(long, value_name = "COUNT")]
+    /// Apply at most this many transformations while compiling for the
+    /// bytecode engine, to find one that breaks the program (see `bf
+    /// bisect-opt`)
+    pub opt_fuel: Option<u64>,
+    #[clap(long, value_name = "ITERATIONS", default_value_t = hot::HOT_LOOP_THRESHOLD)]
+    /// After how many iterations the bytecode engine tries to run the rest of
+    /// a loop at once, 0 to never do that
+    pub hot_loop_threshold: u32,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Engine {
+    #[default]
+    /// The step-by-step interpreter, in the debugger
+    Interpreter,
+    /// The bytecode engine with a `match` on each op
+    Match,
+    /// The bytecode engine with each op resolved to its handler ahead of time
+    Threaded,
+    /// The bytecode engine with handlers that tail-call each other (needs the
+    /// `tail-calls` feature)
+    TailCall,
+    /// The bytecode engine with whichever dispatcher runs the start of the
+    /// program fastest
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// What happens when the pointer moves off either end of the tape
+pub enum PointerMode {
+    /// It stays at the end
+    Clamp,
+    /// It continues from the other end
+    Wrap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The cell whose value `bf` exits with once the program halts
+pub enum ExitCell {
+    /// Whichever cell the pointer ends up on
+    Current,
+    Index(usize),
+}
+
+impl FromStr for ExitCell {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "current" => Ok(Self::Current),
+            _ => s
+                .parse()
+                .map(Self::Index)
+                .map_err(|_| format!("`{}` is not `current` or the index of a cell", s)),
+        }
+    }
+}
+
+impl ExitCell {
+    /// Exits with the value of the cell on `memory`, where the pointer is at
+    /// `pointer`. Of a big integer, only the lowest 8 bits are an exit status.
+    fn exit(self, memory: &Memory, pointer: usize) -> Result {
+        let index = match self {
+            Self::Current => pointer,
+            Self::Index(index) => index,
+        };
+        if index >= memory.len() {
+            return Err(format!(
+                "cannot exit with the value of cell {}, the tape only has {} cells",
+                index,
+                memory.len()
+            )
+            .into());
+        }
+        exit(memory.get(index).low_byte() as i32)
+    }
+}
+
+impl RunOptions {
+    /// The whole input, from the input file, `--input-env` or `--fixed-input`,
+    /// if one was given.
+    fn read_input(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(text) = &self.fixed_input {
+            return Ok(Some(text.clone().into_bytes()));
+        }
+        if let Some(var) = &self.input_env {
+            return match std::env::var_os(var) {
+                Some(value) => Ok(Some(value.into_encoded_bytes())),
+                None => Err(format!("the environment variable `{}` is not set", var).into()),
+            };
+        }
+
+        Ok(match &self.input {
+            Some(path) => Some(compress::read(path)?),
+            None => None,
+        })
+    }
+
+    /// The semantics to run `code` with, from its pragmas and the flags, and a
+    /// warning for everything overridden.
+    fn semantics(&self, code: &str) -> Result<(Effective, Vec<String>)> {
+        let flags = Flags {
+            wrap_pointer: match self.pointer {
+                Some(pointer) => {
+                    let name = pointer.to_possible_value().expect("not skipped");
+                    Some((
+                        pointer == PointerMode::Wrap,
+                        format!("--pointer {}", name.get_name()),
+                    ))
+                }
+                None => self
+                    .wrap_pointer
+                    .then(|| (true, "--wrap-pointer".to_string())),
+            },
+            cell_size: None,
+            eof: self.eof,
+        };
+        let flags = match self.profile {
+            Some(profile) => flags.or_profile(profile, &format!("--profile {}", profile.name())),
+            None => flags,
+        };
+        pragma::resolve(code, &flags, self.pragma_policy)
+    }
+}
 
 fn main() {
     if let Err(e) = main_inner() {
@@ -30,474 +987,1549 @@ fn main() {
 }
 
 fn main_inner() -> Result {
-    let mut args = std::env::args().skip(1);
-    let code_path = args.next().ok_or("no file specified")?;
-    let input_path = args.next();
+    let cli = Cli::parse();
+    verbosity::init(cli.verbosity.level());
+
+    match cli.command {
+        Some(Command::Run { code, eval, run }) => run_program(code, eval, &run),
+        Some(Command::Examples { command }) => examples(command),
+        Some(Command::Mutate(options)) => mutate(options),
+        Some(Command::GenRandom(options)) => {
+            let program = RandomProgram {
+                ops: options.ops,
+                max_depth: options.max_depth,
+                reads: !options.no_input,
+            };
+            println!("{}", random_program(program, &mut Rng::new(options.seed)));
+            Ok(())
+        }
+        Some(Command::Equiv(options)) => equivalence(options),
+        Some(Command::Analyze {
+            code,
+            budget,
+            regions,
+        }) => {
+            let code = fs::read_to_string(code)?;
+            let file: File<Repeated> = code.parse()?;
+            if regions {
+                print_regions(&code, &file);
+            } else {
+                print_analysis(&file, budget);
+            }
+            Ok(())
+        }
+        Some(Command::Lint(options)) => lint_file(options),
+        Some(Command::Lsp { rules }) => lsp::serve(LintConfig { overrides: rules }),
+        Some(Command::Golf {
+            code,
+            search_print,
+            output,
+        }) => golf_file(code, search_print, output),
+        Some(Command::Bench {
+            code,
+            input,
+            fuel,
+            fusions,
+            hot_loop_threshold,
+            profile,
+        }) => bench(code, input, fuel, fusions, hot_loop_threshold, profile),
+        Some(Command::Disasm(options)) => disasm(options),
+        Some(Command::BisectOpt {
+            code,
+            input,
+            fuel,
+            fusions,
+        }) => bisect_opt(code, input, fuel, fusions),
+        Some(Command::Attach(options)) => attach(options),
+        Some(Command::Symbolize { map, input }) => symbolize(map, input),
+        Some(Command::Transcript { file }) => transcript::view(&file),
+        Some(Command::Test(options)) => test(options),
+        Some(Command::Conformance(options)) => conformance_suite(options),
+        Some(Command::MutationTest(options)) => mutation_test(options),
+        Some(Command::Watch(options)) => watch::watch(options),
+        Some(Command::Link(options)) => link_files(options),
+        Some(Command::Pipe(options)) => pipe::pipe(options),
+        Some(Command::Batch(options)) => batch::batch(options),
+        Some(Command::Textgen {
+            text,
+            file,
+            strategy,
+        }) => {
+            let program = match (text, file) {
+                (Some(text), _) => synthesize_print(&text, strategy),
+                (None, Some(file)) => synthesize_print_bytes(&fs::read(file)?, strategy),
+                // clap makes sure one of them is given
+                (None, None) => unreachable!(),
+            };
+            println!("{}", program);
+            Ok(())
+        }
+        None => {
+            // clap makes sure the code is given without a subcommand
+            let path = cli.code.expect("no source code file");
+            run_program(path, cli.eval, &cli.run)
+        }
+    }
+}
 
-    let code = fs::read_to_string(code_path)?;
-    let input = if let Some(input_path) = input_path {
-        Some(fs::read_to_string(input_path)?)
+/// Runs the program in the file at `path`, or `path` itself with `-e`.
+fn run_program(path: PathBuf, eval: bool, options: &RunOptions) -> Result {
+    let (name, code) = if eval {
+        let code = path
+            .into_os_string()
+            .into_string()
+            .map_err(|_| "the program given with `-e` is not UTF-8")?;
+        ("-e".to_string(), code)
     } else {
-        None
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        (name, fs::read_to_string(&path)?)
     };
 
-    let mut i = BrainfuckInterpreter::new(&code, input.as_ref())?;
+    run(name, &code, options)
+}
 
-    let debugger = if DEBUG {
-        ctrlc::set_handler(|| {
-            cleanup_terminal();
-        })?;
+fn examples(command: ExamplesCommand) -> Result {
+    let find = |name: &str| {
+        gallery::find(name).ok_or_else(|| {
+            format!(
+                "there is no example named `{}`, see `bf examples list`",
+                name
+            )
+        })
+    };
+
+    match command {
+        ExamplesCommand::List => {
+            let width = gallery::EXAMPLES
+                .iter()
+                .map(|example| example.name.len())
+                .max()
+                .unwrap_or(0);
+
+            for example in gallery::EXAMPLES {
+                println!("{:width$}  {}", example.name, example.description);
+            }
+        }
+        ExamplesCommand::Show { name } => {
+            print!("{}", find(&name)?.code);
+        }
+        ExamplesCommand::Run { name, run: options } => {
+            let example = find(&name)?;
+            run(example.name.to_string(), example.code, &options)?;
+        }
+    }
 
-        Some(Debugger::new()?)
+    Ok(())
+}
+
+/// Prints (or writes) mutants of a program, describing each one on stderr.
+fn mutate(options: MutateOptions) -> Result {
+    let code = fs::read_to_string(&options.code)?;
+    let file: File<Repeated> = code.parse()?;
+
+    let input = match &options.input {
+        Some(path) => compress::read(path)?,
+        None => Vec::new(),
+    };
+
+    let mutations = if options.mutations.is_empty() {
+        Mutation::ALL.to_vec()
     } else {
-        None
+        options.mutations.clone()
     };
 
-    i.run(debugger)?;
+    if let Some(dir) = &options.output_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut rng = Rng::new(options.seed);
+    let mut found = 0;
+
+    // give up eventually if hardly any mutant halts
+    for _ in 0..options.count * 100 {
+        if found == options.count {
+            break;
+        }
+
+        let mutation = mutations[rng.below(mutations.len())];
+        let Some(mutant) = codegen::mutate::mutate(&file, mutation, &mut rng) else {
+            continue;
+        };
+
+        if let Some(max_steps) = options.halts_within {
+            let mut evaluator = Evaluator::new(input.clone(), max_steps);
+            if evaluator.run(&mutant.file.segments) != Outcome::Halted {
+                continue;
+            }
+        }
+
+        found += 1;
+
+        let description = format!(
+            "mutant {}: {} at line {} column {}",
+            found, mutant.mutation, mutant.span.line, mutant.span.column
+        );
+        let code = ast::to_code(&mutant.file.segments);
+
+        match &options.output_dir {
+            Some(dir) => {
+                let path = dir.join(format!("mutant-{}.b", found));
+                fs::write(&path, format!("{}\n{}\n", description, code))?;
+                eprintln!("{} ({})", description, path.display());
+            }
+            None => {
+                eprintln!("{}", description);
+                println!("{}", code);
+            }
+        }
+    }
+
+    if found < options.count {
+        eprintln!("only found {} of {} mutants", found, options.count);
+    }
 
     Ok(())
 }
 
-fn setup_terminal() {
-    let mut stdout = stdout();
+/// Compares two programs, exiting with 1 if they differ and 2 if that could
+/// not be decided.
+fn equivalence(options: EquivOptions) -> Result {
+    let a: File<Repeated> = fs::read_to_string(&options.a)?.parse()?;
+    let b: File<Repeated> = fs::read_to_string(&options.b)?.parse()?;
 
-    execute!(stdout, terminal::EnterAlternateScreen).unwrap();
-    execute!(stdout, cursor::Hide).unwrap();
+    // the empty input is always worth checking
+    let mut inputs = vec![Vec::new()];
+    for path in &options.input {
+        inputs.push(compress::read(path)?);
+    }
+    inputs.extend(
+        options
+            .input_text
+            .iter()
+            .map(|text| text.as_bytes().to_vec()),
+    );
 
-    // Needed for when ytop is run in a TTY since TTYs don't actually have an alternate screen.
-    // Must be executed after attempting to enter the alternate screen so that it only clears the
-    // 		primary screen if we are running in a TTY.
-    // If not running in a TTY, then we just end up clearing the alternate screen which should have
-    // 		no effect.
-    execute!(stdout, terminal::Clear(terminal::ClearType::All)).unwrap();
+    let mut rng = Rng::new(options.seed);
+    for _ in 0..options.random_inputs {
+        let length = rng.below(options.max_input_length + 1);
+        inputs.push((0..length).map(|_| rng.next_u64() as u8).collect());
+    }
 
-    terminal::enable_raw_mode().unwrap();
+    let describe = |name: &str, run: &Run| {
+        let halted = match run.outcome {
+            Outcome::Halted => "",
+            Outcome::OutOfSteps => " before running out of steps",
+            Outcome::DivisionByZero => " before dividing by 0",
+        };
+        format!(
+            "{} printed \"{}\"{}",
+            name,
+            escape_bytes(&run.output),
+            halted
+        )
+    };
+
+    match equiv::check(&a, &b, &inputs, options.max_steps) {
+        Verdict::Same { inputs, proven } => {
+            if proven {
+                println!("equivalent: neither program reads input, and both print the same output");
+            } else {
+                println!("no difference found on {} inputs", inputs);
+            }
+        }
+        Verdict::Different { input, a, b } => {
+            println!("different on input \"{}\":", escape_bytes(&input));
+            println!("  {}", describe("a", &a));
+            println!("  {}", describe("b", &b));
+            exit(1);
+        }
+        Verdict::Inconclusive { input, a, b } => {
+            println!(
+                "inconclusive: no difference found, but on input \"{}\" a program did not halt:",
+                escape_bytes(&input)
+            );
+            println!("  {}", describe("a", &a));
+            println!("  {}", describe("b", &b));
+            exit(2);
+        }
+    }
+
+    Ok(())
 }
 
-fn cleanup_terminal() {
-    let mut stdout = stdout();
+fn print_analysis(file: &File<Repeated>, budget: u64) {
+    let analysis = analyze(&file.segments, budget);
+    let at = |span: Span| format!("line {} column {}", span.line, span.column);
+
+    match analysis.concrete_until {
+        Some(span) if analysis.budget_exhausted => println!(
+            "concrete prefix: {} steps, until the step budget ran out at {}",
+            analysis.concrete_steps,
+            at(span)
+        ),
+        Some(span) => println!(
+            "concrete prefix: {} steps, until {} depends on the input",
+            analysis.concrete_steps,
+            at(span)
+        ),
+        None => println!(
+            "concrete prefix: the whole program ({} steps) runs without depending on the input",
+            analysis.concrete_steps
+        ),
+    }
+
+    println!(
+        "known output: \"{}\"{}",
+        escape_bytes(&analysis.known_output),
+        if analysis.output_complete {
+            " (complete)"
+        } else {
+            ""
+        }
+    );
+
+    match (analysis.pointer_bounds, analysis.unbounded_loop) {
+        (Some((lowest, highest)), _) => {
+            println!("pointer: always within cells {} to {}", lowest, highest)
+        }
+        (None, Some(span)) => println!(
+            "pointer: unbounded, the loop at {} moves it by a data-dependent amount",
+            at(span)
+        ),
+        (None, None) => println!("pointer: unbounded"),
+    }
 
-    // Needed for when ytop is run in a TTY since TTYs don't actually have an alternate screen.
-    // Must be executed before attempting to leave the alternate screen so that it only modifies the
-    // 		primary screen if we are running in a TTY.
-    // If not running in a TTY, then we just end up modifying the alternate screen which should have
-    // 		no effect.
-    execute!(stdout, cursor::MoveTo(0, 0)).unwrap();
-    execute!(stdout, terminal::Clear(terminal::ClearType::All)).unwrap();
+    println!("dead loops: {}", analysis.dead_loops.len());
+    for span in analysis.dead_loops {
+        println!("  {}", at(span));
+    }
+}
+
+fn print_regions(code: &str, file: &File<Repeated>) {
+    let report = regions::check(code, &file.segments);
+    let at = |span: Span| format!("line {} column {}", span.line, span.column);
+
+    println!("regions: {}", report.regions.len());
+    for usage in &report.regions {
+        let declared = match &usage.region.cells {
+            Some(cells) => format!("declares cells {}:{}", cells.start, cells.end),
+            None => "declares no cells".to_string(),
+        };
+
+        // the touched cells as runs like `0:3`
+        let mut runs: Vec<(isize, isize)> = Vec::new();
+        for cell in usage.touched.keys() {
+            match runs.last_mut() {
+                Some((_, end)) if *end == *cell => *end += 1,
+                _ => runs.push((*cell, cell + 1)),
+            }
+        }
+        let touched = if runs.is_empty() {
+            "touches no cells".to_string()
+        } else {
+            let runs: Vec<String> = runs
+                .iter()
+                .map(|(start, end)| format!("{}:{}", start, end))
+                .collect();
+            format!("touches cells {}", runs.join(", "))
+        };
+
+        println!(
+            "  {} (line {}): {}, {}",
+            usage.region.name, usage.region.span.line, declared, touched
+        );
+    }
 
-    execute!(stdout, terminal::LeaveAlternateScreen).unwrap();
-    execute!(stdout, cursor::Show).unwrap();
+    if let Some(span) = report.lost_at {
+        println!(
+            "pointer: lost at the loop at {}, which moves it by a data-dependent amount, so the code after it is not checked",
+            at(span)
+        );
+    }
 
-    terminal::disable_raw_mode().unwrap();
+    println!("warnings: {}", report.warnings.len());
+    for warning in &report.warnings {
+        println!("  {}: {}", at(warning.span), warning.problem);
+    }
 }
 
-struct BrainfuckInterpreter {
-    pub memory: [u8; MEMORY_SIZE],
-    pub pointer: usize,
-    pub loop_stack: Vec<usize>,
-    pub input: Vec<AsciiChar>,
-    pub input_pos: usize,
-    pub code: Vec<char>,
-    pub code_pos: usize,
+fn lint_file(options: LintOptions) -> Result {
+    let path = options.code.to_string_lossy();
+    let mut code = fs::read_to_string(&options.code)?;
+    let config = LintConfig {
+        overrides: options.rules,
+    };
+
+    if options.fix {
+        let fixed = lint::fix(&code, &config);
+        if fixed != code {
+            fs::write(&options.code, &fixed)?;
+            print!("{}", lint::diff(&path, &code, &fixed));
+            code = fixed;
+        }
+    }
+
+    let diagnostics = lint(&code, &config);
+    let print = |line: String| {
+        // the diff is on stdout
+        if options.fix {
+            eprintln!("{}", line)
+        } else {
+            println!("{}", line)
+        }
+    };
+
+    match options.format {
+        LintFormat::Text => {
+            for diagnostic in &diagnostics {
+                print(format_diagnostic(&path, diagnostic));
+            }
+        }
+        LintFormat::Json => {
+            let objects: Vec<_> = diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    format!(
+                        r#"{{"rule":"{}","severity":"{}","line":{},"column":{},"start":{},"end":{},"message":"{}"}}"#,
+                        diagnostic.rule.name(),
+                        diagnostic.severity,
+                        diagnostic.span.line,
+                        diagnostic.span.column,
+                        diagnostic.span.start,
+                        diagnostic.span.end,
+                        diagnostic.message.replace('\\', "\\\\").replace('"', "\\\"")
+                    )
+                })
+                .collect();
+            print(format!("[{}]", objects.join(",")));
+        }
+    }
+
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        exit(1);
+    }
 
-    pub output: String,
+    Ok(())
 }
 
-struct Debugger {
-    stdout: Stdout,
-    paused: bool,
-    size: (u16, u16),
+fn golf_file(path: PathBuf, search_print: bool, output: Option<PathBuf>) -> Result {
+    let code = fs::read_to_string(path)?;
 
-    op_counter: usize,
-    last_op_reset: SystemTime,
-    last_ops_per_second: usize,
+    println!("original: {}", code.len());
 
-    memory_range: Range<usize>,
+    let steps = golf::golf(&code);
+    for step in &steps {
+        println!("{}: {}", step.transform, step.code.len());
+    }
+
+    let mut shortest = steps.last().map_or(code.clone(), |step| step.code.clone());
 
-    update_frequency: usize,
-    update_counter: usize,
+    if search_print {
+        match golf::search_print(&code) {
+            Some(program) => {
+                println!("search-print: {}", program.len());
+                if program.len() < shortest.len() {
+                    shortest = program;
+                }
+            }
+            None => {
+                println!("search-print: the output depends on the input or could not be evaluated")
+            }
+        }
+    }
+
+    if let Some(output) = output {
+        fs::write(output, shortest)?;
+    }
+
+    Ok(())
 }
 
-impl Drop for Debugger {
-    fn drop(&mut self) {
-        cleanup_terminal();
+fn link_files(options: LinkOptions) -> Result {
+    let modules = options
+        .modules
+        .iter()
+        .map(|(path, offset)| {
+            Ok(Module {
+                name: path.display().to_string(),
+                code: fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+                offset: *offset,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let linked = link(&modules, options.glue)?;
+    match options.output {
+        Some(path) => fs::write(path, linked)?,
+        None => print!("{}", linked),
     }
+
+    Ok(())
 }
 
-enum DebugCommand {
-    Quit,
-    Step,
-    // Continue,
-    // Pause,
+fn format_diagnostic(path: &str, diagnostic: &Diagnostic) -> String {
+    format!(
+        "{}:{}:{}: {}: {} [{}]",
+        path,
+        diagnostic.span.line,
+        diagnostic.span.column,
+        diagnostic.severity,
+        diagnostic.message,
+        diagnostic.rule.name()
+    )
 }
 
-struct Bounds {
-    pub start: usize,
-    pub end: usize,
-    pub rel: u16,
+fn escape_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|byte| std::ascii::escape_default(*byte))
+        .map(char::from)
+        .collect()
 }
 
-impl Debugger {
-    pub fn new() -> Result<Self> {
-        setup_terminal();
+/// Runs a program in the debugger, or headless if it is disabled.
+fn run(name: String, code: &str, options: &RunOptions) -> Result {
+    if options.dialect == Dialect::FloatExt {
+        // running it on bytes would give different answers than the real thing
+        return Err(
+            "the float-ext dialect needs floating-point cells, which only \
+            `bf-codegen --dialect float-ext` generates, the cells here are bytes"
+                .into(),
+        );
+    }
 
-        let mut stdout = stdout();
-        stdout.flush()?;
+    let mut warnings = Vec::new();
 
-        let size = size()?;
-
-        Ok(Self {
-            stdout,
-            paused: true,
-            size,
-            op_counter: 0,
-            last_op_reset: SystemTime::now(),
-            last_ops_per_second: 0,
-            memory_range: 0..size.0 as usize / 4,
-            update_frequency: 0,
-            update_counter: 0,
-        })
+    if options.lint {
+        for diagnostic in lint(code, &LintConfig::default()) {
+            let warning = format_diagnostic(&name, &diagnostic);
+            eprintln!("{}", warning);
+            warnings.push(warning);
+        }
     }
 
-    /// Calculates the region of the buffer which should be displayed.
-    ///
-    /// `width`: The width of the resulting rendered text (**in characters**)
-    /// `buf_len`: The length of the buffer to be rendered
-    /// `pos`: The position of the cursor in the buffer
-    fn region_bounds(width: u16, buf_len: usize, pos: usize) -> Bounds {
-        let width = width as usize;
+    if options.sandbox {
+        return run_sandboxed(code, options);
+    }
 
-        let buf_start = pos.saturating_sub(width / 2);
-        let buf_end = (buf_start + width / 2).min(buf_len);
+    if options.engine != Engine::Interpreter {
+        return run_bytecode(&name, code, options);
+    }
 
-        let pos_rel = pos - buf_start;
+    if options.opt_fuel.is_some() {
+        return Err("--opt-fuel only applies to the bytecode engine, see --engine".into());
+    }
+    // the debugger steps through the ops with `i`
+    if options.fusions.is_some() && options.no_debugger && options.debug_script.is_none() {
+        return Err(
+            "--fusions only applies to the bytecode engine and the debugger, see --engine".into(),
+        );
+    }
+    let (semantics, overridden) = options.semantics(code)?;
+    for warning in overridden {
+        let warning = format!("{}: {}", name, warning);
+        verbosity::warn(&warning);
+        warnings.push(warning);
+    }
+    verbosity::info(format!("{}: {}", name, semantics));
+
+    let mut i = BrainfuckInterpreter::new(code, options.output_mode, options.io);
+    i.pragmas.wrap_pointer = semantics.wrap_pointer;
+    i.pragmas.eof = semantics.eof;
+    i.pragmas.cell_size = Some(semantics.cell_size);
+    i.resize_memory(options.memory_size);
+    if options.assertions {
+        i.enable_assertions()?;
+    }
+    if options.memoize {
+        i.enable_memoization();
+    }
 
-        Bounds {
-            start: buf_start,
-            end: buf_end,
-            rel: pos_rel as u16,
-        }
+    if let Some(input) = options.read_input()? {
+        i.push_input(&input);
+        i.close_input();
     }
 
-    // fn region_bounds(width: u16, buf_len: usize, pos: usize) -> Bounds {
-    //     let width = width as usize;
+    if let Some(path) = &options.resume {
+        i.restore_state(SavedState::read(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
 
-    //     let buf_start = pos.saturating_sub(width / 2);
+    let mut stop = StopConditions::from_options(options)?;
+    let max_memory = options.max_rss.map(|mb| mb * 1024 * 1024);
+    let disk = options.checkpoint_every.map(|interval| {
+        let path = options
+            .checkpoint_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("{}.checkpoint", name)));
+        DiskCheckpoints::new(path, interval, options.checkpoint_keep)
+    });
+
+    // the debugger shows both
+    let debugger = !options.no_debugger && options.debug_script.is_none();
+    if debugger || options.output_timestamps.is_some() || options.transcript.is_some() {
+        i.record_output_steps();
+    }
+    if options.transcript.is_some() {
+        i.record_input_steps();
+    }
 
-    //     let pos_rel = pos - buf_start;
+    if debugger || options.step_hashes.is_some() {
+        i.record_step_hashes(options.hash_interval);
+    }
 
-    //     let missing_left = width / 2 - pos_rel;
+    if options.trace.is_some() || options.html_replay {
+        i.record_trace(options.trace_sample);
+    }
 
-    //     let buf_end = (buf_start + width / 2 + missing_left).min(buf_len);
+    if options.export_html.is_some() {
+        i.record_heat();
+    }
 
-    //     Bounds {
-    //         start: buf_start,
-    //         end: buf_end,
-    //         rel: pos_rel as u16,
-    //     }
-    // }
+    let start = Instant::now();
 
-    fn draw_region(
-        &mut self,
-        label: &str,
-        (px, py): (u16, u16),
-        width: u16,
-        buf: impl AsRef<str>,
-        pos: usize,
-    ) -> Result {
-        execute!(self.stdout, cursor::MoveTo(px, py))?;
-        print!("{}:", label);
+    let result = if let Some(path) = &options.debug_script {
+        let commands = parse_script(&fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let fusions = read_fusions(&options.fusions)?;
 
-        let buf = buf.as_ref();
+        let mut view = View::new(i.code.clone(), i.brackets(), i.memory.clone());
+        let engine = engine::spawn(
+            i,
+            stop,
+            options.checkpoint_interval,
+            max_memory,
+            disk.clone(),
+        );
 
-        let Bounds { start, end, rel } = Self::region_bounds(width, buf.len(), pos);
+        let result = run_script(&engine, &mut view, &commands, &fusions);
+        let notices;
+        (i, notices) = engine.join();
+        warnings.extend(notices);
 
-        execute!(self.stdout, cursor::MoveTo(px, py + 1))?;
-        print!("{}", &buf[start..end]);
+        result.map(|_| {
+            if i.halted() {
+                ExitReason::Halted
+            } else {
+                ExitReason::Quit
+            }
+        })
+    } else if debugger {
+        ctrlc::set_handler(|| {
+            cleanup_terminal();
+        })?;
+
+        let program = ProgramInfo {
+            name: name.clone(),
+            output_mode: options.output_mode,
+            io_mode: options.io,
+            pragmas: i.pragmas,
+            fusions: read_fusions(&options.fusions)?,
+            memory_size: i.memory.len(),
+        };
+
+        let mut view = View::new(i.code.clone(), i.brackets(), i.memory.clone());
+        let engine = engine::spawn(
+            i,
+            stop,
+            options.checkpoint_interval,
+            max_memory,
+            disk.clone(),
+        );
+
+        let result = {
+            let mut debugger =
+                Debugger::new(program, !options.no_color, options.explain, options.history)?;
+            debugger.alerts = Alerts {
+                bell: options.bell,
+                command: options.notify_cmd.clone(),
+            };
+            debugger.run(&engine, &mut view)
+        };
+        let notices;
+        (i, notices) = engine.join();
+        warnings.extend(notices);
+
+        result.map(|_| {
+            if i.halted() {
+                ExitReason::Halted
+            } else {
+                ExitReason::Quit
+            }
+        })
+    } else {
+        run_headless(&mut i, &mut stop, max_memory, disk, &mut warnings)
+    };
+
+    let exit = match &result {
+        Ok(exit) => exit.clone(),
+        Err(e) => ExitReason::Failed {
+            error: e.to_string(),
+        },
+    };
 
-        execute!(self.stdout, cursor::MoveTo(px + rel as u16, py + 2))?;
-        print!("^");
+    if let Some(path) = &options.export_html {
+        let report = html::render(&name, &i, &exit, start.elapsed(), options.html_replay);
+        fs::write(path, report)?;
+    }
 
-        Ok(())
+    if let Some(path) = &options.summary_json {
+        let summary = Summary::new(exit, &i, semantics, warnings, start.elapsed());
+        fs::write(path, serde_json::to_string_pretty(&summary)?)?;
     }
 
-    fn draw_memory(
-        &mut self,
-        (px, py): (u16, u16),
-        width: u16,
-        memory: &[u8],
-        pointer: usize,
-    ) -> Result {
-        let cell_count = width / 4;
-        // let usable_width = width - width % 4;
+    if let Some(path) = &options.transcript {
+        transcript::write(path, &i)?;
+    }
+
+    if let Some(path) = &options.output_timestamps {
+        write_output_timestamps(path, &i)?;
+    }
 
-        if pointer >= self.memory_range.end {
-            self.memory_range.start += 1;
-            self.memory_range.end = self.memory_range.start + cell_count as usize;
-            self.memory_range.end = self.memory_range.end.min(MEMORY_SIZE);
-            self.memory_range.start = self
-                .memory_range
-                .start
-                .min(self.memory_range.end - cell_count as usize);
-        } else if pointer < self.memory_range.start {
-            self.memory_range.start -= 1;
-            self.memory_range.start = self.memory_range.start.max(0);
-            self.memory_range.end = self.memory_range.start + cell_count as usize;
-            self.memory_range.end = self
-                .memory_range
-                .end
-                .max(self.memory_range.start + cell_count as usize);
+    if let Some(path) = &options.step_hashes {
+        write_step_hashes(path, &i)?;
+    }
+
+    if let Some(path) = &options.trace {
+        write_trace(path, &i)?;
+    }
+
+    write_dump(options, &i.memory)?;
+
+    // everything asked for is written first, a failed run is when it helps most
+    if let Err(e) = result {
+        report_failure(&name, code, e, options);
+    }
+
+    if options.stats {
+        eprintln!("{}", i.stats);
+        if let Some(memo) = &i.memo {
+            eprintln!(
+                "memoized loops:  {} replayed, {} recorded",
+                memo.replayed, memo.recorded
+            );
         }
+    }
 
-        execute!(self.stdout, cursor::MoveTo(px, py))?;
-        print!("Memory:");
+    match options.exit_from_cell {
+        Some(cell) if i.halted() => cell.exit(&i.memory, i.pointer),
+        _ => Ok(()),
+    }
+}
 
-        // let Bounds { start, end, rel } = Self::region_bounds(unit_width, MEMORY_SIZE, pointer);
-        let rel = pointer - self.memory_range.start;
+fn test(options: TestOptions) -> Result {
+    let limits = Limits {
+        max_steps: options.max_steps,
+        timeout: Duration::from_secs_f64(options.timeout),
+    };
+    let start = Instant::now();
 
-        execute!(self.stdout, cursor::MoveTo(px, py + 1))?;
+    let mut cases = Vec::new();
+    for path in testing::discover(&options.paths)? {
+        cases.extend(testing::load(&path)?);
+    }
 
-        let region = &memory[self.memory_range.clone()];
+    println!(
+        "running {} test{}",
+        cases.len(),
+        if cases.len() == 1 { "" } else { "s" }
+    );
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        match case.run(limits) {
+            testing::Outcome::Passed { .. } => println!("test {} ... ok", case.name),
+            testing::Outcome::Failed(reason) => {
+                println!("test {} ... FAILED", case.name);
+                failures.push((&case.name, reason));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, reason) in &failures {
+            println!("\n---- {} ----\n{}", name, reason.trim_end());
+        }
+    }
+
+    println!(
+        "\ntest result: {}. {} passed; {} failed; finished in {:.2}s",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        cases.len() - failures.len(),
+        failures.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    if !failures.is_empty() {
+        exit(1);
+    }
+
+    Ok(())
+}
+
+fn conformance_suite(options: ConformanceOptions) -> Result {
+    let eof = match options.eof.or(options.profile.map(|profile| profile.eof())) {
+        Some(Eof::Value(value)) => Some(value),
+        Some(Eof::Unchanged) | None => None,
+    };
+    let wrap_pointer = match options.pointer {
+        Some(pointer) => pointer == PointerMode::Wrap,
+        None => options
+            .profile
+            .is_some_and(|profile| profile.wrap_pointer()),
+    };
+    let semantics = conformance::semantics(eof, wrap_pointer);
+    let runners = Runner::available();
+    let suite = conformance::suite();
+
+    let mut observed = Observed::default();
+    let mut failures = Vec::new();
+    for case in &suite {
+        let outcomes: Vec<BisectOutcome> = runners
+            .iter()
+            .map(|runner| runner.run(case, &semantics))
+            .collect();
+        let outcome = &outcomes[0];
+
+        let describe = |outcome: &BisectOutcome| {
+            format!(
+                "printed \"{}\" and {}",
+                escape_bytes(&outcome.output),
+                outcome.end
+            )
+        };
+        match case.check {
+            Check::Prints(expected) => {
+                if outcome.output == expected && outcome.end == "halted" {
+                    println!("case {} ... ok", case.name);
+                } else {
+                    println!("case {} ... FAILED", case.name);
+                    failures.push(format!(
+                        "{}: {} {}, instead of printing \"{}\" and halting",
+                        case.name,
+                        runners[0],
+                        describe(outcome),
+                        escape_bytes(expected)
+                    ));
+                }
+            }
+            Check::Probe(property) => {
+                observed.record(property, outcome);
+                println!(
+                    "case {} ... {}",
+                    case.name,
+                    conformance::describe(property, outcome)
+                );
+            }
+        }
 
-        let region = region
+        // the interpreter is the reference for the rest of the build
+        for (runner, other) in runners.iter().zip(&outcomes).skip(1) {
+            if other.output != outcome.output || other.end != outcome.end {
+                failures.push(format!(
+                    "{}: {} {}, but {} {}",
+                    case.name,
+                    runner,
+                    describe(other),
+                    runners[0],
+                    describe(outcome)
+                ));
+            }
+        }
+    }
+
+    println!(
+        "\nran {} cases on {}",
+        suite.len(),
+        runners
             .iter()
-            .map(|b| format!("{b:03}"))
+            .map(ToString::to_string)
             .collect::<Vec<_>>()
-            .join(" ");
+            .join(", ")
+    );
+
+    let (matches, others): (Vec<_>, Vec<_>) = conformance::REFERENCES
+        .iter()
+        .map(|reference| (reference, observed.differences(reference)))
+        .partition(|(_, differences)| differences.is_empty());
+    if matches.is_empty() {
+        println!("\nthis matches none of the common semantics");
+    }
+    for (reference, _) in &matches {
+        match reference.profile {
+            Some(profile) => println!(
+                "\nthis matches {} (`--profile {}`)",
+                reference.name,
+                profile.name()
+            ),
+            None => println!("\nthis matches {}", reference.name),
+        }
+    }
+    if !others.is_empty() {
+        println!("\nto match");
+        for (reference, differences) in &others {
+            match reference.profile {
+                Some(profile) => println!(
+                    "- {}: needs {}, or just `--profile {}`",
+                    reference.name,
+                    differences.join(", "),
+                    profile.name()
+                ),
+                None => println!("- {}: needs {}", reference.name, differences.join(", ")),
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for failure in &failures {
+            println!("- {}", failure);
+        }
+        exit(1);
+    }
 
-        print!("{}", region);
+    Ok(())
+}
 
-        execute!(self.stdout, cursor::MoveTo(px + rel as u16 * 4, py + 2))?;
-        print!("^");
+fn mutation_test(options: MutationTestOptions) -> Result {
+    let source = fs::read_to_string(&options.program)?;
+    let program = fs::canonicalize(&options.program)?;
+    let limits = Limits {
+        max_steps: options.max_steps,
+        timeout: Duration::from_secs_f64(options.timeout),
+    };
 
-        Ok(())
+    let mut cases = Vec::new();
+    for path in testing::discover(&options.paths)? {
+        for case in testing::load(&path)? {
+            if fs::canonicalize(&case.program).is_ok_and(|path| path == program) {
+                cases.push(case);
+            }
+        }
+    }
+    if cases.is_empty() {
+        return Err(format!(
+            "no tests run {}, point a test file at it with `@program`",
+            options.program.display()
+        )
+        .into());
     }
 
-    pub fn draw(
-        &mut self,
-        interpreter: &BrainfuckInterpreter,
-        force: bool,
-    ) -> Result<DebugCommand> {
-        // calculate op/s once every second
-        let now = SystemTime::now();
-        if now.duration_since(self.last_op_reset)? > Duration::from_secs(1) {
-            self.last_ops_per_second = self.op_counter;
-            self.op_counter = 0;
-            self.last_op_reset = SystemTime::now();
+    // a mutant that runs much longer than the original counts as hanging
+    let mut case_limits = Vec::new();
+    for case in &cases {
+        match case.run(limits) {
+            testing::Outcome::Passed { steps } => case_limits.push(Limits {
+                max_steps: options.max_steps.min(steps * 10 + 10_000),
+                ..limits
+            }),
+            testing::Outcome::Failed(reason) => {
+                return Err(format!(
+                    "the tests must pass before mutating the program, but {} failed: {}",
+                    case.name, reason
+                )
+                .into());
+            }
         }
+    }
 
-        self.op_counter += 1;
+    let mutations = if options.mutations.is_empty() {
+        Mutation::ALL.to_vec()
+    } else {
+        options.mutations.clone()
+    };
+    let mutants = mutation::mutants(
+        &source,
+        options.count,
+        &mutations,
+        &mut Rng::new(options.seed),
+    )?;
+
+    println!(
+        "running {} test{} against {} mutants of {}",
+        cases.len(),
+        if cases.len() == 1 { "" } else { "s" },
+        mutants.len(),
+        options.program.display()
+    );
+
+    let mut survivors = Vec::new();
+    for (n, mutant) in mutants.iter().enumerate() {
+        let description = format!(
+            "mutant {}: {} at line {} column {}",
+            n + 1,
+            mutant.mutant.mutation,
+            mutant.mutant.span.line,
+            mutant.mutant.span.column
+        );
 
-        if !force && self.update_counter < self.update_frequency {
-            self.update_counter += 1;
-            return Ok(DebugCommand::Step);
+        let killer = cases.iter().zip(&case_limits).find(|(case, limits)| {
+            matches!(
+                case.run_code(&mutant.source, **limits),
+                testing::Outcome::Failed(_)
+            )
+        });
+
+        match killer {
+            Some((case, _)) => println!("{} ... killed by {}", description, case.name),
+            None => {
+                println!("{} ... SURVIVED", description);
+                survivors.push((description, mutant));
+            }
         }
+    }
 
-        self.update_counter = 0;
+    if !survivors.is_empty() {
+        println!("\nsurviving mutants:");
+        for (description, mutant) in &survivors {
+            let diff = testing::diff_lines(&source, &mutant.source);
+            let changes: Vec<&str> = diff.lines().filter(|line| !line.starts_with(' ')).collect();
+            println!("\n---- {} ----\n{}", description, changes.join("\n"));
+        }
+    }
 
-        execute!(self.stdout, terminal::Clear(terminal::ClearType::All))?;
+    let killed = mutants.len() - survivors.len();
+    println!(
+        "\nmutation score: {}/{} mutants killed ({:.1}%)",
+        killed,
+        mutants.len(),
+        100.0 * killed as f64 / mutants.len().max(1) as f64
+    );
 
-        self.draw_region(
-            "Input",
-            (0, 0),
-            self.size.0,
-            &interpreter.input.as_ascii_str().unwrap(),
-            interpreter.input_pos,
-        )?;
+    if !survivors.is_empty() {
+        exit(1);
+    }
 
-        execute!(self.stdout, cursor::MoveTo(0, 4))?;
-        print!("Pos: {}", interpreter.code_pos);
+    Ok(())
+}
 
-        self.draw_memory(
-            (0, 6),
-            self.size.0,
-            &interpreter.memory,
-            interpreter.pointer,
-        )?;
+fn symbolize(map: PathBuf, input: Option<PathBuf>) -> Result {
+    let symbolizer = Symbolizer::open(&map)?;
+    let text = match input {
+        Some(path) => String::from_utf8(compress::read(&path)?)
+            .map_err(|_| format!("{} is not text", path.display()))?,
+        None => io::read_to_string(stdin())?,
+    };
 
-        execute!(self.stdout, cursor::MoveTo(0, 10))?;
-        print!("Pointer: {}", interpreter.pointer);
+    print!("{}", symbolizer.symbolize(&text));
+    Ok(())
+}
 
-        self.draw_region(
-            "Output",
-            (0, 12),
-            self.size.0,
-            &interpreter.output,
-            interpreter.output.len(),
-        )?;
+fn attach(options: AttachOptions) -> Result {
+    let code = fs::read_to_string(&options.code)?;
+    let stop = StopConditions::from_flags(
+        &options.break_on_output,
+        &options.break_on_output_regex,
+        &options.expect,
+    )?;
+
+    let engine = remote::attach(&options.address, &code, options.output_mode, stop)?;
 
-        self.draw_region(
-            "Code",
-            (0, 16),
-            self.size.0,
-            &interpreter
+    ctrlc::set_handler(|| {
+        cleanup_terminal();
+    })?;
+
+    let program = ProgramInfo {
+        name: format!(
+            "{} at {}",
+            options
                 .code
-                .iter()
-                .map(|c| if *c == '\n' { ' ' } else { *c })
-                .collect::<String>(),
-            interpreter.code_pos,
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            options.address
+        ),
+        output_mode: options.output_mode,
+        io_mode: IoMode::Text,
+        // the generated program honors them itself
+        pragmas: codegen::pragma::parse_pragmas(&code).0,
+        fusions: FusionTable::default(),
+        // what the mirror of the program's tape holds
+        memory_size: MEMORY_SIZE,
+    };
+
+    // the generated program passes over its pragmas, and its brackets with them
+    let chars: Vec<char> = code.chars().collect();
+    let brackets = BracketTable::skipping(&chars, &pragma::skipped(&code));
+    let mut view = View::new(
+        chars,
+        brackets,
+        Memory::new(CellWidth::Bits(8), MEMORY_SIZE),
+    );
+    let result = {
+        let mut debugger =
+            Debugger::new(program, !options.no_color, options.explain, options.history)?;
+        debugger.alerts = Alerts {
+            bell: options.bell,
+            command: options.notify_cmd,
+        };
+        debugger.run(&engine, &mut view)
+    };
+    engine.join();
+
+    result
+}
+
+/// Writes the cells of the `--dump-range`, if any, to stdout or the `--dump-file`.
+fn write_dump(options: &RunOptions, memory: &Memory) -> Result {
+    let Some(range) = &options.dump_range else {
+        return Ok(());
+    };
+
+    let dump = Dump::new(
+        range.clone(),
+        options.dump_file.clone(),
+        options.dump_format,
+    );
+    if let Some(dump) = dump.write(memory)? {
+        let mut stdout = stdout();
+        stdout.write_all(&dump)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+fn write_output_timestamps(path: &Path, i: &BrainfuckInterpreter) -> Result {
+    let mut file = compress::create(path)?;
+
+    for record in i.output_log.iter().flatten() {
+        writeln!(file, r#"{{"step":{},"byte":{}}}"#, record.step, record.byte)?;
+    }
+
+    file.finish()
+}
+
+fn write_step_hashes(path: &Path, i: &BrainfuckInterpreter) -> Result {
+    let mut file = compress::create(path)?;
+
+    for hash in i.step_hasher.iter().flat_map(|hasher| &hasher.history) {
+        writeln!(
+            file,
+            r#"{{"step":{},"hash":"{:016x}"}}"#,
+            hash.step, hash.hash
         )?;
+    }
 
-        execute!(self.stdout, cursor::MoveTo(0, self.size.1 - 2))?;
-        print!(
-            "Update frequency: 1/{} updates displayed",
-            self.update_frequency + 1
-        );
+    file.finish()
+}
 
-        execute!(self.stdout, cursor::MoveTo(0, self.size.1 - 1))?;
-        print!("Ops/s: {:.2}", self.last_ops_per_second);
+fn write_trace(path: &Path, i: &BrainfuckInterpreter) -> Result {
+    let mut file = compress::create(path)?;
+
+    for record in i.tracer.iter().flat_map(|tracer| &tracer.records) {
+        let event = match &record.event {
+            TraceEvent::Sample(c) => format!(r#""op":{}"#, serde_json::to_string(c)?),
+            TraceEvent::Phase(name) => format!(r#""phase":{}"#, serde_json::to_string(name)?),
+        };
+        writeln!(
+            file,
+            r#"{{"step":{},"pos":{},{},"ptr":{},"cell":{}}}"#,
+            record.step, record.position, event, record.pointer, record.cell
+        )?;
+    }
 
-        self.stdout.flush()?;
+    file.finish()
+}
 
-        if self.paused {
-            loop {
-                match read()? {
-                    Event::Key(key) => match key.code {
-                        KeyCode::Char('q') => {
-                            break Ok(DebugCommand::Quit);
-                        }
-                        KeyCode::Char('c') => {
-                            self.paused = false;
-                            // break Ok(DebugCommand::Continue);
-                            break Ok(DebugCommand::Step);
-                        }
-                        KeyCode::Char(_)
-                        | KeyCode::Left
-                        | KeyCode::Right
-                        | KeyCode::Up
-                        | KeyCode::Down => {
-                            break Ok(DebugCommand::Step);
+/// Exits after a failed run, with a report pointing at the instruction that
+/// failed if there is one.
+fn report_failure(
+    name: &str,
+    code: &str,
+    e: Box<dyn std::error::Error>,
+    options: &RunOptions,
+) -> ! {
+    cleanup_terminal();
+
+    match e.downcast_ref::<RuntimeError>() {
+        Some(error) => {
+            let color = !options.no_color && stderr().is_terminal();
+            eprint!(
+                "{}",
+                report::render(name, code, options.memory_size, error, color)
+            );
+        }
+        None => eprintln!("Error: {}", e),
+    }
+    exit(1);
+}
+
+/// Runs the program to completion on the bytecode engine, streaming its output
+/// to stdout and reading a line of stdin whenever it runs out of input.
+fn run_bytecode(name: &str, code: &str, options: &RunOptions) -> Result {
+    let assertions = if options.assertions {
+        parse_assertions(code)?
+    } else {
+        Vec::new()
+    };
+    let (semantics, overridden) = options.semantics(code)?;
+    for warning in overridden {
+        verbosity::warn(format!("{}: {}", name, warning));
+    }
+    verbosity::info(format!("{}: {}", name, semantics));
+    let program = bytecode::Program::compile_with_fuel(
+        code,
+        &read_fusions(&options.fusions)?,
+        assertions,
+        &semantics,
+        &mut OptFuel::new(options.opt_fuel),
+    )?;
+    let mut machine = Machine::new(options.io);
+    machine.resize_memory(options.memory_size);
+    machine.hot_loop_threshold = options.hot_loop_threshold;
+
+    if let Some(input) = options.read_input()? {
+        machine.push_input(&input);
+        machine.close_input();
+    }
+
+    let dispatch = match options.engine {
+        Engine::Interpreter | Engine::Match => Dispatch::Match,
+        Engine::Threaded => Dispatch::Threaded,
+        Engine::TailCall => Dispatch::TailCall,
+        Engine::Auto => bytecode::fastest(&program, &machine)?,
+    };
+
+    let mut stdout = stdout();
+    let mut pending = Vec::new();
+    let mut fuel = u64::MAX;
+
+    loop {
+        let status = match machine.run(&program, dispatch, &mut fuel) {
+            Ok(status) => status,
+            Err(e) => {
+                // the dump is most useful for a run that failed
+                write_dump(options, &machine.memory.to_vec().into())?;
+                report_failure(name, code, e, options)
+            }
+        };
+
+        let mut text = String::new();
+        let mut failure = None;
+        match options.output_mode {
+            OutputMode::Ascii => {
+                for byte in machine.output.drain(..) {
+                    match byte.to_ascii_char() {
+                        Ok(char) => text.push(char.as_char()),
+                        Err(e) => {
+                            failure = Some(e);
+                            break;
                         }
-                        _ => {}
-                    },
-                    _ => {}
+                    }
                 }
             }
-        } else {
-            if poll(Duration::from_micros(10))? {
-                match read()? {
-                    Event::Key(key) => match key.code {
-                        KeyCode::Char('q') => {
-                            return Ok(DebugCommand::Quit);
-                        }
-                        KeyCode::Char('p') => {
-                            self.paused = true;
-                        }
-                        KeyCode::Up => {
-                            if self.update_frequency == 0 {
-                                self.update_frequency = 1;
-                            } else {
-                                self.update_frequency = self.update_frequency.saturating_mul(2);
-                            }
-                        }
-                        KeyCode::Down => {
-                            if self.update_frequency == 1 {
-                                self.update_frequency = 0;
-                            } else {
-                                self.update_frequency = self.update_frequency.saturating_div(2);
-                            }
-                        }
-                        _ => {}
-                    },
-                    _ => {}
+            OutputMode::Utf8 => {
+                pending.append(&mut machine.output);
+                decode_utf8(&mut pending, &mut text);
+            }
+        }
+        stdout.write_all(text.as_bytes())?;
+        if let Some(e) = failure {
+            stdout.flush()?;
+            write_dump(options, &machine.memory.to_vec().into())?;
+            return Err(e.into());
+        }
+
+        match status {
+            Status::Output | Status::OutOfFuel => {}
+            Status::NeedsInput => {
+                stdout.flush()?;
+
+                let mut line = Vec::new();
+                if stdin().lock().read_until(b'\n', &mut line)? == 0 {
+                    machine.close_input();
+                } else {
+                    machine.push_input(&line);
                 }
             }
-            Ok(DebugCommand::Step)
+            Status::Halted => {
+                if !pending.is_empty() {
+                    write!(stdout, "{}", char::REPLACEMENT_CHARACTER)?;
+                }
+                stdout.flush()?;
+                let memory = machine.memory.to_vec().into();
+                write_dump(options, &memory)?;
+                break match options.exit_from_cell {
+                    Some(cell) => cell.exit(&memory, machine.pointer),
+                    None => Ok(()),
+                };
+            }
         }
     }
 }
 
-impl BrainfuckInterpreter {
-    pub fn new(code: &str, input: Option<&String>) -> Result<Self> {
-        let input = input.cloned().unwrap_or_else(|| String::new());
-        let input_ascii = input.as_ascii_str()?;
-        let input = input_ascii.chars().collect::<Vec<_>>();
+fn run_sandboxed(code: &str, options: &RunOptions) -> Result {
+    let input = options.read_input()?.unwrap_or_default();
+    let limits = SandboxLimits {
+        io_mode: options.io,
+        ..SandboxLimits::default()
+    };
+
+    let report = sandbox::run(code, &input, &limits);
+    print!("{}", report.output);
+    stdout().flush()?;
 
-        Ok(Self {
-            memory: [0; MEMORY_SIZE],
-            pointer: 0,
-            loop_stack: Vec::new(),
-            input,
-            input_pos: 0,
-            code: code.chars().collect::<Vec<_>>(),
-            code_pos: 0,
-            output: String::new(),
-        })
+    if let Some(error) = &report.error {
+        eprintln!("{}", serde_json::to_string(error)?);
+        exit(1);
     }
+    Ok(())
+}
 
-    pub fn run(&mut self, mut debugger: Option<Debugger>) -> Result {
-        loop {
-            if let Some(debugger) = &mut debugger {
-                if matches!(debugger.draw(self, false)?, DebugCommand::Quit) {
-                    break Ok(());
-                }
+fn read_fusions(path: &Option<PathBuf>) -> Result<FusionTable> {
+    match path {
+        Some(path) => FusionTable::parse(&fs::read_to_string(path)?),
+        None => Ok(FusionTable::default()),
+    }
+}
+
+fn bench(
+    path: PathBuf,
+    input: Option<PathBuf>,
+    fuel: u64,
+    fusions: Option<PathBuf>,
+    hot_loop_threshold: u32,
+    profile: bool,
+) -> Result {
+    let code = fs::read_to_string(path)?;
+    let mut machine = Machine::new(IoMode::Text);
+    machine.hot_loop_threshold = hot_loop_threshold;
+    if let Some(input) = input {
+        machine.push_input(&compress::read(&input)?);
+    }
+    // a program that needs more input than it was given sees EOF
+    machine.close_input();
+
+    if profile {
+        let program = bytecode::Program::compile_with(&code, &FusionTable::none())?;
+        println!("# the most frequent first, from {} ops", fuel);
+        for (fusion, count) in fusion::profile(&program, &machine, fuel)? {
+            println!("{:<24} # {} times", fusion.to_string(), count);
+        }
+        return Ok(());
+    }
+
+    let program = bytecode::Program::compile_with(&code, &read_fusions(&fusions)?)?;
+
+    println!("{} ops", program.instrs.len());
+    for (dispatch, steps, time) in bytecode::benchmark(&program, &machine, fuel)? {
+        let name = dispatch
+            .to_possible_value()
+            .map_or(String::new(), |value| value.get_name().to_string());
+        println!(
+            "{:<10} {:>12} steps in {:>8.3}s, {:>8.1}M steps/s",
+            name,
+            steps,
+            time.as_secs_f64(),
+            steps as f64 / time.as_secs_f64().max(1e-9) / 1e6
+        );
+    }
+
+    Ok(())
+}
+
+fn disasm(options: DisasmOptions) -> Result {
+    let code = fs::read_to_string(&options.code)?;
+    let fusions = if options.no_fusions {
+        FusionTable::none()
+    } else {
+        read_fusions(&options.fusions)?
+    };
+
+    if options.dump_ir_after.is_some() || options.dump_ir_diff {
+        let passes = disasm::passes(&code, &fusions, options.opt_fuel)?;
+        if let Some(after) = options.dump_ir_after {
+            dump_passes(&options, &passes, after)?;
+        }
+        if options.dump_ir_diff {
+            for pair in passes.windows(2) {
+                let ((from, before), (to, after)) = (&pair[0], &pair[1]);
+                println!("--- after {} ({} ops)", from.name(), before.len());
+                println!("+++ after {} ({} ops)", to.name(), after.len());
+                print!("{}", disasm::diff(before, after));
             }
+            return Ok(());
+        }
+    }
 
-            let c = self.code[self.code_pos];
+    let mut fuel = OptFuel::new(options.opt_fuel);
+    let lines = disasm::listing(&code, &fusions, &mut fuel)?;
 
-            let mut increment = true;
+    if options.opt_fuel.is_some() {
+        println!("{} transformations applied", fuel.used);
+    }
+    println!(
+        "{} ops, {} jump targets marked with >",
+        lines.len(),
+        lines.iter().filter(|line| line.target).count()
+    );
+    println!(
+        "  {:<4}  {:<7} {:>5}  {:<38} source",
+        "op", "at", "steps", "does"
+    );
+    for line in lines {
+        println!("{}", line.to_string().trim_end());
+    }
+    Ok(())
+}
 
-            match c {
-                '>' => {
-                    self.pointer += 1;
-                    if WRAPPING {
-                        self.pointer = self.pointer % MAX_POINTER;
-                    } else {
-                        self.pointer = self.pointer.min(MAX_POINTER);
-                    }
-                }
-                '<' => {
-                    if WRAPPING {
-                        if self.pointer == 0 {
-                            self.pointer = MAX_POINTER;
-                        } else {
-                            self.pointer -= 1;
-                        }
-                    } else if self.pointer > 0 {
-                        self.pointer -= 1;
+/// Writes the listings of `--dump-ir-after` to `prog.01-parse.ir` and so on.
+fn dump_passes(
+    options: &DisasmOptions,
+    passes: &[(Pass, Vec<disasm::Line>)],
+    after: DumpAfter,
+) -> Result {
+    let stem = options
+        .code
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    for (i, (pass, lines)) in passes.iter().enumerate() {
+        if after != DumpAfter::All && after != DumpAfter::Pass(*pass) {
+            continue;
+        }
+
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(line.to_string().trim_end());
+            text.push('\n');
+        }
+        let path = options
+            .dump_ir_dir
+            .join(format!("{}.{:02}-{}.ir", stem, i + 1, pass.name()));
+        fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Bisects the optimization fuel of a program (see [`bisect`]), exiting with 1
+/// if some transformation changes what it does.
+fn bisect_opt(
+    path: PathBuf,
+    input: Option<PathBuf>,
+    fuel: u64,
+    fusions: Option<PathBuf>,
+) -> Result {
+    let code = fs::read_to_string(path)?;
+    let semantics = pragma::read_pragmas(&code)?;
+    let mut machine = Machine::new(IoMode::Text);
+    // hot loops would run the same either way
+    machine.hot_loop_threshold = 0;
+    if let Some(input) = input {
+        machine.push_input(&compress::read(&input)?);
+    }
+    machine.close_input();
+
+    let bisection = bisect::bisect(&code, &read_fusions(&fusions)?, &semantics, &machine, fuel)?;
+    let Some(culprit) = bisection.culprit else {
+        println!(
+            "the program does the same with all {} transformations as with none",
+            bisection.total
+        );
+        return Ok(());
+    };
+
+    let chars: Vec<char> = code.chars().collect();
+    let (line, column) = report::line_and_column(&chars, culprit.position);
+    let describe = |name: &str, outcome: &BisectOutcome| {
+        format!(
+            "{} printed \"{}\" and {}",
+            name,
+            escape_bytes(&outcome.output),
+            outcome.end
+        )
+    };
+
+    println!(
+        "transformation {} of {} changes what the program does: {} at {}:{}",
+        culprit.number, bisection.total, culprit.transformation, line, column
+    );
+    println!("  {}", describe("without it, the program", &culprit.before));
+    println!("  {}", describe("with it, the program", &culprit.after));
+    println!(
+        "`--opt-fuel {}` runs the program without it",
+        culprit.number - 1
+    );
+    exit(1);
+}
+
+/// Runs the program to completion, streaming its output to stdout and
+/// reading a line of stdin whenever it runs out of input.
+fn run_headless(
+    i: &mut BrainfuckInterpreter,
+    stop: &mut StopConditions,
+    max_memory: Option<usize>,
+    mut disk: Option<DiskCheckpoints>,
+    warnings: &mut Vec<String>,
+) -> Result<ExitReason> {
+    let mut stdout = stdout();
+    let mut printed = 0;
+
+    loop {
+        if let Some(disk) = &mut disk {
+            disk.save_if_due(i)?;
+        }
+
+        let result = i.step()?;
+
+        if let Some(limit) = max_memory {
+            if i.passed_multiple(MEMORY_CHECK_INTERVAL) {
+                for dropped in i.fit_memory(limit, 0)? {
+                    let warning = format!("over --max-rss, dropped the recorded {}", dropped);
+                    if verbosity::level() > Verbosity::Quiet {
+                        eprintln!("\nwarning: {}", warning);
                     }
+                    warnings.push(warning);
                 }
-                '+' => {
-                    self.memory[self.pointer] = self.memory[self.pointer].wrapping_add(1);
-                }
-                '-' => {
-                    self.memory[self.pointer] = self.memory[self.pointer].wrapping_sub(1);
-                }
-                '.' => {
-                    self.output
-                        .push(self.memory[self.pointer].to_ascii_char().unwrap().as_char());
-                }
-                ',' => {
-                    if let Some(in_c) = self.input.get(self.input_pos) {
-                        self.memory[self.pointer] = in_c.as_byte();
-                        self.input_pos += 1;
+            }
+        }
+
+        if i.output.len() != printed || result == StepResult::Halted {
+            stdout.write_all(&i.output.as_bytes()[printed..])?;
+            printed = i.output.len();
+
+            match stop.check(i) {
+                Some(Stop::Break(reason)) => {
+                    stdout.flush()?;
+                    if verbosity::level() > Verbosity::Quiet {
+                        eprintln!("\nStopped: {}", reason);
                     }
-                    // if there is no next char, do not clobber the current pointer
-                }
-                '[' => {
-                    self.loop_stack.push(self.code_pos + 1);
+                    break Ok(ExitReason::Stopped { reason });
                 }
-                ']' => {
-                    if self.memory[self.pointer] != 0 {
-                        self.code_pos = *self.loop_stack.last().ok_or("unmatched ]")?;
-                        increment = false;
-                    } else {
-                        self.loop_stack.pop();
-                    }
+                Some(Stop::Diverged(_, context)) => {
+                    stdout.flush()?;
+                    eprintln!();
+                    break Err(context.into());
                 }
-                _ => {}
+                None => {}
             }
+        }
 
-            if increment {
-                self.code_pos += 1;
-            }
+        match result {
+            StepResult::Continue => {}
+            StepResult::NeedsInput => {
+                stdout.flush()?;
 
-            if self.code_pos >= self.code.len() {
-                if let Some(debugger) = &mut debugger {
-                    debugger.paused = true;
-                    debugger.draw(self, true)?;
+                let mut line = Vec::new();
+                if stdin().lock().read_until(b'\n', &mut line)? == 0 {
+                    i.close_input();
+                } else {
+                    i.push_input(&line);
                 }
-                break Ok(());
+            }
+            StepResult::Halted => {
+                stdout.flush()?;
+                break Ok(ExitReason::Halted);
             }
         }
     }
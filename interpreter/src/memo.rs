@@ -0,0 +1,262 @@
+//! Memoization of pure loops, for `bf --memoize`. A loop that ends on the cell
+//! it started on and does no I/O only depends on the cells it can reach,
+//! which [`codegen::regions::footprint`] bounds. The first run of such a loop
+//! from some contents of those cells is recorded, and whenever it starts from
+//! the same contents again, the cells, steps and stats it ended with are
+//! applied at once instead of running it.
+//!
+//! A replayed loop is a single call to [`BrainfuckInterpreter::step`], so
+//! breakpoints inside it are not hit, and the step hashes differ from a run
+//! without memoization. Everything else, including the stats, ends up the
+//! same.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+};
+
+use codegen::{
+    ast::{File, Repeated},
+    link::net_movement,
+    regions::footprint,
+};
+
+use crate::{
+    cell::{with_cells, Memory},
+    BracketTable, BrainfuckInterpreter, ExecutionStats,
+};
+
+/// The most cells a loop may reach to be memoized
+const MAX_WINDOW: usize = 64;
+
+/// How many recorded runs are kept
+const MAX_ENTRIES: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy)]
+/// A loop that can be memoized, with the cells it reaches relative to its own
+struct PureLoop {
+    /// The position of its `]`
+    end: usize,
+    lowest: isize,
+    highest: isize,
+    /// How far the pointer can move either way while it runs, which has to
+    /// stay on the tape so clamping never changes what it does
+    left: usize,
+    right: usize,
+}
+
+#[derive(Debug)]
+/// What a run of a loop did
+struct Effect {
+    /// The cells it reaches, once it ended
+    cells: Memory,
+    steps: u64,
+    stats: ExecutionStats,
+    /// The highest cell the pointer was on, relative to the loop's
+    max_offset: usize,
+}
+
+/// The recorded runs by the position of the loop and the cells it reaches,
+/// when it started
+type Cache = HashMap<(usize, Memory), Effect>;
+
+#[derive(Debug, Clone)]
+/// A run of a loop that is being recorded
+struct Recording {
+    start: usize,
+    end: usize,
+    /// The cell the loop started on
+    base: usize,
+    /// The cells it reaches, when it started
+    key: Memory,
+    /// How many loops it is inside of, counting its own, which ends once it is
+    /// back to 0
+    depth: usize,
+    steps: u64,
+    stats: ExecutionStats,
+    max_pointer: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+/// The pure loops of a program and their recorded runs
+pub struct Memo {
+    /// By the position of their `[`
+    loops: BTreeMap<usize, PureLoop>,
+    /// Shared with every copy of the interpreter, since the recorded runs
+    /// always go the same way
+    cache: Arc<Mutex<Cache>>,
+    recording: Option<Recording>,
+    /// How many runs of loops were replayed and recorded so far
+    pub replayed: u64,
+    pub recorded: u64,
+}
+
+impl Memo {
+    /// Finds the loops of `code` that can be memoized, among the pairs in
+    /// `brackets`, which are the ones that run as loops.
+    pub fn new(code: &[char], brackets: &BracketTable) -> Self {
+        let mut loops = BTreeMap::new();
+
+        for start in (0..code.len()).filter(|&pos| code[pos] == '[') {
+            let Some(end) = brackets.matching(start) else {
+                continue;
+            };
+            let body = &code[start + 1..end];
+            // directives are not instructions, but are still not pure
+            if body.iter().any(|c| matches!(c, '.' | ',' | '@' | '#')) {
+                continue;
+            }
+
+            let file: File<Repeated> = match body.iter().collect::<String>().parse() {
+                Ok(file) => file,
+                Err(e) => match e {},
+            };
+            if net_movement(&file.segments) != Ok(0) {
+                continue;
+            }
+            let Some((lowest, highest)) = footprint(&file.segments) else {
+                continue;
+            };
+
+            // the loop's own cell is read by its brackets
+            let (lowest, highest) = (lowest.min(0), highest.max(0));
+            if (highest - lowest) as usize >= MAX_WINDOW {
+                continue;
+            }
+
+            let count = |instruction| body.iter().filter(|&&c| c == instruction).count();
+            loops.insert(
+                start,
+                PureLoop {
+                    end,
+                    lowest,
+                    highest,
+                    left: count('<'),
+                    right: count('>'),
+                },
+            );
+        }
+
+        Self {
+            loops,
+            ..Self::default()
+        }
+    }
+}
+
+/// The cells a loop starting on `base` reaches.
+fn window(base: usize, lowest: isize, highest: isize) -> RangeInclusive<usize> {
+    (base as isize + lowest) as usize..=(base as isize + highest) as usize
+}
+
+impl BrainfuckInterpreter {
+    /// Starts memoizing the pure loops of the program (see [`crate::memo`]).
+    pub fn enable_memoization(&mut self) {
+        if self.memo.is_none() {
+            self.memo = Some(Memo::new(&self.code, &self.brackets()));
+        }
+    }
+
+    /// Replays the loop starting at the code pointer if it ran from the same
+    /// cells before, returning whether it did. Otherwise starts recording it,
+    /// unless another loop is being recorded.
+    pub(crate) fn replay_loop(&mut self) -> bool {
+        let Some(memo) = &mut self.memo else {
+            return false;
+        };
+        let Some(pure) = memo.loops.get(&self.code_pos).copied() else {
+            return false;
+        };
+
+        let base = self.pointer;
+        if base < pure.left || base + pure.right >= self.memory.len() {
+            return false;
+        }
+        let cells = window(base, pure.lowest, pure.highest);
+        let key = self.memory.window(cells.clone());
+
+        let cache = memo.cache.lock().unwrap();
+        if let Some(effect) = cache.get(&(self.code_pos, key.clone())) {
+            self.memory.replace(*cells.start(), &effect.cells);
+            // after its `]`, like `seek` would, which `memo` is still borrowed for
+            self.pc = self.positions.partition_point(|&start| start <= pure.end);
+            self.code_pos = self.positions[self.pc];
+            self.previous_steps = self.steps;
+            self.steps += effect.steps;
+            self.stats.add(&effect.stats);
+            self.stats.max_pointer = self.stats.max_pointer.max(base + effect.max_offset);
+            memo.replayed += 1;
+            if let Some(recording) = &mut memo.recording {
+                recording.max_pointer = recording.max_pointer.max(base + effect.max_offset);
+            }
+
+            if let Some(step_hasher) = &mut self.step_hasher {
+                for cell in cells {
+                    step_hasher.mark(cell);
+                }
+                with_cells!(&self.memory, memory => step_hasher.update(
+                    self.previous_steps,
+                    self.steps,
+                    self.pointer,
+                    memory,
+                ));
+            }
+            return true;
+        }
+
+        if memo.recording.is_none() && cache.len() < MAX_ENTRIES {
+            memo.recording = Some(Recording {
+                start: self.code_pos,
+                end: pure.end,
+                base,
+                key,
+                depth: 0,
+                steps: self.steps,
+                stats: self.stats,
+                max_pointer: base,
+            });
+        }
+        false
+    }
+
+    /// Follows the loop being recorded after the instruction at `position`
+    /// ran, and keeps its run once it ends.
+    pub(crate) fn record_loop(&mut self, position: usize) {
+        let Some(memo) = &mut self.memo else {
+            return;
+        };
+        let Some(recording) = &mut memo.recording else {
+            return;
+        };
+
+        recording.max_pointer = recording.max_pointer.max(self.pointer);
+        // a replayed loop never gets here, and the pointer is still on the
+        // cell a bracket read
+        match self.code[position] {
+            '[' if !self.memory.is_zero(self.pointer) => recording.depth += 1,
+            ']' if self.memory.is_zero(self.pointer) => recording.depth -= 1,
+            _ => {}
+        }
+        if position != recording.end || recording.depth != 0 {
+            return;
+        }
+
+        let recording = memo.recording.take().expect("just checked");
+        let pure = memo.loops[&recording.start];
+        let effect = Effect {
+            cells: self
+                .memory
+                .window(window(recording.base, pure.lowest, pure.highest)),
+            steps: self.steps - recording.steps,
+            stats: self.stats.since(&recording.stats),
+            max_offset: recording.max_pointer - recording.base,
+        };
+
+        let mut cache = memo.cache.lock().unwrap();
+        if cache.len() < MAX_ENTRIES {
+            cache.insert((recording.start, recording.key), effect);
+            memo.recorded += 1;
+        }
+    }
+}
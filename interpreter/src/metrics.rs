@@ -0,0 +1,186 @@
+//! The metrics of sandboxed runs (see [`crate::sandbox`]) in the Prometheus
+//! text format, as `bf-server` serves them at `GET /metrics` and `bf batch`
+//! writes them to a file.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+};
+
+use crate::sandbox::SandboxReport;
+
+/// How many of the latest runs the runtime quantiles are computed from
+pub const WINDOW: usize = 1000;
+
+/// The quantiles of the runtime that are reported
+pub const QUANTILES: [f64; 3] = [0.5, 0.95, 0.99];
+
+#[derive(Debug, Clone)]
+/// What the runs so far did
+pub struct RunMetrics {
+    /// What ran the programs, like `POST /run`, for the help of each metric
+    by: &'static str,
+    /// The `endpoint` label of their steps
+    endpoint: &'static str,
+    runs: u64,
+    /// The failed runs by the `kind` of their error
+    failures: BTreeMap<&'static str, u64>,
+    steps: u64,
+    /// The runtimes of the latest runs, in seconds
+    runtimes: VecDeque<f64>,
+    runtime_sum: f64,
+}
+
+impl RunMetrics {
+    pub fn new(by: &'static str, endpoint: &'static str) -> Self {
+        Self {
+            by,
+            endpoint,
+            runs: 0,
+            failures: BTreeMap::new(),
+            steps: 0,
+            runtimes: VecDeque::new(),
+            runtime_sum: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, report: &SandboxReport) {
+        self.runs += 1;
+        self.steps += report.steps;
+        if let Some(error) = &report.error {
+            *self.failures.entry(error.error.kind()).or_default() += 1;
+        }
+
+        if self.runtimes.len() == WINDOW {
+            self.runtimes.pop_front();
+        }
+        self.runtimes.push_back(report.wall_time_secs);
+        self.runtime_sum += report.wall_time_secs;
+    }
+
+    /// Writes the runs, their failures by reason, their steps and their
+    /// runtimes. `other_steps` are the steps of other endpoints, by their
+    /// label, which share the metric of the steps.
+    pub fn render(&self, out: &mut String, other_steps: &[(&str, u64)]) {
+        metric(
+            out,
+            "bf_runs_total",
+            "counter",
+            &format!("Programs run by {}", self.by),
+            &[(String::new(), self.runs.to_string())],
+        );
+        let failures: Vec<_> = self
+            .failures
+            .iter()
+            .map(|(reason, count)| (format!("{{reason=\"{}\"}}", reason), count.to_string()))
+            .collect();
+        metric(
+            out,
+            "bf_run_failures_total",
+            "counter",
+            &format!(
+                "Programs run by {} that failed, by the kind of error",
+                self.by
+            ),
+            &failures,
+        );
+
+        let steps: Vec<_> = [(self.endpoint, self.steps)]
+            .iter()
+            .chain(other_steps)
+            .map(|(endpoint, steps)| (format!("{{endpoint=\"{}\"}}", endpoint), steps.to_string()))
+            .collect();
+        metric(
+            out,
+            "bf_steps_total",
+            "counter",
+            "Instructions executed, by endpoint",
+            &steps,
+        );
+
+        let mut sorted: Vec<f64> = self.runtimes.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let mut runtime: Vec<_> = QUANTILES
+            .iter()
+            .filter(|_| !sorted.is_empty())
+            .map(|quantile| {
+                let index = ((sorted.len() - 1) as f64 * quantile).round() as usize;
+                (
+                    format!("{{quantile=\"{}\"}}", quantile),
+                    sorted[index].to_string(),
+                )
+            })
+            .collect();
+        runtime.push(("_sum".to_string(), self.runtime_sum.to_string()));
+        runtime.push(("_count".to_string(), self.runs.to_string()));
+        metric(
+            out,
+            "bf_run_duration_seconds",
+            "summary",
+            &format!(
+                "How long {} took to run programs, with quantiles over the latest {} runs",
+                self.by, WINDOW
+            ),
+            &runtime,
+        );
+    }
+}
+
+/// Writes a metric with its samples, each of which is a suffix of its name
+/// (usually labels) and a value.
+pub fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, String)]) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    for (suffix, value) in samples {
+        writeln!(out, "{}{} {}", name, suffix, value).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::{self, SandboxLimits};
+
+    #[test]
+    fn runs_are_counted_with_their_failures_steps_and_runtimes() {
+        let limits = SandboxLimits {
+            max_steps: 100,
+            ..SandboxLimits::default()
+        };
+        let mut metrics = RunMetrics::new("bf batch", "batch");
+        for code in ["+++.", "+[]", "[", "+[]"] {
+            metrics.record(&sandbox::run(code, b"", &limits));
+        }
+
+        let mut out = String::new();
+        metrics.render(&mut out, &[("debug", 7)]);
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert!(lines.contains(&"# HELP bf_runs_total Programs run by bf batch"));
+        assert!(lines.contains(&"# TYPE bf_runs_total counter"));
+        assert!(lines.contains(&"bf_runs_total 4"));
+        assert!(lines.contains(&"bf_run_failures_total{reason=\"step_limit\"} 2"));
+        assert!(lines.contains(&"bf_run_failures_total{reason=\"unmatched_bracket\"} 1"));
+        // the step limit is reached twice, after the 4 steps of the first run
+        assert!(lines.contains(&"bf_steps_total{endpoint=\"batch\"} 204"));
+        assert!(lines.contains(&"bf_steps_total{endpoint=\"debug\"} 7"));
+        assert!(lines.contains(&"bf_run_duration_seconds_count 4"));
+        for quantile in QUANTILES {
+            let sample = format!("bf_run_duration_seconds{{quantile=\"{}\"}} ", quantile);
+            assert!(
+                lines.iter().any(|line| line.starts_with(&sample)),
+                "{}",
+                out
+            );
+        }
+    }
+
+    #[test]
+    fn no_quantiles_are_written_before_the_first_run() {
+        let mut out = String::new();
+        RunMetrics::new("POST /run", "run").render(&mut out, &[]);
+        assert!(!out.contains("{quantile="), "{}", out);
+        assert!(out.contains("bf_run_duration_seconds_count 0\n"));
+        assert!(out.contains("bf_steps_total{endpoint=\"run\"} 0\n"));
+    }
+}
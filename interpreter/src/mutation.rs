@@ -0,0 +1,119 @@
+//! `bf mutation-test`: measures how strong the tests of a program are by
+//! mutating it and checking that some test fails for every mutant. A mutant
+//! that passes every test survives, and points at behavior nobody checks.
+
+use std::collections::HashSet;
+
+use brainfuck_extended::{assertion::parse_assertions, Result};
+use codegen::{
+    ast::{self, File, Repeated},
+    mutate::{mutate, Mutant, Mutation},
+    rng::Rng,
+};
+
+/// A mutant written back into the program's source
+pub struct SourceMutant {
+    pub mutant: Mutant,
+    pub source: String,
+}
+
+/// Whether a character is a Brainfuck instruction
+fn is_instruction(c: char) -> bool {
+    matches!(c, '+' | '-' | '<' | '>' | '[' | ']' | '.' | ',')
+}
+
+/// Produces up to `count` distinct mutants of the program. They keep its
+/// comments, including `@assert` directives, whose text is never mutated.
+pub fn mutants(
+    source: &str,
+    count: usize,
+    mutations: &[Mutation],
+    rng: &mut Rng,
+) -> Result<Vec<SourceMutant>> {
+    // hide the instructions in directives like `@assert cell[5] == 72`
+    let directives = parse_assertions(source)?;
+    let hidden: String = source
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let in_directive = directives
+                .iter()
+                .any(|directive| (directive.start..directive.end).contains(&i));
+            if in_directive && is_instruction(c) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    let file: File<Repeated> = hidden.parse()?;
+    let original = ast::to_code(&file.segments);
+    let offsets: Vec<usize> = hidden
+        .char_indices()
+        .filter(|(_, c)| is_instruction(*c))
+        .map(|(offset, _)| offset)
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+
+    // give up eventually if most mutations produce the same code
+    for _ in 0..count * 100 {
+        if found.len() == count {
+            break;
+        }
+
+        let mutation = mutations[rng.below(mutations.len())];
+        let Some(mutant) = mutate(&file, mutation, rng) else {
+            continue;
+        };
+
+        let code = ast::to_code(&mutant.file.segments);
+        if code == original || !seen.insert(code.clone()) {
+            continue;
+        }
+
+        let source = splice(source, &offsets, &original, &code);
+        found.push(SourceMutant { mutant, source });
+    }
+
+    Ok(found)
+}
+
+/// Writes the instructions in which `mutated` differs from `original` into the
+/// source, where `offsets` are the byte offsets of the original instructions.
+fn splice(source: &str, offsets: &[usize], original: &str, mutated: &str) -> String {
+    // `to_code` leaves out unmatched brackets, which the offsets would not
+    if offsets.len() != original.len() {
+        return mutated.to_string();
+    }
+
+    let (old, new) = (original.as_bytes(), mutated.as_bytes());
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let replaced = prefix..old.len() - suffix;
+    let (start, end) = if replaced.is_empty() {
+        // only inserted instructions
+        let at = offsets
+            .get(prefix)
+            .copied()
+            .unwrap_or_else(|| offsets.last().map_or(source.len(), |last| last + 1));
+        (at, at)
+    } else {
+        (offsets[replaced.start], offsets[replaced.end - 1] + 1)
+    };
+
+    format!(
+        "{}{}{}",
+        &source[..start],
+        &mutated[prefix..new.len() - suffix],
+        &source[end..]
+    )
+}
@@ -0,0 +1,158 @@
+//! Named phases of a run, for profiling a program the way its author thinks
+//! of it:
+//!
+//! ```text
+//! @phase read input
+//! ,[>,]
+//! @phase print it backwards
+//! <[.<]
+//! @endphase
+//! ```
+//!
+//! The markers are the `@phase <name>` labels of [`crate::trace`]. A phase
+//! lasts from its marker until the next one, an `@endphase` or the end of
+//! the program, and adds up how long it ran and the instructions and output
+//! bytes in that stretch. A phase entered again keeps adding to its totals.
+//! Like the labels, the markers are still executed, so keep instructions
+//! out of the names.
+
+use std::{collections::BTreeMap, time::Instant};
+
+use crate::trace::parse_phases;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+/// What happened while execution was in a phase
+pub struct PhaseStats {
+    pub name: String,
+    /// How many times execution passed its marker
+    pub entered: u64,
+    /// The instructions executed while in it
+    pub steps: u64,
+    pub output_bytes: u64,
+    /// The wall time spent in it, including any time the debugger was paused
+    pub time_secs: f64,
+}
+
+#[derive(Debug, Clone)]
+/// The phase execution is in, and the state when it was entered
+struct Current {
+    index: usize,
+    since: Instant,
+    steps: u64,
+    output_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Tracks the phases of a run as execution passes their markers
+pub struct Phases {
+    /// By the position (in characters) of their `@`, the name of the phase a
+    /// marker starts, or `None` for an `@endphase`
+    markers: BTreeMap<usize, Option<String>>,
+    /// Every phase entered so far, in the order they were first entered. The
+    /// one execution is in does not include its current stretch yet.
+    stats: Vec<PhaseStats>,
+    current: Option<Current>,
+}
+
+/// Finds the `@endphase` markers in the code, by the position (in
+/// characters) of their `@`.
+fn parse_phase_ends(code: &str) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut start = 0;
+
+    for line in code.split('\n') {
+        if let Some(at) = line.find("@endphase") {
+            let rest = &line[at + "@endphase".len()..];
+            // not part of a longer word like `@endphases`
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                ends.push(start + line[..at].chars().count());
+            }
+        }
+
+        start += line.chars().count() + 1;
+    }
+
+    ends
+}
+
+impl Phases {
+    pub fn new(code: &str) -> Self {
+        let mut markers: BTreeMap<_, _> = parse_phases(code)
+            .into_iter()
+            .map(|(position, name)| (position, Some(name)))
+            .collect();
+        markers.extend(parse_phase_ends(code).into_iter().map(|end| (end, None)));
+
+        Self {
+            markers,
+            ..Self::default()
+        }
+    }
+
+    /// Switches phases if there is a marker at `position`, which the `@` there
+    /// was just executed from. The stretch that ends is counted up to `steps`
+    /// and `output_bytes` written.
+    pub fn pass(&mut self, position: usize, steps: u64, output_bytes: u64) {
+        let Some(marker) = self.markers.get(&position) else {
+            return;
+        };
+        let marker = marker.clone();
+
+        self.finish(steps, output_bytes);
+        if let Some(name) = marker {
+            let index = match self.stats.iter().position(|phase| phase.name == name) {
+                Some(index) => index,
+                None => {
+                    self.stats.push(PhaseStats {
+                        name,
+                        ..PhaseStats::default()
+                    });
+                    self.stats.len() - 1
+                }
+            };
+
+            self.stats[index].entered += 1;
+            self.current = Some(Current {
+                index,
+                since: Instant::now(),
+                steps,
+                output_bytes,
+            });
+        }
+    }
+
+    /// Leaves the current phase, if any, once `steps` ran and `output_bytes`
+    /// were written.
+    pub fn finish(&mut self, steps: u64, output_bytes: u64) {
+        if let Some(current) = self.current.take() {
+            let phase = &mut self.stats[current.index];
+            phase.steps += steps - current.steps;
+            phase.output_bytes += output_bytes - current.output_bytes;
+            phase.time_secs += current.since.elapsed().as_secs_f64();
+        }
+    }
+
+    /// The phase execution is in, with its totals up to `steps` and
+    /// `output_bytes` written.
+    pub fn current(&self, steps: u64, output_bytes: u64) -> Option<PhaseStats> {
+        let current = self.current.as_ref()?;
+        let phase = &self.stats[current.index];
+
+        Some(PhaseStats {
+            steps: phase.steps + steps - current.steps,
+            output_bytes: phase.output_bytes + output_bytes - current.output_bytes,
+            time_secs: phase.time_secs + current.since.elapsed().as_secs_f64(),
+            ..phase.clone()
+        })
+    }
+
+    /// Every phase entered so far, in the order they were first entered, with
+    /// the current one counted up to `steps` and `output_bytes` written.
+    pub fn report(&self, steps: u64, output_bytes: u64) -> Vec<PhaseStats> {
+        let mut stats = self.stats.clone();
+        if let (Some(current), Some(phase)) = (&self.current, self.current(steps, output_bytes)) {
+            stats[current.index] = phase;
+        }
+        stats
+    }
+}
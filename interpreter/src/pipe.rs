@@ -0,0 +1,195 @@
+//! `bf pipe`: runs programs like a shell pipeline, each one's output the next
+//! one's input, in one process. Every program gets a bytecode machine and a
+//! thread of its own, and hands its output to the next one over a bounded
+//! channel, in the chunks [`Machine::run`] stops at. The bytes are handed over
+//! as they are, without being decoded as text, and a program is paused while
+//! the channel is full, until the next one reads from it.
+
+use std::{
+    fs,
+    io::{self, stderr, stdin, stdout, BufRead, IsTerminal, Write},
+    mem,
+    path::Path,
+    process::exit,
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread,
+};
+
+use brainfuck_extended::{
+    bytecode::{self, Dispatch, Machine, Program, Status, OUTPUT_CHUNK},
+    IoMode, Result, RuntimeError,
+};
+use codegen::verbosity;
+
+use crate::{compress, report, Engine, PipeOptions};
+
+/// How many ops a program runs before handing over the output it has so far
+const SLICE: u64 = 1 << 16;
+
+/// One program of the pipeline
+struct Stage {
+    /// The file name of the source code
+    name: String,
+    code: String,
+    program: Program,
+    machine: Machine,
+    dispatch: Dispatch,
+}
+
+impl Stage {
+    fn new(path: &Path, engine: Engine, hot_loop_threshold: u32) -> Result<Self> {
+        let code = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let program = Program::compile(&code).map_err(|e| format!("{}: {}", name, e))?;
+        let mut machine = Machine::new(IoMode::Text);
+        machine.hot_loop_threshold = hot_loop_threshold;
+
+        let dispatch = match engine {
+            Engine::Interpreter | Engine::Match => Dispatch::Match,
+            Engine::Threaded => Dispatch::Threaded,
+            Engine::TailCall => Dispatch::TailCall,
+            Engine::Auto => bytecode::fastest(&program, &machine)?,
+        };
+
+        Ok(Self {
+            name,
+            code,
+            program,
+            machine,
+            dispatch,
+        })
+    }
+
+    /// Runs the program for a slice, exiting with a report if it fails.
+    fn run(&mut self) -> Status {
+        let mut fuel = SLICE;
+        match self.machine.run(&self.program, self.dispatch, &mut fuel) {
+            Ok(status) => status,
+            Err(e) => {
+                let Some(error) = e.downcast_ref::<RuntimeError>() else {
+                    eprintln!("Error: {}: {}", self.name, e);
+                    exit(1);
+                };
+                let color = stderr().is_terminal();
+                eprint!(
+                    "{}",
+                    report::render(
+                        &self.name,
+                        &self.code,
+                        self.machine.memory.len(),
+                        error,
+                        color
+                    )
+                );
+                exit(1);
+            }
+        }
+    }
+
+    /// Runs the program to the end, reading from the program before it, or
+    /// stdin for the first one, and writing to the one after it, or stdout for
+    /// the last one.
+    fn pump(
+        mut self,
+        previous: Option<Receiver<Vec<u8>>>,
+        next: Option<SyncSender<Vec<u8>>>,
+    ) -> io::Result<()> {
+        loop {
+            let status = self.run();
+            let output = mem::take(&mut self.machine.output);
+            if !output.is_empty() {
+                match &next {
+                    Some(next) => {
+                        // like with SIGPIPE, nothing reads the output anymore
+                        if next.send(output).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    None => stdout().write_all(&output)?,
+                }
+            }
+
+            match status {
+                Status::Halted => {
+                    verbosity::info(format!(
+                        "{}: halted after {} steps",
+                        self.name, self.machine.steps
+                    ));
+                    // dropping the channel gives the next program EOF
+                    return stdout().flush();
+                }
+                Status::Output | Status::OutOfFuel => {}
+                Status::NeedsInput => {
+                    if next.is_none() {
+                        stdout().flush()?;
+                    }
+                    self.machine.drop_read_input();
+
+                    match &previous {
+                        Some(previous) => match previous.recv() {
+                            Ok(bytes) => self.machine.push_input(&bytes),
+                            // the program before halted
+                            Err(_) => self.machine.close_input(),
+                        },
+                        None => {
+                            let mut line = Vec::new();
+                            if stdin().lock().read_until(b'\n', &mut line)? == 0 {
+                                self.machine.close_input();
+                            } else {
+                                self.machine.push_input(&line);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn pipe(options: PipeOptions) -> Result {
+    if options.engine == Engine::Interpreter {
+        return Err(
+            "`bf pipe` runs programs on the bytecode engine, pick one with --engine".into(),
+        );
+    }
+
+    let mut stages = options
+        .programs
+        .iter()
+        .map(|path| Stage::new(path, options.engine, options.hot_loop_threshold))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(path) = &options.input {
+        stages[0].machine.push_input(&compress::read(path)?);
+        stages[0].machine.close_input();
+    }
+
+    // the chunks are at most a line or `OUTPUT_CHUNK` bytes
+    let capacity = (options.buffer / OUTPUT_CHUNK).max(1);
+    let mut previous = None;
+    let mut threads = Vec::new();
+    let count = stages.len();
+    for (index, stage) in stages.into_iter().enumerate() {
+        let input = previous.take();
+        let output = (index + 1 < count).then(|| {
+            let (sender, receiver) = sync_channel(capacity);
+            previous = Some(receiver);
+            sender
+        });
+        threads.push(thread::spawn(move || stage.pump(input, output)));
+    }
+
+    // the pipeline is done once the last program is, the others are either
+    // done too or would be stopped as soon as they write something
+    let last = threads.pop().expect("there is at least one program");
+    last.join().expect("a program's thread panicked")?;
+    for thread in threads.into_iter().filter(|thread| thread.is_finished()) {
+        thread.join().expect("a program's thread panicked")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! The `@pragma` directives of [`codegen::pragma`], as the engines here honor
+//! them. Cells are 8 bits wide, or big integers on the step-by-step
+//! interpreter (see [`crate::cell`]), so a program asking for 16 or 32 bits
+//! is rejected.
+
+use std::ops::Range;
+
+pub use codegen::pragma::{
+    CellWidth, Effective, Eof, Flags, PragmaPolicy, Pragmas, SemanticsProfile,
+};
+
+use crate::Result;
+
+/// Reads the pragmas of a program, failing on any that cannot be read or
+/// honored.
+pub fn read_pragmas(code: &str) -> Result<Effective> {
+    Ok(resolve(code, &Flags::default(), PragmaPolicy::default())?.0)
+}
+
+/// Merges the pragmas of a program with `flags` by `policy` (see
+/// [`codegen::pragma::resolve`]), failing on pragmas that cannot be read and
+/// on semantics the engines cannot honor. Returns a warning for every pragma or flag that was overridden.
+pub fn resolve(
+    code: &str,
+    flags: &Flags,
+    policy: PragmaPolicy,
+) -> Result<(Effective, Vec<String>)> {
+    if let Some(e) = codegen::pragma::parse_pragmas(code).1.into_iter().next() {
+        return Err(e.into());
+    }
+
+    let (effective, warnings) = codegen::pragma::resolve(code, flags, policy, CellWidth::Bits(8))?;
+    match effective.cell_size {
+        CellWidth::Bits(8) | CellWidth::BigInt => {}
+        CellWidth::Bits(bits) => {
+            return Err(format!(
+                "{}-bit cells are not supported, cells are 8 bits wide or big integers here (bf-codegen supports them)",
+                bits
+            )
+            .into())
+        }
+    }
+    Ok((effective, warnings))
+}
+
+/// The positions of the directives in characters, in order. They are skipped
+/// in a single step instead of executed.
+pub fn skipped(code: &str) -> Vec<Range<usize>> {
+    let ranges = codegen::pragma::skipped(code);
+    if ranges.is_empty() {
+        return ranges;
+    }
+
+    let chars: Vec<usize> = code.char_indices().map(|(byte, _)| byte).collect();
+    let position = |byte: usize| chars.partition_point(|&start| start < byte);
+    ranges
+        .into_iter()
+        .map(|range| position(range.start)..position(range.end))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_pragmas_and_wider_cells_are_rejected() {
+        let error = read_pragmas("@pragma eof -1\n+.").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: `@pragma eof` needs a value from 0 to 255"
+        );
+
+        let error = read_pragmas("@pragma cell-size 16\n+.").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("16-bit cells are not supported"));
+
+        // also when only the flag asks for them
+        let flags = Flags {
+            cell_size: Some(CellWidth::Bits(32)),
+            ..Flags::default()
+        };
+        assert!(resolve("+.", &flags, PragmaPolicy::Source).is_err());
+    }
+
+    #[test]
+    fn big_integer_cells_are_accepted() {
+        let effective = read_pragmas("@pragma cell-size bigint\n+.").unwrap();
+        assert_eq!(effective.cell_size, CellWidth::BigInt);
+    }
+
+    #[test]
+    fn a_conflict_is_an_error_only_by_the_policy() {
+        let flags = Flags {
+            eof: Some(Eof::Value(255)),
+            ..Flags::default()
+        };
+        let code = "@pragma eof 0\n,.";
+
+        let (effective, warnings) = resolve(code, &flags, PragmaPolicy::Source).unwrap();
+        assert_eq!(effective.eof, Some(0));
+        assert_eq!(warnings.len(), 1);
+
+        let error = resolve(code, &flags, PragmaPolicy::Error).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`@pragma eof 0` on line 1 conflicts with `--eof 255`"
+        );
+    }
+
+    #[test]
+    fn directives_are_skipped_by_characters() {
+        // `é` is two bytes but one character
+        let ranges = skipped("é\n@pragma eof 0\n+");
+        assert_eq!(ranges, vec![2..15]);
+    }
+}
@@ -0,0 +1,332 @@
+//! Lets the debugger drive a program compiled with `--codegen-style
+//! state-machine --debug-server` instead of the interpreter. The program's
+//! state is mirrored into a [`BrainfuckInterpreter`] after every batch of ops,
+//! so the debugger UI does not know the difference.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use brainfuck_extended::{
+    interpreter::decode_utf8, BrainfuckInterpreter, IoMode, OutputMode, Result,
+};
+
+use crate::{
+    engine::{Command, EngineHandle, Event, Sent, Snapshot},
+    stop::{Stop, StopConditions},
+};
+
+/// The version of the protocol spoken by the generated `serve`
+const PROTOCOL: &str = "bf-debug 1";
+
+/// How many ops run between two replies while continuing at full speed
+const BATCH_SIZE: u64 = 100_000;
+
+/// How many batches a second run while throttled
+const BATCHES_PER_SECOND: u64 = 60;
+
+/// Connects to a program listening on `addr` and starts driving it. `code` is
+/// the source it was compiled from, which the debugger shows.
+pub fn attach(
+    addr: &str,
+    code: &str,
+    output_mode: OutputMode,
+    stop: StopConditions,
+) -> Result<EngineHandle> {
+    let stream = TcpStream::connect(addr)
+        .map_err(|e| format!("failed to connect to the program at {}: {}", addr, e))?;
+    // every request is a single write, which should not wait for more
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut hello = String::new();
+    reader.read_line(&mut hello)?;
+    let positions = hello
+        .trim_end()
+        .strip_prefix(PROTOCOL)
+        .ok_or_else(|| {
+            format!(
+                "{} did not greet with `{}`, was it compiled with --debug-server?",
+                addr, PROTOCOL
+            )
+        })?
+        .trim();
+
+    // the program knows its ops by byte offset, the debugger by character
+    let offsets: Vec<usize> = code.char_indices().map(|(offset, _)| offset).collect();
+    let positions = positions
+        .split(',')
+        .filter(|position| !position.is_empty())
+        .map(|position| {
+            let offset: usize = position.parse()?;
+            offsets.binary_search(&offset).map_err(|_| {
+                format!(
+                    "the program has an op at byte {}, which is not in this source code",
+                    offset
+                )
+                .into()
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // a compiled program always writes plain bytes
+    let mirror = BrainfuckInterpreter::new(code, output_mode, IoMode::Text);
+
+    let (commands_tx, commands_rx) = channel();
+    let (events_tx, events_rx) = channel();
+
+    let thread = thread::spawn(move || {
+        let mut remote = Remote {
+            mirror,
+            positions,
+            stop,
+            events: events_tx,
+            reader,
+            writer: stream,
+            running: false,
+            speed: None,
+            throttle_start: (Instant::now(), 0),
+            sent: Sent::default(),
+        };
+        remote.run(commands_rx);
+        (remote.mirror, Vec::new())
+    });
+
+    Ok(EngineHandle {
+        commands: commands_tx,
+        events: events_rx,
+        thread,
+    })
+}
+
+struct Remote {
+    /// The program's state as of its last reply. A step is one op of the
+    /// state machine, which may be a whole run of repeated instructions
+    mirror: BrainfuckInterpreter,
+    /// The character in the source each op starts at
+    positions: Vec<usize>,
+    stop: StopConditions,
+    events: Sender<Event>,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+
+    running: bool,
+    /// The maximum number of ops per second, if throttled
+    speed: Option<u64>,
+    /// When the current throttled stretch of execution began, and the op count at that time
+    throttle_start: (Instant, u64),
+
+    sent: Sent,
+}
+
+/// Parses hex digits into bytes, with `-` for none.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex == "-" {
+        return Some(Vec::new());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl Remote {
+    fn snapshot(&mut self) -> Snapshot {
+        self.sent.snapshot(&self.mirror, 0)
+    }
+
+    fn send(&self, event: Event) {
+        // the UI going away means the session is over
+        self.events.send(event).ok();
+    }
+
+    fn pause(&mut self, reason: Option<String>) {
+        self.running = false;
+        let snapshot = self.snapshot();
+        self.send(Event::Paused(snapshot, reason));
+    }
+
+    /// Asks the program to run up to `ops` ops and mirrors its reply.
+    fn request(&mut self, ops: u64) -> std::result::Result<(), String> {
+        self.writer
+            .write_all(format!("run {}\n", ops).as_bytes())
+            .map_err(|e| format!("lost the connection to the program: {}", e))?;
+
+        let mut reply = String::new();
+        match self.reader.read_line(&mut reply) {
+            Ok(0) => return Err("the program closed the connection".to_string()),
+            Ok(_) => {}
+            Err(e) => return Err(format!("lost the connection to the program: {}", e)),
+        }
+
+        if let Some(error) = reply.trim_end().strip_prefix("error ") {
+            return Err(error.to_string());
+        }
+
+        let fields: Vec<&str> = reply.split_whitespace().collect();
+        let malformed = || format!("the program sent a malformed reply: {:?}", reply.trim_end());
+        let [ops, state, pointer, halted, output, tape] = fields[..] else {
+            return Err(malformed());
+        };
+
+        let i = &mut self.mirror;
+        i.steps = ops.parse().map_err(|_| malformed())?;
+        i.pointer = pointer.parse().map_err(|_| malformed())?;
+
+        let state: usize = state.parse().map_err(|_| malformed())?;
+        i.seek(if halted == "1" {
+            i.code.len()
+        } else {
+            *self.positions.get(state).ok_or_else(malformed)?
+        });
+
+        let tape = parse_hex(tape).ok_or_else(malformed)?;
+        for (cell, byte) in tape.into_iter().take(i.memory.len()).enumerate() {
+            i.memory.set_byte(cell, byte);
+        }
+
+        let output = parse_hex(output).ok_or_else(malformed)?;
+        i.stats.output_bytes += output.len() as u64;
+        i.pending_output.extend(output);
+        decode_utf8(&mut i.pending_output, &mut i.output);
+
+        Ok(())
+    }
+
+    /// Applies a command. Returns `false` once the thread should exit.
+    fn handle(&mut self, command: Command) -> bool {
+        match command {
+            // each state of the program is already about an op
+            Command::Step | Command::StepOp(_) => {
+                self.running = false;
+                self.execute(1);
+            }
+            Command::Continue => {
+                self.running = true;
+                self.reset_throttle();
+            }
+            Command::Pause => {
+                self.pause(None);
+            }
+            Command::RequestSnapshot => {
+                let snapshot = self.snapshot();
+                self.send(Event::Snapshot(snapshot));
+            }
+            Command::SetSpeed(speed) => {
+                self.speed = speed;
+                self.reset_throttle();
+            }
+            // the program reads its own stdin
+            Command::PushInput(_) | Command::CloseInput => {}
+            Command::Rewind => {
+                self.pause(Some(
+                    "rewinding is not possible with an attached program".to_string(),
+                ));
+            }
+            Command::SetBreakpoint(_) | Command::ClearBreakpoint(_) => {
+                self.pause(Some(
+                    "breakpoints on instructions are not possible with an attached program"
+                        .to_string(),
+                ));
+            }
+            Command::Quit => return false,
+        }
+
+        true
+    }
+
+    fn reset_throttle(&mut self) {
+        self.throttle_start = (Instant::now(), self.mirror.steps);
+    }
+
+    /// How long to wait before the next batch to stay under the speed limit.
+    fn throttle_delay(&self) -> Option<Duration> {
+        let speed = self.speed?;
+        let (start, start_steps) = self.throttle_start;
+
+        let due = Duration::from_secs_f64((self.mirror.steps - start_steps) as f64 / speed as f64);
+        due.checked_sub(start.elapsed())
+    }
+
+    fn run(&mut self, commands: Receiver<Command>) {
+        if let Err(e) = self.request(0) {
+            self.send(Event::Failed(e));
+            return;
+        }
+
+        let snapshot = self.snapshot();
+        self.send(Event::Paused(snapshot, None));
+
+        loop {
+            let command = if self.running {
+                if let Some(delay) = self.throttle_delay() {
+                    match commands.recv_timeout(delay) {
+                        Ok(command) => Some(command),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                } else {
+                    match commands.try_recv() {
+                        Ok(command) => Some(command),
+                        Err(TryRecvError::Empty) => None,
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+            } else {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                }
+            };
+
+            if let Some(command) = command {
+                if !self.handle(command) {
+                    return;
+                }
+            } else {
+                let batch = self.speed.map_or(BATCH_SIZE, |speed| {
+                    (speed / BATCHES_PER_SECOND).clamp(1, BATCH_SIZE)
+                });
+                if !self.execute(batch) {
+                    self.running = false;
+                }
+            }
+        }
+    }
+
+    /// Runs up to `ops` ops and reports anything the UI needs to know. The
+    /// stop conditions are only checked once they ran. Returns `false` if
+    /// execution cannot continue.
+    fn execute(&mut self, ops: u64) -> bool {
+        if let Err(e) = self.request(ops) {
+            self.send(Event::Failed(e));
+            return false;
+        }
+
+        let stop = self.stop.check(&self.mirror).map(Stop::into_reason);
+
+        if self.mirror.halted() {
+            let snapshot = self.snapshot();
+            self.send(Event::Halted(snapshot, stop));
+            return false;
+        }
+
+        if let Some(reason) = stop {
+            self.running = false;
+            let snapshot = self.snapshot();
+            self.send(Event::Stopped(snapshot, reason));
+            return false;
+        }
+
+        if !self.running {
+            self.pause(None);
+        }
+
+        true
+    }
+}
@@ -0,0 +1,160 @@
+//! Reports of runtime errors that point at the instruction that failed, with
+//! the state of the tape at that moment and hints about what likely went
+//! wrong, like a loop walking the pointer off the start of the tape.
+
+use std::fmt::Write;
+
+use brainfuck_extended::{BracketTable, RuntimeError};
+use codegen::{
+    ast::{File, Repeated},
+    link::net_movement,
+    pragma::parse_pragmas,
+};
+use crossterm::style::Stylize;
+
+/// How many characters of a long line are shown around the failing one
+const WINDOW: usize = 60;
+
+/// Renders `error` as a report on `code`, which was read from `name` and ran
+/// on a tape of `cells` cells.
+pub fn render(name: &str, code: &str, cells: usize, error: &RuntimeError, color: bool) -> String {
+    let chars: Vec<char> = code.chars().collect();
+    let (line, column) = line_and_column(&chars, error.position);
+    let source = chars
+        .split(|&c| c == '\n')
+        .nth(line - 1)
+        .unwrap_or_default();
+
+    // only a window of long lines fits, and columns start at 1
+    let start = (column - 1).saturating_sub(WINDOW / 2);
+    let end = (start + WINDOW).min(source.len());
+    let start = end.saturating_sub(WINDOW).min(start);
+    let mut shown: String = source[start..end].iter().collect();
+    if start > 0 {
+        shown.insert_str(0, "...");
+    }
+    if end < source.len() {
+        shown.push_str("...");
+    }
+    let marker_column = column - 1 - start + if start > 0 { 3 } else { 0 };
+
+    let gutter = " ".repeat(line.to_string().len());
+    let paint = |text: String, style: fn(String) -> String| {
+        if color {
+            style(text)
+        } else {
+            text
+        }
+    };
+    let blue = |text: String| text.blue().bold().to_string();
+
+    let mut out = String::new();
+    let heading = paint("error".to_string(), |text| text.red().bold().to_string());
+    writeln!(out, "{}: {}", heading, error.message).unwrap();
+    writeln!(
+        out,
+        "{}{} {}:{}:{}",
+        gutter,
+        paint("-->".to_string(), blue),
+        name,
+        line,
+        column
+    )
+    .unwrap();
+    writeln!(out, "{} {}", gutter, paint("|".to_string(), blue)).unwrap();
+    writeln!(
+        out,
+        "{} {} {}",
+        paint(line.to_string(), blue),
+        paint("|".to_string(), blue),
+        shown
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{} {} {}{}",
+        gutter,
+        paint("|".to_string(), blue),
+        " ".repeat(marker_column),
+        paint("^".to_string(), |text| text.red().bold().to_string())
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{} {} pointer: cell {}, holding {}",
+        gutter,
+        paint("=".to_string(), blue),
+        error.pointer,
+        error.cell
+    )
+    .unwrap();
+    for hint in hints(code, &chars, cells, error) {
+        writeln!(
+            out,
+            "{} {} hint: {}",
+            gutter,
+            paint("=".to_string(), blue),
+            hint
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+/// The line and column of the character at `position`, both starting at 1.
+/// Positions past the end are just past the last character.
+pub(crate) fn line_and_column(chars: &[char], position: usize) -> (usize, usize) {
+    let before = &chars[..position.min(chars.len())];
+    let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|&c| c == '\n')
+        .map_or(0, |newline| newline + 1);
+
+    (line, before.len() - line_start + 1)
+}
+
+/// Guesses at the cause of `error` from where it happened.
+fn hints(code: &str, chars: &[char], cells: usize, error: &RuntimeError) -> Vec<String> {
+    let mut hints = Vec::new();
+
+    if chars.get(error.position) == Some(&']') && error.message.contains("unmatched") {
+        hints.push("this `]` closes no `[`, so it has nowhere to jump back to".to_string());
+    }
+
+    if chars.get(error.position) == Some(&'.')
+        && error.cell.to_byte().is_some_and(|byte| !byte.is_ascii())
+    {
+        hints.push(format!(
+            "{} is not an ASCII character; with `--output-mode utf8` cells are written as UTF-8 bytes",
+            error.cell
+        ));
+    }
+
+    // the pointer stops at either end of the tape, so a loop that keeps
+    // moving it that way piles up there
+    let table = BracketTable::new(chars);
+    let wraps = parse_pragmas(code).0.wrap_pointer;
+    if let Some((start, end)) = table.enclosing_loop(error.position).filter(|_| !wraps) {
+        let body: String = chars[start + 1..end].iter().collect();
+        let file: File<Repeated> = match body.parse() {
+            Ok(file) => file,
+            Err(e) => match e {},
+        };
+
+        match net_movement(&file.segments) {
+            Ok(movement) if movement < 0 && error.pointer == 0 => hints.push(format!(
+                "this loop moves the pointer left by {} each iteration, and the pointer stops at cell 0 instead of moving past it",
+                -movement
+            )),
+            Ok(movement) if movement > 0 && error.pointer == cells - 1 => hints.push(format!(
+                "this loop moves the pointer right by {} each iteration, and the pointer stops at the last cell instead of moving past it",
+                movement
+            )),
+            _ => {}
+        }
+    }
+
+    hints
+}
@@ -0,0 +1,317 @@
+//! Running programs nobody has vetted, like the ones sent to a playground or
+//! a judge. [`run`] puts a bound on everything a program can use: the
+//! instructions it executes, the time they take, the output it writes and the
+//! memory it holds. It rejects unmatched brackets and `@pragma` directives it
+//! cannot honor (see [`crate::pragma`]) before running anything, and never
+//! reads files on the program's behalf (`@assert` directives are plain
+//! comments in it and no fusion table is loaded). Every way a run can fail is
+//! a [`SandboxError`], which serializes to JSON along with the rest of the
+//! [`SandboxReport`].
+//!
+//! The defaults of [`SandboxLimits`] are meant to be safe for a service
+//! handling many small requests.
+
+use std::{
+    fmt,
+    mem::size_of,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bytecode::{Dispatch, Instr, Machine, Program, Status},
+    fusion::FusionTable,
+    interpreter::decode_utf8,
+    pragma::{read_pragmas, CellWidth},
+    IoMode, MEMORY_SIZE,
+};
+
+/// How many ops run between checks of the time and memory limits
+const CHECK_INTERVAL: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a sandboxed program may use
+pub struct SandboxLimits {
+    /// The longest source accepted, in bytes
+    pub max_code: usize,
+    /// The most input accepted, in bytes
+    pub max_input: usize,
+    /// How many instructions may run
+    pub max_steps: u64,
+    /// How long the run may take
+    pub timeout: Duration,
+    /// How many bytes may be written
+    pub max_output: usize,
+    /// How many bytes the tape, the code, the input and the output may take
+    /// together
+    pub max_memory: usize,
+    pub io_mode: IoMode,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            max_code: 64 * 1024,
+            max_input: 64 * 1024,
+            max_steps: 100_000_000,
+            timeout: Duration::from_secs(2),
+            max_output: 64 * 1024,
+            max_memory: 4 * 1024 * 1024,
+            io_mode: IoMode::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// Why a sandboxed run failed
+pub enum SandboxError {
+    /// A bracket without a partner, found before anything ran
+    UnmatchedBracket {
+        position: usize,
+        bracket: char,
+    },
+    /// A `@pragma` directive that cannot be read or honored, found before
+    /// anything ran
+    Pragma {
+        reason: String,
+    },
+    CodeTooLong {
+        limit: usize,
+    },
+    InputTooLong {
+        limit: usize,
+    },
+    StepLimit {
+        limit: u64,
+    },
+    Timeout {
+        limit_ms: u64,
+    },
+    OutputLimit {
+        limit: usize,
+    },
+    MemoryLimit {
+        limit: usize,
+    },
+    /// The engine itself failed, like on invalid numeric input
+    Runtime {
+        message: String,
+    },
+}
+
+impl SandboxError {
+    /// The `kind` it serializes with, like `step_limit`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UnmatchedBracket { .. } => "unmatched_bracket",
+            Self::Pragma { .. } => "pragma",
+            Self::CodeTooLong { .. } => "code_too_long",
+            Self::InputTooLong { .. } => "input_too_long",
+            Self::StepLimit { .. } => "step_limit",
+            Self::Timeout { .. } => "timeout",
+            Self::OutputLimit { .. } => "output_limit",
+            Self::MemoryLimit { .. } => "memory_limit",
+            Self::Runtime { .. } => "runtime",
+        }
+    }
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnmatchedBracket { position, bracket } => {
+                write!(f, "unmatched {} at position {}", bracket, position)
+            }
+            Self::Pragma { reason } => write!(f, "{}", reason),
+            Self::CodeTooLong { limit } => write!(f, "the code is longer than {} bytes", limit),
+            Self::InputTooLong { limit } => write!(f, "the input is longer than {} bytes", limit),
+            Self::StepLimit { limit } => write!(f, "did not halt within {} steps", limit),
+            Self::Timeout { limit_ms } => write!(f, "did not halt within {}ms", limit_ms),
+            Self::OutputLimit { limit } => write!(f, "wrote more than {} bytes", limit),
+            Self::MemoryLimit { limit } => write!(f, "used more than {} bytes of memory", limit),
+            Self::Runtime { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// A [`SandboxError`] with its description, for clients that only show it
+pub struct ReportedError {
+    #[serde(flatten)]
+    pub error: SandboxError,
+    pub message: String,
+}
+
+impl From<SandboxError> for ReportedError {
+    fn from(error: SandboxError) -> Self {
+        Self {
+            message: error.to_string(),
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+/// How a sandboxed run went. A failed run still has the output (up to the
+/// limit) and steps of everything before it failed.
+pub struct SandboxReport {
+    pub ok: bool,
+    pub error: Option<ReportedError>,
+    pub output: String,
+    pub steps: u64,
+    pub wall_time_secs: f64,
+}
+
+/// Pairs up the brackets, returning the first one without a partner.
+pub fn check_brackets(code: &str) -> Result<(), SandboxError> {
+    let mut open = Vec::new();
+
+    for (position, c) in code.chars().enumerate() {
+        match c {
+            '[' => open.push(position),
+            ']' => {
+                open.pop().ok_or(SandboxError::UnmatchedBracket {
+                    position,
+                    bracket: ']',
+                })?;
+            }
+            _ => {}
+        }
+    }
+
+    match open.first() {
+        Some(&position) => Err(SandboxError::UnmatchedBracket {
+            position,
+            bracket: '[',
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks everything that can be checked before the program runs: the size
+/// of the code and the input, the brackets and the pragmas.
+pub fn check_source(code: &str, input: &[u8], limits: &SandboxLimits) -> Result<(), SandboxError> {
+    if code.len() > limits.max_code {
+        return Err(SandboxError::CodeTooLong {
+            limit: limits.max_code,
+        });
+    }
+    if input.len() > limits.max_input {
+        return Err(SandboxError::InputTooLong {
+            limit: limits.max_input,
+        });
+    }
+    check_brackets(code)?;
+    let semantics = read_pragmas(code).map_err(|e| SandboxError::Pragma {
+        reason: e.to_string(),
+    })?;
+    if semantics.cell_size == CellWidth::BigInt {
+        return Err(SandboxError::Pragma {
+            reason: "big-integer cells are not supported in the sandbox".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Runs the program on the bytecode engine with the input closed after
+/// `input`, stopping at the first limit it reaches.
+pub fn run(code: &str, input: &[u8], limits: &SandboxLimits) -> SandboxReport {
+    let start = Instant::now();
+    let mut output = String::new();
+    let mut steps = 0;
+
+    let result = execute(code, input, limits, &mut output, &mut steps);
+
+    SandboxReport {
+        ok: result.is_ok(),
+        error: result.err().map(ReportedError::from),
+        output,
+        steps,
+        wall_time_secs: start.elapsed().as_secs_f64(),
+    }
+}
+
+fn execute(
+    code: &str,
+    input: &[u8],
+    limits: &SandboxLimits,
+    output: &mut String,
+    steps: &mut u64,
+) -> Result<(), SandboxError> {
+    let runtime = |e: Box<dyn std::error::Error>| SandboxError::Runtime {
+        message: e.to_string(),
+    };
+
+    check_source(code, input, limits)?;
+
+    let program = Program::compile_with(code, &FusionTable::default()).map_err(runtime)?;
+    let mut machine = Machine::new(limits.io_mode);
+    machine.push_input(input);
+    machine.close_input();
+
+    // everything but the output is known up front
+    let fixed = MEMORY_SIZE + code.len() + input.len() + program.instrs.len() * size_of::<Instr>();
+    let start = Instant::now();
+    let mut written = 0;
+    let mut pending = Vec::new();
+
+    loop {
+        // every op runs at least one instruction, so this stops right after the limit
+        let mut fuel = CHECK_INTERVAL.min(
+            limits
+                .max_steps
+                .saturating_sub(machine.steps)
+                .saturating_add(1),
+        );
+        let status = machine
+            .run(&program, Dispatch::Threaded, &mut fuel)
+            .map_err(runtime);
+        // it runs past the limit to find out that it goes over, which is
+        // not reported as steps it was allowed
+        *steps = machine.steps.min(limits.max_steps);
+
+        written += machine.output.len();
+        let allowed = machine
+            .output
+            .len()
+            .saturating_sub(written.saturating_sub(limits.max_output));
+        pending.extend_from_slice(&machine.output[..allowed]);
+        machine.output.clear();
+        decode_utf8(&mut pending, output);
+
+        let status = status?;
+        if written > limits.max_output {
+            return Err(SandboxError::OutputLimit {
+                limit: limits.max_output,
+            });
+        }
+        if machine.steps > limits.max_steps {
+            return Err(SandboxError::StepLimit {
+                limit: limits.max_steps,
+            });
+        }
+        if fixed + written > limits.max_memory {
+            return Err(SandboxError::MemoryLimit {
+                limit: limits.max_memory,
+            });
+        }
+        if start.elapsed() > limits.timeout {
+            return Err(SandboxError::Timeout {
+                limit_ms: limits.timeout.as_millis() as u64,
+            });
+        }
+
+        match status {
+            // the input is closed, so `,` never waits
+            Status::Output | Status::OutOfFuel | Status::NeedsInput => {}
+            Status::Halted => {
+                if !pending.is_empty() {
+                    output.push(char::REPLACEMENT_CHARACTER);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Finding 0 cells for scan loops like `[>]`. With the `simd` feature (which
+//! needs a nightly compiler) whole vectors of cells are compared at once,
+//! otherwise the cells are searched one by one.
+
+/// The index of the first 0 in `cells`
+pub fn find_zero(cells: &[u8]) -> Option<usize> {
+    #[cfg(feature = "simd")]
+    return vector::find_zero(cells);
+    #[cfg(not(feature = "simd"))]
+    return scalar::find_zero(cells);
+}
+
+/// The index of the last 0 in `cells`
+pub fn rfind_zero(cells: &[u8]) -> Option<usize> {
+    #[cfg(feature = "simd")]
+    return vector::rfind_zero(cells);
+    #[cfg(not(feature = "simd"))]
+    return scalar::rfind_zero(cells);
+}
+
+mod scalar {
+    pub fn find_zero(cells: &[u8]) -> Option<usize> {
+        cells.iter().position(|cell| *cell == 0)
+    }
+
+    pub fn rfind_zero(cells: &[u8]) -> Option<usize> {
+        cells.iter().rposition(|cell| *cell == 0)
+    }
+}
+
+#[cfg(feature = "simd")]
+mod vector {
+    use std::simd::{cmp::SimdPartialEq, u8x32};
+
+    use super::scalar;
+
+    const LANES: usize = 32;
+
+    /// A bit for each of the cells that is 0
+    fn zeros(chunk: &[u8]) -> u64 {
+        u8x32::from_slice(chunk)
+            .simd_eq(u8x32::splat(0))
+            .to_bitmask()
+    }
+
+    pub fn find_zero(cells: &[u8]) -> Option<usize> {
+        let mut chunks = cells.chunks_exact(LANES);
+        let mut start = 0;
+
+        for chunk in &mut chunks {
+            let zeros = zeros(chunk);
+            if zeros != 0 {
+                return Some(start + zeros.trailing_zeros() as usize);
+            }
+            start += LANES;
+        }
+
+        scalar::find_zero(chunks.remainder()).map(|i| start + i)
+    }
+
+    pub fn rfind_zero(cells: &[u8]) -> Option<usize> {
+        let mut chunks = cells.rchunks_exact(LANES);
+        let mut start = cells.len();
+
+        for chunk in &mut chunks {
+            start -= LANES;
+            let zeros = zeros(chunk);
+            if zeros != 0 {
+                return Some(start + 63 - zeros.leading_zeros() as usize);
+            }
+        }
+
+        // the cells before the first full chunk
+        scalar::rfind_zero(chunks.remainder())
+    }
+}
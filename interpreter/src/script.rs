@@ -0,0 +1,300 @@
+//! Runs the debugger from a file of gdb-style commands instead of the
+//! keyboard, so a debugging session can be repeated, e.g. in a regression test.
+//!
+//! Every line holds one command, and `#` starts a comment:
+//!
+//! - `break <pos>` (`b`): stop before executing the instruction at this code position
+//! - `delete <pos>` (`d`): remove that breakpoint again
+//! - `step [<count>]` (`s`): execute this many instructions (default 1)
+//! - `stepi [<count>]` (`si`): execute this many bytecode ops (default 1), as
+//!   listed by `bf disasm`
+//! - `continue` (`c`): execute until a breakpoint is hit, the program halts or it needs input
+//! - `print` (`p`): print the step count, code position, pointer and current cell
+//! - `dump <start>..<end> [<file>] [<format>]`: write the cells in the range
+//!   (`..=` includes the end) to the file, or stdout without one, as `hex`,
+//!   `raw` bytes or a `rust` array literal. Files get raw bytes by default,
+//!   stdout gets hex
+//! - `input <text>`: append the text to the input, with `\n`, `\t`, `\\` and
+//!   `\xNN` escapes
+//! - `eof`: close the input
+//! - `quit` (`q`): end the session, ignoring the rest of the script
+
+use std::{
+    io::{stdout, Write},
+    sync::Arc,
+};
+
+use brainfuck_extended::{bytecode::Program, FusionTable, Result};
+use codegen::verbosity;
+
+use crate::{
+    disasm::compile,
+    dump::Dump,
+    engine::{Command, EngineHandle, Event, Snapshot, View},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptCommand {
+    Break(usize),
+    Delete(usize),
+    Step(u64),
+    StepOp(u64),
+    Continue,
+    Print,
+    Dump(Dump),
+    Input(Vec<u8>),
+    Eof,
+    Quit,
+}
+
+/// Decodes the escapes of an `input` command.
+pub(crate) fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("`\\x{}` is not a byte", hex))?;
+                bytes.push(byte);
+            }
+            other => {
+                return Err(format!("unknown escape `\\{}`", other.unwrap_or(' ')).into());
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+impl ScriptCommand {
+    fn parse(line: &str) -> Result<Self> {
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+
+        let position = || -> Result<usize> {
+            args.parse()
+                .map_err(|_| format!("`{}` expects a code position", name).into())
+        };
+        let no_args = |command: Self| -> Result<Self> {
+            if args.is_empty() {
+                Ok(command)
+            } else {
+                Err(format!("`{}` takes no arguments", name).into())
+            }
+        };
+
+        match name {
+            "break" | "b" => Ok(Self::Break(position()?)),
+            "delete" | "d" => Ok(Self::Delete(position()?)),
+            "step" | "s" if args.is_empty() => Ok(Self::Step(1)),
+            "step" | "s" => {
+                Ok(Self::Step(args.parse().map_err(|_| {
+                    format!("`{}` expects a number of instructions", name)
+                })?))
+            }
+            "stepi" | "si" if args.is_empty() => Ok(Self::StepOp(1)),
+            "stepi" | "si" => {
+                Ok(Self::StepOp(args.parse().map_err(|_| {
+                    format!("`{}` expects a number of ops", name)
+                })?))
+            }
+            "continue" | "c" => no_args(Self::Continue),
+            "print" | "p" => no_args(Self::Print),
+            "dump" => Ok(Self::Dump(Dump::parse(args)?)),
+            "input" => Ok(Self::Input(unescape(args)?)),
+            "eof" => no_args(Self::Eof),
+            "quit" | "q" => no_args(Self::Quit),
+            _ => Err(format!("unknown command `{}`", name).into()),
+        }
+    }
+}
+
+/// Parses a whole script, reporting the line of the first invalid command.
+pub fn parse_script(script: &str) -> Result<Vec<ScriptCommand>> {
+    script
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            (!line.is_empty()).then(|| {
+                ScriptCommand::parse(line).map_err(|e| format!("line {}: {}", i + 1, e).into())
+            })
+        })
+        .collect()
+}
+
+/// Drives the engine through the commands, stepping through the bytecode ops
+/// `fusions` gives with `stepi`. The program's output is printed to stdout as
+/// it is produced, and what the script reports to stderr.
+pub fn run_script(
+    engine: &EngineHandle,
+    view: &mut View,
+    commands: &[ScriptCommand],
+    fusions: &FusionTable,
+) -> Result {
+    let mut session = Session {
+        engine,
+        view,
+        printed: 0,
+    };
+    // the bytecode, once a script steps by its ops
+    let mut program: Option<Arc<Program>> = None;
+
+    // the engine starts by reporting where the program starts
+    session.wait()?;
+
+    for command in commands {
+        let view = &session.view;
+        match command {
+            ScriptCommand::Break(pos) => {
+                if *pos >= view.code.len() {
+                    return Err(format!(
+                        "cannot break at position {}, the program is {} characters long",
+                        pos,
+                        view.code.len()
+                    )
+                    .into());
+                }
+                engine.send(Command::SetBreakpoint(*pos));
+            }
+            ScriptCommand::Delete(pos) => engine.send(Command::ClearBreakpoint(*pos)),
+            ScriptCommand::Step(count) => {
+                for _ in 0..*count {
+                    engine.send(Command::Step);
+                    if !session.wait()? {
+                        break;
+                    }
+                }
+            }
+            ScriptCommand::StepOp(count) => {
+                let program = match &program {
+                    Some(program) => program.clone(),
+                    None => {
+                        let code: String = view.code.iter().collect();
+                        let compiled = compile(&code, fusions)
+                            .map_err(|e| format!("cannot step by op: {}", e))?;
+                        program.insert(Arc::new(compiled)).clone()
+                    }
+                };
+                for _ in 0..*count {
+                    engine.send(Command::StepOp(program.clone()));
+                    if !session.wait()? {
+                        break;
+                    }
+                }
+            }
+            ScriptCommand::Continue => {
+                engine.send(Command::Continue);
+                session.wait()?;
+            }
+            ScriptCommand::Print => {
+                let cell = view.memory.get(view.pointer);
+                // a big integer is only a character if it is a byte
+                let char = match cell.to_byte() {
+                    Some(byte) => format!(" ({:?})", byte as char),
+                    None => String::new(),
+                };
+                println!(
+                    "step {}, position {}, pointer {}, cell {}{}",
+                    view.steps, view.code_pos, view.pointer, cell, char
+                );
+            }
+            ScriptCommand::Dump(dump) => {
+                if let Some(dump) = dump.write(&view.memory)? {
+                    let mut stdout = stdout();
+                    stdout.write_all(&dump)?;
+                    stdout.flush()?;
+                }
+            }
+            ScriptCommand::Input(bytes) => engine.send(Command::PushInput(bytes.clone())),
+            ScriptCommand::Eof => engine.send(Command::CloseInput),
+            ScriptCommand::Quit => break,
+        }
+    }
+
+    Ok(())
+}
+
+struct Session<'a> {
+    engine: &'a EngineHandle,
+    view: &'a mut View,
+    /// How much of the output was printed
+    printed: usize,
+}
+
+impl Session<'_> {
+    /// Brings the view up to date and prints the new output.
+    fn apply(&mut self, snapshot: Snapshot) -> Result {
+        self.view.apply(snapshot);
+
+        let mut stdout = stdout();
+        stdout.write_all(&self.view.output.as_bytes()[self.printed..])?;
+        stdout.flush()?;
+        self.printed = self.view.output.len();
+
+        Ok(())
+    }
+
+    /// Waits for the engine to pause again, reporting why if it did not
+    /// simply finish a step. Returns `false` if the program cannot go on
+    /// without something changing, i.e. it halted, hit a breakpoint or needs
+    /// input.
+    fn wait(&mut self) -> Result<bool> {
+        loop {
+            let event = self
+                .engine
+                .events
+                .recv()
+                .map_err(|_| "the interpreter thread stopped unexpectedly")?;
+
+            match event {
+                Event::Snapshot(snapshot) => self.apply(snapshot)?,
+                Event::Paused(snapshot, reason) => {
+                    self.apply(snapshot)?;
+                    if let Some(reason) = reason {
+                        verbosity::status(format!("Paused: {}", reason));
+                    }
+                    return Ok(true);
+                }
+                Event::Stopped(snapshot, reason) => {
+                    self.apply(snapshot)?;
+                    verbosity::status(format!("Stopped at step {}: {}", self.view.steps, reason));
+                    return Ok(false);
+                }
+                Event::NeedsInput(snapshot) => {
+                    self.apply(snapshot)?;
+                    verbosity::status(format!("Waiting for input at step {}", self.view.steps));
+                    return Ok(false);
+                }
+                Event::Halted(snapshot, reason) => {
+                    self.apply(snapshot)?;
+                    match reason {
+                        Some(reason) => verbosity::status(format!(
+                            "Halted after {} steps: {}",
+                            self.view.steps, reason
+                        )),
+                        None => {
+                            verbosity::status(format!("Halted after {} steps", self.view.steps))
+                        }
+                    }
+                    return Ok(false);
+                }
+                Event::Notice(notice) => verbosity::warn(notice),
+                Event::Failed(e) => return Err(e.into()),
+                Event::Crashed(e) => return Err(e.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Counters describing how a program ran, for asserting on more than its
+//! output. They only depend on the program and its input, never on timing.
+
+use std::fmt;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// What the interpreter did so far
+pub struct ExecutionStats {
+    /// `+`
+    pub increments: u64,
+    /// `-`
+    pub decrements: u64,
+    /// `>`
+    pub moves_right: u64,
+    /// `<`
+    pub moves_left: u64,
+    /// `.`
+    pub writes: u64,
+    /// `,`, not counting the ones that had to wait for input
+    pub reads: u64,
+    /// `[`
+    pub loop_starts: u64,
+    /// `]`
+    pub loop_ends: u64,
+    /// The characters that are not instructions passed over, and the `#`s
+    /// and `@`s, which take a step (a directive as a whole is one)
+    pub comments: u64,
+    /// How often a `]` jumped back to the start of its loop
+    pub loop_iterations: u64,
+    /// The input bytes consumed by `,`
+    pub input_bytes: u64,
+    /// The bytes written by `.`, which is more than one per `.` for numeric I/O
+    pub output_bytes: u64,
+    /// The highest cell the pointer was on
+    pub max_pointer: usize,
+}
+
+impl ExecutionStats {
+    /// Counts `times` executions of `c`.
+    pub(crate) fn count(&mut self, c: char, times: u64) {
+        let counter = match c {
+            '+' => &mut self.increments,
+            '-' => &mut self.decrements,
+            '>' => &mut self.moves_right,
+            '<' => &mut self.moves_left,
+            '.' => &mut self.writes,
+            ',' => &mut self.reads,
+            '[' => &mut self.loop_starts,
+            ']' => &mut self.loop_ends,
+            _ => &mut self.comments,
+        };
+        *counter += times;
+    }
+
+    /// What happened since the stats were `earlier`, except for `max_pointer`,
+    /// which is left at 0.
+    pub(crate) fn since(&self, earlier: &Self) -> Self {
+        Self {
+            increments: self.increments - earlier.increments,
+            decrements: self.decrements - earlier.decrements,
+            moves_right: self.moves_right - earlier.moves_right,
+            moves_left: self.moves_left - earlier.moves_left,
+            writes: self.writes - earlier.writes,
+            reads: self.reads - earlier.reads,
+            loop_starts: self.loop_starts - earlier.loop_starts,
+            loop_ends: self.loop_ends - earlier.loop_ends,
+            comments: self.comments - earlier.comments,
+            loop_iterations: self.loop_iterations - earlier.loop_iterations,
+            input_bytes: self.input_bytes - earlier.input_bytes,
+            output_bytes: self.output_bytes - earlier.output_bytes,
+            max_pointer: 0,
+        }
+    }
+
+    /// Adds the counters of `other`, except for `max_pointer`.
+    pub(crate) fn add(&mut self, other: &Self) {
+        self.increments += other.increments;
+        self.decrements += other.decrements;
+        self.moves_right += other.moves_right;
+        self.moves_left += other.moves_left;
+        self.writes += other.writes;
+        self.reads += other.reads;
+        self.loop_starts += other.loop_starts;
+        self.loop_ends += other.loop_ends;
+        self.comments += other.comments;
+        self.loop_iterations += other.loop_iterations;
+        self.input_bytes += other.input_bytes;
+        self.output_bytes += other.output_bytes;
+    }
+
+    /// Every instruction executed, including the comments passed over
+    pub fn instructions(&self) -> u64 {
+        self.increments
+            + self.decrements
+            + self.moves_right
+            + self.moves_left
+            + self.writes
+            + self.reads
+            + self.loop_starts
+            + self.loop_ends
+            + self.comments
+    }
+}
+
+impl fmt::Display for ExecutionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instructions:    {}", self.instructions())?;
+        for (name, count) in [
+            ("+", self.increments),
+            ("-", self.decrements),
+            (">", self.moves_right),
+            ("<", self.moves_left),
+            (".", self.writes),
+            (",", self.reads),
+            ("[", self.loop_starts),
+            ("]", self.loop_ends),
+            ("comments", self.comments),
+        ] {
+            writeln!(f, "  {:<13} {}", name, count)?;
+        }
+        writeln!(f, "loop iterations: {}", self.loop_iterations)?;
+        writeln!(f, "input bytes:     {}", self.input_bytes)?;
+        writeln!(f, "output bytes:    {}", self.output_bytes)?;
+        write!(f, "max pointer:     {}", self.max_pointer)
+    }
+}
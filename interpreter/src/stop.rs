@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use brainfuck_extended::{
+    BrainfuckInterpreter, Expectation, OutputBreakpoint, OutputPattern, Result,
+};
+use regex::Regex;
+
+use crate::{compress, RunOptions};
+
+/// Why execution should stop
+pub enum Stop {
+    /// A breakpoint was hit
+    Break(String),
+    /// The output diverged from the expected output. Holds a short message
+    /// and the full diff context
+    Diverged(String, String),
+}
+
+impl Stop {
+    /// The short, single-line description of why execution stopped.
+    pub fn into_reason(self) -> String {
+        match self {
+            Self::Break(reason) | Self::Diverged(reason, _) => reason,
+        }
+    }
+}
+
+/// Conditions checked after every step that stop a headless run or pause the debugger
+#[derive(Clone)]
+pub struct StopConditions {
+    output_break: Option<OutputBreakpoint>,
+    expect: Option<Expectation>,
+}
+
+impl StopConditions {
+    pub fn from_options(options: &RunOptions) -> Result<Self> {
+        Self::from_flags(
+            &options.break_on_output,
+            &options.break_on_output_regex,
+            &options.expect,
+        )
+    }
+
+    /// The conditions given by `--break-on-output`, `--break-on-output-regex`
+    /// and `--expect`
+    pub fn from_flags(
+        break_on_output: &Option<String>,
+        break_on_output_regex: &Option<Regex>,
+        expect: &Option<PathBuf>,
+    ) -> Result<Self> {
+        let pattern = if let Some(substring) = break_on_output {
+            Some(OutputPattern::Substring(substring.clone()))
+        } else {
+            break_on_output_regex.clone().map(OutputPattern::Regex)
+        };
+
+        let expect = if let Some(path) = expect {
+            Some(Expectation::new(compress::read(path)?))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            output_break: pattern.map(OutputBreakpoint::new),
+            expect,
+        })
+    }
+
+    /// Returns the reason execution should stop, if any.
+    pub fn check(&mut self, i: &BrainfuckInterpreter) -> Option<Stop> {
+        if let Some(expect) = &mut self.expect {
+            let output = i.output.as_bytes();
+            let mismatch = if i.halted() {
+                expect.finish(output)
+            } else {
+                expect.check(output)
+            };
+
+            if let Some(mismatch) = mismatch {
+                return Some(Stop::Diverged(
+                    mismatch.to_string(),
+                    expect.context(output, &mismatch),
+                ));
+            }
+        }
+
+        if let Some(output_break) = &mut self.output_break {
+            if output_break.check(&i.output) {
+                return Some(Stop::Break(format!(
+                    "output matched {}",
+                    output_break.pattern
+                )));
+            }
+        }
+
+        None
+    }
+}
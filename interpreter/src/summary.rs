@@ -0,0 +1,95 @@
+//! The end-of-run document written by `--summary-json`, so CI and graders can
+//! check a run without scraping its output.
+
+use std::time::Duration;
+
+use brainfuck_extended::{
+    hash::fnv1a, pragma::Effective, BrainfuckInterpreter, ExecutionStats, MemoryUsage, PhaseStats,
+};
+
+/// How many characters of the output the summary includes
+const PREVIEW_LENGTH: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// Why the run ended
+pub enum ExitReason {
+    /// The end of the program was reached
+    Halted,
+    /// A breakpoint stopped a headless run
+    Stopped { reason: String },
+    /// The debugger was quit before the program halted
+    Quit,
+    /// A runtime error, or output that differed from `--expect`
+    Failed { error: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputSummary {
+    /// The length of the output text in bytes
+    pub bytes: usize,
+    /// The FNV-1a hash of the whole output text, in hex
+    pub hash: String,
+    /// The start of the output
+    pub preview: String,
+    /// Whether `preview` is only part of the output
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Resources {
+    pub wall_time_secs: f64,
+    /// What the interpreter held on to when the run ended
+    pub memory: MemoryUsage,
+    pub memory_bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub exit: ExitReason,
+    pub steps: u64,
+    pub stats: ExecutionStats,
+    /// The `@phase` sections of the program, in the order they were first
+    /// entered
+    pub phases: Vec<PhaseStats>,
+    /// The semantics the program ran with, from its pragmas and the flags
+    pub semantics: Effective,
+    /// Lint diagnostics, pragmas or flags that were overridden and anything
+    /// given up to stay under `--max-rss`
+    pub warnings: Vec<String>,
+    pub resources: Resources,
+    pub output: OutputSummary,
+}
+
+impl Summary {
+    pub fn new(
+        exit: ExitReason,
+        i: &BrainfuckInterpreter,
+        semantics: Effective,
+        warnings: Vec<String>,
+        wall_time: Duration,
+    ) -> Self {
+        let preview: String = i.output.chars().take(PREVIEW_LENGTH).collect();
+        let memory = i.memory_usage();
+
+        Self {
+            exit,
+            steps: i.steps,
+            stats: i.stats,
+            phases: i.phases.report(i.steps, i.stats.output_bytes),
+            semantics,
+            warnings,
+            resources: Resources {
+                wall_time_secs: wall_time.as_secs_f64(),
+                memory,
+                memory_bytes: memory.total(),
+            },
+            output: OutputSummary {
+                bytes: i.output.len(),
+                hash: format!("{:016x}", fnv1a(i.output.as_bytes())),
+                truncated: preview.len() < i.output.len(),
+                preview,
+            },
+        }
+    }
+}
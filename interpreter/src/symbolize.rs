@@ -0,0 +1,167 @@
+//! `bf symbolize`, which translates the locations in the output of a program
+//! compiled with `bf-codegen --source-map` (see [`codegen::source_map`]) back to
+//! its Brainfuck source. A panic like
+//!
+//! ```text
+//! thread 'main' panicked at src/main.rs:9:2:
+//! attempt to subtract with overflow
+//! ```
+//!
+//! reads
+//!
+//! ```text
+//! thread 'main' panicked at src/main.rs:9:2 (overflow.b:3:3 `-`):
+//! attempt to subtract with overflow
+//! note: in the Brainfuck source, a `-` took a cell below 0
+//! ```
+
+use std::{fs, path::Path};
+
+use brainfuck_extended::Result;
+use codegen::source_map::{Entry, SourceMap};
+use regex::{Captures, Regex};
+
+/// How many instructions of the source are shown for a location
+const MAX_SOURCE: usize = 16;
+
+/// A source map and the source it leads back to.
+pub struct Symbolizer {
+    map: SourceMap,
+    /// `None` if the copy of the source next to the map is gone
+    source: Option<String>,
+    location: Regex,
+}
+
+impl Symbolizer {
+    /// Reads the source map at `path`, along with the copy of the source the
+    /// generated crate keeps next to it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let map: SourceMap = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let source = path
+            .parent()
+            .and_then(|dir| fs::read_to_string(dir.join(&map.source)).ok());
+
+        Ok(Self {
+            map,
+            source,
+            location: Regex::new(r"(src/\w+\.rs):(\d+):(\d+)").unwrap(),
+        })
+    }
+
+    /// `text` with each location in the generated code followed by the one in
+    /// the source it belongs to, and a note after each panic message saying
+    /// what it means for the instructions that panicked.
+    pub fn symbolize(&self, text: &str) -> String {
+        let mut out = String::new();
+        // the code the message on the next line is about
+        let mut panicked: Option<&Entry> = None;
+
+        for line in text.split_inclusive('\n') {
+            let mut located = None;
+            out.push_str(&self.location.replace_all(line, |captures: &Captures| {
+                let found = captures[2]
+                    .parse()
+                    .ok()
+                    .and_then(|line| self.map.locate(&captures[1], line));
+                match found {
+                    Some(entry) => {
+                        located = Some(entry);
+                        let instructions = self.instructions(entry);
+                        format!(
+                            "{} ({}:{}:{}{})",
+                            &captures[0],
+                            self.map.source,
+                            entry.span.line,
+                            entry.span.column,
+                            if instructions.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" `{}`", instructions)
+                            }
+                        )
+                    }
+                    None => captures[0].to_string(),
+                }
+            }));
+
+            // since Rust 1.73 the message is on the line after the location,
+            // before that it was quoted in front of it
+            let message = match (panicked.take(), located) {
+                (Some(entry), _) => Some((line.trim_end(), entry)),
+                (None, Some(entry)) if line.contains(" panicked at '") => line
+                    .split_once(" panicked at '")
+                    .and_then(|(_, rest)| rest.rsplit_once("', "))
+                    .map(|(message, _)| (message, entry)),
+                (None, Some(entry))
+                    if line.trim_end().ends_with(':') && line.contains(" panicked at ") =>
+                {
+                    panicked = Some(entry);
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some(note) = message.and_then(|(message, entry)| self.explain(message, entry)) {
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                out.push_str(&format!("note: in the Brainfuck source, {}\n", note));
+            }
+        }
+
+        out
+    }
+
+    /// What a panic message means for the instructions of `entry`, for the
+    /// panics the generated code can run into.
+    fn explain(&self, message: &str, entry: &Entry) -> Option<String> {
+        let first = self.instructions(entry).chars().next();
+
+        if let Some(rest) = message.strip_prefix("index out of bounds: the len is ") {
+            let (len, index) = rest.split_once(" but the index is ")?;
+            return Some(format!(
+                "the pointer was at cell {}, past the end of the tape of {} cells",
+                index, len
+            ));
+        }
+
+        let note = match (message, first) {
+            ("attempt to add with overflow", Some('+')) => {
+                "a `+` took a cell past its largest value"
+            }
+            ("attempt to add with overflow", Some('>')) => {
+                "a `>` moved the pointer past the largest address"
+            }
+            ("attempt to add with overflow", _) => "a cell or the pointer got too big",
+            ("attempt to subtract with overflow", Some('-')) => "a `-` took a cell below 0",
+            ("attempt to subtract with overflow", Some('<')) => {
+                "a `<` moved the pointer left of the first cell"
+            }
+            ("attempt to subtract with overflow", _) => "a cell or the pointer went below 0",
+            ("called `Option::unwrap()` on a `None` value", Some('.')) => {
+                "a `.` wrote a cell that is not an ASCII character"
+            }
+            _ => return None,
+        };
+        Some(note.to_string())
+    }
+
+    /// The instructions of an entry, shortened if there are many, or nothing
+    /// without the source.
+    fn instructions(&self, entry: &Entry) -> String {
+        let Some(code) = self
+            .source
+            .as_ref()
+            .and_then(|source| source.get(entry.span.start..entry.span.end))
+        else {
+            return String::new();
+        };
+
+        let mut instructions: String = code.chars().filter(|c| "+-<>.,[]".contains(*c)).collect();
+        if instructions.chars().count() > MAX_SOURCE {
+            instructions = instructions.chars().take(MAX_SOURCE - 1).collect();
+            instructions.push('…');
+        }
+        instructions
+    }
+}
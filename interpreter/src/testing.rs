@@ -0,0 +1,313 @@
+//! `bf test`: runs Brainfuck test files and reports which pass, like `cargo
+//! test`. A test file is named `*_test.b` (or `*_test.bf`) and holds a program
+//! that is run with its `@assert` directives checked until it halts. Lines
+//! starting with one of these directives configure it, and are not executed:
+//!
+//! - `@input <text>`: append the text to the input, with `\n`, `\t`, `\\` and
+//!   `\xNN` escapes. Without any, `<name>.in` next to the file is the input
+//! - `@output <text>`: append the text to the expected output, with the same
+//!   escapes. Without any, `<name>.out` next to the file is expected, if it exists
+//! - `@test <name>`: start a test of its own, made of the code and directives up
+//!   to the next `@test`. Everything before the first one is shared by every test
+//! - `@program <file>`: run this program (relative to the test file) instead of
+//!   the code in the test file, so a program can be tested without changing it
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use brainfuck_extended::{BrainfuckInterpreter, IoMode, OutputMode, Result, StepResult};
+
+use crate::script::unescape;
+
+/// How many steps run between two checks of the timeout
+const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// How long any test may run
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_steps: u64,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    /// The test file, followed by `::<name>` for an `@test` block
+    pub name: String,
+    /// The program that is run
+    pub program: PathBuf,
+    pub code: String,
+    pub input: Vec<u8>,
+    pub expected: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed {
+        steps: u64,
+    },
+    /// Why the test failed
+    Failed(String),
+}
+
+/// Finds the test files in `paths`, searching directories recursively.
+/// Files that are given directly are used whatever they are called.
+pub fn discover(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            search(path, &mut found)?;
+        } else if path.exists() {
+            found.push(path.clone());
+        } else {
+            return Err(format!("{} does not exist", path.display()).into());
+        }
+    }
+
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+fn search(dir: &Path, found: &mut Vec<PathBuf>) -> Result {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        // hidden directories and build output are never worth searching
+        if name.starts_with('.') || name == "target" {
+            continue;
+        }
+
+        if path.is_dir() {
+            search(&path, found)?;
+        } else if name.ends_with("_test.b") || name.ends_with("_test.bf") {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// A directive of the test runner, if the line is one.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim_end_matches('\r');
+
+    match name {
+        "@input" | "@output" | "@test" | "@program" => Some((name, rest)),
+        _ => None,
+    }
+}
+
+/// The input or expected output of one part of a test file
+#[derive(Default, Clone)]
+struct Section {
+    /// The lines that are executed, by index
+    lines: Vec<usize>,
+    input: Option<Vec<u8>>,
+    output: Option<Vec<u8>>,
+}
+
+/// Reads the tests in a test file.
+pub fn load(path: &Path) -> Result<Vec<TestCase>> {
+    let text = fs::read_to_string(path)?;
+    // found by searching `.`
+    let path = path.strip_prefix(".").unwrap_or(path);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let at =
+        |i: usize, e: Box<dyn std::error::Error>| format!("{}:{}: {}", path.display(), i + 1, e);
+
+    let mut program = None;
+    let mut shared = Section::default();
+    let mut blocks: Vec<(String, Section)> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let Some((name, rest)) = directive(line) else {
+            let section = blocks.last_mut().map_or(&mut shared, |(_, block)| block);
+            section.lines.push(i);
+            continue;
+        };
+
+        let section = blocks.last_mut().map_or(&mut shared, |(_, block)| block);
+        match name {
+            "@input" => section
+                .input
+                .get_or_insert_with(Vec::new)
+                .extend(unescape(rest).map_err(|e| at(i, e))?),
+            "@output" => section
+                .output
+                .get_or_insert_with(Vec::new)
+                .extend(unescape(rest).map_err(|e| at(i, e))?),
+            "@test" => blocks.push((rest.trim().to_string(), Section::default())),
+            _ => {
+                if !blocks.is_empty() {
+                    return Err(
+                        at(i, "`@program` must come before the first `@test`".into()).into(),
+                    );
+                }
+                let dir = path.parent().unwrap_or(Path::new("."));
+                program = Some(dir.join(rest.trim()));
+            }
+        }
+    }
+
+    // the program under test, or the executable lines of this file
+    let code_of = |section_lines: &[usize]| -> Result<(PathBuf, String)> {
+        match &program {
+            Some(program) => Ok((
+                program.clone(),
+                fs::read_to_string(program).map_err(|e| format!("{}: {}", program.display(), e))?,
+            )),
+            // blank the other lines, so positions and line numbers match the file
+            None => Ok((
+                path.to_path_buf(),
+                lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        if section_lines.contains(&i) {
+                            line.to_string()
+                        } else {
+                            String::new()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )),
+        }
+    };
+    let expected = |output: Option<Vec<u8>>| {
+        output.map(|output| String::from_utf8_lossy(&output).into_owned())
+    };
+
+    if blocks.is_empty() {
+        let sidecar = |extension| fs::read(path.with_extension(extension)).ok();
+        let (program, code) = code_of(&shared.lines)?;
+        return Ok(vec![TestCase {
+            name: path.display().to_string(),
+            program,
+            code,
+            input: shared.input.or_else(|| sidecar("in")).unwrap_or_default(),
+            expected: expected(shared.output.or_else(|| sidecar("out"))),
+        }]);
+    }
+
+    blocks
+        .into_iter()
+        .map(|(name, block)| {
+            let mut executed = shared.lines.clone();
+            executed.extend(&block.lines);
+            let (program, code) = code_of(&executed)?;
+
+            let mut input = shared.input.clone().unwrap_or_default();
+            input.extend(block.input.unwrap_or_default());
+
+            Ok(TestCase {
+                name: format!("{}::{}", path.display(), name),
+                program,
+                code,
+                input,
+                expected: expected(block.output.or_else(|| shared.output.clone())),
+            })
+        })
+        .collect()
+}
+
+impl TestCase {
+    /// Runs the test with its `@assert` directives checked.
+    pub fn run(&self, limits: Limits) -> Outcome {
+        self.run_code(&self.code, limits)
+    }
+
+    /// Runs the test on other code, like a mutant of its program.
+    pub fn run_code(&self, code: &str, limits: Limits) -> Outcome {
+        let mut i = BrainfuckInterpreter::new(code, OutputMode::Utf8, IoMode::Text);
+        if let Err(e) = i.enable_assertions() {
+            return Outcome::Failed(format!("{}: {}", self.program.display(), e));
+        }
+        i.push_input(&self.input);
+        i.close_input();
+
+        let start = Instant::now();
+        loop {
+            match i.step() {
+                Ok(StepResult::Halted) => break,
+                Ok(_) => {}
+                Err(e) => return Outcome::Failed(e.to_string()),
+            }
+
+            if i.steps >= limits.max_steps {
+                return Outcome::Failed(format!("did not halt within {} steps", limits.max_steps));
+            }
+            if i.passed_multiple(TIMEOUT_CHECK_INTERVAL) && start.elapsed() > limits.timeout {
+                return Outcome::Failed(format!(
+                    "did not halt within {:.1}s",
+                    limits.timeout.as_secs_f64()
+                ));
+            }
+        }
+
+        match &self.expected {
+            Some(expected) if *expected != i.output => Outcome::Failed(format!(
+                "the output differs from the expected output:\n{}",
+                diff_lines(expected, &i.output)
+            )),
+            _ => Outcome::Passed { steps: i.steps },
+        }
+    }
+}
+
+/// The lines of `expected` missing from `actual` prefixed with `-`, the ones
+/// only in `actual` with `+` and the shared ones with a space.
+pub fn diff_lines(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.split('\n').collect();
+    let new: Vec<&str> = actual.split('\n').collect();
+
+    // the length of the longest common subsequence of every pair of suffixes
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!(" {}\n", visible(old[i])));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            diff.push_str(&format!("-{}\n", visible(old[i])));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", visible(new[j])));
+            j += 1;
+        }
+    }
+
+    diff
+}
+
+/// Escapes the control characters in a line, which would not show up in a diff.
+fn visible(line: &str) -> String {
+    line.chars()
+        .map(|c| {
+            if c.is_control() && c != '\t' {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
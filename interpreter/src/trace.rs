@@ -0,0 +1,123 @@
+//! Sampled execution traces. Tracing every step of a run that takes billions
+//! of them is infeasible, so only every Nth step is recorded, along with a
+//! phase marker whenever execution passes a `#` or a label comment like
+//! `@phase parse input`. The markers show which part of the program a sample
+//! falls in, so a trace still localizes a problem.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    cell::{CellValue, Memory},
+    Result,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A sampled step, which executed this character
+    Sample(char),
+    /// Execution passed a `#` (named after its position) or an `@phase` label
+    Phase(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The state right after a step
+pub struct TraceRecord {
+    /// How many steps ran, counting this one
+    pub step: u64,
+    /// The position of the executed character in the code
+    pub position: usize,
+    pub pointer: usize,
+    pub cell: CellValue,
+    pub event: TraceEvent,
+}
+
+#[derive(Debug, Clone)]
+/// Records every `interval`th step and every phase marker of a run
+pub struct Tracer {
+    interval: u64,
+    /// The names of the `@phase` labels, by the position of their `@`
+    phases: BTreeMap<usize, String>,
+    /// Every record so far
+    pub records: Vec<TraceRecord>,
+}
+
+/// Parses a sampling rate like `1/10000`, or just `10000`, into the number of
+/// steps between two samples.
+pub fn parse_sample(s: &str) -> Result<u64> {
+    let invalid = || format!("`{}` is not a sampling rate like `1/10000`", s);
+
+    let interval = match s.split_once('/') {
+        Some((one, interval)) if one.trim() == "1" => interval.trim().parse(),
+        Some(_) => return Err(invalid().into()),
+        None => s.trim().parse(),
+    }
+    .map_err(|_| invalid())?;
+
+    if interval == 0 {
+        return Err(invalid().into());
+    }
+
+    Ok(interval)
+}
+
+/// Finds the `@phase <name>` labels in the code, by the position (in
+/// characters) of their `@`. A name takes the rest of its line.
+pub fn parse_phases(code: &str) -> BTreeMap<usize, String> {
+    let mut phases = BTreeMap::new();
+    let mut start = 0;
+
+    for line in code.split('\n') {
+        if let Some(at) = line.find("@phase") {
+            let name = &line[at + "@phase".len()..];
+            // not part of a longer word like `@phases`
+            if name.starts_with(char::is_whitespace) && !name.trim().is_empty() {
+                phases.insert(start + line[..at].chars().count(), name.trim().to_string());
+            }
+        }
+
+        start += line.chars().count() + 1;
+    }
+
+    phases
+}
+
+impl Tracer {
+    pub fn new(code: &str, interval: u64) -> Self {
+        Self {
+            interval: interval.max(1),
+            phases: parse_phases(code),
+            records: Vec::new(),
+        }
+    }
+
+    /// Records the state after the instruction at `position`, whose first
+    /// character is `c`, took the steps from `before` to `step`, if one of
+    /// them is sampled or it is a phase marker.
+    pub fn observe(
+        &mut self,
+        before: u64,
+        step: u64,
+        position: usize,
+        c: char,
+        pointer: usize,
+        memory: &Memory,
+    ) {
+        let event = if c == '#' {
+            TraceEvent::Phase(format!("#{}", position))
+        } else if let Some(name) = self.phases.get(&position).filter(|_| c == '@') {
+            TraceEvent::Phase(name.clone())
+        } else if step / self.interval > before / self.interval {
+            TraceEvent::Sample(c)
+        } else {
+            return;
+        };
+
+        self.records.push(TraceRecord {
+            step,
+            position,
+            pointer,
+            cell: memory.get(pointer),
+            event,
+        });
+    }
+}
@@ -0,0 +1,181 @@
+//! `--transcript`: the input a run read and the output it wrote, in the order
+//! the program did it, with the step of each, so that the output of an
+//! interactive program can be told apart from the answers it got. The file
+//! has one JSON object per line, a run of input, output or reads at EOF with
+//! the step it started at, and `bf transcript` prints it as a timeline.
+
+use std::{
+    io::{stdout, Write},
+    path::Path,
+};
+
+use brainfuck_extended::{BrainfuckInterpreter, Result};
+
+use crate::compress;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Entry {
+    step: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    /// How many reads in a row hit EOF
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eof: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Input,
+    Output,
+}
+
+/// The bytes read or written by consecutive steps
+struct Run {
+    kind: Kind,
+    step: u64,
+    bytes: Vec<u8>,
+}
+
+impl Run {
+    fn into_entry(self) -> Entry {
+        let text = Some(String::from_utf8_lossy(&self.bytes).into_owned());
+        match self.kind {
+            Kind::Input => Entry {
+                step: self.step,
+                input: text,
+                ..Entry::default()
+            },
+            Kind::Output => Entry {
+                step: self.step,
+                output: text,
+                ..Entry::default()
+            },
+        }
+    }
+}
+
+/// The reads and writes of the run, in runs of the same kind.
+fn entries(i: &BrainfuckInterpreter) -> Vec<Entry> {
+    let mut reads = i.input_log.iter().flatten().peekable();
+    let mut writes = i.output_log.iter().flatten().peekable();
+    let mut entries = Vec::new();
+    let mut run: Option<Run> = None;
+
+    loop {
+        // a step either reads or writes
+        let read_first = match (reads.peek(), writes.peek()) {
+            (Some(read), Some(write)) => read.step < write.step,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        let (kind, step, bytes) = if read_first {
+            let read = reads.next().expect("peeked");
+            if read.eof {
+                entries.extend(run.take().map(Run::into_entry));
+                match entries.last_mut() {
+                    Some(Entry {
+                        eof: Some(count), ..
+                    }) => *count += 1,
+                    _ => entries.push(Entry {
+                        step: read.step,
+                        eof: Some(1),
+                        ..Entry::default()
+                    }),
+                }
+                continue;
+            }
+            (Kind::Input, read.step, &i.input[read.start..read.end])
+        } else {
+            let write = writes.next().expect("peeked");
+            (Kind::Output, write.step, std::slice::from_ref(&write.byte))
+        };
+
+        match &mut run {
+            Some(run) if run.kind == kind => run.bytes.extend_from_slice(bytes),
+            _ => {
+                entries.extend(run.take().map(Run::into_entry));
+                run = Some(Run {
+                    kind,
+                    step,
+                    bytes: bytes.to_vec(),
+                });
+            }
+        }
+    }
+    entries.extend(run.map(Run::into_entry));
+
+    entries
+}
+
+/// Writes the transcript of the run to `path`, compressed if it is named
+/// `*.zst`.
+pub fn write(path: &Path, i: &BrainfuckInterpreter) -> Result {
+    let mut file = compress::create(path)?;
+
+    for entry in entries(i) {
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    }
+
+    file.finish()
+}
+
+/// `text` with the control characters escaped, but for tabs.
+fn visible(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_control() && c != '\t' {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Prints the transcript at `path` as a timeline, a line of it per line of
+/// the input or output.
+pub fn view(path: &Path) -> Result {
+    let text = String::from_utf8(compress::read(path)?)
+        .map_err(|_| format!("{} is not a transcript", path.display()))?;
+    let mut stdout = stdout().lock();
+
+    for (number, line) in text.lines().enumerate() {
+        let entry: Entry = serde_json::from_str(line).map_err(|e| {
+            format!(
+                "line {} of {} is not a transcript entry: {}",
+                number + 1,
+                path.display(),
+                e
+            )
+        })?;
+
+        let (label, text) = match (&entry.input, &entry.output, entry.eof) {
+            (Some(input), _, _) => ("in ", input.as_str()),
+            (_, Some(output), _) => ("out", output.as_str()),
+            (_, _, Some(1)) => {
+                writeln!(stdout, "{:>12}  in   (EOF)", entry.step)?;
+                continue;
+            }
+            (_, _, Some(count)) => {
+                writeln!(stdout, "{:>12}  in   (EOF, {} reads)", entry.step, count)?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        for (index, piece) in text.split_inclusive('\n').enumerate() {
+            // the step is only shown on the first line of the run
+            let step = if index == 0 {
+                entry.step.to_string()
+            } else {
+                String::new()
+            };
+            writeln!(stdout, "{:>12}  {}  {}", step, label, visible(piece))?;
+        }
+    }
+
+    Ok(())
+}
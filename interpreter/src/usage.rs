@@ -0,0 +1,155 @@
+//! Accounting for the memory the interpreter itself holds on to, so a long run
+//! can give up its history before it runs the machine out of memory.
+
+use std::{fmt, mem::size_of};
+
+use crate::{
+    instr::Instr, BrainfuckInterpreter, InputRecord, OutputRecord, Result, StepHash, TraceRecord,
+};
+
+/// How many steps pass between checks of the memory limit
+pub const MEMORY_CHECK_INTERVAL: u64 = 1 << 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+/// The bytes held by each part of the interpreter's state
+pub struct MemoryUsage {
+    pub tape: usize,
+    pub code: usize,
+    pub input: usize,
+    pub output: usize,
+    pub output_log: usize,
+    pub step_hashes: usize,
+    pub trace: usize,
+    /// The saved states the debugger rewinds to, which the interpreter does
+    /// not know about itself
+    pub checkpoints: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.tape
+            + self.code
+            + self.input
+            + self.output
+            + self.output_log
+            + self.step_hashes
+            + self.trace
+            + self.checkpoints
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (tape {}, output {}, history {})",
+            Bytes(self.total()),
+            Bytes(self.tape),
+            Bytes(self.output + self.output_log),
+            Bytes(self.step_hashes + self.trace + self.checkpoints)
+        )
+    }
+}
+
+/// A byte count printed with a binary unit, like `1.5 MiB`
+pub struct Bytes(pub usize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+
+        write!(f, "{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl BrainfuckInterpreter {
+    /// Roughly how much memory the interpreter's state takes, counting the
+    /// capacity of its buffers rather than what is in use.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            tape: self.memory.size(),
+            code: self.code.capacity() * size_of::<char>()
+                + self.instrs.capacity() * size_of::<Instr>()
+                + self.positions.capacity() * size_of::<usize>()
+                + self.comments.capacity() * size_of::<u64>()
+                + self
+                    .heat
+                    .as_ref()
+                    .map_or(0, |heat| heat.capacity() * size_of::<u64>()),
+            input: self.input.capacity()
+                + self
+                    .input_log
+                    .as_ref()
+                    .map_or(0, |log| log.capacity() * size_of::<InputRecord>()),
+            output: self.output.capacity() + self.pending_output.capacity(),
+            output_log: self
+                .output_log
+                .as_ref()
+                .map_or(0, |log| log.capacity() * size_of::<OutputRecord>()),
+            step_hashes: self.step_hasher.as_ref().map_or(0, |hasher| {
+                hasher.history.capacity() * size_of::<StepHash>() + hasher.buffer_size()
+            }),
+            trace: self.tracer.as_ref().map_or(0, |tracer| {
+                tracer.records.capacity() * size_of::<TraceRecord>()
+            }),
+            checkpoints: 0,
+        }
+    }
+
+    /// Drops recorded history until the state, along with `checkpoints` bytes
+    /// of saved states, takes at most `limit` bytes. The step hashes except the
+    /// latest go first, then the output log and then the trace. Returns what was dropped, or an
+    /// error if even that is not enough.
+    pub fn fit_memory(&mut self, limit: usize, checkpoints: usize) -> Result<Vec<&'static str>> {
+        let mut dropped = Vec::new();
+        let usage = |i: &Self| MemoryUsage {
+            checkpoints,
+            ..i.memory_usage()
+        };
+        let over = |i: &Self| usage(i).total() > limit;
+
+        if over(self) {
+            if let Some(hasher) = &mut self.step_hasher {
+                if hasher.history.len() > 1 {
+                    let latest = hasher.history.split_off(hasher.history.len() - 1);
+                    hasher.history = latest;
+                    dropped.push("step hashes");
+                }
+            }
+        }
+
+        if over(self) && self.output_log.take().is_some() {
+            dropped.push("output steps");
+        }
+
+        if over(self) && self.input_log.take().is_some() {
+            dropped.push("input steps");
+        }
+
+        if over(self) && self.tracer.take().is_some() {
+            dropped.push("trace");
+        }
+
+        if over(self) {
+            return Err(format!(
+                "the interpreter needs {}, more than the limit of {}",
+                usage(self),
+                Bytes(limit)
+            )
+            .into());
+        }
+
+        Ok(dropped)
+    }
+}
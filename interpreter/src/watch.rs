@@ -0,0 +1,334 @@
+//! `bf watch`: runs a program again whenever its source, its input or any
+//! other watched file changes, for a tight edit-run loop. Each run is a child
+//! process, so one that never halts is simply killed by the next change. With
+//! `--diff`, the output of each run (and its final tape with `--diff-tape`)
+//! is compared with the previous one's.
+
+use std::{
+    env, fs,
+    io::{self, stdout, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, ChildStdout, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use brainfuck_extended::Result;
+use clap::ValueEnum;
+use codegen::verbosity;
+use crossterm::style::Stylize;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::{testing::diff_lines, Engine, WatchOptions};
+
+/// How often a run is checked for having exited while waiting for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for more events after one, since editors often write a
+/// file in several steps
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How many unchanged lines are shown around each change in a diff
+const DIFF_CONTEXT: usize = 2;
+
+type Events = Receiver<notify::Result<Event>>;
+
+/// What a finished run left behind, to compare the next one with
+struct RunResult {
+    output: Vec<u8>,
+    /// The `--diff-tape` cells as a hex dump
+    tape: Option<Vec<u8>>,
+}
+
+pub fn watch(options: WatchOptions) -> Result {
+    if options.engine == Engine::Interpreter {
+        return Err(
+            "`bf watch` runs programs headless, pick a bytecode engine with --engine".into(),
+        );
+    }
+
+    let mut files = vec![absolute(&options.program)?];
+    for path in options.input.iter().chain(&options.also) {
+        files.push(absolute(path)?);
+    }
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // watch the directories, since editors often replace a file instead of
+    // writing to it, which would end a watch on the file itself
+    let mut dirs: Vec<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    let tape = env::temp_dir().join(format!("bf-watch-{}.tape", process::id()));
+    let mut previous: Option<RunResult> = None;
+    let mut reason = "started".to_string();
+
+    for run in 1.. {
+        if options.clear {
+            eprint!("\x1b[2J\x1b[H");
+        }
+        eprintln!("---- run {}: {} ----", run, reason);
+
+        let start = Instant::now();
+        let changed = match spawn(&options, &tape) {
+            Ok(mut child) => {
+                let capture = child
+                    .stdout
+                    .take()
+                    .map(|out| thread::spawn(move || tee(out)));
+
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        eprintln!(
+                            "\n---- run {} {} after {:.2}s, waiting for changes ----",
+                            run,
+                            match status.code() {
+                                Some(0) => "finished".to_string(),
+                                Some(code) => format!("failed with exit code {}", code),
+                                None => "was killed".to_string(),
+                            },
+                            start.elapsed().as_secs_f64()
+                        );
+
+                        if let Some(output) = join(capture)? {
+                            let result = RunResult {
+                                output,
+                                tape: options.diff_tape.as_ref().map(|_| {
+                                    let dump = fs::read(&tape).unwrap_or_default();
+                                    let _ = fs::remove_file(&tape);
+                                    dump
+                                }),
+                            };
+                            if let Some(previous) = &previous {
+                                compare(previous, &result, !options.no_color);
+                            }
+                            previous = Some(result);
+                        }
+
+                        break wait_for_change(&events, &files)?;
+                    }
+
+                    if let Some(changed) = poll_change(&events, &files, POLL_INTERVAL)? {
+                        // the output of a stale run is not worth waiting for
+                        child.kill()?;
+                        child.wait()?;
+                        join(capture)?;
+                        eprintln!(
+                            "\n---- run {} stopped after {:.2}s ----",
+                            run,
+                            start.elapsed().as_secs_f64()
+                        );
+                        break changed;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("---- run {} could not start: {} ----", run, e);
+                wait_for_change(&events, &files)?
+            }
+        };
+
+        reason = format!("{} changed", display(&changed));
+    }
+
+    Ok(())
+}
+
+/// Starts a run: the `--cmd`, or the program on the bytecode engine, dumping
+/// the `--diff-tape` cells to `tape`. Its output is piped for `--diff`.
+fn spawn(options: &WatchOptions, tape: &Path) -> Result<Child> {
+    let mut command = match &options.cmd {
+        Some(cmd) => {
+            let mut command = process::Command::new("sh");
+            command
+                .arg("-c")
+                .arg(cmd)
+                .env("BF_PROGRAM", &options.program)
+                .env("BF_INPUT", options.input.clone().unwrap_or_default());
+            command
+        }
+        None => {
+            let engine = options
+                .engine
+                .to_possible_value()
+                .expect("no skipped engines");
+            let mut command = process::Command::new(env::current_exe()?);
+            command
+                .arg(&options.program)
+                .args(&options.input)
+                .arg("--engine")
+                .arg(engine.get_name())
+                .args(verbosity::level().flag())
+                .stdin(if options.input.is_some() {
+                    Stdio::null()
+                } else {
+                    Stdio::inherit()
+                });
+            if let Some(range) = &options.diff_tape {
+                command
+                    .arg("--dump-range")
+                    .arg(format!("{}..{}", range.start, range.end))
+                    .arg("--dump-file")
+                    .arg(tape)
+                    .arg("--dump-format")
+                    .arg("hex");
+            }
+            command
+        }
+    };
+
+    if options.diff || options.diff_tape.is_some() {
+        command.stdout(Stdio::piped());
+    }
+
+    Ok(command.spawn()?)
+}
+
+/// Copies the output of a run to stdout as it arrives, and returns all of it.
+fn tee(mut out: ChildStdout) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = [0; 8192];
+    let mut stdout = stdout();
+
+    loop {
+        let read = out.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(output);
+        }
+        stdout.write_all(&buffer[..read])?;
+        stdout.flush()?;
+        output.extend_from_slice(&buffer[..read]);
+    }
+}
+
+/// Waits for the output of a run, if it is captured.
+fn join(capture: Option<JoinHandle<io::Result<Vec<u8>>>>) -> Result<Option<Vec<u8>>> {
+    match capture {
+        Some(capture) => Ok(Some(
+            capture.join().map_err(|_| "copying the output failed")??,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Prints how a run's output and tape differ from the previous run's.
+fn compare(previous: &RunResult, current: &RunResult, color: bool) {
+    print_diff("output", &previous.output, &current.output, color);
+    if let (Some(previous), Some(current)) = (&previous.tape, &current.tape) {
+        print_diff("tape", previous, current, color);
+    }
+}
+
+fn print_diff(what: &str, previous: &[u8], current: &[u8], color: bool) {
+    if previous == current {
+        eprintln!("---- {} unchanged since the last run ----", what);
+        return;
+    }
+
+    eprintln!("---- {} changed since the last run ----", what);
+    let diff = diff_lines(
+        &String::from_utf8_lossy(previous),
+        &String::from_utf8_lossy(current),
+    );
+    let mut lines: Vec<&str> = diff.lines().collect();
+    // the empty line after a final newline
+    if lines.last() == Some(&" ") {
+        lines.pop();
+    }
+    let changed = |i: usize| !lines[i].starts_with(' ');
+
+    let mut skipped = false;
+    for (i, line) in lines.iter().enumerate() {
+        let near_change = (i.saturating_sub(DIFF_CONTEXT)..=i + DIFF_CONTEXT)
+            .any(|j| j < lines.len() && changed(j));
+        if !near_change {
+            if !skipped {
+                eprintln!(" ...");
+                skipped = true;
+            }
+            continue;
+        }
+        skipped = false;
+
+        match line.chars().next() {
+            Some('-') if color => eprintln!("{}", line.red()),
+            Some('+') if color => eprintln!("{}", line.green()),
+            _ => eprintln!("{}", line),
+        }
+    }
+}
+
+/// Waits for one of the files to change, and returns it once the events
+/// have settled.
+fn wait_for_change(events: &Events, files: &[PathBuf]) -> Result<PathBuf> {
+    loop {
+        let event = events.recv().map_err(|_| "the file watcher stopped")?;
+        if let Some(changed) = changed_file(event?, files) {
+            settle(events);
+            return Ok(changed);
+        }
+    }
+}
+
+/// Like [`wait_for_change`], but gives up after `timeout`.
+fn poll_change(events: &Events, files: &[PathBuf], timeout: Duration) -> Result<Option<PathBuf>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let event = match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+            Err(RecvTimeoutError::Disconnected) => return Err("the file watcher stopped".into()),
+        };
+        if let Some(changed) = changed_file(event?, files) {
+            settle(events);
+            return Ok(Some(changed));
+        }
+    }
+}
+
+/// Swallows the rest of a burst of events.
+fn settle(events: &Events) {
+    while events.recv_timeout(DEBOUNCE).is_ok() {}
+}
+
+/// The watched file an event is about, if any.
+fn changed_file(event: Event, files: &[PathBuf]) -> Option<PathBuf> {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return None;
+    }
+
+    event.paths.into_iter().find(|path| files.contains(path))
+}
+
+/// The path with its directory resolved, so it can be compared to the paths
+/// of events. The file itself may be missing for a moment.
+fn absolute(path: &Path) -> Result<PathBuf> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file", path.display()))?;
+
+    Ok(dir
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .join(name))
+}
+
+/// The path relative to the current directory, if it is inside it.
+fn display(path: &Path) -> String {
+    env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
+        .display()
+        .to_string()
+}
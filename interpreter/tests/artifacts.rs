@@ -0,0 +1,119 @@
+//! The files `bf` writes about a run, which a failed run needs the most.
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// A directory of its own for the files of `test`.
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bf-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_failed_run_still_writes_every_artifact() {
+    let dir = scratch("failed-run");
+    let artifacts = [
+        ("--trace", "trace.jsonl"),
+        ("--output-timestamps", "timestamps.jsonl"),
+        ("--step-hashes", "hashes.jsonl"),
+        ("--transcript", "transcript.jsonl"),
+        ("--summary-json", "summary.json"),
+        ("--export-html", "report.html"),
+        ("--dump-file", "dump.bin"),
+    ];
+
+    let mut bf = Command::new(env!("CARGO_BIN_EXE_bf"));
+    bf.args([
+        "--no-debugger",
+        "--trace-sample",
+        "1/1",
+        "--hash-interval",
+        "1",
+    ])
+    .args(["--dump-range", "0..4"]);
+    for (flag, file) in artifacts {
+        bf.arg(flag).arg(dir.join(file));
+    }
+    // 65 is written fine, but 255 is not ASCII
+    bf.args(["-e", "++++++++[>++++++++<-]>+.-[-]-."]);
+
+    let output = bf.output().unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert_eq!(output.stdout, b"A");
+
+    for (flag, file) in artifacts {
+        let contents = fs::read(dir.join(file))
+            .unwrap_or_else(|e| panic!("{} did not write {}: {}", flag, file, e));
+        assert!(!contents.is_empty(), "{} wrote an empty {}", flag, file);
+    }
+
+    let summary = fs::read_to_string(dir.join("summary.json")).unwrap();
+    assert!(summary.contains("\"failed\""), "{}", summary);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_compressed_dump_can_be_read_back_as_input() {
+    let dir = scratch("compressed-dump");
+    let dump = dir.join("tape.bin.zst");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(["--no-debugger", "--dump-range", "1..3", "--dump-file"])
+        .arg(&dump)
+        .args(["-e", "++++++++[>++++++++<-]>+>++"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    // the zstd magic number
+    assert_eq!(fs::read(&dump).unwrap()[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(["--no-debugger", "-e", ",[.[-],]"])
+        .arg(&dump)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"A\x02");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_failed_run_on_a_bytecode_engine_still_writes_the_dump() {
+    let dir = scratch("failed-bytecode-run");
+    fs::write(dir.join("input.txt"), "many").unwrap();
+    let runs = [
+        // 255 is not ASCII
+        ("ascii", vec!["-e", "++++++++[>++++++++<-]>+.-[-]-."], 255),
+        // the engine itself fails on input that is not a number
+        (
+            "numeric",
+            vec!["--io", "numeric", "-e", "++++++++[>++++++++<-]>+>,"],
+            65,
+        ),
+    ];
+
+    for (run, args, cell) in runs {
+        let dump = dir.join(format!("{}.bin", run));
+        let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+            .args([
+                "--engine",
+                "threaded",
+                "--dump-range",
+                "0..2",
+                "--dump-file",
+            ])
+            .arg(&dump)
+            .args(args)
+            .arg(dir.join("input.txt"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1), "{}: {:?}", run, output);
+
+        let dumped = fs::read(&dump).unwrap_or_else(|e| panic!("{} wrote no dump: {}", run, e));
+        assert_eq!(dumped, [0, cell], "{}", run);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,107 @@
+//! `bf batch`, which runs programs in the sandbox and writes the metrics of
+//! the runs.
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+/// Runs `bf batch` on `jobs` given on stdin, returning the report of each
+/// job and the metrics file.
+fn batch(jobs: &str, args: &[&str]) -> (Vec<Value>, String) {
+    let dir = std::env::temp_dir().join(format!("bf-batch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let metrics = dir.join("bf.prom");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .arg("batch")
+        .arg("--metrics")
+        .arg(&metrics)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(jobs.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let reports = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let text = fs::read_to_string(&metrics).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+    (reports, text)
+}
+
+#[test]
+fn each_program_is_reported_and_counted_in_the_metrics() {
+    let jobs = r#"{"code": ",[.[-],]", "input": "hi"}
+{"code": "+[]"}
+
+{"code": "+[", "input": ""}
+{"code": "+[]"}
+"#;
+    let (reports, metrics) = batch(jobs, &["--max-steps", "1000"]);
+
+    assert_eq!(reports.len(), 4);
+    assert_eq!(reports[0]["ok"], true);
+    assert_eq!(reports[0]["output"], "hi");
+    assert_eq!(reports[1]["error"]["kind"], "step_limit");
+    assert_eq!(reports[2]["error"]["kind"], "unmatched_bracket");
+
+    let steps: u64 = reports.iter().map(|r| r["steps"].as_u64().unwrap()).sum();
+    for line in [
+        "bf_runs_total 4".to_string(),
+        "bf_run_failures_total{reason=\"step_limit\"} 2".to_string(),
+        "bf_run_failures_total{reason=\"unmatched_bracket\"} 1".to_string(),
+        format!("bf_steps_total{{endpoint=\"batch\"}} {}", steps),
+        "bf_run_duration_seconds_count 4".to_string(),
+        "# TYPE bf_run_duration_seconds summary".to_string(),
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == line),
+            "{} in\n{}",
+            line,
+            metrics
+        );
+    }
+    assert!(metrics.contains("bf_run_duration_seconds{quantile=\"0.95\"} "));
+}
+
+#[test]
+fn a_malformed_job_names_its_line() {
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(["batch", "/dev/stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(b"{\"code\": \"+\"}\n{\"program\": \"+\"}\n")?;
+            child.wait_with_output()
+        })
+        .unwrap();
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("line 2"), "{}", error);
+}
@@ -0,0 +1,151 @@
+//! Programs with `@pragma cell-size bigint`, whose cells never wrap around,
+//! on the step-by-step interpreter and on what records its state.
+
+use std::process::Command;
+
+use brainfuck_extended::{
+    cell::{CellValue, Memory},
+    BrainfuckInterpreter, IoMode, OutputMode, StepResult,
+};
+
+/// 8 * 8 * 8 in cell 2, then -1 in cell 3
+const CUBE: &str = "@pragma cell-size bigint\n++++++++[>++++++++[>++++++++<-]<-]>>.>-.";
+
+fn interpreter(code: &str, io_mode: IoMode) -> BrainfuckInterpreter {
+    let mut i = BrainfuckInterpreter::new(code, OutputMode::Ascii, io_mode);
+    i.resize_memory(8);
+    i.close_input();
+    i
+}
+
+#[test]
+fn cells_go_past_255_and_below_0() {
+    let mut i = interpreter(CUBE, IoMode::Numeric);
+    assert_eq!(i.run().unwrap().0, StepResult::Halted);
+    assert_eq!(i.output, "512 -1 ");
+    assert!(matches!(i.memory, Memory::BigInt(_)));
+    assert_eq!(i.memory.get(2).to_string(), "512");
+
+    // a number is read whole
+    let mut i = interpreter("@pragma cell-size bigint\n,+.", IoMode::Numeric);
+    i.push_input(b"99999999999999999999");
+    i.run().unwrap();
+    assert_eq!(i.output, "100000000000000000000 ");
+}
+
+#[test]
+fn only_a_byte_is_written_as_text() {
+    let mut i = interpreter("@pragma cell-size bigint\n-.", IoMode::Text);
+    let error = i.run().unwrap_err();
+    assert_eq!(error.to_string(), "cannot write -1, it is not a byte");
+
+    let mut i = interpreter("@pragma cell-size bigint\n,+.", IoMode::Text);
+    i.push_input(b"a");
+    i.run().unwrap();
+    assert_eq!(i.output, "b");
+}
+
+#[test]
+fn step_hashes_tell_apart_what_bytes_would_wrap() {
+    let plus = "+".repeat(256);
+    let hashes = |code: &str| {
+        let mut i = interpreter(code, IoMode::Text);
+        i.record_step_hashes(1);
+        i.run().unwrap();
+        i.step_hasher.unwrap().history
+    };
+
+    let bytes = hashes(&format!("@pragma cell-size 8\n{}", plus));
+    let big = hashes(&format!("@pragma cell-size bigint\n{}", plus));
+    assert_eq!(bytes.len(), 2);
+    assert_eq!(big.len(), 2);
+    // the same until the cell is 256, which is 0 as a byte
+    assert_eq!(bytes[0], big[0]);
+    assert_ne!(bytes[1].hash, big[1].hash);
+}
+
+#[test]
+fn a_checkpoint_resumes_with_the_big_integers() {
+    let mut whole = interpreter(CUBE, IoMode::Numeric);
+    whole.run().unwrap();
+
+    let mut first = interpreter(CUBE, IoMode::Numeric);
+    while first.steps < 300 {
+        first.step().unwrap();
+    }
+    let json = serde_json::to_string(&first.save_state()).unwrap();
+    assert!(json.contains(r#""memory":[""#), "{}", json);
+
+    let mut resumed = interpreter(CUBE, IoMode::Numeric);
+    resumed
+        .restore_state(serde_json::from_str(&json).unwrap())
+        .unwrap();
+    resumed.run().unwrap();
+    assert_eq!(resumed.output, whole.output);
+    assert_eq!(resumed.memory, whole.memory);
+    assert_eq!(resumed.steps, whole.steps);
+
+    // the same program, made to run on bytes by the profile
+    let mut bytes = interpreter(CUBE, IoMode::Numeric);
+    bytes.pragmas.cell_size = None;
+    bytes.resize_memory(8);
+    let error = bytes
+        .restore_state(serde_json::from_str(&json).unwrap())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the checkpoint has big-integer cells, but this run has 8-bit cells"
+    );
+}
+
+#[test]
+fn a_runtime_error_holds_the_whole_cell() {
+    let mut i = interpreter("@pragma cell-size bigint\n--.", IoMode::Text);
+    let error = i.run().unwrap_err();
+    let error = error
+        .downcast::<brainfuck_extended::RuntimeError>()
+        .unwrap();
+    assert_eq!(error.cell, CellValue::BigInt((-2).into()));
+}
+
+fn bf(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(["-e", CUBE, "--io", "numeric"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn dumps_write_the_whole_values() {
+    let output = bf(&[
+        "--no-debugger",
+        "--dump-range",
+        "1..4",
+        "--dump-format",
+        "hex",
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "512 -1 00001: 0 200 -1\n"
+    );
+}
+
+#[test]
+fn the_bytecode_engine_and_the_sandbox_reject_big_integers() {
+    for args in [&["--engine", "match"][..], &["--sandbox"]] {
+        let output = bf(args);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("big-integer cells")
+                || String::from_utf8_lossy(&output.stderr).contains("big-integer cells"),
+            "{:?}",
+            output
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Checkpoints written by `--checkpoint-every`, and runs resumed from them.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::Value;
+
+/// A directory of its own for the files of `test`.
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bf-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn hello() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../sources.b/hello.b")
+}
+
+/// Runs `bf` headless on hello.b with `args`, returning its output and the
+/// steps and stats of its summary.
+fn run(dir: &Path, args: &[&str]) -> (String, Value, Value) {
+    let summary = dir.join("summary.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .arg("--no-debugger")
+        .arg("--summary-json")
+        .arg(&summary)
+        .args(args)
+        .arg(hello())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let summary: Value = serde_json::from_str(&fs::read_to_string(&summary).unwrap()).unwrap();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        summary["steps"].clone(),
+        summary["stats"].clone(),
+    )
+}
+
+fn checkpoint_steps(path: &PathBuf) -> u64 {
+    let checkpoint: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    checkpoint["steps"].as_u64().unwrap()
+}
+
+#[test]
+fn checkpoints_rotate_and_each_resumes_to_the_same_run() {
+    let dir = scratch("checkpoints");
+    let file = dir.join("hello.checkpoint");
+    let file = file.to_str().unwrap();
+
+    let whole = run(
+        &dir,
+        &[
+            "--checkpoint-every",
+            "1000",
+            "--checkpoint-file",
+            file,
+            "--checkpoint-keep",
+            "3",
+        ],
+    );
+
+    // the newest one first, and nothing past the ones kept
+    let kept: Vec<PathBuf> = ["", ".1", ".2"]
+        .iter()
+        .map(|suffix| PathBuf::from(format!("{}{}", file, suffix)))
+        .collect();
+    for path in &kept {
+        assert!(path.exists(), "{} was not written", path.display());
+    }
+    assert!(!PathBuf::from(format!("{}.3", file)).exists());
+    assert!(!PathBuf::from(format!("{}.tmp", file)).exists());
+    let steps: Vec<u64> = kept.iter().map(checkpoint_steps).collect();
+    assert!(
+        steps.windows(2).all(|pair| pair[0] > pair[1]),
+        "{:?}",
+        steps
+    );
+
+    for path in &kept {
+        let resumed = run(&dir, &["--resume", path.to_str().unwrap()]);
+        assert_eq!(resumed, whole, "resumed from {}", path.display());
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_checkpoint_of_another_program_or_tape_is_rejected() {
+    let dir = scratch("checkpoint-mismatch");
+    let file = dir.join("hello.checkpoint");
+    run(
+        &dir,
+        &[
+            "--checkpoint-every",
+            "1000",
+            "--checkpoint-file",
+            file.to_str().unwrap(),
+        ],
+    );
+
+    let resume = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+            .arg("--no-debugger")
+            .arg("--resume")
+            .arg(&file)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let error = resume(&["-e", "+++."]);
+    assert!(error.contains("a different program"), "{}", error);
+
+    let hello = hello();
+    let error = resume(&["--memory-size", "100", hello.to_str().unwrap()]);
+    assert!(error.contains("has 30000 cells"), "{}", error);
+    assert!(error.contains("this run has 100"), "{}", error);
+
+    fs::remove_dir_all(dir).unwrap();
+}
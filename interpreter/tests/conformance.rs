@@ -0,0 +1,37 @@
+//! `bf conformance` on every engine of the build, which must all agree with
+//! each other on the cases every implementation agrees on.
+
+use std::process::Command;
+
+/// Runs `bf conformance` with `args`, failing the test if a case failed.
+fn conformance(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .arg("conformance")
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+#[test]
+fn every_engine_passes_the_suite() {
+    let report = conformance(&[]);
+    assert!(
+        report.contains("a skipped loop does not read the input it would have ... ok"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn every_engine_passes_the_suite_with_a_wrapping_pointer() {
+    let report = conformance(&["--profile", "golf"]);
+    assert!(report.contains("bytecode engine"), "{}", report);
+}
@@ -0,0 +1,175 @@
+//! Every bytecode engine against the step interpreter, on the programs in
+//! `sources.b`: the output, the tape and the steps have to be the same with
+//! any superinstructions, and whether or not hot loops are specialized.
+
+use std::fs;
+
+use brainfuck_extended::{
+    bytecode::{Machine, Program, Status},
+    cell::Memory,
+    hot::HOT_LOOP_THRESHOLD,
+    BrainfuckInterpreter, Dispatch, FusionTable, IoMode, OutputMode, StepResult,
+};
+
+/// What the programs that read get, the same for every engine
+const INPUT: &[u8] = b"Hello, World!\nThe quick brown fox jumps over the lazy dog.\n";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Run {
+    output: String,
+    steps: u64,
+    pointer: usize,
+    memory: Memory,
+}
+
+fn source(name: &str) -> String {
+    let path = format!("{}/../sources.b/{}", env!("CARGO_MANIFEST_DIR"), name);
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path, e))
+}
+
+fn interpret(code: &str) -> Run {
+    let mut i = BrainfuckInterpreter::new(code, OutputMode::Utf8, IoMode::Text);
+    i.push_input(INPUT);
+    i.close_input();
+    assert_eq!(i.run().unwrap().0, StepResult::Halted);
+
+    Run {
+        output: i.output,
+        steps: i.steps,
+        pointer: i.pointer,
+        memory: i.memory,
+    }
+}
+
+fn execute(program: &Program, mut machine: Machine, dispatch: Dispatch) -> Run {
+    machine.push_input(INPUT);
+    machine.close_input();
+    let mut output = Vec::new();
+    let mut fuel = u64::MAX;
+
+    loop {
+        let status = machine.run(program, dispatch, &mut fuel).unwrap();
+        output.append(&mut machine.output);
+        match status {
+            Status::Halted => break,
+            Status::Output | Status::OutOfFuel => {}
+            Status::NeedsInput => panic!("read past the end of the closed input"),
+        }
+    }
+
+    Run {
+        output: String::from_utf8(output).unwrap(),
+        steps: machine.steps,
+        pointer: machine.pointer,
+        memory: machine.memory.to_vec().into(),
+    }
+}
+
+/// The default superinstructions, none at all, and the default ones with the
+/// shorter patterns winning where they overlap.
+fn fusion_tables() -> Vec<(&'static str, FusionTable)> {
+    let mut shortest_first = FusionTable::default();
+    shortest_first
+        .fusions
+        .sort_by_key(|fusion| fusion.pattern().len());
+
+    vec![
+        ("the default fusions", FusionTable::default()),
+        ("no fusions", FusionTable::none()),
+        ("the shortest fusions first", shortest_first),
+    ]
+}
+
+/// Never specializing loops and specializing them from their first
+/// iteration, besides the default
+const HOT_LOOP_THRESHOLDS: [u32; 2] = [0, 1];
+
+/// Runs `name` on every dispatcher this build has, with each of the
+/// [`fusion_tables`], and with each of the [`HOT_LOOP_THRESHOLDS`] and the
+/// default fusions. The two are independent, so not every pair is run, which
+/// would take minutes on mandelbrot.b.
+fn differential(name: &str) {
+    let code = source(name);
+    let expected = interpret(&code);
+
+    for (fusions, table) in fusion_tables() {
+        let program = Program::compile_with(&code, &table).unwrap();
+        let thresholds: &[u32] = if table == FusionTable::default() {
+            &HOT_LOOP_THRESHOLDS
+        } else {
+            &[]
+        };
+
+        for dispatch in Dispatch::available() {
+            for &threshold in [HOT_LOOP_THRESHOLD].iter().chain(thresholds) {
+                let mut machine = Machine::new(IoMode::Text);
+                machine.hot_loop_threshold = threshold;
+                let run = execute(&program, machine, dispatch);
+                assert!(
+                    run == expected,
+                    "{} on {:?} with {} and a hot loop threshold of {} differs from the step \
+                     interpreter",
+                    name,
+                    dispatch,
+                    fusions,
+                    threshold
+                );
+            }
+        }
+    }
+}
+
+macro_rules! sources {
+    ($($test:ident => $name:literal),* $(,)?) => {
+        $(
+            #[test]
+            fn $test() {
+                differential($name);
+            }
+        )*
+    };
+}
+
+sources! {
+    cat => "cat.b",
+    collatz => "collatz.b",
+    fibonacci => "fibonacci.b",
+    hanoi => "hanoi.bf",
+    hello => "hello.b",
+    mandelbrot => "mandelbrot.b",
+    rot13 => "rot13.b",
+    txt2brainfuck => "txt2brainfuck.b",
+    wc => "wc.b",
+}
+
+#[test]
+fn the_fusions_take_fewer_ops() {
+    let code = source("mandelbrot.b");
+    let fused = Program::compile_with(&code, &FusionTable::default()).unwrap();
+    let unfused = Program::compile_with(&code, &FusionTable::none()).unwrap();
+    assert!(fused.instrs.len() < unfused.instrs.len());
+}
+
+#[test]
+fn every_source_is_covered() {
+    let dir = format!("{}/../sources.b", env!("CARGO_MANIFEST_DIR"));
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "cat.b",
+            "collatz.b",
+            "fibonacci.b",
+            "hanoi.bf",
+            "hello.b",
+            "mandelbrot.b",
+            "rot13.b",
+            "txt2brainfuck.b",
+            "wc.b",
+        ]
+    );
+}
@@ -0,0 +1,40 @@
+//! `--exit-from-cell`, whose cell is optional and so has to follow an `=`.
+
+use std::{fs, process::Command};
+
+/// Runs `code` from a file named after `test`, with `args` before it.
+fn exit_code(test: &str, args: &[&str], code: &str) -> Option<i32> {
+    let path = std::env::temp_dir().join(format!("bf-{}-{}.b", test, std::process::id()));
+    fs::write(&path, code).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .arg("--no-debugger")
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.stderr.is_empty(), "{:?}", output);
+
+    output.status.code()
+}
+
+#[test]
+fn the_program_after_the_flag_is_not_taken_for_the_cell() {
+    assert_eq!(
+        exit_code("next-argument", &["--exit-from-cell"], "+++>+"),
+        Some(1)
+    );
+}
+
+#[test]
+fn the_cell_follows_an_equals_sign() {
+    assert_eq!(
+        exit_code("index", &["--exit-from-cell=0"], "+++>+"),
+        Some(3)
+    );
+    assert_eq!(
+        exit_code("current", &["--exit-from-cell=current"], "+++>+"),
+        Some(1)
+    );
+}
@@ -0,0 +1,149 @@
+//! The instructions the interpreter lowers the code to, and how running them
+//! still lines up with the source.
+
+use brainfuck_extended::{
+    cell::Memory,
+    instr::{lower, Instr},
+    pragma, BrainfuckInterpreter, IoMode, OutputMode, StepResult,
+};
+
+fn lowered(code: &str) -> (Vec<Instr>, Vec<usize>) {
+    let chars: Vec<char> = code.chars().collect();
+    let (instrs, positions, _) = lower(&chars, &pragma::skipped(code), &[]);
+    (instrs, positions)
+}
+
+fn interpreter(code: &str) -> BrainfuckInterpreter {
+    let mut i = BrainfuckInterpreter::new(code, OutputMode::Ascii, IoMode::Text);
+    i.close_input();
+    i
+}
+
+#[test]
+fn runs_are_merged_and_comments_dropped() {
+    let (instrs, positions) = lowered("+++ -- >><<< comment .,");
+    assert_eq!(
+        instrs,
+        [
+            Instr::Add(3),
+            Instr::Add(-2),
+            Instr::Move(2),
+            Instr::Move(-3),
+            Instr::Write,
+            Instr::Read,
+        ]
+    );
+    assert_eq!(positions, [0, 4, 7, 9, 21, 22, 23]);
+}
+
+#[test]
+fn brackets_know_the_index_of_their_partner() {
+    let (instrs, positions) = lowered("a[-[>]<]b");
+    assert_eq!(
+        instrs,
+        [
+            Instr::Open(6),
+            Instr::Add(-1),
+            Instr::Open(4),
+            Instr::Move(1),
+            Instr::Close(2),
+            Instr::Move(-1),
+            Instr::Close(0),
+        ]
+    );
+    assert_eq!(positions, [1, 2, 3, 4, 5, 6, 7, 9]);
+}
+
+#[test]
+fn directives_and_markers_are_single_instructions() {
+    let (instrs, positions) = lowered("+\n@pragma eof 0 [\n# @phase x\n-");
+    assert_eq!(
+        instrs,
+        [
+            Instr::Add(1),
+            Instr::Skip,
+            Instr::Marker,
+            Instr::Marker,
+            Instr::Add(-1),
+        ]
+    );
+    assert_eq!(positions, [0, 2, 18, 20, 29, 30]);
+}
+
+#[test]
+fn a_run_is_one_step_of_the_interpreter_but_counts_every_character() {
+    let mut i = interpreter("+++ comment >>");
+    i.record_heat();
+
+    assert_eq!(i.step().unwrap(), StepResult::Continue);
+    assert_eq!((i.steps, i.code_pos, i.memory.to_byte(0)), (3, 12, Some(3)));
+    assert_eq!(i.step().unwrap(), StepResult::Halted);
+    assert_eq!((i.steps, i.pointer), (5, 2));
+
+    assert_eq!((i.stats.increments, i.stats.moves_right), (3, 2));
+    assert_eq!(i.stats.comments, 9);
+    let heat = i.heat.as_ref().unwrap();
+    assert_eq!(&heat[..], &[1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+}
+
+#[test]
+fn comments_are_counted_each_time_they_are_passed_over() {
+    // the loop's own on each of its three iterations, the ones after it and
+    // after the directive once, a skipped loop's never, and the directive is
+    // one step
+    let mut i = interpreter("+++[ab-]cd[ef]@pragma eof 0\ngh");
+    i.run().unwrap();
+    assert_eq!(i.stats.comments, 3 * 2 + 2 + 3 + 1);
+}
+
+#[test]
+fn moves_stop_at_the_ends_of_the_tape_or_wrap_around() {
+    let mut i = interpreter("<<<+>>>>>+");
+    i.resize_memory(4);
+    i.run().unwrap();
+    assert_eq!(i.memory, Memory::Bytes(vec![1, 0, 0, 1]));
+    assert_eq!(i.stats.max_pointer, 3);
+
+    let mut i = interpreter("@pragma wrap-pointer\n<+>>>>>>+");
+    i.pragmas.wrap_pointer = true;
+    i.resize_memory(4);
+    i.run().unwrap();
+    assert_eq!(i.memory, Memory::Bytes(vec![0, 1, 0, 1]));
+    assert_eq!(i.stats.max_pointer, 3);
+}
+
+#[test]
+fn a_breakpoint_covers_the_comments_before_an_instruction_and_the_run_itself() {
+    let mut i = interpreter("+ comment ---.");
+    assert_eq!(i.next_span(), 0..1);
+    i.step().unwrap();
+    assert_eq!(i.next_span(), 1..13);
+    i.step().unwrap();
+    assert_eq!(i.next_span(), 13..14);
+}
+
+#[test]
+fn seeking_lands_on_the_next_instruction() {
+    let mut i = interpreter("++ comment [-]");
+    i.seek(4);
+    assert_eq!((i.pc, i.code_pos), (1, 11));
+    i.seek(14);
+    assert!(i.halted());
+}
+
+#[test]
+fn the_brackets_of_directives_are_not_loops() {
+    let code = "@pragma eof 0 [\n[-]@assert cell[0] == 0\n]";
+    let mut i = interpreter(code);
+    i.enable_assertions().unwrap();
+    let brackets = i.brackets();
+
+    let loop_start = code.find("[-]").unwrap();
+    assert_eq!(brackets.matching(loop_start), Some(loop_start + 2));
+    // the `[` of the pragma and the `]` at the end would pair up otherwise
+    assert_eq!(brackets.matching(14), None);
+    assert_eq!(brackets.matching(code.len() - 1), None);
+    let condition = code.find("[0]").unwrap();
+    assert_eq!(brackets.matching(condition + 2), None);
+    assert_eq!(brackets.matching(condition), None);
+}
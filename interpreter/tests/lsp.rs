@@ -0,0 +1,140 @@
+//! `bf lsp`, driven the way an editor would drive it.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+/// Sends the messages to a new `bf lsp` and returns what it sent back, and
+/// whether it exited successfully.
+fn session(messages: Vec<Value>) -> (Vec<Value>, bool) {
+    let mut lsp = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input: String = messages.into_iter().map(frame).collect();
+    lsp.stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = lsp.wait_with_output().unwrap();
+
+    let mut replies = Vec::new();
+    let mut rest = std::str::from_utf8(&output.stdout).unwrap();
+    while let Some((headers, body)) = rest.split_once("\r\n\r\n") {
+        let length: usize = headers
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        replies.push(serde_json::from_str(&body[..length]).unwrap());
+        rest = &body[length..];
+    }
+
+    (replies, output.status.success())
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+const URI: &str = "file:///tmp/program.b";
+
+#[test]
+fn diagnostics_are_published_for_every_version_of_a_document() {
+    let (replies, success) = session(vec![
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("initialized", json!({})),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "brainfuck", "version": 1, "text": "ä +-.\n[" } }),
+        ),
+        notification(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": "+." }],
+            }),
+        ),
+        notification(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": URI } }),
+        ),
+        request(2, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+    assert!(success);
+    assert_eq!(replies.len(), 5, "{:#?}", replies);
+
+    assert_eq!(replies[0]["id"], 1);
+    assert_eq!(replies[0]["result"]["capabilities"]["textDocumentSync"], 1);
+
+    let opened = &replies[1]["params"];
+    assert_eq!(replies[1]["method"], "textDocument/publishDiagnostics");
+    assert_eq!(opened["uri"], URI);
+    let diagnostics = opened["diagnostics"].as_array().unwrap();
+    let pair = diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic["code"] == "redundant-op-pairs")
+        .unwrap();
+    // `ä` is two bytes, but a single UTF-16 code unit
+    assert_eq!(
+        pair["range"],
+        json!({ "start": { "line": 0, "character": 2 }, "end": { "line": 0, "character": 4 } })
+    );
+    assert_eq!(pair["severity"], 3);
+    let bracket = diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic["code"] == "unmatched-bracket")
+        .unwrap();
+    assert_eq!(
+        bracket["range"]["start"],
+        json!({ "line": 1, "character": 0 })
+    );
+    assert_eq!(bracket["severity"], 1);
+
+    assert_eq!(replies[2]["params"]["diagnostics"], json!([]));
+    assert_eq!(replies[3]["params"]["diagnostics"], json!([]));
+    assert_eq!(
+        replies[4],
+        json!({ "jsonrpc": "2.0", "id": 2, "result": null })
+    );
+}
+
+#[test]
+fn unknown_requests_get_an_error() {
+    let (replies, success) = session(vec![
+        request(1, "initialize", json!({ "capabilities": {} })),
+        request(2, "textDocument/hover", json!({})),
+        notification("$/setTrace", json!({ "value": "off" })),
+        request(3, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+    assert!(success);
+    assert_eq!(replies.len(), 3, "{:#?}", replies);
+    assert_eq!(replies[1]["id"], 2);
+    assert_eq!(replies[1]["error"]["code"], -32601);
+}
+
+#[test]
+fn exiting_without_shutting_down_fails() {
+    let (_, success) = session(vec![
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("exit", Value::Null),
+    ]);
+    assert!(!success);
+}
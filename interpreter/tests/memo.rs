@@ -0,0 +1,78 @@
+//! Replaying the recorded runs of pure loops ends up where running them does.
+
+use brainfuck_extended::{cell::Memory, BrainfuckInterpreter, ExecutionStats, IoMode, OutputMode};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Run {
+    memory: Memory,
+    pointer: usize,
+    output: String,
+    steps: u64,
+    stats: ExecutionStats,
+}
+
+/// Runs `code` on a tape of `cells`, with or without memoization, and how
+/// many loops were replayed.
+fn run(code: &str, cells: usize, wrap_pointer: bool, memoize: bool) -> (Run, u64) {
+    let mut i = BrainfuckInterpreter::new(code, OutputMode::Ascii, IoMode::Text);
+    i.resize_memory(cells);
+    i.pragmas.wrap_pointer = wrap_pointer;
+    if memoize {
+        i.enable_memoization();
+    }
+    i.close_input();
+    i.run().unwrap();
+
+    let replayed = i.memo.as_ref().map_or(0, |memo| memo.replayed);
+    let run = Run {
+        memory: i.memory,
+        pointer: i.pointer,
+        output: i.output,
+        steps: i.steps,
+        stats: i.stats,
+    };
+    (run, replayed)
+}
+
+/// Checks that memoizing `code` replays some loop and changes nothing else.
+fn same_with_memoization(code: &str, cells: usize, wrap_pointer: bool) {
+    let (plain, _) = run(code, cells, wrap_pointer, false);
+    let (memoized, replayed) = run(code, cells, wrap_pointer, true);
+    assert_eq!(memoized, plain, "{}", code);
+    assert!(replayed > 0, "{} replayed nothing", code);
+}
+
+// the outer loops write, so that they are not pure and the loops inside them
+// run many times from the same cells
+
+#[test]
+fn clear_loops() {
+    same_with_memoization("++++++++[>+++++[-]<-.]", 8, false);
+}
+
+#[test]
+fn copy_loops_on_equal_cells() {
+    same_with_memoization("++++++++[>[-]>[-]>[-]<<+++[->+>+<<]<-.]", 8, false);
+}
+
+#[test]
+fn nested_loops() {
+    same_with_memoization("++++++++[>++[>+++[-]<-]<-.]", 8, false);
+}
+
+#[test]
+fn loops_near_the_ends_of_the_tape() {
+    // on the second cell reaching the first, and on the one before the last
+    // reaching the last
+    same_with_memoization(">>++++++++[<++[-<+>]<[-]>>>+++[->+<]>[-]<<-.]", 5, false);
+}
+
+#[test]
+fn loops_with_a_wrapping_pointer() {
+    // the counter is on the last cell, and the loop on the one after it
+    same_with_memoization(
+        "@pragma wrap-pointer\n<++++++++[>>++[-<+>]<[-]<-.]",
+        4,
+        true,
+    );
+}
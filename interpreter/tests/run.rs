@@ -0,0 +1,35 @@
+//! `bf run`, which runs a program the same as `bf` without a subcommand.
+
+use std::process::Command;
+
+fn bf(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn a_one_liner_runs_on_a_fixed_input() {
+    let args = ["-e", ",[.[-],]", "--no-debugger", "--fixed-input", "abc"];
+
+    let mut run = vec!["run"];
+    run.extend(args);
+    assert_eq!(bf(&run), "abc");
+    assert_eq!(bf(&args), "abc");
+}
+
+#[test]
+fn a_fixed_input_cannot_be_combined_with_an_input_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(["run", "-e", ",.", "input.txt", "--fixed-input", "abc"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
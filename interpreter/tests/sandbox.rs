@@ -0,0 +1,151 @@
+//! Every way a sandboxed run can fail, and what is reported about it.
+
+use std::time::Duration;
+
+use brainfuck_extended::{
+    sandbox::{run, SandboxError, SandboxLimits, SandboxReport},
+    IoMode,
+};
+
+fn sandboxed(code: &str, input: &str, limits: SandboxLimits) -> SandboxReport {
+    run(code, input.as_bytes(), &limits)
+}
+
+fn error(report: &SandboxReport) -> &SandboxError {
+    assert!(!report.ok, "{:?}", report);
+    &report
+        .error
+        .as_ref()
+        .expect("a failed run has an error")
+        .error
+}
+
+#[test]
+fn a_run_within_the_limits_is_ok() {
+    let report = sandboxed(",[.[-],]", "echo", SandboxLimits::default());
+    assert!(report.ok, "{:?}", report);
+    assert_eq!(report.error, None);
+    assert_eq!(report.output, "echo");
+    assert!(report.steps > 0);
+}
+
+#[test]
+fn unmatched_brackets_are_rejected_before_running() {
+    let report = sandboxed("+.[", "", SandboxLimits::default());
+    assert_eq!(
+        error(&report),
+        &SandboxError::UnmatchedBracket {
+            position: 2,
+            bracket: '['
+        }
+    );
+    assert_eq!((report.steps, report.output.as_str()), (0, ""));
+
+    let report = sandboxed("+]", "", SandboxLimits::default());
+    assert_eq!(
+        error(&report),
+        &SandboxError::UnmatchedBracket {
+            position: 1,
+            bracket: ']'
+        }
+    );
+}
+
+#[test]
+fn pragmas_that_cannot_be_honored_are_rejected() {
+    let report = sandboxed("@pragma sideways\n+.", "", SandboxLimits::default());
+    assert!(
+        matches!(error(&report), SandboxError::Pragma { .. }),
+        "{:?}",
+        report
+    );
+    assert_eq!(report.steps, 0);
+}
+
+#[test]
+fn code_and_input_past_their_limits_are_rejected() {
+    let limits = SandboxLimits {
+        max_code: 4,
+        max_input: 2,
+        ..SandboxLimits::default()
+    };
+
+    let report = sandboxed("+++++", "", limits);
+    assert_eq!(error(&report), &SandboxError::CodeTooLong { limit: 4 });
+
+    let report = sandboxed(",.", "abc", limits);
+    assert_eq!(error(&report), &SandboxError::InputTooLong { limit: 2 });
+}
+
+#[test]
+fn a_program_that_does_not_halt_hits_the_step_limit() {
+    let limits = SandboxLimits {
+        max_steps: 1000,
+        ..SandboxLimits::default()
+    };
+    let report = sandboxed("+[>+<]", "", limits);
+    assert_eq!(error(&report), &SandboxError::StepLimit { limit: 1000 });
+    assert_eq!(report.steps, 1000);
+}
+
+#[test]
+fn a_program_that_takes_too_long_times_out() {
+    let limits = SandboxLimits {
+        max_steps: u64::MAX,
+        timeout: Duration::ZERO,
+        ..SandboxLimits::default()
+    };
+    let report = sandboxed("+[>+<]", "", limits);
+    assert_eq!(error(&report), &SandboxError::Timeout { limit_ms: 0 });
+}
+
+#[test]
+fn output_past_the_limit_is_cut_off() {
+    let limits = SandboxLimits {
+        max_output: 3,
+        ..SandboxLimits::default()
+    };
+    let report = sandboxed("++++++++[>++++++++<-]>+[.]", "", limits);
+    assert_eq!(error(&report), &SandboxError::OutputLimit { limit: 3 });
+    assert_eq!(report.output, "AAA");
+}
+
+#[test]
+fn memory_past_the_limit_is_rejected() {
+    let limits = SandboxLimits {
+        max_memory: 1024,
+        ..SandboxLimits::default()
+    };
+    let report = sandboxed("+", "", limits);
+    assert_eq!(error(&report), &SandboxError::MemoryLimit { limit: 1024 });
+}
+
+#[test]
+fn engine_failures_are_runtime_errors() {
+    let limits = SandboxLimits {
+        io_mode: IoMode::Numeric,
+        ..SandboxLimits::default()
+    };
+    let report = sandboxed(",.", "twelve", limits);
+    assert!(
+        matches!(error(&report), SandboxError::Runtime { .. }),
+        "{:?}",
+        report
+    );
+}
+
+#[test]
+fn errors_serialize_with_their_kind_and_message() {
+    let limits = SandboxLimits {
+        max_steps: 10,
+        ..SandboxLimits::default()
+    };
+    let report = sandboxed("+[]", "", limits);
+    let json = serde_json::to_value(&report).unwrap();
+
+    assert_eq!(json["ok"], false);
+    assert_eq!(json["error"]["kind"], "step_limit");
+    assert_eq!(json["error"]["kind"], error(&report).kind());
+    assert_eq!(json["error"]["limit"], 10);
+    assert_eq!(json["error"]["message"], "did not halt within 10 steps");
+}
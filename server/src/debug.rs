@@ -0,0 +1,521 @@
+//! The `/debug` websocket. Each text message is a JSON-RPC 2.0 request, and
+//! each connection gets its own interpreter, with the same step, time and
+//! output limits as `/run` (the time limit applies to every request instead
+//! of the whole session, and the step limit to every program the session
+//! loads together). A session that gets no message for `--idle-timeout-secs`
+//! is dropped. The methods are:
+//!
+//! - `load {code, input?}`: start debugging a program, with the input closed
+//!   after `input`
+//! - `step {count?}`: execute `count` instructions (default 1)
+//! - `continue`: execute until the program halts, reaches a breakpoint or hits
+//!   a limit
+//! - `set_breakpoints {positions}`: pause before the instructions at these
+//!   positions of the code, replacing the previous breakpoints
+//! - `memory {start?, length?}`: read cells of the tape
+//! - `state`: describe the program without running it
+//!
+//! `load`, `step`, `continue` and `state` reply with a [`State`], whose
+//! `output` is only what was written since the previous reply.
+
+use std::{
+    collections::BTreeSet,
+    io,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Instant,
+};
+
+use brainfuck_extended::{
+    cell::CellValue,
+    sandbox::{check_source, ReportedError, SandboxError, SandboxLimits},
+    BrainfuckInterpreter, OutputMode, StepResult,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tiny_http::{Header, Request, Response, StatusCode};
+use tungstenite::{
+    handshake::derive_accept_key,
+    protocol::{Role, WebSocketConfig},
+    Message, WebSocket,
+};
+
+use crate::{error, Shared};
+
+/// The socket of a session, boxed by `tiny_http`
+type Socket = WebSocket<Box<dyn tiny_http::ReadWrite + Send>>;
+
+/// How many steps run between checks of the time limit
+const TIME_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// The largest request accepted, which is enough for the largest code and
+/// input the sandbox accepts
+fn max_message_size(limits: &SandboxLimits) -> usize {
+    2 * (limits.max_code + limits.max_input) + 4096
+}
+
+// the error codes defined by JSON-RPC
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// and ours
+const REJECTED: i64 = -32000;
+const NOT_LOADED: i64 = -32001;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// Left out for notifications, which get no reply
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoadParams {
+    code: String,
+    #[serde(default)]
+    input: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepParams {
+    #[serde(default = "one")]
+    count: u64,
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BreakpointParams {
+    positions: BTreeSet<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MemoryParams {
+    #[serde(default)]
+    start: usize,
+    #[serde(default = "sixteen")]
+    length: usize,
+}
+
+fn sixteen() -> usize {
+    16
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// Why `step` or `continue` stopped
+enum Stopped {
+    /// The end of the program was reached
+    Halted,
+    /// The instruction at `position` has a breakpoint
+    Breakpoint { position: usize },
+    /// `step` executed as many instructions as it was asked to
+    Stepped,
+    /// A limit of the sandbox was reached
+    Limit { error: ReportedError },
+    /// The program failed, like on invalid numeric input
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize)]
+/// Where a program is
+struct State {
+    steps: u64,
+    code_pos: usize,
+    pointer: usize,
+    /// The value of the cell under the pointer
+    cell: CellValue,
+    halted: bool,
+    /// The output written since the previous reply
+    output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopped: Option<Stopped>,
+}
+
+/// A loaded program and what the client was already told about it
+struct Program {
+    interpreter: BrainfuckInterpreter,
+    breakpoints: BTreeSet<usize>,
+    /// How much of the output was already sent
+    sent: usize,
+}
+
+struct Session {
+    limits: SandboxLimits,
+    program: Option<Program>,
+    /// The steps run by programs loaded before this one
+    earlier_steps: u64,
+}
+
+/// Answers the websocket handshake and serves the session until the client
+/// disconnects or stays quiet for longer than the idle timeout.
+pub fn serve(request: Request, shared: &Shared) -> io::Result<()> {
+    let limits = shared.limits;
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| header.value.to_string());
+    let Some(key) = key else {
+        return request.respond(error(400, "/debug only speaks websocket"));
+    };
+
+    let header = |text: String| text.parse::<Header>().expect("a valid header");
+    let response = Response::empty(StatusCode(101))
+        .with_header(header("Upgrade: websocket".to_string()))
+        .with_header(header("Connection: Upgrade".to_string()))
+        .with_header(header(format!(
+            "Sec-WebSocket-Accept: {}",
+            derive_accept_key(key.as_bytes())
+        )));
+    let stream = request.upgrade("websocket", response);
+    shared.metrics.record_debug_session();
+
+    let config = WebSocketConfig::default().max_message_size(Some(max_message_size(&limits)));
+    let socket = WebSocket::from_raw_socket(stream, Role::Server, Some(config));
+    let mut session = Session {
+        limits,
+        program: None,
+        earlier_steps: 0,
+    };
+
+    // reading blocks for as long as the client is quiet, so it happens on a
+    // thread of its own and the session can give up on the client
+    let (messages, incoming) = channel();
+    let (replies, outgoing) = channel();
+    thread::spawn(move || relay(socket, messages, outgoing));
+
+    loop {
+        match incoming.recv_timeout(shared.idle_timeout) {
+            Ok(Ok(text)) => {
+                let steps = session.steps();
+                let reply = session.handle(&text);
+                shared.metrics.record_debug_steps(session.steps() - steps);
+                if replies.send(reply).is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(Err(e)) => return Err(e),
+            // after a timeout, the socket is only closed once the client sends
+            // something or the connection drops, but the session is gone
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Passes the text messages of `socket` on to the session and sends back its
+/// replies, until the client disconnects or the session is dropped.
+fn relay(
+    mut socket: Socket,
+    messages: Sender<io::Result<String>>,
+    replies: Receiver<Option<String>>,
+) {
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if messages.send(Ok(text.to_string())).is_err() {
+                    break;
+                }
+                match replies.recv() {
+                    Ok(Some(reply)) => {
+                        if let Err(e) = socket.send(Message::text(reply)) {
+                            let _ = messages.send(Err(io::Error::other(e)));
+                            return;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(_) => break,
+                }
+            }
+            // pings are answered by the socket itself
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => return,
+            Err(e) => {
+                let _ = messages.send(Err(io::Error::other(e)));
+                return;
+            }
+        }
+    }
+
+    // the session was dropped while the client was away
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // methods without parameters may be sent without `params`
+    let params = if params.is_null() {
+        Value::Object(Default::default())
+    } else {
+        params
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+impl Session {
+    /// Handles a message, returning the reply if there is one.
+    fn handle(&mut self, text: &str) -> Option<String> {
+        let (id, result) = match serde_json::from_str::<RpcRequest>(text) {
+            Ok(request) if request.jsonrpc == "2.0" => {
+                let result = self.call(&request.method, request.params);
+                (request.id?, result)
+            }
+            Ok(request) => (
+                request.id.unwrap_or(Value::Null),
+                Err(RpcError::new(INVALID_REQUEST, "`jsonrpc` must be \"2.0\"")),
+            ),
+            Err(e) => {
+                let code = if serde_json::from_str::<Value>(text).is_ok() {
+                    INVALID_REQUEST
+                } else {
+                    PARSE_ERROR
+                };
+                (Value::Null, Err(RpcError::new(code, e.to_string())))
+            }
+        };
+
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        let response = RpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        };
+        Some(serde_json::to_string(&response).expect("responses serialize"))
+    }
+
+    fn call(&mut self, method: &str, params_value: Value) -> Result<Value, RpcError> {
+        let result = match method {
+            "load" => {
+                let load: LoadParams = params(params_value)?;
+                self.load(load)?
+            }
+            "step" => {
+                let step: StepParams = params(params_value)?;
+                let stopped = self.run(step.count)?;
+                self.state(Some(stopped))?
+            }
+            "continue" => {
+                params::<Value>(params_value)?;
+                let stopped = self.run(u64::MAX)?;
+                self.state(Some(stopped))?
+            }
+            "set_breakpoints" => {
+                let breakpoints: BreakpointParams = params(params_value)?;
+                let program = self.program()?;
+                program.breakpoints = breakpoints.positions;
+                serde_json::json!({ "positions": program.breakpoints })
+            }
+            "memory" => {
+                let memory: MemoryParams = params(params_value)?;
+                let program = self.program()?;
+                let cells = program.interpreter.memory.len();
+                let start = memory.start.min(cells);
+                let end = start.saturating_add(memory.length).min(cells);
+                serde_json::json!({
+                    "start": start,
+                    "cells": program.interpreter.memory.window(start..end),
+                })
+            }
+            "state" => {
+                params::<Value>(params_value)?;
+                self.state(None)?
+            }
+            _ => {
+                return Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("no method `{}`", method),
+                ))
+            }
+        };
+
+        Ok(result)
+    }
+
+    /// How many instructions the program ran, including any loaded before it.
+    fn steps(&self) -> u64 {
+        self.earlier_steps + self.program.as_ref().map_or(0, |p| p.interpreter.steps)
+    }
+
+    fn program(&mut self) -> Result<&mut Program, RpcError> {
+        self.program
+            .as_mut()
+            .ok_or_else(|| RpcError::new(NOT_LOADED, "no program is loaded, call `load` first"))
+    }
+
+    fn load(&mut self, load: LoadParams) -> Result<Value, RpcError> {
+        if let Err(e) = check_source(&load.code, load.input.as_bytes(), &self.limits) {
+            let reported = ReportedError::from(e);
+            return Err(RpcError {
+                code: REJECTED,
+                message: reported.message.clone(),
+                data: serde_json::to_value(reported).ok(),
+            });
+        }
+
+        let mut interpreter =
+            BrainfuckInterpreter::new(&load.code, OutputMode::Utf8, self.limits.io_mode);
+        interpreter.push_input(load.input.as_bytes());
+        interpreter.close_input();
+
+        self.earlier_steps = self.steps();
+        self.program = Some(Program {
+            interpreter,
+            breakpoints: BTreeSet::new(),
+            sent: 0,
+        });
+        self.state(None)
+    }
+
+    /// Executes up to `count` instructions, stopping early at the end of the
+    /// program, a breakpoint or a limit.
+    fn run(&mut self, count: u64) -> Result<Stopped, RpcError> {
+        let limits = self.limits;
+        // loading another program does not start the step limit over
+        let earlier_steps = self.earlier_steps;
+        let program = self.program()?;
+        let i = &mut program.interpreter;
+        let start = Instant::now();
+        let limit = |error: SandboxError| Stopped::Limit {
+            error: error.into(),
+        };
+
+        for n in 0..count {
+            if i.halted() {
+                return Ok(Stopped::Halted);
+            }
+            if earlier_steps + i.steps >= limits.max_steps {
+                return Ok(limit(SandboxError::StepLimit {
+                    limit: limits.max_steps,
+                }));
+            }
+            if i.output.len() > limits.max_output {
+                return Ok(limit(SandboxError::OutputLimit {
+                    limit: limits.max_output,
+                }));
+            }
+            if n % TIME_CHECK_INTERVAL == TIME_CHECK_INTERVAL - 1
+                && start.elapsed() > limits.timeout
+            {
+                return Ok(limit(SandboxError::Timeout {
+                    limit_ms: limits.timeout.as_millis() as u64,
+                }));
+            }
+
+            match i.step() {
+                Ok(StepResult::Halted) => return Ok(Stopped::Halted),
+                // the input is closed, so `,` never waits
+                Ok(StepResult::Continue | StepResult::NeedsInput) => {}
+                Err(e) => {
+                    return Ok(Stopped::Error {
+                        message: e.to_string(),
+                    })
+                }
+            }
+
+            if let Some(&position) = program.breakpoints.range(i.next_span()).next() {
+                return Ok(Stopped::Breakpoint { position });
+            }
+        }
+
+        Ok(Stopped::Stepped)
+    }
+
+    fn state(&mut self, stopped: Option<Stopped>) -> Result<Value, RpcError> {
+        let program = self.program()?;
+        let i = &program.interpreter;
+        let output = i.output[program.sent..].to_string();
+        program.sent = i.output.len();
+
+        let state = State {
+            steps: i.steps,
+            code_pos: i.code_pos,
+            pointer: i.pointer,
+            cell: i.memory.get(i.pointer),
+            halted: i.halted(),
+            output,
+            stopped,
+        };
+        Ok(serde_json::to_value(state).expect("states serialize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(session: &mut Session, method: &str, params: Value) -> Value {
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let reply = session.handle(&request.to_string()).unwrap();
+        serde_json::from_str(&reply).unwrap()
+    }
+
+    #[test]
+    fn loading_again_does_not_start_the_step_limit_over() {
+        let mut session = Session {
+            limits: SandboxLimits {
+                max_steps: 10,
+                ..SandboxLimits::default()
+            },
+            program: None,
+            earlier_steps: 0,
+        };
+        let program = serde_json::json!({ "code": "+[]" });
+
+        call(&mut session, "load", program.clone());
+        let reply = call(&mut session, "continue", Value::Null);
+        assert_eq!(reply["result"]["stopped"]["kind"], "limit", "{}", reply);
+        assert_eq!(reply["result"]["steps"], 10);
+
+        call(&mut session, "load", program);
+        let reply = call(&mut session, "step", Value::Null);
+        assert_eq!(reply["result"]["stopped"]["kind"], "limit", "{}", reply);
+        assert_eq!(reply["result"]["steps"], 0);
+    }
+}
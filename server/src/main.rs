@@ -0,0 +1,251 @@
+//! `bf-server`: a self-hosted Brainfuck playground. `POST /run` runs a program
+//! in the sandbox of `brainfuck_extended::sandbox` and answers with its report,
+//! and `/debug` is a websocket for stepping through a program with JSON-RPC
+//! (see [`debug`]). Every run is bounded by the server's limits, which requests
+//! can only lower. `GET /metrics` reports what the server did for Prometheus
+//! (see [`metrics`]).
+
+#[macro_use]
+extern crate serde;
+
+use std::{
+    io::{self, Read},
+    process::exit,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use brainfuck_extended::{
+    sandbox::{self, SandboxLimits},
+    IoMode, Result,
+};
+use clap::Parser;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+mod debug;
+mod metrics;
+
+use metrics::Metrics;
+
+#[derive(Parser)]
+/// Serve a Brainfuck playground: POST /run runs a program in the sandbox, the
+/// /debug websocket steps through one with JSON-RPC, and GET /metrics reports
+/// for Prometheus
+struct Options {
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    /// The address to listen on
+    listen: String,
+    #[clap(long, value_name = "COUNT", default_value_t = 4)]
+    /// How many /run requests are handled at once
+    workers: usize,
+    #[clap(long, value_name = "COUNT", default_value_t = 16)]
+    /// How many /debug sessions may be open at once
+    max_sessions: usize,
+    #[clap(long, value_name = "SECS", default_value_t = 300)]
+    /// How long a /debug session may go without a message before it is
+    /// dropped, so abandoned ones do not take up `--max-sessions`
+    idle_timeout_secs: u64,
+    #[clap(long, value_name = "STEPS")]
+    /// The most instructions a run (or a debug session, across all the
+    /// programs it loads) may execute, instead of the sandbox's default
+    max_steps: Option<u64>,
+    #[clap(long, value_name = "MS")]
+    /// How long a run (or a single debug request) may take, instead of the
+    /// sandbox's default
+    timeout_ms: Option<u64>,
+    #[clap(long, value_name = "BYTES")]
+    /// How much output a run may write, instead of the sandbox's default
+    max_output: Option<usize>,
+    #[clap(long, value_enum, default_value_t)]
+    /// How `.` and `,` interpret cell values
+    io: IoMode,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Limits a request asks for, each only applied if it is lower than the
+/// server's
+pub struct RequestedLimits {
+    pub max_steps: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub max_output: Option<usize>,
+}
+
+impl RequestedLimits {
+    pub fn apply(&self, limits: &SandboxLimits) -> SandboxLimits {
+        SandboxLimits {
+            max_steps: self.max_steps.map_or(limits.max_steps, |max_steps| {
+                max_steps.min(limits.max_steps)
+            }),
+            timeout: self.timeout_ms.map_or(limits.timeout, |timeout_ms| {
+                Duration::from_millis(timeout_ms).min(limits.timeout)
+            }),
+            max_output: self.max_output.map_or(limits.max_output, |max_output| {
+                max_output.min(limits.max_output)
+            }),
+            ..*limits
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// The body of `POST /run`
+struct RunRequest {
+    code: String,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    limits: RequestedLimits,
+}
+
+fn main() {
+    if let Err(e) = main_inner() {
+        eprintln!("Error: {}", e);
+        exit(1);
+    }
+}
+
+fn main_inner() -> Result {
+    let options = Options::parse();
+    let defaults = SandboxLimits::default();
+    let limits = SandboxLimits {
+        max_steps: options.max_steps.unwrap_or(defaults.max_steps),
+        timeout: options
+            .timeout_ms
+            .map_or(defaults.timeout, Duration::from_millis),
+        max_output: options.max_output.unwrap_or(defaults.max_output),
+        io_mode: options.io,
+        ..defaults
+    };
+
+    let server = Arc::new(
+        Server::http(&options.listen)
+            .map_err(|e| format!("failed to listen on {}: {}", options.listen, e))?,
+    );
+    let shared = Arc::new(Shared {
+        limits,
+        max_sessions: options.max_sessions,
+        idle_timeout: Duration::from_secs(options.idle_timeout_secs),
+        sessions: AtomicUsize::new(0),
+        metrics: Metrics::default(),
+        started: Instant::now(),
+    });
+    eprintln!("listening on http://{}", server.server_addr());
+
+    let workers: Vec<_> = (0..options.workers.max(1))
+        .map(|_| {
+            let server = server.clone();
+            let shared = shared.clone();
+
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    if let Err(e) = handle(request, &shared) {
+                        eprintln!("request failed: {}", e);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().map_err(|_| "a worker thread panicked")?;
+    }
+    Ok(())
+}
+
+/// What every request can see
+pub struct Shared {
+    pub limits: SandboxLimits,
+    pub max_sessions: usize,
+    /// How long a debug session may go without a message
+    pub idle_timeout: Duration,
+    /// How many debug sessions are open
+    pub sessions: AtomicUsize,
+    pub metrics: Metrics,
+    pub started: Instant,
+}
+
+fn handle(request: Request, shared: &Arc<Shared>) -> io::Result<()> {
+    let path = request.url().split('?').next().unwrap_or_default();
+
+    match (request.method(), path) {
+        (Method::Post, "/run") => run(request, shared),
+        (Method::Get, "/debug") => {
+            if shared.sessions.fetch_add(1, Ordering::SeqCst) >= shared.max_sessions {
+                shared.sessions.fetch_sub(1, Ordering::SeqCst);
+                shared.metrics.record_rejected();
+                return request.respond(error(503, "too many debug sessions"));
+            }
+
+            // a session lasts as long as the client wants, so it must not
+            // hold up a worker
+            let shared = shared.clone();
+            thread::spawn(move || {
+                if let Err(e) = debug::serve(request, &shared) {
+                    eprintln!("debug session failed: {}", e);
+                }
+                shared.sessions.fetch_sub(1, Ordering::SeqCst);
+            });
+            Ok(())
+        }
+        (Method::Get, "/metrics") => {
+            let text = shared.metrics.render(
+                shared.sessions.load(Ordering::SeqCst),
+                shared.started.elapsed(),
+            );
+            let content_type: Header = "Content-Type: text/plain; version=0.0.4"
+                .parse()
+                .expect("a valid header");
+            request.respond(Response::from_string(text).with_header(content_type))
+        }
+        (_, "/run" | "/debug" | "/metrics") => request.respond(error(405, "method not allowed")),
+        _ => request.respond(error(404, "not found")),
+    }
+}
+
+/// Runs the program of a `POST /run` request and answers with the report,
+/// whether the program failed or not.
+fn run(mut request: Request, shared: &Shared) -> io::Result<()> {
+    let limits = &shared.limits;
+    // generous for the JSON escaping of the largest code and input accepted
+    let max_body = 2 * (limits.max_code + limits.max_input) + 4096;
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(max_body as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > max_body {
+        shared.metrics.record_rejected();
+        return request.respond(error(413, "the request is too large"));
+    }
+
+    let run: RunRequest = match serde_json::from_slice(&body) {
+        Ok(run) => run,
+        Err(e) => {
+            shared.metrics.record_rejected();
+            return request.respond(error(400, &format!("invalid request: {}", e)));
+        }
+    };
+
+    let report = sandbox::run(&run.code, run.input.as_bytes(), &run.limits.apply(limits));
+    shared.metrics.record_run(&report);
+    request.respond(json(200, &serde_json::to_string(&report)?))
+}
+
+fn json(status: u16, body: &str) -> Response<io::Cursor<Vec<u8>>> {
+    let content_type: Header = "Content-Type: application/json"
+        .parse()
+        .expect("a valid header");
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn error(status: u16, message: &str) -> Response<io::Cursor<Vec<u8>>> {
+    json(status, &serde_json::json!({ "error": message }).to_string())
+}
@@ -0,0 +1,98 @@
+//! What the server did so far, served at `GET /metrics` in the Prometheus
+//! text format so deployments can graph and alert on it. The metrics of the
+//! runs themselves are those of [`brainfuck_extended::metrics`], which `bf
+//! batch` writes too.
+
+use std::{sync::Mutex, time::Duration};
+
+use brainfuck_extended::{
+    metrics::{metric, RunMetrics},
+    sandbox::SandboxReport,
+};
+
+struct Counters {
+    /// The runs of `/run`
+    runs: RunMetrics,
+    /// The instructions executed by debug sessions
+    debug_steps: u64,
+    debug_sessions: u64,
+    rejected_requests: u64,
+}
+
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            counters: Mutex::new(Counters {
+                runs: RunMetrics::new("POST /run", "run"),
+                debug_steps: 0,
+                debug_sessions: 0,
+                rejected_requests: 0,
+            }),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_run(&self, report: &SandboxReport) {
+        self.counters.lock().unwrap().runs.record(report);
+    }
+
+    /// Counts a request that was turned away before anything ran.
+    pub fn record_rejected(&self) {
+        self.counters.lock().unwrap().rejected_requests += 1;
+    }
+
+    pub fn record_debug_session(&self) {
+        self.counters.lock().unwrap().debug_sessions += 1;
+    }
+
+    pub fn record_debug_steps(&self, steps: u64) {
+        self.counters.lock().unwrap().debug_steps += steps;
+    }
+
+    /// Writes everything in the Prometheus text format. `open_sessions` is
+    /// how many debug sessions are open right now.
+    pub fn render(&self, open_sessions: usize, uptime: Duration) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        let plain = |value: &dyn ToString| vec![(String::new(), value.to_string())];
+
+        counters
+            .runs
+            .render(&mut out, &[("debug", counters.debug_steps)]);
+        metric(
+            &mut out,
+            "bf_rejected_requests_total",
+            "counter",
+            "Requests turned away before running anything, like malformed ones",
+            &plain(&counters.rejected_requests),
+        );
+        metric(
+            &mut out,
+            "bf_debug_sessions_total",
+            "counter",
+            "Debug sessions opened",
+            &plain(&counters.debug_sessions),
+        );
+        metric(
+            &mut out,
+            "bf_debug_sessions",
+            "gauge",
+            "Debug sessions open right now",
+            &plain(&open_sessions),
+        );
+        metric(
+            &mut out,
+            "bf_uptime_seconds",
+            "gauge",
+            "How long the server has been running",
+            &plain(&uptime.as_secs_f64()),
+        );
+
+        out
+    }
+}
@@ -0,0 +1,7 @@
+Prints the Fibonacci numbers that fit in a cell
++++++++++++++>+>+<<[>[->>>+>+<<<<]>>>>[-<<<<+>>>>]++++++++++<[->-[>+>>]>
+[+[-<+>]>+>>]<<<<<]>[-]>>[->+<]>>++++++++++<[->-[>+>>]>[+[-<+>]>+>>]<<<<
+<]>[-]>>[>+<++++++++++++++++++++++++++++++++++++++++++++++++.[-]]>[-<<++
+++++++++++++++++++++++++++++++++++++++++++++++.[-]>>]<<[++++++++++++++++
+++++++++++++++++++++++++++++++++.[-]]<<<<+++++++++++++++++++++++++++++++
++++++++++++++++++.[-]<++++++++++.[-]<<<<[->>+<<]>[-<+>>+<]>[-<+>]<<<-]